@@ -0,0 +1,5 @@
+ALTER TABLE donations DROP COLUMN IF EXISTS organization_id;
+ALTER TABLE wallets DROP COLUMN IF EXISTS organization_id;
+DROP TABLE IF EXISTS organization_invites;
+DROP TABLE IF EXISTS organization_members;
+DROP TABLE IF EXISTS organizations;
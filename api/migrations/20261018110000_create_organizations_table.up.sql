@@ -0,0 +1,37 @@
+CREATE TABLE organizations (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  name VARCHAR(100) NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE organization_members (
+  organization_id uuid REFERENCES organizations(id) ON DELETE CASCADE NOT NULL,
+  user_id uuid REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+  role VARCHAR(20) NOT NULL CHECK (role IN ('owner', 'admin', 'viewer')),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX organization_members_user_id_idx ON organization_members (user_id);
+
+CREATE TABLE organization_invites (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  organization_id uuid REFERENCES organizations(id) ON DELETE CASCADE NOT NULL,
+  email VARCHAR(100) NOT NULL,
+  role VARCHAR(20) NOT NULL CHECK (role IN ('owner', 'admin', 'viewer')),
+  invited_by uuid REFERENCES users(id) ON DELETE SET NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (organization_id, email)
+);
+
+-- Every existing user gets a personal organization (sharing the user's id) owning their data
+INSERT INTO organizations (id, name) SELECT id, email FROM users;
+INSERT INTO organization_members (organization_id, user_id, role) SELECT id, id, 'owner' FROM users;
+
+ALTER TABLE wallets ADD COLUMN organization_id uuid REFERENCES organizations(id);
+UPDATE wallets SET organization_id = user_id;
+ALTER TABLE wallets ALTER COLUMN organization_id SET NOT NULL;
+
+ALTER TABLE donations ADD COLUMN organization_id uuid REFERENCES organizations(id);
+UPDATE donations SET organization_id = user_id;
+ALTER TABLE donations ALTER COLUMN organization_id SET NOT NULL;
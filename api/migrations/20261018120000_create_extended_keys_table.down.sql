@@ -0,0 +1,2 @@
+ALTER TABLE donations DROP COLUMN IF EXISTS extended_key_id;
+DROP TABLE IF EXISTS extended_keys;
@@ -0,0 +1,12 @@
+CREATE TABLE extended_keys (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  organization_id uuid REFERENCES organizations(id) NOT NULL,
+  user_id uuid REFERENCES users(id) NOT NULL,
+  name VARCHAR(100) NOT NULL,
+  xpub VARCHAR(200) NOT NULL,
+  next_index INTEGER NOT NULL DEFAULT 0,  -- next unused index on the external chain
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (organization_id, xpub)
+);
+
+ALTER TABLE donations ADD COLUMN extended_key_id uuid REFERENCES extended_keys(id) DEFAULT NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE wallets ADD COLUMN chain VARCHAR(20) NOT NULL DEFAULT 'tron'
+  CHECK (chain IN ('tron', 'ethereum', 'bsc', 'bitcoin'));
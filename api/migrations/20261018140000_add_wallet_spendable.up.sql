@@ -0,0 +1,5 @@
+ALTER TABLE wallets ADD COLUMN spendable BOOLEAN NOT NULL DEFAULT false;
+
+-- wallets holding a private key (generated or imported) can sign transactions
+UPDATE wallets SET spendable = true
+WHERE data ? 'encrypted_private_key' OR data ? 'private_key';
@@ -0,0 +1,7 @@
+CREATE TABLE wallet_pool (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  data JSONB NOT NULL,  -- address and sealed private key, same shape as wallets.data
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX wallet_pool_created_at_idx ON wallet_pool (created_at);
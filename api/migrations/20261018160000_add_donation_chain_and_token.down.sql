@@ -0,0 +1,2 @@
+ALTER TABLE donations DROP COLUMN IF EXISTS token;
+ALTER TABLE donations DROP COLUMN IF EXISTS chain;
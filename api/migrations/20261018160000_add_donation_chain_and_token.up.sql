@@ -0,0 +1,3 @@
+ALTER TABLE donations ADD COLUMN chain VARCHAR(20) NOT NULL DEFAULT 'tron'
+  CHECK (chain IN ('tron', 'ethereum', 'bsc', 'bitcoin'));
+ALTER TABLE donations ADD COLUMN token VARCHAR(20) DEFAULT NULL;  -- NULL is the chain's native coin
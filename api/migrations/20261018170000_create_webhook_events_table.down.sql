@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS webhook_attempts;
+DROP TABLE IF EXISTS webhook_events;
+ALTER TABLE users DROP COLUMN IF EXISTS webhook_secret;
@@ -0,0 +1,30 @@
+ALTER TABLE users ADD COLUMN webhook_secret VARCHAR(100) DEFAULT NULL;  -- generated on first use
+
+CREATE TABLE webhook_events (
+  id uuid PRIMARY KEY,
+  donation_id uuid REFERENCES donations(id) ON DELETE CASCADE NOT NULL,
+  event_type VARCHAR(50) NOT NULL,
+  transaction_id VARCHAR(100) NOT NULL,
+  payload JSONB NOT NULL,
+  status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (donation_id, event_type, transaction_id)  -- redelivered deposits are ignored
+);
+
+CREATE INDEX webhook_events_due_idx ON webhook_events (next_attempt_at) WHERE status = 'pending';
+
+CREATE TABLE webhook_attempts (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  event_id uuid REFERENCES webhook_events(id) ON DELETE CASCADE NOT NULL,
+  attempt INTEGER NOT NULL,
+  url VARCHAR(255) NOT NULL,
+  response_status INTEGER DEFAULT NULL,  -- NULL when no response was received
+  latency_ms INTEGER NOT NULL,
+  error TEXT DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX webhook_attempts_event_id_idx ON webhook_attempts (event_id);
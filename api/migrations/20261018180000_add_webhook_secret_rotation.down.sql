@@ -0,0 +1,7 @@
+ALTER TABLE webhook_attempts DROP COLUMN IF EXISTS response_body;
+
+DELETE FROM webhook_events WHERE transaction_id IS NULL;
+ALTER TABLE webhook_events ALTER COLUMN transaction_id SET NOT NULL;
+
+ALTER TABLE users DROP COLUMN IF EXISTS previous_webhook_secret_expires_at;
+ALTER TABLE users DROP COLUMN IF EXISTS previous_webhook_secret;
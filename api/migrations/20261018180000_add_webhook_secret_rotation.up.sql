@@ -0,0 +1,6 @@
+ALTER TABLE users ADD COLUMN previous_webhook_secret VARCHAR(100) DEFAULT NULL;  -- still accepted until it expires
+ALTER TABLE users ADD COLUMN previous_webhook_secret_expires_at TIMESTAMPTZ DEFAULT NULL;
+
+ALTER TABLE webhook_events ALTER COLUMN transaction_id DROP NOT NULL;  -- test pings have no transaction
+
+ALTER TABLE webhook_attempts ADD COLUMN response_body TEXT DEFAULT NULL;  -- first kilobyte of the response
@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS notifications;
+
+DROP INDEX IF EXISTS webhook_events_dedupe_key_idx;
+DELETE FROM webhook_events WHERE endpoint_id IS NOT NULL OR donation_id IS NULL;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS dedupe_key;
+ALTER TABLE webhook_events ALTER COLUMN donation_id SET NOT NULL;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS endpoint_id;
+ALTER TABLE webhook_events ADD CONSTRAINT webhook_events_donation_id_event_type_transaction_id_key
+  UNIQUE (donation_id, event_type, transaction_id);
+
+DROP TABLE IF EXISTS webhook_endpoints;
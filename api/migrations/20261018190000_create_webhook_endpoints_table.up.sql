@@ -0,0 +1,40 @@
+CREATE TABLE webhook_endpoints (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  user_id uuid REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+  url VARCHAR(255) NOT NULL,
+  description VARCHAR(255) DEFAULT NULL,
+  event_types VARCHAR(50)[] NOT NULL,
+  donation_ids uuid[] NOT NULL DEFAULT '{}',  -- empty means every donation the user can see
+  is_active BOOLEAN NOT NULL DEFAULT TRUE,
+  consecutive_failures INTEGER NOT NULL DEFAULT 0,
+  disabled_reason TEXT DEFAULT NULL,
+  disabled_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX webhook_endpoints_user_id_idx ON webhook_endpoints (user_id);
+
+-- events for an endpoint; NULL ones go to the donation's own `webhook`
+ALTER TABLE webhook_events ADD COLUMN endpoint_id uuid REFERENCES webhook_endpoints(id) ON DELETE CASCADE DEFAULT NULL;
+ALTER TABLE webhook_events ALTER COLUMN donation_id DROP NOT NULL;  -- wallet events
+
+-- events with the same key are only queued once per target
+ALTER TABLE webhook_events ADD COLUMN dedupe_key VARCHAR(255) DEFAULT NULL;
+UPDATE webhook_events SET dedupe_key = donation_id || ':' || event_type || ':' || transaction_id
+WHERE transaction_id IS NOT NULL;
+ALTER TABLE webhook_events DROP CONSTRAINT webhook_events_donation_id_event_type_transaction_id_key;
+CREATE UNIQUE INDEX webhook_events_dedupe_key_idx
+  ON webhook_events (COALESCE(endpoint_id, '00000000-0000-0000-0000-000000000000'), dedupe_key)
+  WHERE dedupe_key IS NOT NULL;
+
+CREATE TABLE notifications (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  user_id uuid REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+  kind VARCHAR(50) NOT NULL,
+  message TEXT NOT NULL,
+  data JSONB NOT NULL DEFAULT '{}',
+  read_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, created_at);
@@ -0,0 +1,3 @@
+ALTER TABLE donations DROP COLUMN IF EXISTS deactivate_wallet_on_completion;
+ALTER TABLE donations DROP COLUMN IF EXISTS completed_at;
+ALTER TABLE donations DROP COLUMN IF EXISTS status;
@@ -0,0 +1,4 @@
+ALTER TABLE donations ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'active'
+  CHECK (status IN ('active', 'completed'));
+ALTER TABLE donations ADD COLUMN completed_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+ALTER TABLE donations ADD COLUMN deactivate_wallet_on_completion BOOLEAN NOT NULL DEFAULT FALSE;
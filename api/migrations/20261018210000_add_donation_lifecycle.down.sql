@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS donations_ends_at_idx;
+DROP INDEX IF EXISTS donations_scheduled_idx;
+ALTER TABLE donations DROP COLUMN IF EXISTS ends_at;
+ALTER TABLE donations DROP COLUMN IF EXISTS starts_at;
+UPDATE donations SET status = CASE WHEN status = 'archived' THEN 'completed' ELSE 'active' END
+  WHERE status NOT IN ('active', 'completed');
+ALTER TABLE donations DROP CONSTRAINT donations_status_check;
+ALTER TABLE donations ADD CONSTRAINT donations_status_check CHECK (status IN ('active', 'completed'));
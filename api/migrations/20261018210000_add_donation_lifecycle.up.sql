@@ -0,0 +1,10 @@
+ALTER TABLE donations DROP CONSTRAINT donations_status_check;
+ALTER TABLE donations ADD CONSTRAINT donations_status_check
+  CHECK (status IN ('draft', 'scheduled', 'active', 'paused', 'completed', 'archived'));
+ALTER TABLE donations ADD COLUMN starts_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+ALTER TABLE donations ADD COLUMN ends_at TIMESTAMP WITH TIME ZONE DEFAULT NULL
+  CHECK (ends_at > starts_at);
+
+-- the scheduler looks these up on every run
+CREATE INDEX donations_scheduled_idx ON donations (starts_at) WHERE status = 'scheduled';
+CREATE INDEX donations_ends_at_idx ON donations (ends_at) WHERE status IN ('scheduled', 'active', 'paused');
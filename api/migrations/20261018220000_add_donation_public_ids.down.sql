@@ -0,0 +1,2 @@
+ALTER TABLE donations DROP COLUMN IF EXISTS slug;
+ALTER TABLE donations DROP COLUMN IF EXISTS public_id;
@@ -0,0 +1,4 @@
+-- donors find a donation by its slug, or by a public id that can't be guessed from the internal one
+ALTER TABLE donations ADD COLUMN public_id VARCHAR(32) NOT NULL UNIQUE
+  DEFAULT replace(uuid_generate_v4()::text, '-', '');
+ALTER TABLE donations ADD COLUMN slug VARCHAR(64) UNIQUE DEFAULT NULL;
@@ -0,0 +1,30 @@
+CREATE TABLE invoices (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  -- what checkout pages poll by, so it can't be guessed from the internal id
+  public_id VARCHAR(32) NOT NULL UNIQUE DEFAULT replace(uuid_generate_v4()::text, '-', ''),
+  donation_id uuid REFERENCES donations(id) ON DELETE CASCADE NOT NULL,
+  wallet_id uuid REFERENCES wallets(id) NOT NULL,
+  chain VARCHAR(20) NOT NULL,
+  token VARCHAR(20) NOT NULL,
+  address VARCHAR(100) NOT NULL,
+  amount DECIMAL(36, 18) NOT NULL CHECK (amount > 0),
+  received DECIMAL(36, 18) NOT NULL DEFAULT 0,
+  reference VARCHAR(100) DEFAULT NULL,
+  status VARCHAR(20) NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'paid', 'underpaid', 'overpaid', 'expired')),
+  expires_at TIMESTAMPTZ NOT NULL,
+  paid_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX invoices_donation_id_idx ON invoices (donation_id, created_at);
+CREATE INDEX invoices_open_idx ON invoices (wallet_id, created_at) WHERE status IN ('pending', 'underpaid');
+
+CREATE TABLE invoice_payments (
+  transaction_id VARCHAR(100) PRIMARY KEY,  -- a transfer pays at most one invoice, once
+  invoice_id uuid REFERENCES invoices(id) ON DELETE CASCADE NOT NULL,
+  amount DECIMAL(36, 18) NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX invoice_payments_invoice_id_idx ON invoice_payments (invoice_id);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS unmatched_deposits;
+DROP INDEX IF EXISTS invoices_unique_amount_idx;
+ALTER TABLE invoices DROP COLUMN IF EXISTS amount_offset;
+ALTER TABLE invoices DROP COLUMN IF EXISTS unique_amount;
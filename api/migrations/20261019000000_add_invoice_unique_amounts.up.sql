@@ -0,0 +1,22 @@
+ALTER TABLE invoices ADD COLUMN unique_amount BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE invoices ADD COLUMN amount_offset DECIMAL(36, 18) NOT NULL DEFAULT 0;  -- already part of amount
+
+-- open unique amount invoices on one wallet never expect the same amount
+CREATE UNIQUE INDEX invoices_unique_amount_idx ON invoices (wallet_id, token, amount)
+  WHERE unique_amount AND status IN ('pending', 'underpaid');
+
+-- deposits that couldn't be told apart, left for the owner to match by hand
+CREATE TABLE unmatched_deposits (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  transaction_id VARCHAR(100) NOT NULL UNIQUE,
+  wallet_id uuid REFERENCES wallets(id) NOT NULL,
+  token VARCHAR(20) NOT NULL,
+  amount DECIMAL(36, 18) NOT NULL,
+  reason VARCHAR(20) NOT NULL CHECK (reason IN ('ambiguous', 'no_exact_match', 'expired')),
+  candidate_invoice_ids uuid[] NOT NULL DEFAULT '{}',
+  resolved_invoice_id uuid REFERENCES invoices(id) ON DELETE SET NULL DEFAULT NULL,
+  resolved_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX unmatched_deposits_wallet_id_idx ON unmatched_deposits (wallet_id, created_at);
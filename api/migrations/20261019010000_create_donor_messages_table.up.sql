@@ -0,0 +1,17 @@
+CREATE TABLE donor_messages (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  donation_id uuid REFERENCES donations(id) ON DELETE CASCADE NOT NULL,
+  invoice_id uuid REFERENCES invoices(id) ON DELETE CASCADE NOT NULL UNIQUE,  -- one message per payment
+  display_name VARCHAR(50) DEFAULT NULL,
+  message VARCHAR(500) NOT NULL DEFAULT '',
+  anonymous BOOLEAN NOT NULL DEFAULT false,
+  hidden BOOLEAN NOT NULL DEFAULT false,
+  token VARCHAR(20) NOT NULL,
+  -- filled in once a deposit pays the invoice
+  transaction_id VARCHAR(100) DEFAULT NULL,
+  amount DECIMAL(36, 18) DEFAULT NULL,
+  paid_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX donor_messages_donation_id_idx ON donor_messages (donation_id, created_at);
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS donation_stream_tokens;
+DROP TABLE IF EXISTS donation_events;
+DROP FUNCTION IF EXISTS notify_donation_event();
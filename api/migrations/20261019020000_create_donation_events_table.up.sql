@@ -0,0 +1,32 @@
+-- what the event streams replay from; ids only grow, so they double as the stream position
+CREATE TABLE donation_events (
+  id BIGSERIAL PRIMARY KEY,
+  donation_id uuid REFERENCES donations(id) ON DELETE CASCADE NOT NULL,
+  organization_id uuid REFERENCES organizations(id) ON DELETE CASCADE NOT NULL,
+  event_type VARCHAR(50) NOT NULL,
+  data JSONB NOT NULL,
+  dedupe_key VARCHAR(200) UNIQUE DEFAULT NULL,  -- redelivered deposits are streamed once
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX donation_events_donation_id_idx ON donation_events (donation_id, id);
+CREATE INDEX donation_events_organization_id_idx ON donation_events (organization_id, id);
+CREATE INDEX donation_events_created_at_idx ON donation_events (created_at);
+
+-- every api instance listens, so live events reach streams whichever instance consumed the deposit
+CREATE FUNCTION notify_donation_event() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify('donation_events', NEW.id::text);
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER donation_events_notify AFTER INSERT ON donation_events
+  FOR EACH ROW EXECUTE FUNCTION notify_donation_event();
+
+-- lets overlays follow one donation without account credentials
+CREATE TABLE donation_stream_tokens (
+  donation_id uuid PRIMARY KEY REFERENCES donations(id) ON DELETE CASCADE,
+  token VARCHAR(100) NOT NULL UNIQUE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS alert_evaluations;
+DROP TABLE IF EXISTS alert_rules;
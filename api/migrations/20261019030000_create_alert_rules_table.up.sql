@@ -0,0 +1,35 @@
+CREATE TABLE alert_rules (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  user_id uuid REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+  name VARCHAR(100) NOT NULL,
+  donation_ids uuid[] NOT NULL DEFAULT '{}',  -- empty means every donation the user can see
+  -- conditions, NULL ones match anything
+  token VARCHAR(20) DEFAULT NULL,
+  min_amount DECIMAL(36, 18) DEFAULT NULL,
+  max_amount DECIMAL(36, 18) DEFAULT NULL,
+  message_required BOOLEAN NOT NULL DEFAULT false,
+  message_contains VARCHAR(100) DEFAULT NULL,
+  actions VARCHAR(50)[] NOT NULL,
+  cooldown_seconds INTEGER NOT NULL DEFAULT 0,
+  is_active BOOLEAN NOT NULL DEFAULT TRUE,
+  last_triggered_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX alert_rules_user_id_idx ON alert_rules (user_id);
+
+CREATE TABLE alert_evaluations (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  rule_id uuid REFERENCES alert_rules(id) ON DELETE CASCADE NOT NULL,
+  donation_id uuid REFERENCES donations(id) ON DELETE CASCADE NOT NULL,
+  transaction_id VARCHAR(100) NOT NULL,
+  amount DECIMAL(36, 18) NOT NULL,
+  token VARCHAR(20) NOT NULL,
+  outcome VARCHAR(20) NOT NULL,
+  reason TEXT DEFAULT NULL,
+  actions VARCHAR(50)[] NOT NULL DEFAULT '{}',  -- what ran, when triggered
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (rule_id, donation_id, transaction_id)  -- redelivered deposits are evaluated once
+);
+
+CREATE INDEX alert_evaluations_rule_id_idx ON alert_evaluations (rule_id, created_at);
@@ -0,0 +1,30 @@
+CREATE TABLE notification_channels (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  user_id uuid REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+  name VARCHAR(100) NOT NULL,
+  kind VARCHAR(20) NOT NULL,  -- email, telegram or chat_webhook
+  config JSONB NOT NULL,
+  notification_kinds VARCHAR(50)[] NOT NULL DEFAULT '{}',  -- empty means every kind
+  template TEXT DEFAULT NULL,  -- NULL sends the notification's message as is
+  is_active BOOLEAN NOT NULL DEFAULT TRUE,
+  last_error TEXT DEFAULT NULL,
+  last_delivered_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX notification_channels_user_id_idx ON notification_channels (user_id);
+
+CREATE TABLE notification_deliveries (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  notification_id uuid REFERENCES notifications(id) ON DELETE CASCADE NOT NULL,
+  channel_id uuid REFERENCES notification_channels(id) ON DELETE CASCADE NOT NULL,
+  status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_error TEXT DEFAULT NULL,
+  delivered_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (notification_id, channel_id)
+);
+
+CREATE INDEX notification_deliveries_due_idx ON notification_deliveries (next_attempt_at) WHERE status = 'pending';
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS donation_receipt_templates;
+
+ALTER TABLE notification_channels DROP COLUMN template_id;
+
+DROP TABLE IF EXISTS message_templates;
@@ -0,0 +1,44 @@
+CREATE TABLE message_templates (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  user_id uuid REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+  name VARCHAR(100) NOT NULL,
+  format VARCHAR(20) NOT NULL DEFAULT 'text' CHECK (format IN ('text', 'html', 'markdown')),
+  subject VARCHAR(255) DEFAULT NULL,  -- for email; NULL uses the notification's message
+  body TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX message_templates_user_id_idx ON message_templates (user_id);
+
+-- channels keep their inline text template, or use one of these instead
+ALTER TABLE notification_channels
+  ADD COLUMN template_id uuid REFERENCES message_templates(id) ON DELETE SET NULL DEFAULT NULL;
+
+-- inline templates could use any field of the notification data, now only these are filled;
+-- channels using others are flagged to their owner
+WITH unknown AS (
+  SELECT id, string_agg(DISTINCT '{{' || placeholder[1] || '}}', ', ') AS placeholders
+  FROM notification_channels, regexp_matches(template, '\{\{\s*([^}\n]*?)\s*\}\}', 'g') AS placeholder
+  WHERE placeholder[1] <> ALL (ARRAY[
+    'donor_name', 'donor_message', 'amount', 'token', 'chain', 'transaction_id', 'donation_title', 'goal', 'received',
+    'progress_percent', 'rule_name', 'kind', 'message'
+  ])
+  GROUP BY id
+), flagged AS (
+  UPDATE notification_channels channels
+  SET last_error = 'template uses placeholders that now render empty: ' || unknown.placeholders
+  FROM unknown
+  WHERE channels.id = unknown.id
+  RETURNING channels.id, channels.user_id, channels.name, unknown.placeholders
+)
+INSERT INTO notifications (user_id, kind, message, data)
+SELECT user_id, 'notification_channel.template',
+       'The template of channel "' || name || '" uses placeholders that now render empty: ' || placeholders,
+       jsonb_build_object('channel_id', id, 'placeholders', placeholders)
+FROM flagged;
+
+CREATE TABLE donation_receipt_templates (
+  donation_id uuid PRIMARY KEY REFERENCES donations(id) ON DELETE CASCADE,
+  template_id uuid REFERENCES message_templates(id) ON DELETE CASCADE NOT NULL
+);
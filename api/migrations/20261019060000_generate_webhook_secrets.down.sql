@@ -0,0 +1 @@
+ALTER TABLE users ALTER COLUMN webhook_secret DROP NOT NULL;
@@ -0,0 +1,5 @@
+-- secrets used to be generated on first read; give every remaining user one up front
+UPDATE users
+SET webhook_secret = 'whsec_' || encode(sha256((gen_random_uuid()::text || gen_random_uuid()::text)::bytea), 'hex')
+WHERE webhook_secret IS NULL;
+ALTER TABLE users ALTER COLUMN webhook_secret SET NOT NULL;
@@ -0,0 +1,4 @@
+-- Progress only counts what the wallet received since the donation was created; donations
+-- from before this column keep counting everything
+ALTER TABLE donations ADD COLUMN created_at TIMESTAMPTZ DEFAULT NULL;
+ALTER TABLE donations ALTER COLUMN created_at SET DEFAULT CURRENT_TIMESTAMP;
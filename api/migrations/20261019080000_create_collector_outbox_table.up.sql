@@ -0,0 +1,6 @@
+-- Wallets switched on or off that the collector hasn't been told about yet; queued in the
+-- same transaction as the switch and cleared once the message went out
+CREATE TABLE collector_outbox (
+  wallet_id UUID PRIMARY KEY REFERENCES wallets(id) ON DELETE CASCADE,
+  queued_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS invoices_client_key_idx;
+ALTER TABLE invoices DROP COLUMN IF EXISTS client_key;
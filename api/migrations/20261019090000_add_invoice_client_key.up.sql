@@ -0,0 +1,5 @@
+-- invoices opened by anonymous donor messages, keyed by the hashed client address
+ALTER TABLE invoices ADD COLUMN client_key VARCHAR(64) DEFAULT NULL;
+
+CREATE INDEX invoices_client_key_idx ON invoices (donation_id, client_key)
+  WHERE client_key IS NOT NULL AND status IN ('pending', 'underpaid');
@@ -0,0 +1,6 @@
+ALTER TABLE notifications DROP COLUMN IF EXISTS dedupe_key;
+ALTER TABLE notification_channels DROP COLUMN IF EXISTS last_manual_send_at;
+ALTER TABLE notification_channels DROP COLUMN IF EXISTS verification_attempts;
+ALTER TABLE notification_channels DROP COLUMN IF EXISTS verification_expires_at;
+ALTER TABLE notification_channels DROP COLUMN IF EXISTS verification_code;
+ALTER TABLE notification_channels DROP COLUMN IF EXISTS verified_at;
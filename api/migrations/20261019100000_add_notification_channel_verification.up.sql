@@ -0,0 +1,13 @@
+-- email addresses and telegram chats get nothing but a code until their owner proves access
+ALTER TABLE notification_channels ADD COLUMN verified_at TIMESTAMPTZ DEFAULT NULL;
+ALTER TABLE notification_channels ADD COLUMN verification_code VARCHAR(64) DEFAULT NULL;  -- hashed
+ALTER TABLE notification_channels ADD COLUMN verification_expires_at TIMESTAMPTZ DEFAULT NULL;
+ALTER TABLE notification_channels ADD COLUMN verification_attempts INTEGER NOT NULL DEFAULT 0;
+-- the latest test message or code, sent right away on request
+ALTER TABLE notification_channels ADD COLUMN last_manual_send_at TIMESTAMPTZ DEFAULT NULL;
+
+-- chat webhook urls are secrets only their owner knows
+UPDATE notification_channels SET verified_at = CURRENT_TIMESTAMP WHERE kind = 'chat_webhook';
+
+-- notifications about one event, such as a deposit the collector reports twice, are only made once
+ALTER TABLE notifications ADD COLUMN dedupe_key VARCHAR(200) DEFAULT NULL UNIQUE;
@@ -0,0 +1,3 @@
+-- accepting takes the token mailed to the invited address, since registering doesn't prove the address;
+-- invites made before can't be accepted until they are sent again
+ALTER TABLE organization_invites ADD COLUMN token_hash VARCHAR(64) UNIQUE DEFAULT NULL;
@@ -0,0 +1,638 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Error, PgExecutor, PgPool};
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::{Decimal, Uuid};
+
+use crate::message::JsonPublicMessage;
+use crate::models::DonationProgress;
+use crate::notification::{self, Notification};
+use crate::stream::StreamEvent;
+use crate::webhook::{Deposit, Publication, WebhookEvent};
+use crate::webhook_endpoint::EventType;
+
+const MAX_RULES_PER_USER: i64 = 50;
+const MAX_NAME_LEN: usize = 100;
+const MAX_MESSAGE_CONTAINS_LEN: usize = 100;
+const MAX_COOLDOWN_SECONDS: i32 = 7 * 24 * 60 * 60;
+const EVALUATIONS_LIMIT: i64 = 100;
+
+/// What a rule does when a deposit meets its conditions.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertAction {
+    /// Sends `alert.triggered` to the rule owner's webhook endpoints subscribed to it.
+    Webhook,
+    /// Sends `alert.triggered` to the donation's event streams, for overlays.
+    Overlay,
+    /// Notifies the rule owner.
+    Notify,
+}
+
+impl AlertAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertAction::Webhook => "webhook",
+            AlertAction::Overlay => "overlay",
+            AlertAction::Notify => "notify",
+        }
+    }
+}
+
+impl TryFrom<&str> for AlertAction {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "webhook" => Ok(AlertAction::Webhook),
+            "overlay" => Ok(AlertAction::Overlay),
+            "notify" => Ok(AlertAction::Notify),
+            _ => Err(format!("unknown alert action: {}", value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlertOutcome {
+    Triggered,
+    NotMatched,
+    CoolingDown,
+}
+
+impl AlertOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertOutcome::Triggered => "triggered",
+            AlertOutcome::NotMatched => "not_matched",
+            AlertOutcome::CoolingDown => "cooling_down",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonAlertRuleIn {
+    pub name: String,
+    /// Only match deposits on these donations; empty or missing means all of them.
+    #[serde(default)]
+    pub donation_ids: Vec<Uuid>,
+    pub token: Option<String>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    /// Only match deposits that paid for a donor message.
+    #[serde(default)]
+    pub message_required: bool,
+    /// Only match deposits whose donor message contains this, ignoring case.
+    pub message_contains: Option<String>,
+    pub actions: Vec<AlertAction>,
+    /// How long after triggering the rule ignores matching deposits.
+    #[serde(default)]
+    pub cooldown_seconds: i32,
+    pub is_active: Option<bool>,
+}
+
+impl JsonAlertRuleIn {
+    /// Checks the conditions and actions, leaving `donation_ids` to the caller.
+    pub fn validate(&mut self) -> Result<(), String> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() || self.name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("name must be 1 to {} characters", MAX_NAME_LEN));
+        }
+
+        self.token = self.token.as_ref().map(|token| token.trim().to_uppercase()).filter(|token| !token.is_empty());
+        if self.min_amount.is_some_and(|amount| amount.is_sign_negative())
+            || self.max_amount.is_some_and(|amount| amount.is_sign_negative()) {
+            return Err("amounts must not be negative".to_string());
+        }
+        if let (Some(min_amount), Some(max_amount)) = (self.min_amount, self.max_amount) {
+            if min_amount > max_amount {
+                return Err("min_amount must not be above max_amount".to_string());
+            }
+        }
+
+        self.message_contains = self.message_contains.as_ref().map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty());
+        if self.message_contains.as_ref().is_some_and(|text| text.chars().count() > MAX_MESSAGE_CONTAINS_LEN) {
+            return Err(format!("message_contains must be at most {} characters", MAX_MESSAGE_CONTAINS_LEN));
+        }
+
+        if self.actions.is_empty() {
+            return Err("actions must not be empty".to_string());
+        }
+        self.actions.sort_by_key(|action| action.as_str());
+        self.actions.dedup();
+
+        if !(0..=MAX_COOLDOWN_SECONDS).contains(&self.cooldown_seconds) {
+            return Err(format!("cooldown_seconds must be between 0 and {}", MAX_COOLDOWN_SECONDS));
+        }
+        self.donation_ids.sort();
+        self.donation_ids.dedup();
+
+        Ok(())
+    }
+
+    fn action_names(&self) -> Vec<String> {
+        self.actions.iter().map(|action| action.as_str().to_string()).collect()
+    }
+}
+
+/// The donation a deposit went to, as alert payloads describe it.
+pub struct AlertDonation<'a> {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub title: &'a str,
+    pub goal: Decimal,
+    /// Everything its wallet received, unless the transactions service couldn't be reached.
+    pub received: Option<Decimal>,
+}
+
+/// A confirmed deposit as rules see it.
+pub struct AlertDeposit<'a> {
+    pub amount: Decimal,
+    /// Upper case symbol, the chain's native coin included.
+    pub token: &'a str,
+    /// The visible donor message the deposit paid for, if any.
+    pub message: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+pub struct AlertRule {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    pub name: String,
+    pub donation_ids: Vec<Uuid>,
+    pub token: Option<String>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    pub message_required: bool,
+    pub message_contains: Option<String>,
+    pub actions: Vec<String>,
+    pub cooldown_seconds: i32,
+    pub is_active: bool,
+    pub last_triggered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AlertRule {
+    pub async fn count(user_id: Uuid, db: &PgPool) -> Result<i64, Error> {
+        sqlx::query!("SELECT COUNT(*) AS count FROM alert_rules WHERE user_id = $1", user_id)
+            .fetch_one(db)
+            .await
+            .map(|row| row.count.unwrap_or_default())
+    }
+
+    pub fn limit_reached(count: i64) -> bool {
+        count >= MAX_RULES_PER_USER
+    }
+
+    pub async fn create(user_id: Uuid, j_rule: &JsonAlertRuleIn, db: &PgPool) -> Result<AlertRule, Error> {
+        sqlx::query_as!(
+            AlertRule,
+            "
+            INSERT INTO alert_rules (
+              user_id, name, donation_ids, token, min_amount, max_amount, message_required, message_contains,
+              actions, cooldown_seconds, is_active
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            RETURNING id, user_id, name, donation_ids, token, min_amount, max_amount, message_required,
+                      message_contains, actions, cooldown_seconds, is_active, last_triggered_at, created_at
+            ",
+            user_id,
+            j_rule.name,
+            &j_rule.donation_ids,
+            j_rule.token,
+            j_rule.min_amount,
+            j_rule.max_amount,
+            j_rule.message_required,
+            j_rule.message_contains,
+            &j_rule.action_names(),
+            j_rule.cooldown_seconds,
+            j_rule.is_active.unwrap_or(true),
+        )
+            .fetch_one(db)
+            .await
+            .map(AlertRule::normalized)
+    }
+
+    pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<AlertRule, Error> {
+        sqlx::query_as!(
+            AlertRule,
+            "
+            SELECT id, user_id, name, donation_ids, token, min_amount, max_amount, message_required,
+                   message_contains, actions, cooldown_seconds, is_active, last_triggered_at, created_at
+            FROM alert_rules
+            WHERE id = $1 AND user_id = $2
+            ",
+            id,
+            user_id
+        )
+            .fetch_one(db)
+            .await
+            .map(AlertRule::normalized)
+    }
+
+    pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<AlertRule>, Error> {
+        sqlx::query_as!(
+            AlertRule,
+            "
+            SELECT id, user_id, name, donation_ids, token, min_amount, max_amount, message_required,
+                   message_contains, actions, cooldown_seconds, is_active, last_triggered_at, created_at
+            FROM alert_rules
+            WHERE user_id = $1
+            ORDER BY created_at
+            ",
+            user_id
+        )
+            .fetch_all(db)
+            .await
+            .map(|rules| rules.into_iter().map(AlertRule::normalized).collect())
+    }
+
+    pub async fn update(id: Uuid, user_id: Uuid, j_rule: &JsonAlertRuleIn, db: &PgPool) -> Result<AlertRule, Error> {
+        sqlx::query_as!(
+            AlertRule,
+            "
+            UPDATE alert_rules
+            SET name = $3, donation_ids = $4, token = $5, min_amount = $6, max_amount = $7, message_required = $8,
+                message_contains = $9, actions = $10, cooldown_seconds = $11, is_active = COALESCE($12, is_active)
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, name, donation_ids, token, min_amount, max_amount, message_required,
+                      message_contains, actions, cooldown_seconds, is_active, last_triggered_at, created_at
+            ",
+            id,
+            user_id,
+            j_rule.name,
+            &j_rule.donation_ids,
+            j_rule.token,
+            j_rule.min_amount,
+            j_rule.max_amount,
+            j_rule.message_required,
+            j_rule.message_contains,
+            &j_rule.action_names(),
+            j_rule.cooldown_seconds,
+            j_rule.is_active,
+        )
+            .fetch_one(db)
+            .await
+            .map(AlertRule::normalized)
+    }
+
+    pub async fn delete(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!("DELETE FROM alert_rules WHERE id = $1 AND user_id = $2", id, user_id)
+            .execute(db)
+            .await
+    }
+
+    /// Active rules of the organization's members that apply to the donation and haven't
+    /// seen the transaction yet.
+    async fn pending(
+        organization_id: Uuid, donation_id: Uuid, transaction_id: &str, db: &PgPool
+    ) -> Result<Vec<AlertRule>, Error> {
+        sqlx::query_as!(
+            AlertRule,
+            "
+            SELECT id, user_id, name, donation_ids, token, min_amount, max_amount, message_required,
+                   message_contains, actions, cooldown_seconds, is_active, last_triggered_at, created_at
+            FROM alert_rules r
+            WHERE is_active
+              AND (cardinality(donation_ids) = 0 OR $2 = ANY(donation_ids))
+              AND user_id IN (SELECT user_id FROM organization_members WHERE organization_id = $1)
+              AND NOT EXISTS (
+                SELECT 1 FROM alert_evaluations e
+                WHERE e.rule_id = r.id AND e.donation_id = $2 AND e.transaction_id = $3
+              )
+            ORDER BY created_at
+            ",
+            organization_id,
+            donation_id,
+            transaction_id,
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    /// Why the deposit doesn't meet the rule's conditions, if it doesn't.
+    pub fn mismatch(&self, deposit: &AlertDeposit<'_>) -> Option<String> {
+        if let Some(token) = self.token.as_deref().filter(|&token| token != deposit.token) {
+            return Some(format!("token {} is not {}", deposit.token, token));
+        }
+        if let Some(min_amount) = self.min_amount.filter(|&min_amount| deposit.amount < min_amount) {
+            return Some(format!("amount {} is below {}", deposit.amount.normalize(), min_amount));
+        }
+        if let Some(max_amount) = self.max_amount.filter(|&max_amount| deposit.amount > max_amount) {
+            return Some(format!("amount {} is above {}", deposit.amount.normalize(), max_amount));
+        }
+
+        let message = deposit.message.filter(|message| !message.is_empty());
+        if self.message_required && message.is_none() {
+            return Some("no donor message".to_string());
+        }
+        if let Some(text) = &self.message_contains {
+            if !message.is_some_and(|message| message.to_lowercase().contains(&text.to_lowercase())) {
+                return Some(format!("donor message doesn't contain {:?}", text));
+            }
+        }
+
+        None
+    }
+
+    /// Starts the rule's cooldown, unless it is still cooling down from the last time.
+    async fn claim<'e, E: PgExecutor<'e>>(&self, executor: E) -> Result<bool, Error> {
+        sqlx::query!(
+            "
+            UPDATE alert_rules SET last_triggered_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+              AND (last_triggered_at IS NULL
+                OR last_triggered_at <= CURRENT_TIMESTAMP - make_interval(secs => cooldown_seconds))
+            ",
+            self.id
+        )
+            .execute(executor)
+            .await
+            .map(|result| result.rows_affected() > 0)
+    }
+
+    fn normalized(mut self) -> AlertRule {
+        self.min_amount = self.min_amount.map(|amount| amount.normalize());
+        self.max_amount = self.max_amount.map(|amount| amount.normalize());
+        self
+    }
+}
+
+/// Why a rule did or didn't fire for a deposit.
+#[derive(Serialize)]
+pub struct AlertEvaluation {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    pub donation_id: Uuid,
+    pub transaction_id: String,
+    pub amount: Decimal,
+    pub token: String,
+    pub outcome: String,
+    pub reason: Option<String>,
+    pub actions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AlertEvaluation {
+    /// Logs an evaluation, returning `false` when the rule saw the deposit already.
+    async fn record<'e, E: PgExecutor<'e>>(
+        rule: &AlertRule, donation_id: Uuid, transaction_id: &str, deposit: &AlertDeposit<'_>, outcome: AlertOutcome,
+        reason: Option<String>, executor: E,
+    ) -> Result<bool, Error> {
+        let actions = if outcome == AlertOutcome::Triggered { rule.actions.clone() } else { vec![] };
+
+        sqlx::query!(
+            "
+            INSERT INTO alert_evaluations (rule_id, donation_id, transaction_id, amount, token, outcome, reason, actions)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT DO NOTHING
+            ",
+            rule.id,
+            donation_id,
+            transaction_id,
+            deposit.amount,
+            deposit.token,
+            outcome.as_str(),
+            reason,
+            &actions,
+        )
+            .execute(executor)
+            .await
+            .map(|result| result.rows_affected() > 0)
+    }
+
+    /// The latest evaluations of a rule, newest first.
+    pub async fn list(rule_id: Uuid, db: &PgPool) -> Result<Vec<AlertEvaluation>, Error> {
+        sqlx::query_as!(
+            AlertEvaluation,
+            "
+            SELECT id, rule_id, donation_id, transaction_id, amount, token, outcome, reason, actions, created_at
+            FROM alert_evaluations
+            WHERE rule_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            ",
+            rule_id,
+            EVALUATIONS_LIMIT,
+        )
+            .fetch_all(db)
+            .await
+            .map(|evaluations| evaluations.into_iter().map(|mut evaluation| {
+                evaluation.amount = evaluation.amount.normalize();
+                evaluation
+            }).collect())
+    }
+}
+
+/// Runs the rules that apply to a confirmed deposit on a donation, returning how many fired.
+///
+/// Each rule is claimed, recorded and acted on in one transaction, so a failed action leaves the
+/// rule pending for the next delivery and a losing concurrent run doesn't use up its cooldown.
+pub async fn evaluate(
+    deposit: &Deposit, donation: &AlertDonation<'_>, message: Option<&JsonPublicMessage>, db: &PgPool
+) -> Result<u64, Error> {
+    let (donation_id, organization_id) = (donation.id, donation.organization_id);
+    let Some(token) = deposit.token() else { return Ok(0) };
+    let alert_deposit = AlertDeposit {
+        amount: deposit.amount,
+        token,
+        message: message.map(|message| message.message.as_str()),
+    };
+
+    let mut triggered = 0;
+    for rule in AlertRule::pending(organization_id, donation_id, &deposit.transaction_id, db).await? {
+        let mut tx = db.begin().await?;
+        let (outcome, reason) = match rule.mismatch(&alert_deposit) {
+            Some(reason) => (AlertOutcome::NotMatched, Some(reason)),
+            None if !rule.claim(&mut *tx).await? => (AlertOutcome::CoolingDown, None),
+            None => (AlertOutcome::Triggered, None),
+        };
+        let recorded = AlertEvaluation::record(
+            &rule, donation_id, &deposit.transaction_id, &alert_deposit, outcome, reason, &mut *tx,
+        ).await?;
+        if !recorded {
+            // another delivery of the deposit evaluated the rule first
+            tx.rollback().await?;
+            continue;
+        }
+        if outcome != AlertOutcome::Triggered {
+            tx.commit().await?;
+            continue;
+        }
+
+        let data = json!({
+            "rule_id": rule.id,
+            "rule_name": rule.name,
+            "donation_id": donation_id,
+            "transaction_id": deposit.transaction_id,
+            "chain": deposit.chain,
+            "amount": deposit.amount,
+            "token": token,
+            "message": message,
+            "donation_title": donation.title,
+            "goal": donation.goal.normalize(),
+            "received": donation.received.map(|received| received.normalize()),
+            "progress_percent": donation.received.map(|received| DonationProgress::percent(donation.goal, received).normalize()),
+        });
+        let dedupe_key = format!("{}:{}:{}", rule.id, EventType::AlertTriggered.as_str(), deposit.transaction_id);
+        for action in rule.actions.iter().filter_map(|action| AlertAction::try_from(action.as_str()).ok()) {
+            match action {
+                AlertAction::Webhook => {
+                    WebhookEvent::publish(&Publication {
+                        user_id: Some(rule.user_id),
+                        transaction_id: Some(deposit.transaction_id.clone()),
+                        dedupe_key: Some(dedupe_key.clone()),
+                        ..Publication::for_donation(EventType::AlertTriggered, organization_id, donation_id, data.clone())
+                    }, &mut *tx).await?;
+                },
+                AlertAction::Overlay => {
+                    StreamEvent::publish(
+                        donation_id, organization_id, EventType::AlertTriggered.as_str(), &data, &dedupe_key, &mut *tx,
+                    ).await?;
+                },
+                AlertAction::Notify => {
+                    let text = format!("Alert \"{}\": received {} {}", rule.name, deposit.amount.normalize(), token);
+                    Notification::create(rule.user_id, notification::ALERT_TRIGGERED, &text, data.clone(), &mut *tx).await?;
+                },
+            }
+        }
+        tx.commit().await?;
+        triggered += 1;
+    }
+
+    Ok(triggered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn j_rule() -> JsonAlertRuleIn {
+        JsonAlertRuleIn {
+            name: " Big tips ".to_string(),
+            donation_ids: vec![],
+            token: Some(" usdt ".to_string()),
+            min_amount: Some(Decimal::from(50)),
+            max_amount: None,
+            message_required: false,
+            message_contains: None,
+            actions: vec![AlertAction::Overlay, AlertAction::Webhook, AlertAction::Overlay],
+            cooldown_seconds: 60,
+            is_active: None,
+        }
+    }
+
+    fn rule(j_rule: JsonAlertRuleIn) -> AlertRule {
+        AlertRule {
+            id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            name: j_rule.name.clone(),
+            donation_ids: j_rule.donation_ids.clone(),
+            token: j_rule.token.clone(),
+            min_amount: j_rule.min_amount,
+            max_amount: j_rule.max_amount,
+            message_required: j_rule.message_required,
+            message_contains: j_rule.message_contains.clone(),
+            actions: j_rule.action_names(),
+            cooldown_seconds: j_rule.cooldown_seconds,
+            is_active: true,
+            last_triggered_at: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn deposit(amount: i64, token: &'static str, message: Option<&'static str>) -> AlertDeposit<'static> {
+        AlertDeposit { amount: Decimal::from(amount), token, message }
+    }
+
+    #[test]
+    fn validates_rules() {
+        let mut j = j_rule();
+        assert!(j.validate().is_ok());
+        assert_eq!(j.name, "Big tips");
+        assert_eq!(j.token.as_deref(), Some("USDT"));
+        assert_eq!(j.actions, vec![AlertAction::Overlay, AlertAction::Webhook]);
+
+        assert!(JsonAlertRuleIn { name: " ".to_string(), ..j_rule() }.validate().is_err());
+        assert!(JsonAlertRuleIn { actions: vec![], ..j_rule() }.validate().is_err());
+        assert!(JsonAlertRuleIn { cooldown_seconds: -1, ..j_rule() }.validate().is_err());
+        assert!(JsonAlertRuleIn { max_amount: Some(Decimal::TEN), ..j_rule() }.validate().is_err());
+        assert!(JsonAlertRuleIn { min_amount: Some(Decimal::NEGATIVE_ONE), ..j_rule() }.validate().is_err());
+        assert!(JsonAlertRuleIn { message_contains: Some("a".repeat(101)), ..j_rule() }.validate().is_err());
+    }
+
+    #[test]
+    fn matches_amount_and_token() {
+        let mut j = j_rule();
+        j.validate().unwrap();
+        let rule = rule(j);
+
+        assert_eq!(rule.mismatch(&deposit(50, "USDT", None)), None);
+        assert_eq!(rule.mismatch(&deposit(49, "USDT", None)), Some("amount 49 is below 50".to_string()));
+        assert_eq!(rule.mismatch(&deposit(50, "TRX", None)), Some("token TRX is not USDT".to_string()));
+    }
+
+    #[test]
+    fn matches_donor_messages() {
+        let rule = rule(JsonAlertRuleIn {
+            token: None,
+            min_amount: None,
+            message_contains: Some("Hello".to_string()),
+            ..j_rule()
+        });
+
+        assert_eq!(rule.mismatch(&deposit(1, "TRX", Some("well HELLO there"))), None);
+        assert!(rule.mismatch(&deposit(1, "TRX", Some("hi"))).is_some());
+        assert!(rule.mismatch(&deposit(1, "TRX", None)).is_some());
+
+        let required = AlertRule { message_contains: None, message_required: true, ..rule };
+        assert_eq!(required.mismatch(&deposit(1, "TRX", Some(""))), Some("no donor message".to_string()));
+        assert_eq!(required.mismatch(&deposit(1, "TRX", Some("hi"))), None);
+    }
+
+    #[sqlx::test]
+    async fn failed_actions_leave_the_rule_pending(db: PgPool) {
+        let owner = fixtures::owner(&db).await;
+        let donation_id = fixtures::donation(&owner, "Roof", None, &db).await;
+        let mut j = JsonAlertRuleIn { actions: vec![AlertAction::Overlay, AlertAction::Notify], ..j_rule() };
+        j.validate().unwrap();
+        let rule = AlertRule::create(owner.user_id, &j, &db).await.unwrap();
+
+        let donation = AlertDonation {
+            id: donation_id, organization_id: owner.organization_id, title: "Roof", goal: Decimal::from(100), received: None,
+        };
+        let deposit = Deposit {
+            wallet_id: Uuid::nil(),
+            chain: crate::chain::Chain::Tron,
+            address: fixtures::TRON_ADDRESS.to_string(),
+            transaction_id: "tx1".to_string(),
+            amount: Decimal::from(60),
+            token_contract: Some(fixtures::USDT_CONTRACT.to_string()),
+            confirmed: true,
+        };
+        let counts = || sqlx::query_as::<_, (i64, i64, i64, bool)>(
+            "
+            SELECT (SELECT COUNT(*) FROM alert_evaluations),
+                   (SELECT COUNT(*) FROM donation_events),
+                   (SELECT COUNT(*) FROM notifications),
+                   (SELECT last_triggered_at IS NOT NULL FROM alert_rules WHERE id = $1)
+            ",
+        )
+            .bind(rule.id)
+            .fetch_one(&db);
+
+        // the overlay event goes out, then the notification fails
+        sqlx::query("ALTER TABLE notifications ADD CONSTRAINT fail CHECK (false) NOT VALID").execute(&db).await.unwrap();
+        assert!(evaluate(&deposit, &donation, None, &db).await.is_err());
+        assert_eq!(counts().await.unwrap(), (0, 0, 0, false));
+
+        // the redelivered deposit fires the rule once
+        sqlx::query("ALTER TABLE notifications DROP CONSTRAINT fail").execute(&db).await.unwrap();
+        assert_eq!(evaluate(&deposit, &donation, None, &db).await.unwrap(), 1);
+        assert_eq!(evaluate(&deposit, &donation, None, &db).await.unwrap(), 0);
+        assert_eq!(counts().await.unwrap(), (1, 1, 1, true));
+    }
+}
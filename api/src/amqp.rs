@@ -4,10 +4,12 @@ use amqprs::{callbacks::{DefaultChannelCallback, DefaultConnectionCallback}, cha
 }, connection::{Connection, OpenConnectionArguments}, BasicProperties};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use crate::chain::Chain;
 use crate::models::Wallet;
 
 #[derive(Serialize, Deserialize)]
 pub struct Message {
+    chain: Chain,
     address: String,
     is_active: bool,
     wallet_id: String,
@@ -15,16 +17,21 @@ pub struct Message {
 
 impl From<Wallet> for Message {
     fn from(wallet: Wallet) -> Self {
-        Message::new(wallet.data.address, wallet.is_active, wallet.id.to_string())
+        Message::new(wallet.chain, wallet.data.address, wallet.is_active, wallet.id.to_string())
     }
 }
 
 impl Message {
-    pub fn new(address: String, is_active: bool, wallet_id: String) -> Self {
-        Message { address, is_active, wallet_id: wallet_id.to_string() }
+    pub fn new(chain: Chain, address: String, is_active: bool, wallet_id: String) -> Self {
+        Message { chain, address, is_active, wallet_id: wallet_id.to_string() }
     }
 
     pub async fn send(self) {
+        self.try_send().await.unwrap();
+    }
+
+    /// Like `send`, for background tasks that must outlive a rabbitmq outage.
+    pub async fn try_send(self) -> Result<(), String> {
         let connection = Connection::open(&OpenConnectionArguments::new(
             &env::var("RABBITMQ_HOST").unwrap_or("localhost".to_string()),
             5672,
@@ -32,25 +39,25 @@ impl Message {
             "guest",
         ))
             .await
-            .unwrap();
+            .map_err(|err| format!("Failed connect to rabbitmq: {}", err))?;
         connection
             .register_callback(DefaultConnectionCallback)
             .await
-            .unwrap();
+            .map_err(|err| err.to_string())?;
 
-        let channel = connection.open_channel(None).await.unwrap();
+        let channel = connection.open_channel(None).await.map_err(|err| err.to_string())?;
         channel
             .register_callback(DefaultChannelCallback)
             .await
-            .unwrap();
+            .map_err(|err| err.to_string())?;
 
         let (queue_name, _, _) = channel
             .queue_declare(QueueDeclareArguments::durable_client_named(
                 "amqprs.examples.basic",
             ))
             .await
-            .unwrap()
-            .unwrap();
+            .map_err(|err| err.to_string())?
+            .ok_or("queue was not declared")?;
 
         let routing_key = "amqprs.example";
         let exchange_name = "amq.topic";
@@ -61,12 +68,12 @@ impl Message {
                 routing_key,
             ))
             .await
-            .unwrap();
+            .map_err(|err| err.to_string())?;
 
         let args = BasicPublishArguments::new(exchange_name, routing_key);
 
         let mut bytes: Vec<u8> = Vec::new();
-        serde_json::to_writer(&mut bytes, &json!(self)).unwrap();
+        serde_json::to_writer(&mut bytes, &json!(self)).map_err(|err| err.to_string())?;
 
         channel
             .basic_publish(
@@ -75,9 +82,9 @@ impl Message {
                 args
             )
             .await
-            .unwrap();
+            .map_err(|err| format!("Failed publish wallet {}: {}", self.wallet_id, err))?;
 
-        channel.close().await.unwrap();
-        connection.close().await.unwrap();
+        channel.close().await.map_err(|err| err.to_string())?;
+        connection.close().await.map_err(|err| err.to_string())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,462 @@
+use std::fmt;
+use k256::PublicKey;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use sqlx::types::Decimal;
+use crate::tron;
+
+const BITCOIN_P2PKH_VERSION: u8 = 0x00;
+const BITCOIN_P2SH_VERSION: u8 = 0x05;
+const BITCOIN_HRP: &str = "bc";
+const ETHEREUM_CHAIN_ID: u64 = 1;
+const BSC_CHAIN_ID: u64 = 56;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chain {
+    #[default]
+    Tron,
+    Ethereum,
+    Bsc,
+    Bitcoin,
+}
+
+impl Chain {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Chain::Tron => "tron",
+            Chain::Ethereum => "ethereum",
+            Chain::Bsc => "bsc",
+            Chain::Bitcoin => "bitcoin",
+        }
+    }
+
+    pub fn validate_address(&self, address: &str) -> Result<(), AddressError> {
+        if address.is_empty() {
+            return Err(AddressError::Empty);
+        }
+
+        match self {
+            Chain::Tron => validate_tron(address),
+            Chain::Ethereum | Chain::Bsc => validate_evm(address),
+            Chain::Bitcoin => validate_bitcoin(address),
+        }
+    }
+
+    /// Tokens a donation on this chain can be denominated in, the native coin first.
+    pub fn tokens(&self) -> &'static [&'static str] {
+        match self {
+            Chain::Tron => &["TRX", "USDT", "USDC"],
+            Chain::Ethereum => &["ETH", "USDT", "USDC"],
+            Chain::Bsc => &["BNB", "USDT", "USDC"],
+            Chain::Bitcoin => &["BTC"],
+        }
+    }
+
+    /// The contract and decimals of a token on this chain; `None` (or the native symbol)
+    /// gives the native coin, which has no contract.
+    pub fn token(&self, symbol: Option<&str>) -> Option<Token> {
+        let symbol = symbol.unwrap_or(self.tokens()[0]);
+        let (contract, decimals) = match (self, symbol) {
+            (Chain::Tron, "TRX") => (None, 6),
+            (Chain::Tron, "USDT") => (Some("TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t"), 6),
+            (Chain::Tron, "USDC") => (Some("TEkxiTehnzSmSe2XqrBj4w32RUN966rdz8"), 6),
+            (Chain::Ethereum, "ETH") | (Chain::Bsc, "BNB") => (None, 18),
+            (Chain::Ethereum, "USDT") => (Some("0xdAC17F958D2ee523a2206206994597C13D831ec7"), 6),
+            (Chain::Ethereum, "USDC") => (Some("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"), 6),
+            (Chain::Bsc, "USDT") => (Some("0x55d398326f99059fF775485246999027B3197955"), 18),
+            (Chain::Bsc, "USDC") => (Some("0x8AC76a51cc950d9822D68b83fE1Ad97B32Cd580d"), 18),
+            (Chain::Bitcoin, "BTC") => (None, 8),
+            _ => return None,
+        };
+
+        Some(Token { contract, decimals })
+    }
+
+    /// The token deployed at `contract`, if it is one this chain knows. Transfers are matched
+    /// by contract rather than by the symbol a contract reports, which anyone can copy.
+    pub fn token_by_contract(&self, contract: &str) -> Option<&'static str> {
+        self.tokens().iter().copied().find(|&symbol| {
+            self.token(Some(symbol)).and_then(|token| token.contract).is_some_and(|known| match self {
+                Chain::Ethereum | Chain::Bsc => known.eq_ignore_ascii_case(contract),
+                _ => known == contract,
+            })
+        })
+    }
+
+    /// Tokens whose deposits the collector picks up; it only scans token transfers, so the
+    /// native coin is never seen.
+    pub fn tracked_tokens(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.tokens().iter().copied()
+            .filter(|&symbol| self.token(Some(symbol)).is_some_and(|token| token.contract.is_some()))
+    }
+
+    /// The URI a wallet app opens to pay `amount` of `token` to `address`: BIP-21 for
+    /// bitcoin, EIP-681 for ethereum and bsc, and the same shape under `tron:` for tron.
+    /// EVM transfers can't carry a memo.
+    pub fn payment_uri(
+        &self, address: &str, token: Option<&str>, amount: Option<Decimal>, memo: Option<&str>
+    ) -> Result<String, String> {
+        let token_info = self.token(token)
+            .ok_or_else(|| format!("{} is not a {} token", token.unwrap_or_default(), self.as_str()))?;
+        if let Some(amount) = amount {
+            if amount <= Decimal::ZERO {
+                return Err("amount must be positive".to_string());
+            }
+            if amount.normalize().scale() > token_info.decimals {
+                return Err(format!("amount has more than {} decimals", token_info.decimals));
+            }
+        }
+        let memo = memo.map(str::trim).filter(|memo| !memo.is_empty());
+        let amount = amount.map(|amount| amount.normalize());
+
+        let mut params = vec![];
+        let path = match self {
+            Chain::Bitcoin => {
+                params.extend(amount.map(|amount| ("amount", amount.to_string())));
+                params.extend(memo.map(|memo| ("message", percent_encode(memo))));
+                format!("bitcoin:{}", address)
+            },
+            Chain::Tron => {
+                params.extend(token_info.contract.map(|contract| ("token", contract.to_string())));
+                params.extend(amount.map(|amount| ("amount", amount.to_string())));
+                params.extend(memo.map(|memo| ("memo", percent_encode(memo))));
+                format!("tron:{}", address)
+            },
+            Chain::Ethereum | Chain::Bsc => {
+                if memo.is_some() {
+                    return Err(format!("{} payments can't carry a memo", self.as_str()));
+                }
+                let chain_id = if *self == Chain::Bsc { BSC_CHAIN_ID } else { ETHEREUM_CHAIN_ID };
+                let units = amount.map(|amount| token_info.base_units(amount));
+                match token_info.contract {
+                    Some(contract) => {
+                        params.push(("address", address.to_string()));
+                        params.extend(units.map(|units| ("uint256", units)));
+                        format!("ethereum:{}@{}/transfer", contract, chain_id)
+                    },
+                    None => {
+                        params.extend(units.map(|units| ("value", units)));
+                        format!("ethereum:{}@{}", address, chain_id)
+                    },
+                }
+            },
+        };
+
+        let query: Vec<String> = params.into_iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        Ok(match query.is_empty() {
+            true => path,
+            false => format!("{}?{}", path, query.join("&")),
+        })
+    }
+
+    /// Whether wallets on this chain can be generated and imported from a bare key.
+    pub fn supports_keys(&self) -> bool {
+        !matches!(self, Chain::Bitcoin)
+    }
+
+    /// `None` for bitcoin, where a key maps to several address types.
+    pub fn address_from_public_key(&self, public_key: &PublicKey) -> Option<String> {
+        match self {
+            Chain::Tron => Some(tron::address_from_public_key(public_key)),
+            Chain::Ethereum | Chain::Bsc => {
+                let point = public_key.to_encoded_point(false);
+                let hash = Keccak256::digest(&point.as_bytes()[1..]);
+                Some(format!("0x{}", eip55_checksum(&hex::encode(&hash[12..]))))
+            },
+            Chain::Bitcoin => None,
+        }
+    }
+
+    /// EVM addresses compare case-insensitively, the checksum is only in the casing.
+    pub fn same_address(&self, a: &str, b: &str) -> bool {
+        match self {
+            Chain::Ethereum | Chain::Bsc => a.eq_ignore_ascii_case(b),
+            Chain::Tron | Chain::Bitcoin => a == b,
+        }
+    }
+}
+
+impl TryFrom<&str> for Chain {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "tron" => Ok(Chain::Tron),
+            "ethereum" => Ok(Chain::Ethereum),
+            "bsc" => Ok(Chain::Bsc),
+            "bitcoin" => Ok(Chain::Bitcoin),
+            _ => Err(format!("unknown chain: {}", value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Token {
+    /// `None` for the chain's native coin.
+    pub contract: Option<&'static str>,
+    pub decimals: u32,
+}
+
+impl Token {
+    /// The amount as an integer count of the token's smallest unit.
+    fn base_units(&self, amount: Decimal) -> String {
+        let amount = amount.normalize();
+        let mut digits = amount.mantissa().to_string();
+        let scale = amount.scale();
+        digits.extend(std::iter::repeat_n('0', (self.decimals - scale) as usize));
+
+        digits
+    }
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+fn percent_encode(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+/// Why an address was rejected; serialized with a `code` tag so clients can point
+/// at the exact problem.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum AddressError {
+    Empty,
+    InvalidCharacter { character: char, position: usize },
+    InvalidLength { expected: usize, actual: usize },
+    InvalidPrefix { expected: String, actual: String },
+    InvalidChecksum,
+    InvalidFormat { reason: String },
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::Empty => write!(f, "address is empty"),
+            AddressError::InvalidCharacter { character, position } =>
+                write!(f, "invalid character '{}' at position {}", character, position),
+            AddressError::InvalidLength { expected, actual } =>
+                write!(f, "expected {} characters, got {}", expected, actual),
+            AddressError::InvalidPrefix { expected, actual } =>
+                write!(f, "expected prefix {}, got {}", expected, actual),
+            AddressError::InvalidChecksum => write!(f, "checksum mismatch"),
+            AddressError::InvalidFormat { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+fn decode_base58check(address: &str) -> Result<Vec<u8>, AddressError> {
+    bs58::decode(address).with_check(None).into_vec().map_err(|err| match err {
+        bs58::decode::Error::InvalidCharacter { character, index } =>
+            AddressError::InvalidCharacter { character, position: index },
+        bs58::decode::Error::InvalidChecksum { .. } => AddressError::InvalidChecksum,
+        err => AddressError::InvalidFormat { reason: err.to_string() },
+    })
+}
+
+/// base58check of `0x41 || 20 byte account id`.
+fn validate_tron(address: &str) -> Result<(), AddressError> {
+    let payload = decode_base58check(address)?;
+    if payload.len() != 21 {
+        return Err(AddressError::InvalidFormat {
+            reason: format!("expected a 21 byte payload, got {}", payload.len()),
+        });
+    }
+    if payload[0] != tron::ADDRESS_PREFIX {
+        return Err(AddressError::InvalidPrefix {
+            expected: format!("{:#04x}", tron::ADDRESS_PREFIX),
+            actual: format!("{:#04x}", payload[0]),
+        });
+    }
+
+    Ok(())
+}
+
+/// `0x` + 40 hex digits; mixed case addresses must carry a valid EIP-55 checksum.
+fn validate_evm(address: &str) -> Result<(), AddressError> {
+    let Some(hex_part) = address.strip_prefix("0x") else {
+        return Err(AddressError::InvalidPrefix {
+            expected: "0x".to_string(),
+            actual: address.chars().take(2).collect(),
+        });
+    };
+    if let Some((position, character)) = hex_part.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        return Err(AddressError::InvalidCharacter { character, position: position + 2 });
+    }
+    if hex_part.len() != 40 {
+        return Err(AddressError::InvalidLength { expected: 42, actual: address.len() });
+    }
+
+    let has_lower = hex_part.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = hex_part.chars().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper && eip55_checksum(hex_part) != hex_part {
+        return Err(AddressError::InvalidChecksum);
+    }
+
+    Ok(())
+}
+
+fn eip55_checksum(hex_part: &str) -> String {
+    let lower = hex_part.to_ascii_lowercase();
+    let hash = Keccak256::digest(lower.as_bytes());
+
+    lower.chars().enumerate().map(|(i, c)| {
+        let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        if c.is_ascii_alphabetic() && nibble >= 8 { c.to_ascii_uppercase() } else { c }
+    }).collect()
+}
+
+/// Mainnet P2PKH / P2SH base58check addresses and segwit bech32 (v0) / bech32m (v1+).
+fn validate_bitcoin(address: &str) -> Result<(), AddressError> {
+    if address.get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case("bc1")) {
+        return validate_segwit(address);
+    }
+
+    let payload = decode_base58check(address)?;
+    if payload.len() != 21 {
+        return Err(AddressError::InvalidFormat {
+            reason: format!("expected a 21 byte payload, got {}", payload.len()),
+        });
+    }
+    if payload[0] != BITCOIN_P2PKH_VERSION && payload[0] != BITCOIN_P2SH_VERSION {
+        return Err(AddressError::InvalidPrefix {
+            expected: "1 or 3".to_string(),
+            actual: address.chars().take(1).collect(),
+        });
+    }
+
+    Ok(())
+}
+
+fn validate_segwit(address: &str) -> Result<(), AddressError> {
+    use bech32::primitives::decode::{CheckedHrpstringError, SegwitHrpstringError};
+
+    let (hrp, _, _) = bech32::segwit::decode(address).map_err(|err| match err.0 {
+        SegwitHrpstringError::Checksum(_) => AddressError::InvalidChecksum,
+        SegwitHrpstringError::Unchecked(err) => AddressError::InvalidFormat {
+            reason: CheckedHrpstringError::Parse(err).to_string(),
+        },
+        err => AddressError::InvalidFormat { reason: err.to_string() },
+    })?;
+
+    if hrp.to_lowercase() != BITCOIN_HRP {
+        return Err(AddressError::InvalidPrefix { expected: BITCOIN_HRP.to_string(), actual: hrp.to_lowercase() });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tron_addresses() {
+        assert_eq!(Chain::Tron.validate_address("TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY"), Ok(()));
+        assert_eq!(Chain::Tron.validate_address("TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZZ"), Err(AddressError::InvalidChecksum));
+        assert_eq!(
+            Chain::Tron.validate_address("TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZ0"),
+            Err(AddressError::InvalidCharacter { character: '0', position: 33 }),
+        );
+        // a valid base58check bitcoin address has the wrong version byte
+        assert!(matches!(
+            Chain::Tron.validate_address("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"),
+            Err(AddressError::InvalidPrefix { .. }),
+        ));
+        assert_eq!(Chain::Tron.validate_address(""), Err(AddressError::Empty));
+    }
+
+    #[test]
+    fn evm_addresses() {
+        // EIP-55 examples
+        assert_eq!(Chain::Ethereum.validate_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"), Ok(()));
+        assert_eq!(Chain::Bsc.validate_address("0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"), Ok(()));
+        assert_eq!(Chain::Ethereum.validate_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"), Ok(()));
+        assert_eq!(
+            Chain::Ethereum.validate_address("0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"),
+            Err(AddressError::InvalidChecksum),
+        );
+        assert_eq!(
+            Chain::Ethereum.validate_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAe"),
+            Err(AddressError::InvalidLength { expected: 42, actual: 41 }),
+        );
+        assert_eq!(
+            Chain::Ethereum.validate_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeg"),
+            Err(AddressError::InvalidCharacter { character: 'g', position: 41 }),
+        );
+        assert!(matches!(
+            Chain::Ethereum.validate_address("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"),
+            Err(AddressError::InvalidPrefix { .. }),
+        ));
+    }
+
+    #[test]
+    fn bitcoin_addresses() {
+        assert_eq!(Chain::Bitcoin.validate_address("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"), Ok(()));
+        assert_eq!(Chain::Bitcoin.validate_address("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy"), Ok(()));
+        // BIP-173 and BIP-350 vectors
+        assert_eq!(Chain::Bitcoin.validate_address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"), Ok(()));
+        assert_eq!(
+            Chain::Bitcoin.validate_address("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"),
+            Ok(()),
+        );
+        assert_eq!(
+            Chain::Bitcoin.validate_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"),
+            Err(AddressError::InvalidChecksum),
+        );
+        // v1 program with a bech32 (not bech32m) checksum
+        assert_eq!(
+            Chain::Bitcoin.validate_address("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd"),
+            Err(AddressError::InvalidChecksum),
+        );
+        assert!(matches!(
+            Chain::Bitcoin.validate_address("TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY"),
+            Err(AddressError::InvalidPrefix { .. }),
+        ));
+    }
+
+    #[test]
+    fn payment_uris() {
+        let tron = "TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY";
+        assert_eq!(Chain::Tron.payment_uri(tron, None, None, None), Ok(format!("tron:{}", tron)));
+        assert_eq!(
+            Chain::Tron.payment_uri(tron, Some("USDT"), Some(Decimal::new(1050, 2)), Some("for the roof")),
+            Ok(format!("tron:{}?token=TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t&amount=10.5&memo=for%20the%20roof", tron)),
+        );
+
+        let evm = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        assert_eq!(
+            Chain::Ethereum.payment_uri(evm, Some("ETH"), Some(Decimal::new(15, 1)), None),
+            Ok(format!("ethereum:{}@1?value=1500000000000000000", evm)),
+        );
+        assert_eq!(
+            Chain::Bsc.payment_uri(evm, Some("USDT"), Some(Decimal::new(2, 0)), None),
+            Ok(format!("ethereum:0x55d398326f99059fF775485246999027B3197955@56/transfer?address={}&uint256=2000000000000000000", evm)),
+        );
+        assert!(Chain::Ethereum.payment_uri(evm, None, None, Some("thanks")).is_err());
+
+        assert_eq!(
+            Chain::Bitcoin.payment_uri("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", None, Some(Decimal::new(1, 3)), Some("a&b")),
+            Ok("bitcoin:1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa?amount=0.001&message=a%26b".to_string()),
+        );
+
+        assert!(Chain::Bitcoin.payment_uri("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", Some("USDT"), None, None).is_err());
+        assert!(Chain::Tron.payment_uri(tron, None, Some(Decimal::new(1, 7)), None).is_err());
+        assert!(Chain::Tron.payment_uri(tron, None, Some(Decimal::ZERO), None).is_err());
+    }
+
+    #[test]
+    fn tokens_by_contract() {
+        assert_eq!(Chain::Tron.token_by_contract("TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t"), Some("USDT"));
+        assert_eq!(Chain::Tron.token_by_contract("tr7nhqjekqxgtci8q8zy4pl8otszgjlj6t"), None);
+        assert_eq!(Chain::Tron.token_by_contract("TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY"), None);
+        assert_eq!(Chain::Bsc.token_by_contract("0x55d398326f99059ff775485246999027b3197955"), Some("USDT"));
+        assert_eq!(Chain::Ethereum.token_by_contract("0x55d398326f99059fF775485246999027B3197955"), None);
+
+        assert_eq!(Chain::Tron.tracked_tokens().collect::<Vec<_>>(), ["USDT", "USDC"]);
+        assert_eq!(Chain::Bitcoin.tracked_tokens().next(), None);
+    }
+}
@@ -0,0 +1,940 @@
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use log::{error, info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Error, PgPool};
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::Uuid;
+use tokio::task::JoinSet;
+
+use crate::auth;
+use crate::notification;
+use crate::ssrf::UrlPolicy;
+use crate::state::AppState;
+use crate::template::{self, Markup, RenderedMessage, TemplateFormat, TemplateValues};
+use crate::webhook;
+
+const MAX_CHANNELS_PER_USER: i64 = 10;
+const MAX_NAME_LEN: usize = 100;
+const MAX_CHAT_ID_LEN: usize = 100;
+const MAX_ERROR_LEN: usize = 500;
+const MAX_SUBJECT_LEN: usize = 150;
+const MAX_TEMPLATE_LEN: usize = 2000;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 20;
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+pub const MANUAL_SEND_INTERVAL: Duration = Duration::from_secs(60);
+const VERIFICATION_CODE_TTL_SECS: f64 = 60.0 * 60.0;
+const MAX_VERIFICATION_ATTEMPTS: i32 = 5;
+pub const TEST_KIND: &str = "test";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatFormat {
+    /// `{"text": ...}`, which Mattermost and Rocket.Chat accept too.
+    #[default]
+    Slack,
+    /// `{"content": ...}`.
+    Discord,
+}
+
+/// Where a channel sends notifications, tagged by `type`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelConfig {
+    Email { to: String },
+    /// A chat the server's bot was added to: a numeric id, or `@name` for public channels.
+    Telegram { chat_id: String },
+    /// A Slack or Discord compatible incoming webhook.
+    ChatWebhook {
+        url: String,
+        #[serde(default)]
+        format: ChatFormat,
+    },
+}
+
+impl ChannelConfig {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ChannelConfig::Email { .. } => "email",
+            ChannelConfig::Telegram { .. } => "telegram",
+            ChannelConfig::ChatWebhook { .. } => "chat_webhook",
+        }
+    }
+
+    /// How messages written in `format` are sent to the channel.
+    pub fn markup(&self, format: TemplateFormat) -> Markup {
+        match (self, format) {
+            (_, TemplateFormat::Html) => Markup::Html,
+            (ChannelConfig::Telegram { .. }, TemplateFormat::Markdown) => Markup::TelegramMarkdown,
+            (ChannelConfig::Email { .. } | ChannelConfig::Telegram { .. }, _) => Markup::Plain,
+            (ChannelConfig::ChatWebhook { format: ChatFormat::Slack, .. }, _) => Markup::Slack,
+            (ChannelConfig::ChatWebhook { format: ChatFormat::Discord, .. }, _) => Markup::Markdown,
+        }
+    }
+
+    /// Checks everything but webhook urls, which the caller checks against the url policy.
+    fn validate(&mut self) -> Result<(), String> {
+        match self {
+            ChannelConfig::Email { to } => {
+                *to = to.trim().to_string();
+                to.parse::<Address>().map_err(|_| format!("Invalid email address: {}", to))?;
+            },
+            ChannelConfig::Telegram { chat_id } => {
+                *chat_id = chat_id.trim().to_string();
+                let valid = match chat_id.strip_prefix('@') {
+                    Some(name) => name.len() >= 5 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                    None => {
+                        let digits = chat_id.strip_prefix('-').unwrap_or(chat_id);
+                        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+                    },
+                };
+                if !valid || chat_id.len() > MAX_CHAT_ID_LEN {
+                    return Err("chat_id must be a numeric chat id or @channel_name".to_string());
+                }
+            },
+            ChannelConfig::ChatWebhook { url, .. } => *url = url.trim().to_string(),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonChannelIn {
+    pub name: String,
+    pub config: ChannelConfig,
+    /// Only forward notifications of these kinds; empty or missing means all of them.
+    #[serde(default)]
+    pub notification_kinds: Vec<String>,
+    /// A plain text template such as `"{{amount}} {{token}}: {{message}}"`.
+    pub template: Option<String>,
+    /// A message template to send with instead; missing both means the notification's message as is.
+    pub template_id: Option<Uuid>,
+    pub is_active: Option<bool>,
+}
+
+impl JsonChannelIn {
+    pub fn validate(&mut self) -> Result<(), String> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() || self.name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("name must be 1 to {} characters", MAX_NAME_LEN));
+        }
+        self.config.validate()?;
+
+        if let Some(kind) = self.notification_kinds.iter().find(|kind| !notification::KINDS.contains(&kind.as_str())) {
+            return Err(format!("unknown notification kind: {}", kind));
+        }
+        self.notification_kinds.sort();
+        self.notification_kinds.dedup();
+
+        self.template = self.template.take().filter(|template| !template.trim().is_empty());
+        if let Some(template) = &self.template {
+            if self.template_id.is_some() {
+                return Err("use either template or template_id".to_string());
+            }
+            if template.chars().count() > MAX_TEMPLATE_LEN {
+                return Err(format!("template must be at most {} characters", MAX_TEMPLATE_LEN));
+            }
+            template::check(template, TemplateFormat::Text)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonChannelCode {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct NotificationChannel {
+    pub id: Uuid,
+    pub name: String,
+    pub kind: String,
+    pub config: Value,
+    pub notification_kinds: Vec<String>,
+    pub template: Option<String>,
+    pub template_id: Option<Uuid>,
+    pub is_active: bool,
+    /// When its owner proved access with a code; chat webhooks count as verified right away.
+    pub verified_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl NotificationChannel {
+    pub async fn count(user_id: Uuid, db: &PgPool) -> Result<i64, Error> {
+        sqlx::query!("SELECT COUNT(*) AS count FROM notification_channels WHERE user_id = $1", user_id)
+            .fetch_one(db)
+            .await
+            .map(|row| row.count.unwrap_or_default())
+    }
+
+    pub fn limit_reached(count: i64) -> bool {
+        count >= MAX_CHANNELS_PER_USER
+    }
+
+    pub async fn create(user_id: Uuid, j_channel: &JsonChannelIn, db: &PgPool) -> Result<NotificationChannel, Error> {
+        sqlx::query_as!(
+            NotificationChannel,
+            "
+            INSERT INTO notification_channels (
+              user_id, name, kind, config, notification_kinds, template, template_id, is_active, verified_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, CASE WHEN $3::VARCHAR = 'chat_webhook' THEN CURRENT_TIMESTAMP END)
+            RETURNING id, name, kind, config, notification_kinds, template, template_id, is_active, verified_at,
+                      last_error, last_delivered_at, created_at
+            ",
+            user_id,
+            j_channel.name,
+            j_channel.config.kind(),
+            json!(j_channel.config),
+            &j_channel.notification_kinds,
+            j_channel.template,
+            j_channel.template_id,
+            j_channel.is_active.unwrap_or(true),
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<NotificationChannel, Error> {
+        sqlx::query_as!(
+            NotificationChannel,
+            "
+            SELECT id, name, kind, config, notification_kinds, template, template_id, is_active, verified_at,
+                   last_error, last_delivered_at, created_at
+            FROM notification_channels
+            WHERE id = $1 AND user_id = $2
+            ",
+            id,
+            user_id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<NotificationChannel>, Error> {
+        sqlx::query_as!(
+            NotificationChannel,
+            "
+            SELECT id, name, kind, config, notification_kinds, template, template_id, is_active, verified_at,
+                   last_error, last_delivered_at, created_at
+            FROM notification_channels
+            WHERE user_id = $1
+            ORDER BY created_at
+            ",
+            user_id
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    pub async fn update(
+        id: Uuid, user_id: Uuid, j_channel: &JsonChannelIn, db: &PgPool
+    ) -> Result<NotificationChannel, Error> {
+        sqlx::query_as!(
+            NotificationChannel,
+            "
+            UPDATE notification_channels
+            SET name = $3, kind = $4, config = $5, notification_kinds = $6, template = $7, template_id = $8,
+                is_active = COALESCE($9, is_active),
+                -- a new address or chat has to be verified again
+                verified_at = CASE
+                  WHEN $4::VARCHAR = 'chat_webhook' THEN COALESCE(verified_at, CURRENT_TIMESTAMP)
+                  WHEN config = $5 THEN verified_at
+                END,
+                verification_code = CASE WHEN config = $5 THEN verification_code END
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, name, kind, config, notification_kinds, template, template_id, is_active, verified_at,
+                      last_error, last_delivered_at, created_at
+            ",
+            id,
+            user_id,
+            j_channel.name,
+            j_channel.config.kind(),
+            json!(j_channel.config),
+            &j_channel.notification_kinds,
+            j_channel.template,
+            j_channel.template_id,
+            j_channel.is_active,
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn delete(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!("DELETE FROM notification_channels WHERE id = $1 AND user_id = $2", id, user_id)
+            .execute(db)
+            .await
+    }
+
+    /// Takes the channel's turn to send a test message or code right away, unless it had one
+    /// less than a minute ago.
+    pub async fn claim_manual_send(id: Uuid, db: &PgPool) -> Result<bool, Error> {
+        sqlx::query!(
+            "
+            UPDATE notification_channels SET last_manual_send_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+              AND (last_manual_send_at IS NULL
+                OR last_manual_send_at <= CURRENT_TIMESTAMP - make_interval(secs => $2))
+            ",
+            id,
+            MANUAL_SEND_INTERVAL.as_secs_f64(),
+        )
+            .execute(db)
+            .await
+            .map(|result| result.rows_affected() > 0)
+    }
+
+    /// Replaces the channel's verification code with a fresh one, returning it to be sent.
+    pub async fn start_verification(id: Uuid, db: &PgPool) -> Result<String, Error> {
+        let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        sqlx::query!(
+            "
+            UPDATE notification_channels
+            SET verification_code = $2, verification_attempts = 0,
+                verification_expires_at = CURRENT_TIMESTAMP + make_interval(secs => $3)
+            WHERE id = $1
+            ",
+            id,
+            auth::hash_token(&code),
+            VERIFICATION_CODE_TTL_SECS,
+        )
+            .execute(db)
+            .await?;
+
+        Ok(code)
+    }
+
+    /// Checks a code sent to the channel, returning whether it verified the channel, or `None`
+    /// if no code is waiting. A code is dropped once used or after a few wrong guesses.
+    pub async fn verify(id: Uuid, user_id: Uuid, code: &str, db: &PgPool) -> Result<Option<bool>, Error> {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE notification_channels
+            SET verified_at = CASE WHEN verification_code = $3 THEN CURRENT_TIMESTAMP ELSE verified_at END,
+                verification_code = CASE
+                  WHEN verification_code = $3 OR verification_attempts + 1 >= $4 THEN NULL
+                  ELSE verification_code
+                END,
+                verification_attempts = verification_attempts + 1
+            WHERE id = $1 AND user_id = $2
+              AND verification_code IS NOT NULL AND verification_expires_at > CURRENT_TIMESTAMP
+            RETURNING verified_at IS NOT NULL AS "verified!"
+            "#,
+            id,
+            user_id,
+            auth::hash_token(code.trim()),
+            MAX_VERIFICATION_ATTEMPTS,
+        )
+            .fetch_optional(db)
+            .await
+    }
+
+    /// Keeps the outcome of the latest send, so users can tell a misconfigured channel.
+    pub async fn record_result(id: Uuid, error: Option<&str>, db: &PgPool) -> Result<(), Error> {
+        sqlx::query!(
+            "
+            UPDATE notification_channels
+            SET last_error = $2,
+                last_delivered_at = CASE WHEN $3 THEN CURRENT_TIMESTAMP ELSE last_delivered_at END
+            WHERE id = $1
+            ",
+            id,
+            error,
+            error.is_none(),
+        )
+            .execute(db)
+            .await
+            .map(|_| ())
+    }
+
+}
+
+struct DueDelivery {
+    id: Uuid,
+    channel_id: Uuid,
+    attempts: i32,
+    created_at: DateTime<Utc>,
+    kind: String,
+    message: String,
+    data: Value,
+    config: Value,
+    is_active: bool,
+    verified: bool,
+    template: Option<String>,
+    template_format: Option<String>,
+    template_subject: Option<String>,
+    template_body: Option<String>,
+}
+
+/// Pushes the next attempt of due deliveries out by five minutes while they are sent, so
+/// other api instances don't pick them up too.
+async fn claim_due(limit: i64, db: &PgPool) -> Result<Vec<DueDelivery>, Error> {
+    sqlx::query_as!(
+        DueDelivery,
+        r#"
+        WITH due AS (
+          UPDATE notification_deliveries
+          SET next_attempt_at = CURRENT_TIMESTAMP + INTERVAL '5 minutes'
+          WHERE id IN (
+            SELECT id FROM notification_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= CURRENT_TIMESTAMP
+            ORDER BY next_attempt_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+          )
+          RETURNING id, notification_id, channel_id, attempts, created_at
+        )
+        SELECT due.id AS "id!", due.channel_id AS "channel_id!", due.attempts AS "attempts!",
+               due.created_at AS "created_at!", notifications.kind, notifications.message, notifications.data,
+               channels.config, channels.is_active, channels.verified_at IS NOT NULL AS "verified!",
+               channels.template, templates.format AS "template_format?",
+               templates.subject AS template_subject, templates.body AS "template_body?"
+        FROM due
+        JOIN notifications ON notifications.id = due.notification_id
+        JOIN notification_channels channels ON channels.id = due.channel_id
+        LEFT JOIN message_templates templates ON templates.id = channels.template_id
+        "#,
+        limit
+    )
+        .fetch_all(db)
+        .await
+}
+
+async fn finish(
+    id: Uuid, status: &str, error: Option<&str>, next_attempt_at: Option<DateTime<Utc>>, db: &PgPool
+) -> Result<(), Error> {
+    sqlx::query!(
+        "
+        UPDATE notification_deliveries
+        SET status = $2, attempts = attempts + 1, last_error = $3,
+            next_attempt_at = COALESCE($4, next_attempt_at),
+            delivered_at = CASE WHEN $5 THEN CURRENT_TIMESTAMP END
+        WHERE id = $1
+        ",
+        id,
+        status,
+        error,
+        next_attempt_at,
+        status == "delivered",
+    )
+        .execute(db)
+        .await
+        .map(|_| ())
+}
+
+/// Delivers queued notifications to their channels until the process exits, retrying
+/// failures on the webhook schedule.
+pub async fn run_delivery(state: Arc<AppState>) {
+    loop {
+        match claim_due(BATCH_SIZE, &state.db).await {
+            Ok(deliveries) if !deliveries.is_empty() => {
+                let mut sends = JoinSet::new();
+                for delivery in deliveries {
+                    sends.spawn(deliver(delivery, state.clone()));
+                }
+                while sends.join_next().await.is_some() {}
+                continue;
+            },
+            Ok(_) => {},
+            Err(err) => error!("Failed claim notification deliveries: {}", err),
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn deliver(delivery: DueDelivery, state: Arc<AppState>) {
+    let result = match serde_json::from_value::<ChannelConfig>(delivery.config) {
+        _ if !delivery.is_active => Err("channel was disabled".to_string()),
+        _ if !delivery.verified => Err("channel is no longer verified".to_string()),
+        Ok(config) => {
+            let format = delivery.template_format.as_deref()
+                .and_then(|format| TemplateFormat::try_from(format).ok())
+                .unwrap_or_default();
+            let template = delivery.template_body.as_deref().map(|body| (format, delivery.template_subject.as_deref(), body));
+            let values = TemplateValues::from_notification(&delivery.kind, &delivery.message, &delivery.data);
+            let rendered = compose(&config, template, delivery.template.as_deref(), &delivery.message, &values);
+            state.channel_client.send(&config, &delivery.message, &rendered).await
+        },
+        Err(err) => Err(format!("Invalid channel config: {}", err)),
+    };
+
+    if let Err(err) = NotificationChannel::record_result(delivery.channel_id, result.as_ref().err().map(String::as_str), &state.db).await {
+        error!("Failed update notification channel {}: {}", delivery.channel_id, err);
+    }
+    let finished = match result {
+        Ok(()) => finish(delivery.id, "delivered", None, None, &state.db).await,
+        Err(err_msg) => {
+            let err_msg = excerpt(&err_msg);
+            let next_attempt_at = webhook::next_attempt_at(delivery.created_at, delivery.attempts + 1, Utc::now())
+                .filter(|_| delivery.is_active && delivery.verified);
+            info!("Notification delivery {} failed ({}), next attempt at {:?}", delivery.id, err_msg, next_attempt_at);
+            let status = if next_attempt_at.is_some() { "pending" } else { "failed" };
+            finish(delivery.id, status, Some(&err_msg), next_attempt_at, &state.db).await
+        },
+    };
+    if let Err(err) = finished {
+        error!("Failed update notification delivery {}: {}", delivery.id, err);
+    }
+}
+
+/// What a channel sends for a notification: its message template, or else its own template,
+/// filled in, or the notification's message as is, escaped for the channel either way.
+pub fn compose(
+    config: &ChannelConfig, template: Option<(TemplateFormat, Option<&str>, &str)>, inline: Option<&str>, message: &str,
+    values: &TemplateValues,
+) -> RenderedMessage {
+    match (template, inline) {
+        (Some((format, subject, body)), _) => template::render_message(format, subject, body, config.markup(format), values),
+        (None, Some(inline)) => {
+            template::render_message(TemplateFormat::Text, None, inline, config.markup(TemplateFormat::Text), values)
+        },
+        (None, None) => RenderedMessage::text(message, config.markup(TemplateFormat::Text)),
+    }
+}
+
+fn excerpt(text: &str) -> String {
+    text.chars().take(MAX_ERROR_LEN).collect()
+}
+
+struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+struct TelegramBot {
+    api_url: String,
+    token: String,
+}
+
+/// Sends notifications to channels: email through `SMTP_HOST`, Telegram through the
+/// `TELEGRAM_BOT_TOKEN` bot, and chat webhooks under the same url policy as webhooks.
+#[derive(Clone)]
+pub struct ChannelClient {
+    mailer: Option<Arc<Mailer>>,
+    telegram: Option<Arc<TelegramBot>>,
+    /// For the Telegram api, which is trusted.
+    client: reqwest::Client,
+    webhook_client: reqwest::Client,
+    pub policy: UrlPolicy,
+}
+
+impl ChannelClient {
+    /// `SMTP_TLS` is `starttls` (the default, port 587), `tls` (port 465) or `none` (port 25,
+    /// for local test servers); `SMTP_PORT`, `SMTP_USERNAME` and `SMTP_PASSWORD` are optional.
+    /// `TELEGRAM_API_URL` points the bot at a stand-in.
+    pub fn from_env(policy: UrlPolicy) -> ChannelClient {
+        let mailer = env::var("SMTP_HOST").ok().filter(|host| !host.is_empty()).map(|host| {
+            let from = env::var("SMTP_FROM").expect("SMTP_FROM must be set with SMTP_HOST")
+                .parse::<Mailbox>()
+                .expect("SMTP_FROM must be an email address");
+            let tls_parameters = || TlsParameters::new(host.clone()).expect("Failed set up smtp tls");
+            let (tls, default_port) = match env::var("SMTP_TLS").unwrap_or_default().as_str() {
+                "" | "starttls" => (Tls::Required(tls_parameters()), 587),
+                "tls" => (Tls::Wrapper(tls_parameters()), 465),
+                "none" => (Tls::None, 25),
+                other => panic!("SMTP_TLS must be starttls, tls or none, got {}", other),
+            };
+            let port = env::var("SMTP_PORT").ok()
+                .map(|port| port.parse().expect("SMTP_PORT must be a port number"))
+                .unwrap_or(default_port);
+
+            let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)
+                .port(port)
+                .tls(tls)
+                .timeout(Some(REQUEST_TIMEOUT));
+            if let Ok(username) = env::var("SMTP_USERNAME") {
+                builder = builder.credentials(Credentials::new(username, env::var("SMTP_PASSWORD").unwrap_or_default()));
+            }
+            Arc::new(Mailer { transport: builder.build(), from })
+        });
+
+        let telegram = env::var("TELEGRAM_BOT_TOKEN").ok().filter(|token| !token.is_empty()).map(|token| {
+            let api_url = env::var("TELEGRAM_API_URL").unwrap_or_else(|_| TELEGRAM_API_URL.to_string());
+            Arc::new(TelegramBot { api_url: api_url.trim_end_matches('/').to_string(), token })
+        });
+
+        ChannelClient {
+            mailer,
+            telegram,
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().expect("Failed build http client"),
+            webhook_client: policy.client(REQUEST_TIMEOUT),
+            policy,
+        }
+    }
+
+    /// Whether the server can send to channels like this one.
+    pub fn supports(&self, config: &ChannelConfig) -> bool {
+        match config {
+            ChannelConfig::Email { .. } => self.mailer.is_some(),
+            ChannelConfig::Telegram { .. } => self.telegram.is_some(),
+            ChannelConfig::ChatWebhook { .. } => true,
+        }
+    }
+
+    /// Sends a message; email uses its subject, or else `fallback_subject`, cut to one short line.
+    pub async fn send(
+        &self, config: &ChannelConfig, fallback_subject: &str, message: &RenderedMessage
+    ) -> Result<(), String> {
+        let text = &message.body;
+        match config {
+            ChannelConfig::Email { to } => {
+                let mailer = self.mailer.as_ref().ok_or("email is not configured on this server")?;
+                let subject = message.subject.as_deref().filter(|subject| !subject.trim().is_empty()).unwrap_or(fallback_subject);
+                let subject: String = subject.lines().next().unwrap_or_default().chars().take(MAX_SUBJECT_LEN).collect();
+                let content_type = match message.markup {
+                    Markup::Html => ContentType::TEXT_HTML,
+                    _ => ContentType::TEXT_PLAIN,
+                };
+                let to = to.parse::<Mailbox>().map_err(|err| format!("Invalid email address: {}", err))?;
+                let email = lettre::Message::builder()
+                    .from(mailer.from.clone())
+                    .to(to)
+                    .subject(subject)
+                    .header(content_type)
+                    .body(text.to_string())
+                    .map_err(|err| format!("Failed build email: {}", err))?;
+
+                mailer.transport.send(email).await.map(|_| ()).map_err(|err| format!("Failed send email: {}", err))
+            },
+            ChannelConfig::Telegram { chat_id } => {
+                let bot = self.telegram.as_ref().ok_or("telegram is not configured on this server")?;
+                let url = format!("{}/bot{}/sendMessage", bot.api_url, bot.token);
+                let mut body = json!({ "chat_id": chat_id, "text": text, "disable_web_page_preview": true });
+                match message.markup {
+                    Markup::Html => body["parse_mode"] = json!("HTML"),
+                    Markup::TelegramMarkdown => body["parse_mode"] = json!("MarkdownV2"),
+                    _ => {},
+                }
+
+                // errors could contain the bot token, which is part of the url
+                let response = self.client.post(url).json(&body).send().await
+                    .map_err(|err| format!("Failed reach telegram: {}", err.without_url()))?;
+                check_response(response).await
+            },
+            ChannelConfig::ChatWebhook { url, format } => {
+                // checked again on every send, like webhooks
+                let url = self.policy.check_url(url)?;
+                let body = match format {
+                    ChatFormat::Slack => json!({ "text": text }),
+                    ChatFormat::Discord => json!({ "content": text }),
+                };
+
+                let response = self.webhook_client.post(url).json(&body).send().await.map_err(|err| err.to_string())?;
+                check_response(response).await
+            },
+        }
+    }
+}
+
+async fn check_response(response: reqwest::Response) -> Result<(), String> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let body = excerpt(&webhook::body_excerpt(response, MAX_ERROR_LEN).await);
+    warn!("Notification channel answered {}: {}", status, body);
+    Err(format!("status {}: {}", status.as_u16(), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{StatusCode, Uri};
+    use axum::{Json, Router};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::fixtures;
+
+    fn j_channel(config: ChannelConfig) -> JsonChannelIn {
+        JsonChannelIn {
+            name: " Tips ".to_string(),
+            config,
+            notification_kinds: vec![],
+            template: None,
+            template_id: None,
+            is_active: None,
+        }
+    }
+
+    #[test]
+    fn validates_configs() {
+        let mut email = j_channel(ChannelConfig::Email { to: " me@example.com ".to_string() });
+        assert!(email.validate().is_ok());
+        assert_eq!(email.name, "Tips");
+        assert_eq!(email.config, ChannelConfig::Email { to: "me@example.com".to_string() });
+        assert!(j_channel(ChannelConfig::Email { to: "me".to_string() }).validate().is_err());
+
+        for chat_id in ["123456", "-1001234567890", "@donation_hub"] {
+            assert!(j_channel(ChannelConfig::Telegram { chat_id: chat_id.to_string() }).validate().is_ok(), "{}", chat_id);
+        }
+        for chat_id in ["", "-", "12a", "@abc", "@bad name"] {
+            assert!(j_channel(ChannelConfig::Telegram { chat_id: chat_id.to_string() }).validate().is_err(), "{}", chat_id);
+        }
+    }
+
+    #[test]
+    fn parses_tagged_configs() {
+        let config: ChannelConfig = serde_json::from_value(json!({
+            "type": "chat_webhook", "url": "https://discord.com/api/webhooks/1/a", "format": "discord",
+        })).unwrap();
+        assert_eq!(config.kind(), "chat_webhook");
+        assert_eq!(config, ChannelConfig::ChatWebhook {
+            url: "https://discord.com/api/webhooks/1/a".to_string(),
+            format: ChatFormat::Discord,
+        });
+
+        let config: ChannelConfig = serde_json::from_value(json!({ "type": "chat_webhook", "url": "https://x" })).unwrap();
+        assert_eq!(config, ChannelConfig::ChatWebhook { url: "https://x".to_string(), format: ChatFormat::Slack });
+    }
+
+    #[test]
+    fn filters_notification_kinds() {
+        let mut channel = JsonChannelIn {
+            notification_kinds: vec![notification::ALERT_TRIGGERED.to_string(), notification::ALERT_TRIGGERED.to_string()],
+            ..j_channel(ChannelConfig::Telegram { chat_id: "1".to_string() })
+        };
+        assert!(channel.validate().is_ok());
+        assert_eq!(channel.notification_kinds, vec![notification::ALERT_TRIGGERED]);
+
+        channel.notification_kinds = vec!["donation.deleted".to_string()];
+        assert!(channel.validate().is_err());
+    }
+
+    #[test]
+    fn checks_inline_templates() {
+        let with_template = |template: &str| JsonChannelIn {
+            template: Some(template.to_string()),
+            ..j_channel(ChannelConfig::Telegram { chat_id: "1".to_string() })
+        };
+        assert!(with_template("{{rule_name}}: {{amount}} {{token}}").validate().is_ok());
+        assert!(with_template("{{amount}} on {{donation_id}}").validate().is_err());
+        assert!(JsonChannelIn { template_id: Some(Uuid::nil()), ..with_template("{{amount}}") }.validate().is_err());
+
+        let mut blank = with_template(" ");
+        assert!(blank.validate().is_ok());
+        assert_eq!(blank.template, None);
+    }
+
+    #[test]
+    fn escapes_messages_for_each_channel() {
+        let values = TemplateValues { amount: Some("12.5".to_string()), donor_name: Some("<!channel>".to_string()), ..Default::default() };
+        let markdown = Some((TemplateFormat::Markdown, None, "*Thanks, {{donor_name}}!* {{amount}} \\- [more](https://x.io/a)"));
+        let compose_for = |config: &ChannelConfig| compose(config, markdown, None, "", &values).body;
+
+        let telegram = ChannelConfig::Telegram { chat_id: "1".to_string() };
+        assert_eq!(compose_for(&telegram), "*Thanks, <\\!channel\\>\\!* 12\\.5 \\- [more](https://x.io/a)");
+        let email = ChannelConfig::Email { to: "me@example.com".to_string() };
+        assert_eq!(compose_for(&email), "*Thanks, <!channel>!* 12.5 - [more](https://x.io/a)");
+        let slack = ChannelConfig::ChatWebhook { url: "https://x".to_string(), format: ChatFormat::Slack };
+        assert_eq!(compose_for(&slack), "*Thanks, &lt;!channel&gt;!* 12.5 - [more](https://x.io/a)");
+        let discord = ChannelConfig::ChatWebhook { url: "https://x".to_string(), format: ChatFormat::Discord };
+        assert_eq!(compose_for(&discord), "*Thanks, <\\!channel\\>!* 12\\.5 \\- [more](https://x.io/a)");
+
+        // inline templates and plain messages are text, shown as written
+        let inline = compose(&slack, None, Some("{{donor_name}} & co"), "", &values);
+        assert_eq!((inline.body.as_str(), inline.markup), ("&lt;!channel&gt; &amp; co", Markup::Slack));
+        assert_eq!(compose(&telegram, None, None, "Got 1.5 USDT!", &values).body, "Got 1.5 USDT!");
+        assert_eq!(compose(&discord, None, None, "Got *1.5*", &values).body, "Got \\*1\\.5\\*");
+    }
+
+    /// An http server answering `status` to every post, handing over the path and json body.
+    async fn mock_http(status: StatusCode) -> (String, UnboundedReceiver<(String, Value)>) {
+        let (sender, receiver) = unbounded_channel();
+        let routes = Router::new().fallback(move |uri: Uri, Json(body): Json<Value>| async move {
+            sender.send((uri.path().to_string(), body)).unwrap();
+            status
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, routes).await.unwrap() });
+
+        (url, receiver)
+    }
+
+    /// Accepts one smtp session, handing over the recipient and the message data.
+    async fn mock_smtp() -> (u16, oneshot::Receiver<(String, String)>) {
+        let (sender, receiver) = oneshot::channel();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let (mut recipient, mut data) = (String::new(), String::new());
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let command = line.to_uppercase();
+                let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                    b"250 localhost\r\n"
+                } else if command.starts_with("RCPT TO:") {
+                    recipient = line[8..].trim_matches(|c| c == '<' || c == '>' || c == ' ').to_string();
+                    b"250 OK\r\n"
+                } else if command == "DATA" {
+                    writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if line == "." {
+                            break;
+                        }
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    b"250 OK\r\n"
+                } else if command == "QUIT" {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 OK\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+            sender.send((recipient, data)).unwrap();
+        });
+
+        (port, receiver)
+    }
+
+    fn channel_client(smtp_port: u16, telegram_url: &str) -> ChannelClient {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(smtp_port)
+            .tls(Tls::None)
+            .timeout(Some(REQUEST_TIMEOUT))
+            .build();
+        let policy = UrlPolicy { allow_http: true, allow_private: true };
+
+        ChannelClient {
+            mailer: Some(Arc::new(Mailer { transport, from: "hub@example.com".parse().unwrap() })),
+            telegram: Some(Arc::new(TelegramBot { api_url: telegram_url.to_string(), token: "123:abc".to_string() })),
+            client: reqwest::Client::new(),
+            webhook_client: policy.client(REQUEST_TIMEOUT),
+            policy,
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_to_telegram() {
+        let (url, mut requests) = mock_http(StatusCode::OK).await;
+        let client = channel_client(0, &url);
+        let config = ChannelConfig::Telegram { chat_id: "-100123".to_string() };
+        let message = RenderedMessage {
+            format: TemplateFormat::Html, subject: None, body: "<b>10 USDT</b>".to_string(), markup: Markup::Html,
+        };
+
+        assert_eq!(client.send(&config, "Tip", &message).await, Ok(()));
+        let (path, body) = requests.recv().await.unwrap();
+        assert_eq!(path, "/bot123:abc/sendMessage");
+        assert_eq!(body, json!({
+            "chat_id": "-100123", "text": "<b>10 USDT</b>", "disable_web_page_preview": true, "parse_mode": "HTML",
+        }));
+
+        let (url, _requests) = mock_http(StatusCode::BAD_REQUEST).await;
+        let error = channel_client(0, &url).send(&config, "Tip", &message).await.unwrap_err();
+        assert!(error.starts_with("status 400"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn sends_to_chat_webhooks() {
+        let (url, mut requests) = mock_http(StatusCode::NO_CONTENT).await;
+        let client = channel_client(0, "");
+
+        for (format, body) in [(ChatFormat::Slack, json!({"text": "hi"})), (ChatFormat::Discord, json!({"content": "hi"}))] {
+            let config = ChannelConfig::ChatWebhook { url: format!("{}/hooks/1", url), format };
+            assert_eq!(client.send(&config, "Tip", &RenderedMessage::text("hi", Markup::Plain)).await, Ok(()));
+            assert_eq!(requests.recv().await.unwrap(), ("/hooks/1".to_string(), body));
+        }
+
+        let internal = ChannelConfig::ChatWebhook { url: format!("{}/hooks/1", url), format: ChatFormat::Slack };
+        let strict = ChannelClient { policy: UrlPolicy::default(), ..client };
+        assert!(strict.send(&internal, "Tip", &RenderedMessage::text("hi", Markup::Plain)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sends_email() {
+        let (port, sent) = mock_smtp().await;
+        let client = channel_client(port, "");
+        let config = ChannelConfig::Email { to: "me@example.com".to_string() };
+        let message = RenderedMessage::text("Received 10 USDT", Markup::Plain);
+
+        assert_eq!(client.send(&config, "Tip from Alice\nsecond line", &message).await, Ok(()));
+        let (recipient, data) = sent.await.unwrap();
+        assert_eq!(recipient, "me@example.com");
+        assert!(data.contains("Subject: Tip from Alice\n"), "{}", data);
+        assert!(data.contains("Content-Type: text/plain"), "{}", data);
+        assert!(data.contains("Received 10 USDT"), "{}", data);
+    }
+
+    #[sqlx::test]
+    async fn only_verified_channels_get_notifications(db: PgPool) {
+        let user_id = fixtures::owner(&db).await.user_id;
+        let email = NotificationChannel::create(
+            user_id, &j_channel(ChannelConfig::Email { to: "me@example.com".to_string() }), &db,
+        ).await.unwrap();
+        let webhook = NotificationChannel::create(
+            user_id, &j_channel(ChannelConfig::ChatWebhook { url: "https://x".to_string(), format: ChatFormat::Slack }), &db,
+        ).await.unwrap();
+        assert!(email.verified_at.is_none());
+        assert!(webhook.verified_at.is_some());
+
+        let deliveries = || sqlx::query_scalar::<_, Uuid>("SELECT channel_id FROM notification_deliveries ORDER BY created_at").fetch_all(&db);
+        notification::Notification::create(user_id, notification::ALERT_TRIGGERED, "hi", json!({}), &db).await.unwrap();
+        assert_eq!(deliveries().await.unwrap(), vec![webhook.id]);
+
+        // no code was sent yet, then a few wrong guesses use it up
+        assert_eq!(NotificationChannel::verify(email.id, user_id, "000000", &db).await.unwrap(), None);
+        let code = NotificationChannel::start_verification(email.id, &db).await.unwrap();
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+        for _ in 0..MAX_VERIFICATION_ATTEMPTS {
+            assert_eq!(NotificationChannel::verify(email.id, user_id, wrong, &db).await.unwrap(), Some(false));
+        }
+        assert_eq!(NotificationChannel::verify(email.id, user_id, &code, &db).await.unwrap(), None);
+
+        let code = NotificationChannel::start_verification(email.id, &db).await.unwrap();
+        assert_eq!(NotificationChannel::verify(email.id, Uuid::nil(), &code, &db).await.unwrap(), None);
+        assert_eq!(NotificationChannel::verify(email.id, user_id, &format!(" {} ", code), &db).await.unwrap(), Some(true));
+
+        notification::Notification::create(user_id, notification::ALERT_TRIGGERED, "hi", json!({}), &db).await.unwrap();
+        assert_eq!(deliveries().await.unwrap().len(), 3);
+
+        // another address has to be verified again
+        let moved = j_channel(ChannelConfig::Email { to: "other@example.com".to_string() });
+        assert!(NotificationChannel::update(email.id, user_id, &moved, &db).await.unwrap().verified_at.is_none());
+        let same = j_channel(ChannelConfig::ChatWebhook { url: "https://x".to_string(), format: ChatFormat::Slack });
+        assert!(NotificationChannel::update(webhook.id, user_id, &same, &db).await.unwrap().verified_at.is_some());
+    }
+
+    #[sqlx::test]
+    async fn limits_manual_sends(db: PgPool) {
+        let user_id = fixtures::owner(&db).await.user_id;
+        let channel = NotificationChannel::create(
+            user_id, &j_channel(ChannelConfig::Telegram { chat_id: "1".to_string() }), &db,
+        ).await.unwrap();
+
+        assert!(NotificationChannel::claim_manual_send(channel.id, &db).await.unwrap());
+        assert!(!NotificationChannel::claim_manual_send(channel.id, &db).await.unwrap());
+        sqlx::query("UPDATE notification_channels SET last_manual_send_at = CURRENT_TIMESTAMP - INTERVAL '2 minutes'")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert!(NotificationChannel::claim_manual_send(channel.id, &db).await.unwrap());
+    }
+}
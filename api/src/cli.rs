@@ -0,0 +1,63 @@
+use sqlx::PgPool;
+
+use crate::auth;
+use crate::crypto::MasterKey;
+use crate::models::{ApiKey, ApiKeyScope, User, Wallet};
+use crate::pool::WalletPool;
+
+const USAGE: &str = "usage:
+  api create-api-key <email> [name] [read|read_write]
+  api rotate-master-key    re-encrypts wallet keys sealed with OLD_MASTER_KEY and any
+                           plaintext keys with MASTER_KEY";
+
+/// Runs a maintenance command instead of the http server.
+pub async fn run(args: &[String], db: &PgPool) -> Result<(), String> {
+    match args.first().map(String::as_str) {
+        Some("create-api-key") => create_api_key(&args[1..], db).await,
+        Some("rotate-master-key") => rotate_master_key(db).await,
+        Some(cmd) => Err(format!("unknown command: {}\n{}", cmd, USAGE)),
+        None => Err(USAGE.to_string()),
+    }
+}
+
+async fn create_api_key(args: &[String], db: &PgPool) -> Result<(), String> {
+    let email = args.first().ok_or(USAGE)?;
+    let name = args.get(1).map_or("cli", String::as_str);
+    let scope: ApiKeyScope = args.get(2).map_or("read_write", String::as_str).try_into()?;
+
+    let user = User::get_by_email(email, db)
+        .await
+        .map_err(|err| format!("Failed get user {}: {}", email, err))?;
+
+    let key = auth::generate_api_key();
+    ApiKey::create(user.id, name, scope, &key.prefix, &key.hash, db)
+        .await
+        .map_err(|err| format!("Failed create api key: {}", err))?;
+
+    println!("{}", key.secret);
+    Ok(())
+}
+
+async fn rotate_master_key(db: &PgPool) -> Result<(), String> {
+    let master_key = MasterKey::from_env("MASTER_KEY")?.ok_or("MASTER_KEY must be set")?;
+    let old_master_key = MasterKey::from_env("OLD_MASTER_KEY")?;
+
+    let mut tx = db.begin().await.map_err(|err| err.to_string())?;
+    let wallets = Wallet::lock_with_keys(&mut tx).await.map_err(|err| err.to_string())?;
+
+    let mut resealed = 0;
+    for (id, data) in wallets {
+        let Some(data) = data.reseal(&master_key, old_master_key.as_ref())
+            .map_err(|err| format!("wallet {}: {}", id, err))? else { continue };
+        Wallet::set_data(id, data, &mut tx).await.map_err(|err| err.to_string())?;
+        resealed += 1;
+    }
+
+    let purged = WalletPool::purge_foreign_keys(master_key.id(), &mut *tx).await.map_err(|err| err.to_string())?;
+
+    tx.commit().await.map_err(|err| err.to_string())?;
+
+    println!("dropped {} pooled wallets", purged);
+    println!("re-encrypted {} wallet keys with master key {}", resealed, master_key.id());
+    Ok(())
+}
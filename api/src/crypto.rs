@@ -0,0 +1,183 @@
+use std::{env, fs};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const KEY_LEN: usize = 32;
+
+/// A secret sealed with envelope encryption: the secret is encrypted with a random
+/// data key, and only the data key is encrypted with the master key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    pub key_id: String,
+    pub wrapped_key: String,
+    pub wrapped_key_nonce: String,
+    pub ciphertext: String,
+    pub nonce: String,
+}
+
+pub struct MasterKey {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl MasterKey {
+    /// Loads a base64 encoded 32 byte key from `<var>` or from the file named by `<var>_FILE`.
+    pub fn from_env(var: &str) -> Result<Option<MasterKey>, String> {
+        let encoded = match (env::var(var), env::var(format!("{}_FILE", var))) {
+            (Ok(value), _) => value,
+            (Err(_), Ok(path)) => fs::read_to_string(&path)
+                .map_err(|err| format!("Failed read {}: {}", path, err))?,
+            (Err(_), Err(_)) => return Ok(None),
+        };
+
+        let bytes = BASE64.decode(encoded.trim())
+            .map_err(|err| format!("{} is not valid base64: {}", var, err))?;
+
+        MasterKey::from_bytes(&bytes).map(Some)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MasterKey, String> {
+        if bytes.len() != KEY_LEN {
+            return Err(format!("master key must be {} bytes, got {}", KEY_LEN, bytes.len()));
+        }
+
+        Ok(MasterKey {
+            id: hex::encode(&Sha256::digest(bytes)[..8]),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(bytes)),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// `aad` binds the ciphertext to its owner (e.g. the wallet address), so a sealed
+    /// secret can not be copied onto another row.
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<EncryptedSecret, String> {
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let data_cipher = Aes256Gcm::new(&data_key);
+
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let ciphertext = data_cipher.encrypt(&nonce, Payload { msg: plaintext, aad })
+            .map_err(|err| format!("Failed encrypt secret: {}", err))?;
+
+        let wrapped_key_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let wrapped_key = self.cipher.encrypt(&wrapped_key_nonce, data_key.as_slice())
+            .map_err(|err| format!("Failed wrap data key: {}", err))?;
+
+        Ok(EncryptedSecret {
+            key_id: self.id.clone(),
+            wrapped_key: BASE64.encode(wrapped_key),
+            wrapped_key_nonce: BASE64.encode(wrapped_key_nonce),
+            ciphertext: BASE64.encode(ciphertext),
+            nonce: BASE64.encode(nonce),
+        })
+    }
+
+    pub fn decrypt(&self, secret: &EncryptedSecret, aad: &[u8]) -> Result<Vec<u8>, String> {
+        if secret.key_id != self.id {
+            return Err(format!("secret is sealed with key {}, not {}", secret.key_id, self.id));
+        }
+
+        let wrapped_key = decode(&secret.wrapped_key)?;
+        let wrapped_key_nonce = decode_nonce(&secret.wrapped_key_nonce)?;
+        let data_key = self.cipher.decrypt(&wrapped_key_nonce, wrapped_key.as_slice())
+            .map_err(|err| format!("Failed unwrap data key: {}", err))?;
+        if data_key.len() != KEY_LEN {
+            return Err("invalid data key".to_string());
+        }
+        let data_cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key));
+
+        let ciphertext = decode(&secret.ciphertext)?;
+        let nonce = decode_nonce(&secret.nonce)?;
+        data_cipher.decrypt(&nonce, Payload { msg: &ciphertext, aad })
+            .map_err(|err| format!("Failed decrypt secret: {}", err))
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    BASE64.decode(value).map_err(|err| format!("invalid base64: {}", err))
+}
+
+fn decode_nonce(value: &str) -> Result<Nonce<<Aes256Gcm as AeadCore>::NonceSize>, String> {
+    let bytes = decode(value)?;
+    if bytes.len() != 12 {
+        return Err("invalid nonce".to_string());
+    }
+    Ok(*Nonce::from_slice(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> MasterKey {
+        MasterKey::from_bytes(&[byte; KEY_LEN]).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let master_key = key(1);
+        let secret = master_key.encrypt(b"private key", b"TAddress").unwrap();
+        assert_eq!(secret.key_id, master_key.id());
+        assert_eq!(master_key.decrypt(&secret, b"TAddress").unwrap(), b"private key");
+
+        // a fresh data key and nonces every time
+        let again = master_key.encrypt(b"private key", b"TAddress").unwrap();
+        assert_ne!(secret.ciphertext, again.ciphertext);
+        assert_ne!(secret.wrapped_key, again.wrapped_key);
+    }
+
+    #[test]
+    fn rejects_the_wrong_key() {
+        let secret = key(1).encrypt(b"private key", b"TAddress").unwrap();
+        assert!(key(2).decrypt(&secret, b"TAddress").is_err());
+
+        // even under the right id, another key can't unwrap the data key
+        let forged = EncryptedSecret { key_id: key(2).id().to_string(), ..secret };
+        assert!(key(2).decrypt(&forged, b"TAddress").is_err());
+    }
+
+    #[test]
+    fn binds_secrets_to_their_owner() {
+        let master_key = key(1);
+        let secret = master_key.encrypt(b"private key", b"TAddress").unwrap();
+        assert!(master_key.decrypt(&secret, b"TOtherAddress").is_err());
+
+        let mut tampered = secret.clone();
+        tampered.ciphertext = BASE64.encode(b"not the ciphertext");
+        assert!(master_key.decrypt(&tampered, b"TAddress").is_err());
+    }
+
+    #[test]
+    fn rotates_wallet_keys() {
+        use crate::models::WalletData;
+
+        let (old, new) = (key(1), key(2));
+        let legacy = WalletData { address: "TAddress".into(), private_key: Some("private key".into()), ..Default::default() };
+
+        let sealed = legacy.reseal(&old, None).unwrap().unwrap();
+        assert!(sealed.private_key.is_none());
+        assert_eq!(sealed.decrypt_private_key(&old).unwrap().as_deref(), Some("private key"));
+        assert!(sealed.reseal(&old, None).unwrap().is_none());
+
+        assert!(sealed.reseal(&new, None).is_err());
+        let rotated = sealed.reseal(&new, Some(&old)).unwrap().unwrap();
+        assert_eq!(rotated.decrypt_private_key(&new).unwrap().as_deref(), Some("private key"));
+        assert!(rotated.decrypt_private_key(&old).is_err());
+
+        let watch_only = WalletData { address: "TAddress".into(), ..Default::default() };
+        assert!(watch_only.reseal(&new, Some(&old)).unwrap().is_none());
+    }
+
+    #[test]
+    fn checks_key_length() {
+        assert!(MasterKey::from_bytes(&[0; 16]).is_err());
+        assert_eq!(key(1).id(), key(1).id());
+        assert_ne!(key(1).id(), key(2).id());
+    }
+}
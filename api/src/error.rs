@@ -4,8 +4,11 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use std::time::Duration;
+use axum::http::header;
 use serde_json::json;
 use thiserror::Error;
+use crate::chain::{AddressError, Chain};
 
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -15,9 +18,24 @@ pub enum AppError {
     #[error("Invalid input: {0}")]
     InvalidInput(String),
 
+    #[error("Invalid {} address: {error}", .chain.as_str())]
+    InvalidAddress { chain: Chain, error: AddressError },
+
     #[error("Resource not found")]
     NotFound,
 
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Too many requests, retry in {}s", .0.as_secs())]
+    RateLimited(Duration),
+
     #[error("Webhook delivery failed: {0}")]
     WebhookError(#[from] reqwest::Error), // If using reqwest for webhooks
 
@@ -28,11 +46,36 @@ pub enum AppError {
 // Implement IntoResponse for AppError
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if let AppError::InvalidAddress { chain, error } = &self {
+            let mut details = serde_json::to_value(error).unwrap();
+            details["chain"] = json!(chain);
+
+            let body = Json(json!({
+                "error": self.to_string(),
+                "details": details,
+            }));
+            return (StatusCode::BAD_REQUEST, body).into_response();
+        }
+
+        if let AppError::RateLimited(retry_after) = &self {
+            // rounded up, so a client retrying on time isn't turned away again
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            let body = Json(json!({
+                "error": "Too many requests",
+            }));
+            return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, seconds.to_string())], body).into_response();
+        }
+
         let (status, error_message) = match self {
             AppError::DbError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()),
             AppError::InvalidInput(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::InvalidAddress { .. } => (StatusCode::BAD_REQUEST, self.to_string()),
             // AppError::InvalidInput(_) => (StatusCode::BAD_REQUEST, "TODO"),
             AppError::NotFound => (StatusCode::NOT_FOUND, "Not found".to_string()),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".to_string()),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, "Too many requests".to_string()),
             AppError::WebhookError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Webhook delivery failed".to_string()),
             AppError::InternalServerError => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string()),
         };
@@ -0,0 +1,103 @@
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use amqprs::{callbacks::{DefaultChannelCallback, DefaultConnectionCallback}, channel::{
+    BasicAckArguments, BasicConsumeArguments, BasicNackArguments, BasicQosArguments, QueueBindArguments,
+    QueueDeclareArguments,
+}, connection::{Connection, OpenConnectionArguments}};
+use log::{error, info, warn};
+
+use crate::state::AppState;
+use crate::webhook::{self, Deposit};
+
+const EXCHANGE: &str = "amq.topic";
+const QUEUE: &str = "donation.deposits";
+const ROUTING_KEY: &str = "deposit.created";
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Turns deposits published by the collector into webhook events, reconnecting
+/// whenever rabbitmq goes away.
+pub async fn run_consumer(state: Arc<AppState>) {
+    loop {
+        match consume(&state).await {
+            Ok(()) => warn!("Deposit consumer stopped, reconnecting"),
+            Err(err_msg) => error!("Deposit consumer failed: {}", err_msg),
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn consume(state: &AppState) -> Result<(), String> {
+    let connection = Connection::open(&OpenConnectionArguments::new(
+        &env::var("RABBITMQ_HOST").unwrap_or("localhost".to_string()),
+        5672,
+        "guest",
+        "guest",
+    ))
+        .await
+        .map_err(|err| format!("Failed connect to rabbitmq: {}", err))?;
+    connection
+        .register_callback(DefaultConnectionCallback)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let channel = connection.open_channel(None).await.map_err(|err| err.to_string())?;
+    channel
+        .register_callback(DefaultChannelCallback)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    channel
+        .queue_declare(QueueDeclareArguments::durable_client_named(QUEUE))
+        .await
+        .map_err(|err| err.to_string())?;
+    channel
+        .queue_bind(QueueBindArguments::new(QUEUE, EXCHANGE, ROUTING_KEY))
+        .await
+        .map_err(|err| err.to_string())?;
+    channel
+        .basic_qos(BasicQosArguments::new(0, 10, false))
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let (_, mut messages) = channel
+        .basic_consume_rx(BasicConsumeArguments::new(QUEUE, "api-deposits"))
+        .await
+        .map_err(|err| err.to_string())?;
+    info!("Consuming deposits from {}", QUEUE);
+
+    while let Some(message) = messages.recv().await {
+        let Some(delivery_tag) = message.deliver.map(|deliver| deliver.delivery_tag()) else {
+            continue;
+        };
+
+        let deposit = match serde_json::from_slice::<Deposit>(&message.content.unwrap_or_default()) {
+            Ok(deposit) => deposit,
+            Err(err) => {
+                // redelivering a malformed message would only fail again
+                error!("Dropped malformed deposit: {}", err);
+                channel.basic_ack(BasicAckArguments::new(delivery_tag, false)).await.map_err(|err| err.to_string())?;
+                continue;
+            },
+        };
+
+        match webhook::handle_deposit(&deposit, state).await {
+            Ok(queued) => {
+                info!("Queued {} webhook events for deposit {}", queued, deposit.transaction_id);
+                channel.basic_ack(BasicAckArguments::new(delivery_tag, false)).await.map_err(|err| err.to_string())?;
+            },
+            Err(err) => {
+                error!("Failed queue webhook events for deposit {}: {}", deposit.transaction_id, err);
+                tokio::time::sleep(RETRY_DELAY).await;
+                channel
+                    .basic_nack(BasicNackArguments::new(delivery_tag, false, true))
+                    .await
+                    .map_err(|err| err.to_string())?;
+            },
+        }
+    }
+
+    Ok(())
+}
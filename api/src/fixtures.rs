@@ -0,0 +1,53 @@
+//! Rows the database tests start from. Queries here and in tests aren't checked at compile time,
+//! so they stay out of the offline query cache.
+
+use serde_json::json;
+use sqlx::PgPool;
+use sqlx::types::Uuid;
+
+use crate::models::User;
+use crate::organization::Organization;
+
+pub const TRON_ADDRESS: &str = "TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY";
+pub const USDT_CONTRACT: &str = "TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t";
+
+/// The user `owner@example.com`, with the organization they own.
+pub struct Owner {
+    pub user_id: Uuid,
+    pub organization_id: Uuid,
+}
+
+pub async fn owner(db: &PgPool) -> Owner {
+    let user = User::create("owner@example.com", "hash", db).await.unwrap();
+    let organization_id = Organization::default_for_user(user.id, db).await.unwrap();
+    Owner { user_id: user.id, organization_id }
+}
+
+/// A tron wallet of the owner's organization.
+pub async fn wallet(owner: &Owner, is_active: bool, db: &PgPool) -> Uuid {
+    sqlx::query_scalar("INSERT INTO wallets (data, is_active, user_id, organization_id) VALUES ($1, $2, $3, $4) RETURNING id")
+        .bind(json!({"address": TRON_ADDRESS}))
+        .bind(is_active)
+        .bind(owner.user_id)
+        .bind(owner.organization_id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+/// An active donation of 100 USDT titled `title`.
+pub async fn donation(owner: &Owner, title: &str, wallet_id: Option<Uuid>, db: &PgPool) -> Uuid {
+    sqlx::query_scalar(
+        "
+        INSERT INTO donations (amount, title, token, wallet_id, user_id, organization_id)
+        VALUES (100, $1, 'USDT', $2, $3, $4) RETURNING id
+        ",
+    )
+        .bind(title)
+        .bind(wallet_id)
+        .bind(owner.user_id)
+        .bind(owner.organization_id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
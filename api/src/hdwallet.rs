@@ -1,36 +1,170 @@
-use std::env;
-use reqwest::Client;
-use serde::Deserialize;
-
-#[derive(Deserialize)]
-struct ResponseBody {
-    private_key: String,
-    addresses: Addresses,
+use std::str::FromStr;
+use bip32::{ChildNumber, DerivationPath, XPrv, XPub};
+use bip39::Mnemonic;
+use k256::{PublicKey, SecretKey};
+use rand::rngs::OsRng;
+
+use crate::chain::Chain;
+use crate::tron;
+
+/// Receive addresses are derived on the external chain (`<xpub>/0/<index>`) of the
+/// registered account-level key, e.g. `m/44'/195'/0'` for TRON.
+const EXTERNAL_CHAIN: u32 = 0;
+
+/// Generates a new wallet in-process, returning `(hex private key, address)`, or `None`
+/// for chains without a single address per key.
+pub fn gen_wallet(chain: Chain) -> Option<(String, String)> {
+    let secret_key = SecretKey::random(&mut OsRng);
+    let address = chain.address_from_public_key(&secret_key.public_key())?;
+
+    Some((hex::encode(secret_key.to_bytes()), address))
+}
+
+pub fn parse_xpub(xpub: &str) -> Result<XPub, String> {
+    XPub::from_str(xpub.trim()).map_err(|_| "invalid extended public key".to_string())
+}
+
+/// Derives the receive address at `index`. Only public derivation is used, so the
+/// server never needs the matching private keys.
+pub fn derive_address(xpub: &str, index: u32) -> Result<String, String> {
+    let child = parse_xpub(xpub)?
+        .derive_child(ChildNumber::new(EXTERNAL_CHAIN, false).map_err(|e| e.to_string())?)
+        .and_then(|chain| chain.derive_child(ChildNumber::new(index, false)?))
+        .map_err(|e| format!("Failed derive address {}: {}", index, e))?;
+
+    Ok(tron::address_from_public_key(&PublicKey::from(child.public_key())))
+}
+
+/// The BIP44 path of the first receive address, used when an import names no path.
+pub fn default_path(chain: Chain) -> Option<&'static str> {
+    match chain {
+        Chain::Tron => Some("m/44'/195'/0'/0/0"),
+        Chain::Ethereum | Chain::Bsc => Some("m/44'/60'/0'/0/0"),
+        Chain::Bitcoin => None,
+    }
+}
+
+pub fn secret_key_from_mnemonic(phrase: &str, passphrase: &str, path: &str) -> Result<SecretKey, String> {
+    let mnemonic = Mnemonic::parse_normalized(phrase.trim())
+        .map_err(|e| format!("invalid mnemonic: {}", e))?;
+    let path = DerivationPath::from_str(path.trim())
+        .map_err(|_| format!("invalid derivation path: {}", path))?;
+
+    let xprv = XPrv::derive_from_path(mnemonic.to_seed(passphrase), &path)
+        .map_err(|e| format!("Failed derive key: {}", e))?;
+
+    Ok(SecretKey::from(xprv.private_key().as_nonzero_scalar()))
 }
 
-#[derive(Deserialize)]
-struct Addresses {
-    p2pkh: String,
+/// Accepts a 32 byte hex private key, with or without a `0x` prefix.
+pub fn parse_private_key(private_key: &str) -> Result<SecretKey, String> {
+    let private_key = private_key.trim();
+    let bytes = hex::decode(private_key.strip_prefix("0x").unwrap_or(private_key))
+        .map_err(|_| "private key must be hex encoded".to_string())?;
+    if bytes.len() != 32 {
+        return Err("private key must be 32 bytes".to_string());
+    }
+
+    SecretKey::from_slice(&bytes).map_err(|_| "invalid private key".to_string())
 }
 
-pub async fn gen_wallet(client: &Client) -> Result<(String, String), String> {
-    let request_body = serde_json::json!({
-        "symbol": "TRX"
-    });
-
-    let response = client
-        .post(
-            env::var("GENERATE_WALLET_URL")
-                .unwrap_or("http://localhost:8001/generate".to_string())
-        )
-        .json(&request_body)
-        .send()
-        .await
-        .map_err(|err_msg| format!("Failed get response: {}", err_msg))?;
-
-    let response_body: ResponseBody = response.json()
-        .await
-        .map_err(|err_msg| format!("Failed read response body: {}", err_msg))?;
-
-    return Ok((response_body.private_key, response_body.addresses.p2pkh));
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bip32::Prefix;
+
+    // BIP32 test vector 1
+    const SEED_HEX: &str = "000102030405060708090a0b0c0d0e0f";
+    const M_0H_XPUB: &str = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+    const M_0H_1_XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+
+    fn xpub_at(seed: &[u8], path: &str) -> XPub {
+        XPrv::derive_from_path(seed, &DerivationPath::from_str(path).unwrap())
+            .unwrap()
+            .public_key()
+    }
+
+    #[test]
+    fn bip32_vector_private_derivation() {
+        let seed = hex::decode(SEED_HEX).unwrap();
+        assert_eq!(xpub_at(&seed, "m/0'").to_string(Prefix::XPUB), M_0H_XPUB);
+        assert_eq!(xpub_at(&seed, "m/0'/1").to_string(Prefix::XPUB), M_0H_1_XPUB);
+    }
+
+    #[test]
+    fn bip32_vector_public_derivation() {
+        let child = parse_xpub(M_0H_XPUB).unwrap()
+            .derive_child(ChildNumber::new(1, false).unwrap())
+            .unwrap();
+        assert_eq!(child.to_string(Prefix::XPUB), M_0H_1_XPUB);
+    }
+
+    #[test]
+    fn bip44_tron_addresses() {
+        let mnemonic = Mnemonic::parse_normalized(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        ).unwrap();
+        let seed = mnemonic.to_seed("");
+        let account_xpub = xpub_at(&seed, "m/44'/195'/0'").to_string(Prefix::XPUB);
+
+        assert_eq!(derive_address(&account_xpub, 0).unwrap(), "TUEZSdKsoDHQMeZwihtdoBiN46zxhGWYdH");
+
+        // public derivation from the account key matches private derivation of the full path
+        for index in 0..3 {
+            let path = format!("m/44'/195'/0'/0/{}", index);
+            let expected = tron::address_from_public_key(
+                &PublicKey::from(xpub_at(&seed, &path).public_key())
+            );
+            assert_eq!(derive_address(&account_xpub, index).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn imports_from_mnemonic() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let secret_key = secret_key_from_mnemonic(phrase, "", default_path(Chain::Tron).unwrap()).unwrap();
+        assert_eq!(tron::address_from_public_key(&secret_key.public_key()), "TUEZSdKsoDHQMeZwihtdoBiN46zxhGWYdH");
+
+        let secret_key = secret_key_from_mnemonic(phrase, "", default_path(Chain::Ethereum).unwrap()).unwrap();
+        assert_eq!(
+            Chain::Ethereum.address_from_public_key(&secret_key.public_key()).unwrap(),
+            "0x9858EfFD232B4033E47d90003D41EC34EcaEda94",
+        );
+
+        assert!(secret_key_from_mnemonic("abandon abandon", "", "m/44'/195'/0'/0/0").is_err());
+        assert!(secret_key_from_mnemonic(phrase, "", "m/44'/x").is_err());
+    }
+
+    #[test]
+    fn imports_private_key() {
+        let private_key = "0xda146374a75310b9666e834ee4ad0866d6f4035967bfc76217c5a495fff9f0d0";
+        let secret_key = parse_private_key(private_key).unwrap();
+        assert_eq!(tron::address_from_public_key(&secret_key.public_key()), "TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY");
+
+        assert!(parse_private_key("da14").is_err());
+        assert!(parse_private_key(&"00".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn generated_wallets_are_consistent() {
+        for chain in [Chain::Tron, Chain::Ethereum, Chain::Bsc] {
+            let (private_key, address) = gen_wallet(chain).unwrap();
+            let secret_key = parse_private_key(&private_key).unwrap();
+            assert_eq!(chain.address_from_public_key(&secret_key.public_key()).unwrap(), address);
+            assert_eq!(chain.validate_address(&address), Ok(()));
+        }
+        assert!(gen_wallet(Chain::Bitcoin).is_none());
+    }
+
+    #[test]
+    fn derivation_is_deterministic() {
+        assert_eq!(derive_address(M_0H_XPUB, 7).unwrap(), derive_address(M_0H_XPUB, 7).unwrap());
+        assert_ne!(derive_address(M_0H_XPUB, 7).unwrap(), derive_address(M_0H_XPUB, 8).unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_xpub() {
+        assert!(parse_xpub("xpub123").is_err());
+        assert!(derive_address(M_0H_XPUB, 1 << 31).is_err());
+    }
+}
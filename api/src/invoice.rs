@@ -0,0 +1,958 @@
+use std::fmt;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Error, PgConnection, PgPool};
+use sqlx::types::{Decimal, Uuid};
+
+use crate::chain::Chain;
+use crate::message::DonorMessage;
+use crate::notification::{self, Notification};
+use crate::template::RenderedMessage;
+use crate::webhook::Deposit;
+
+const DEFAULT_EXPIRES_IN: i64 = 30 * 60;
+const MIN_EXPIRES_IN: i64 = 60;
+const MAX_EXPIRES_IN: i64 = 7 * 24 * 60 * 60;
+const MAX_REFERENCE_LEN: usize = 100;
+/// Unique amounts step by a micro unit, or the token's smallest unit if that is coarser.
+const OFFSET_DECIMALS: u32 = 6;
+const MAX_OFFSET_STEPS: i64 = 999;
+/// How long after expiring an invoice still claims late deposits of its exact amount.
+const LATE_PAYMENT_HOURS: i32 = 24;
+const UNIQUE_AMOUNT_ATTEMPTS: usize = 3;
+/// Invoices opened by anonymous donor messages expire sooner and are capped, so that nobody
+/// can hold a wallet's unique amounts by posting messages.
+pub const CLIENT_EXPIRES_IN: i64 = 15 * 60;
+pub const MAX_OPEN_CLIENT_INVOICES: i64 = 50;
+pub const MAX_OPEN_INVOICES_PER_CLIENT: i64 = 3;
+const DUPLICATE_CODE: &str = "23505";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    Pending,
+    Paid,
+    Underpaid,
+    Overpaid,
+    Expired,
+}
+
+impl InvoiceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvoiceStatus::Pending => "pending",
+            InvoiceStatus::Paid => "paid",
+            InvoiceStatus::Underpaid => "underpaid",
+            InvoiceStatus::Overpaid => "overpaid",
+            InvoiceStatus::Expired => "expired",
+        }
+    }
+
+    /// The status of an invoice for `amount` once `received` was paid towards it.
+    pub fn for_received(amount: Decimal, received: Decimal) -> InvoiceStatus {
+        match received {
+            received if received <= Decimal::ZERO => InvoiceStatus::Pending,
+            received if received < amount => InvoiceStatus::Underpaid,
+            received if received == amount => InvoiceStatus::Paid,
+            _ => InvoiceStatus::Overpaid,
+        }
+    }
+}
+
+impl fmt::Display for InvoiceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for InvoiceStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "pending" => Ok(InvoiceStatus::Pending),
+            "paid" => Ok(InvoiceStatus::Paid),
+            "underpaid" => Ok(InvoiceStatus::Underpaid),
+            "overpaid" => Ok(InvoiceStatus::Overpaid),
+            "expired" => Ok(InvoiceStatus::Expired),
+            _ => Err(format!("unknown invoice status: {}", value)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonInvoiceIn {
+    pub amount: Decimal,
+    /// Defaults to the donation's token, or the chain's first token the collector tracks.
+    pub token: Option<String>,
+    /// Seconds until the invoice expires, 30 minutes unless given.
+    pub expires_in: Option<i64>,
+    /// The payer's order or cart id, echoed back as is.
+    pub reference: Option<String>,
+    /// Offsets the amount by a few of the token's smallest units so that no other open invoice
+    /// on the wallet expects the same amount, which lets deposits be told apart.
+    #[serde(default)]
+    pub unique_amount: bool,
+    /// The hashed address of the anonymous client whose donor message opened the invoice. Such
+    /// invoices only claim deposits of their exact amount.
+    #[serde(skip)]
+    pub client_key: Option<String>,
+}
+
+impl JsonInvoiceIn {
+    /// Checks the invoice against the donation's chain, returning the token it is paid in.
+    pub fn validate(&mut self, chain: Chain, donation_token: Option<&str>) -> Result<String, String> {
+        let token = self.token.as_deref().or(donation_token).or_else(|| chain.tracked_tokens().next())
+            .ok_or_else(|| format!("No {} token is tracked", chain.as_str()))?
+            .to_uppercase();
+        let token_info = chain.token(Some(&token))
+            .ok_or_else(|| format!("Unsupported {} token: {}", chain.as_str(), token))?;
+        if token_info.contract.is_none() {
+            let tracked: Vec<&str> = chain.tracked_tokens().collect();
+            return Err(format!("{} deposits are not tracked, use {}", token, tracked.join(" or ")));
+        }
+        if self.amount <= Decimal::ZERO {
+            return Err("amount must be positive".to_string());
+        }
+        if self.amount.normalize().scale() > token_info.decimals {
+            return Err(format!("amount has more than {} decimals", token_info.decimals));
+        }
+
+        let expires_in = self.expires_in.unwrap_or(DEFAULT_EXPIRES_IN);
+        if !(MIN_EXPIRES_IN..=MAX_EXPIRES_IN).contains(&expires_in) {
+            return Err(format!("expires_in must be between {} and {} seconds", MIN_EXPIRES_IN, MAX_EXPIRES_IN));
+        }
+        self.reference = self.reference.as_ref().map(|reference| reference.trim().to_string())
+            .filter(|reference| !reference.is_empty());
+        if self.reference.as_ref().is_some_and(|reference| reference.len() > MAX_REFERENCE_LEN) {
+            return Err("reference is too long".to_string());
+        }
+
+        Ok(token)
+    }
+
+    pub fn expires_at(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now + Duration::seconds(self.expires_in.unwrap_or(DEFAULT_EXPIRES_IN))
+    }
+}
+
+#[derive(Serialize)]
+pub struct Invoice {
+    pub id: Uuid,
+    pub public_id: String,
+    pub donation_id: Uuid,
+    pub wallet_id: Uuid,
+    pub chain: String,
+    pub token: String,
+    pub address: String,
+    /// What the payer is asked to send, including `amount_offset`.
+    pub amount: Decimal,
+    pub amount_offset: Decimal,
+    pub unique_amount: bool,
+    pub received: Decimal,
+    pub reference: Option<String>,
+    pub status: String,
+    pub expires_at: DateTime<Utc>,
+    pub paid_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a checkout page polls: the invoice without the donation and wallet behind it.
+#[derive(Serialize)]
+pub struct JsonPublicInvoice {
+    pub id: String,
+    pub status: String,
+    pub chain: String,
+    pub token: String,
+    pub address: String,
+    pub amount: Decimal,
+    pub received: Decimal,
+    pub payment_uri: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub paid_at: Option<DateTime<Utc>>,
+    /// The donation's receipt template rendered for this payment, once paid.
+    pub receipt: Option<RenderedMessage>,
+}
+
+impl From<Invoice> for JsonPublicInvoice {
+    fn from(invoice: Invoice) -> Self {
+        let payment_uri = invoice.payment_uri(None).ok();
+
+        JsonPublicInvoice {
+            id: invoice.public_id,
+            status: invoice.status,
+            chain: invoice.chain,
+            token: invoice.token,
+            address: invoice.address,
+            amount: invoice.amount,
+            received: invoice.received,
+            payment_uri,
+            expires_at: invoice.expires_at,
+            paid_at: invoice.paid_at,
+            receipt: None,
+        }
+    }
+}
+
+impl Invoice {
+    /// Pending invoices past their expiry read as expired before the scheduler gets to them,
+    /// and amounts lose the zeros the column pads them with.
+    fn current(mut self, now: DateTime<Utc>) -> Invoice {
+        if self.status == InvoiceStatus::Pending.as_str() && self.expires_at <= now {
+            self.status = InvoiceStatus::Expired.as_str().to_string();
+        }
+        self.amount = self.amount.normalize();
+        self.amount_offset = self.amount_offset.normalize();
+        self.received = self.received.normalize();
+
+        self
+    }
+
+    /// What is left to pay, while the invoice can still be paid.
+    pub fn outstanding(&self) -> Option<Decimal> {
+        let open = self.status == InvoiceStatus::Pending.as_str() || self.status == InvoiceStatus::Underpaid.as_str();
+        (open && self.received < self.amount).then(|| self.amount - self.received)
+    }
+
+    /// The payment URI for what is left to pay, or just the address once nothing is.
+    pub fn payment_uri(&self, memo: Option<&str>) -> Result<String, String> {
+        let chain = Chain::try_from(self.chain.as_str())?;
+        chain.payment_uri(&self.address, Some(&self.token), self.outstanding(), memo)
+    }
+
+    /// Amounts a new unique invoice on a wallet can't expect in a token: those open invoices
+    /// were created for (which `invoices_unique_amount_idx` holds them to) and still expect,
+    /// and those of invoices that expired recently enough for a late payment to show up.
+    pub async fn taken_amounts(wallet_id: Uuid, token: &str, db: &PgPool) -> Result<Vec<Decimal>, Error> {
+        sqlx::query!(
+            r#"
+            SELECT UNNEST(ARRAY[amount, amount - received]) AS "amount!"
+            FROM invoices
+            WHERE wallet_id = $1 AND token = $2
+              AND (
+                status IN ('pending', 'underpaid')
+                OR (status = 'expired' AND expires_at > CURRENT_TIMESTAMP - make_interval(hours => $3))
+              )
+            "#,
+            wallet_id,
+            token,
+            LATE_PAYMENT_HOURS,
+        )
+            .fetch_all(db)
+            .await
+            .map(|rows| rows.into_iter().map(|row| row.amount).collect())
+    }
+
+    /// Creates an invoice offset by the smallest amount no other invoice on the wallet takes,
+    /// trying again when a concurrent one got there first. `None` once no offset is free.
+    pub async fn create_unique(
+        donation_id: Uuid, wallet_id: Uuid, chain: Chain, token: &str, j_invoice: &JsonInvoiceIn, db: &PgPool,
+    ) -> Result<Option<Invoice>, Error> {
+        let decimals = chain.token(Some(token)).map_or(0, |token| token.decimals);
+        for _ in 0..UNIQUE_AMOUNT_ATTEMPTS {
+            let taken = Invoice::taken_amounts(wallet_id, token, db).await?;
+            let Some(offset) = unique_offset(j_invoice.amount, decimals, &taken) else {
+                break;
+            };
+            match Invoice::create(donation_id, wallet_id, chain, token, j_invoice, offset, db).await {
+                // another invoice took the same amount meanwhile
+                Err(Error::Database(db_err)) if db_err.code().as_deref() == Some(DUPLICATE_CODE) => continue,
+                result => return result.map(Some),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// How many invoices opened by donor messages are still open on the donation, overall and for
+    /// the one client.
+    pub async fn open_client_counts(donation_id: Uuid, client_key: &str, db: &PgPool) -> Result<(i64, i64), Error> {
+        let counts = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "total!", COUNT(*) FILTER (WHERE client_key = $2) AS "client!"
+            FROM invoices
+            WHERE donation_id = $1 AND client_key IS NOT NULL
+              AND status IN ('pending', 'underpaid') AND expires_at > CURRENT_TIMESTAMP
+            "#,
+            donation_id,
+            client_key
+        )
+            .fetch_one(db)
+            .await?;
+
+        Ok((counts.total, counts.client))
+    }
+
+    /// Creates an invoice for `j_invoice.amount` plus `amount_offset`, paid to the wallet's address.
+    pub async fn create(
+        donation_id: Uuid, wallet_id: Uuid, chain: Chain, token: &str, j_invoice: &JsonInvoiceIn,
+        amount_offset: Decimal, db: &PgPool,
+    ) -> Result<Invoice, Error> {
+        sqlx::query_as!(
+            Invoice,
+            "
+            INSERT INTO invoices (
+              donation_id, wallet_id, chain, token, address, amount, amount_offset, unique_amount, reference,
+              expires_at, client_key
+            )
+            SELECT $1, $2, $3, $4, data->>'address', $5::DECIMAL + $6::DECIMAL, $6, $7, $8, $9, $10
+            FROM wallets WHERE id = $2
+            RETURNING id, public_id, donation_id, wallet_id, chain, token, address, amount, amount_offset, unique_amount, received, reference,
+                      status, expires_at, paid_at, created_at
+            ",
+            donation_id,
+            wallet_id,
+            chain.as_str(),
+            token,
+            j_invoice.amount,
+            amount_offset,
+            j_invoice.unique_amount,
+            j_invoice.reference,
+            j_invoice.expires_at(Utc::now()),
+            j_invoice.client_key,
+        )
+            .fetch_one(db)
+            .await
+            .map(|invoice| invoice.current(Utc::now()))
+    }
+
+    pub async fn get(id: Uuid, donation_id: Uuid, db: &PgPool) -> Result<Invoice, Error> {
+        sqlx::query_as!(
+            Invoice,
+            "
+            SELECT id, public_id, donation_id, wallet_id, chain, token, address, amount, amount_offset, unique_amount, received, reference,
+                   status, expires_at, paid_at, created_at
+            FROM invoices
+            WHERE id = $1 AND donation_id = $2
+            ",
+            id,
+            donation_id
+        )
+            .fetch_one(db)
+            .await
+            .map(|invoice| invoice.current(Utc::now()))
+    }
+
+    pub async fn get_public(public_id: &str, db: &PgPool) -> Result<Invoice, Error> {
+        sqlx::query_as!(
+            Invoice,
+            "
+            SELECT id, public_id, donation_id, wallet_id, chain, token, address, amount, amount_offset, unique_amount, received, reference,
+                   status, expires_at, paid_at, created_at
+            FROM invoices
+            WHERE public_id = $1
+            ",
+            public_id
+        )
+            .fetch_one(db)
+            .await
+            .map(|invoice| invoice.current(Utc::now()))
+    }
+
+    pub async fn list(donation_id: Uuid, db: &PgPool) -> Result<Vec<Invoice>, Error> {
+        let now = Utc::now();
+
+        sqlx::query_as!(
+            Invoice,
+            "
+            SELECT id, public_id, donation_id, wallet_id, chain, token, address, amount, amount_offset, unique_amount, received, reference,
+                   status, expires_at, paid_at, created_at
+            FROM invoices
+            WHERE donation_id = $1
+            ORDER BY created_at DESC
+            ",
+            donation_id
+        )
+            .fetch_all(db)
+            .await
+            .map(|invoices| invoices.into_iter().map(|invoice| invoice.current(now)).collect())
+    }
+
+    /// Counts a confirmed deposit towards the open invoice on its wallet and token that it
+    /// pays: the one expecting exactly the deposited amount, or the only open one. Deposits
+    /// that several invoices could claim, or that arrive after their invoice expired, are kept
+    /// as unmatched for the owner to resolve. Returns the updated invoice, or `None` when no
+    /// invoice took the deposit or it was handled before.
+    pub async fn apply_deposit(deposit: &Deposit, db: &PgPool) -> Result<Option<Invoice>, Error> {
+        let Some(token) = deposit.token() else { return Ok(None) };
+        let mut tx = db.begin().await?;
+
+        let seen = sqlx::query!(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM invoice_payments WHERE transaction_id = $1)
+                OR EXISTS (SELECT 1 FROM unmatched_deposits WHERE transaction_id = $1) AS "seen!"
+            "#,
+            deposit.transaction_id
+        )
+            .fetch_one(&mut *tx)
+            .await?
+            .seen;
+        if seen {
+            return Ok(None);
+        }
+
+        let open = sqlx::query!(
+            r#"
+            SELECT id, amount - received AS "outstanding!", client_key IS NOT NULL AS "exact_only!"
+            FROM invoices
+            WHERE wallet_id = $1 AND token = $2
+              AND status IN ('pending', 'underpaid') AND expires_at > CURRENT_TIMESTAMP
+            ORDER BY created_at
+            FOR UPDATE
+            "#,
+            deposit.wallet_id,
+            token,
+        )
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|row| OpenInvoice { id: row.id, outstanding: row.outstanding, exact_only: row.exact_only })
+            .collect::<Vec<_>>();
+
+        let matched = match match_deposit(&open, deposit.amount) {
+            DepositMatch::Invoice(id) => Some(id),
+            DepositMatch::Unmatched(reason, candidates) => {
+                UnmatchedDeposit::create(deposit, token, reason, &candidates, &mut tx).await?;
+                None
+            },
+            DepositMatch::None => {
+                let late = Invoice::expired_expecting(deposit.wallet_id, token, deposit.amount, &mut tx).await?;
+                if !late.is_empty() {
+                    UnmatchedDeposit::create(deposit, token, UnmatchedReason::Expired, &late, &mut tx).await?;
+                }
+                None
+            },
+        };
+        let invoice = match matched {
+            Some(id) => Some(Invoice::credit(id, &deposit.transaction_id, deposit.amount, &mut tx).await?),
+            None => None,
+        };
+
+        tx.commit().await?;
+
+        Ok(invoice)
+    }
+
+    /// Invoices that recently expired still expecting exactly `amount`.
+    async fn expired_expecting(
+        wallet_id: Uuid, token: &str, amount: Decimal, conn: &mut PgConnection
+    ) -> Result<Vec<Uuid>, Error> {
+        sqlx::query!(
+            "
+            SELECT id FROM invoices
+            WHERE wallet_id = $1 AND token = $2 AND amount - received = $3
+              AND status IN ('pending', 'underpaid', 'expired')
+              AND expires_at <= CURRENT_TIMESTAMP
+              AND expires_at > CURRENT_TIMESTAMP - make_interval(hours => $4)
+            ",
+            wallet_id,
+            token,
+            amount,
+            LATE_PAYMENT_HOURS,
+        )
+            .fetch_all(conn)
+            .await
+            .map(|rows| rows.into_iter().map(|row| row.id).collect())
+    }
+
+    /// Records a payment towards an invoice, moves it to the status that leaves it in and ties
+    /// its donor message, if any, to the payment.
+    async fn credit(id: Uuid, transaction_id: &str, amount: Decimal, conn: &mut PgConnection) -> Result<Invoice, Error> {
+        sqlx::query!(
+            "INSERT INTO invoice_payments (transaction_id, invoice_id, amount) VALUES ($1, $2, $3)",
+            transaction_id,
+            id,
+            amount,
+        )
+            .execute(&mut *conn)
+            .await?;
+
+        let invoice = sqlx::query!("SELECT amount, received FROM invoices WHERE id = $1 FOR UPDATE", id)
+            .fetch_one(&mut *conn)
+            .await?;
+        let received = invoice.received + amount;
+        let status = InvoiceStatus::for_received(invoice.amount, received);
+
+        let invoice = sqlx::query_as!(
+            Invoice,
+            "
+            UPDATE invoices
+            SET received = $2, status = $3,
+                paid_at = CASE WHEN $4 THEN CURRENT_TIMESTAMP ELSE paid_at END
+            WHERE id = $1
+            RETURNING id, public_id, donation_id, wallet_id, chain, token, address, amount, amount_offset, unique_amount,
+                      received, reference, status, expires_at, paid_at, created_at
+            ",
+            id,
+            received,
+            status.as_str(),
+            matches!(status, InvoiceStatus::Paid | InvoiceStatus::Overpaid),
+        )
+            .fetch_one(&mut *conn)
+            .await?;
+        DonorMessage::attach(id, transaction_id, received, conn).await?;
+
+        Ok(invoice.current(Utc::now()))
+    }
+
+    /// Expires pending invoices nothing was paid towards, returning their ids. Underpaid ones
+    /// keep their status, so the shortfall stays visible.
+    pub async fn expire_due(db: &PgPool) -> Result<Vec<Uuid>, Error> {
+        sqlx::query!(
+            "
+            UPDATE invoices SET status = 'expired'
+            WHERE status = 'pending' AND expires_at <= CURRENT_TIMESTAMP
+            RETURNING id
+            "
+        )
+            .fetch_all(db)
+            .await
+            .map(|rows| rows.into_iter().map(|row| row.id).collect())
+    }
+}
+
+/// An invoice still waiting for deposits on the wallet.
+pub struct OpenInvoice {
+    pub id: Uuid,
+    pub outstanding: Decimal,
+    /// Takes only deposits of its outstanding amount, never a stray one.
+    pub exact_only: bool,
+}
+
+/// Where a deposit goes among the open invoices on its wallet.
+#[derive(Debug, PartialEq)]
+pub enum DepositMatch {
+    Invoice(Uuid),
+    Unmatched(UnmatchedReason, Vec<Uuid>),
+    /// No invoice is open, so the deposit is a plain donation.
+    None,
+}
+
+pub fn match_deposit(open: &[OpenInvoice], amount: Decimal) -> DepositMatch {
+    let exact: Vec<Uuid> = open.iter()
+        .filter(|invoice| invoice.outstanding == amount)
+        .map(|invoice| invoice.id)
+        .collect();
+    let candidates: Vec<Uuid> = open.iter()
+        .filter(|invoice| !invoice.exact_only)
+        .map(|invoice| invoice.id)
+        .collect();
+
+    match exact.len() {
+        1 => DepositMatch::Invoice(exact[0]),
+        0 => match candidates[..] {
+            [] => DepositMatch::None,
+            [id] => DepositMatch::Invoice(id),
+            _ => DepositMatch::Unmatched(UnmatchedReason::NoExactMatch, candidates),
+        },
+        _ => DepositMatch::Unmatched(UnmatchedReason::Ambiguous, exact),
+    }
+}
+
+/// The smallest offset, in micro units or the token's smallest unit if that is coarser, that
+/// makes `amount` differ from every amount in `taken`.
+pub fn unique_offset(amount: Decimal, decimals: u32, taken: &[Decimal]) -> Option<Decimal> {
+    let step = Decimal::new(1, decimals.min(OFFSET_DECIMALS));
+    (1..=MAX_OFFSET_STEPS)
+        .map(|steps| step * Decimal::from(steps))
+        .find(|offset| !taken.contains(&(amount + offset)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmatchedReason {
+    /// Several open invoices expect exactly the deposited amount.
+    Ambiguous,
+    /// Several invoices are open and none expects the deposited amount.
+    NoExactMatch,
+    /// Only invoices that already expired expect the deposited amount.
+    Expired,
+}
+
+impl UnmatchedReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnmatchedReason::Ambiguous => "ambiguous",
+            UnmatchedReason::NoExactMatch => "no_exact_match",
+            UnmatchedReason::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonUnmatchedResolution {
+    /// The invoice the deposit pays; `null` dismisses it as a plain donation.
+    pub invoice_id: Option<Uuid>,
+}
+
+/// A deposit no invoice could be picked for on its own.
+#[derive(Serialize)]
+pub struct UnmatchedDeposit {
+    pub id: Uuid,
+    pub transaction_id: String,
+    pub wallet_id: Uuid,
+    pub token: String,
+    pub amount: Decimal,
+    pub reason: String,
+    pub candidate_invoice_ids: Vec<Uuid>,
+    pub resolved_invoice_id: Option<Uuid>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UnmatchedDeposit {
+    /// Keeps the deposit and tells the wallet's owner it needs their attention.
+    async fn create(
+        deposit: &Deposit, token: &str, reason: UnmatchedReason, candidates: &[Uuid], conn: &mut PgConnection
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "
+            INSERT INTO unmatched_deposits (transaction_id, wallet_id, token, amount, reason, candidate_invoice_ids)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ",
+            deposit.transaction_id,
+            deposit.wallet_id,
+            token,
+            deposit.amount,
+            reason.as_str(),
+            candidates,
+        )
+            .execute(&mut *conn)
+            .await?;
+
+        let owner = sqlx::query!("SELECT user_id FROM wallets WHERE id = $1", deposit.wallet_id)
+            .fetch_one(&mut *conn)
+            .await?
+            .user_id;
+        Notification::create(
+            owner,
+            notification::DEPOSIT_UNMATCHED,
+            &format!(
+                "A deposit of {} {} to {} couldn't be matched to an invoice",
+                deposit.amount.normalize(), token, deposit.address,
+            ),
+            json!({
+                "wallet_id": deposit.wallet_id,
+                "transaction_id": deposit.transaction_id,
+                "amount": deposit.amount,
+                "token": token,
+                "reason": reason,
+                "candidate_invoice_ids": candidates,
+            }),
+            &mut *conn,
+        ).await?;
+
+        Ok(())
+    }
+
+    pub async fn list(user_id: Uuid, unresolved_only: bool, db: &PgPool) -> Result<Vec<UnmatchedDeposit>, Error> {
+        sqlx::query_as!(
+            UnmatchedDeposit,
+            "
+            SELECT id, transaction_id, wallet_id, token, amount, reason, candidate_invoice_ids, resolved_invoice_id,
+                   resolved_at, created_at
+            FROM unmatched_deposits
+            WHERE wallet_id IN (
+                SELECT id FROM wallets
+                WHERE organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
+              )
+              AND (NOT $2 OR resolved_at IS NULL)
+            ORDER BY created_at DESC
+            ",
+            user_id,
+            unresolved_only,
+        )
+            .fetch_all(db)
+            .await
+            .map(|deposits| deposits.into_iter().map(UnmatchedDeposit::normalized).collect())
+    }
+
+    pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<UnmatchedDeposit, Error> {
+        sqlx::query_as!(
+            UnmatchedDeposit,
+            "
+            SELECT id, transaction_id, wallet_id, token, amount, reason, candidate_invoice_ids, resolved_invoice_id,
+                   resolved_at, created_at
+            FROM unmatched_deposits
+            WHERE id = $1
+              AND wallet_id IN (
+                SELECT id FROM wallets
+                WHERE organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $2)
+              )
+            ",
+            id,
+            user_id,
+        )
+            .fetch_one(db)
+            .await
+            .map(UnmatchedDeposit::normalized)
+    }
+
+    /// Credits the deposit to `invoice_id`, which must be on its wallet and token, or dismisses
+    /// it when that's `None`. `RowNotFound` means it was resolved already or the invoice doesn't fit.
+    pub async fn resolve(id: Uuid, invoice_id: Option<Uuid>, db: &PgPool) -> Result<UnmatchedDeposit, Error> {
+        let mut tx = db.begin().await?;
+
+        let deposit = sqlx::query_as!(
+            UnmatchedDeposit,
+            "
+            UPDATE unmatched_deposits
+            SET resolved_invoice_id = $2, resolved_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND resolved_at IS NULL
+              AND ($2::uuid IS NULL OR EXISTS (
+                SELECT 1 FROM invoices
+                WHERE invoices.id = $2
+                  AND invoices.wallet_id = unmatched_deposits.wallet_id
+                  AND invoices.token = unmatched_deposits.token
+              ))
+            RETURNING id, transaction_id, wallet_id, token, amount, reason, candidate_invoice_ids, resolved_invoice_id,
+                      resolved_at, created_at
+            ",
+            id,
+            invoice_id,
+        )
+            .fetch_one(&mut *tx)
+            .await?;
+        if let Some(invoice_id) = invoice_id {
+            Invoice::credit(invoice_id, &deposit.transaction_id, deposit.amount, &mut tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(deposit.normalized())
+    }
+
+    fn normalized(mut self) -> UnmatchedDeposit {
+        self.amount = self.amount.normalize();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn json_invoice(amount: Decimal) -> JsonInvoiceIn {
+        JsonInvoiceIn { amount, token: None, expires_in: None, reference: None, unique_amount: false, client_key: None }
+    }
+
+    #[test]
+    fn status_follows_received_amount() {
+        let amount = Decimal::new(10, 0);
+        assert_eq!(InvoiceStatus::for_received(amount, Decimal::ZERO), InvoiceStatus::Pending);
+        assert_eq!(InvoiceStatus::for_received(amount, Decimal::new(95, 1)), InvoiceStatus::Underpaid);
+        assert_eq!(InvoiceStatus::for_received(amount, Decimal::new(1000, 2)), InvoiceStatus::Paid);
+        assert_eq!(InvoiceStatus::for_received(amount, Decimal::new(1001, 2)), InvoiceStatus::Overpaid);
+    }
+
+    #[test]
+    fn statuses_round_trip() {
+        for status in [
+            InvoiceStatus::Pending, InvoiceStatus::Paid, InvoiceStatus::Underpaid,
+            InvoiceStatus::Overpaid, InvoiceStatus::Expired,
+        ] {
+            assert_eq!(InvoiceStatus::try_from(status.as_str()), Ok(status));
+            assert_eq!(serde_json::to_value(status).unwrap(), status.as_str());
+        }
+        assert!(InvoiceStatus::try_from("refunded").is_err());
+    }
+
+    #[test]
+    fn validates_invoices() {
+        assert_eq!(json_invoice(Decimal::new(5, 0)).validate(Chain::Tron, None), Ok("USDT".to_string()));
+        assert!(json_invoice(Decimal::new(5, 0)).validate(Chain::Tron, Some("TRX")).is_err());
+        assert!(JsonInvoiceIn { token: Some("trx".to_string()), ..json_invoice(Decimal::ONE) }.validate(Chain::Tron, None).is_err());
+        assert_eq!(json_invoice(Decimal::new(5, 0)).validate(Chain::Tron, Some("USDT")), Ok("USDT".to_string()));
+        assert_eq!(
+            JsonInvoiceIn { token: Some("usdc".to_string()), ..json_invoice(Decimal::new(5, 0)) }
+                .validate(Chain::Ethereum, Some("USDT")),
+            Ok("USDC".to_string()),
+        );
+
+        assert!(json_invoice(Decimal::new(5, 0)).validate(Chain::Bitcoin, Some("USDT")).is_err());
+        assert!(json_invoice(Decimal::ZERO).validate(Chain::Tron, None).is_err());
+        assert!(json_invoice(Decimal::new(1, 7)).validate(Chain::Tron, None).is_err());
+        assert!(JsonInvoiceIn { expires_in: Some(10), ..json_invoice(Decimal::ONE) }.validate(Chain::Tron, None).is_err());
+        assert!(
+            JsonInvoiceIn { reference: Some("x".repeat(101)), ..json_invoice(Decimal::ONE) }
+                .validate(Chain::Tron, None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn expired_and_paid_invoices_have_nothing_outstanding() {
+        let now = Utc::now();
+        let invoice = Invoice {
+            id: Uuid::nil(),
+            public_id: "0".repeat(32),
+            donation_id: Uuid::nil(),
+            wallet_id: Uuid::nil(),
+            chain: "tron".to_string(),
+            token: "USDT".to_string(),
+            address: "TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY".to_string(),
+            amount: Decimal::new(10_000_000_000_000_000, 15),
+            amount_offset: Decimal::ZERO,
+            unique_amount: false,
+            received: Decimal::new(4, 0),
+            reference: None,
+            status: "underpaid".to_string(),
+            expires_at: now + Duration::minutes(5),
+            paid_at: None,
+            created_at: now,
+        }.current(now);
+        assert_eq!(invoice.amount.to_string(), "10");
+        assert_eq!(invoice.outstanding(), Some(Decimal::new(6, 0)));
+        assert!(invoice.payment_uri(None).unwrap().ends_with("&amount=6"));
+
+        let paid = Invoice { status: "paid".to_string(), received: Decimal::new(10, 0), ..invoice };
+        assert_eq!(paid.outstanding(), None);
+
+        let expired = Invoice { status: "pending".to_string(), expires_at: now, ..paid }.current(now);
+        assert_eq!(expired.status, "expired");
+        assert_eq!(expired.outstanding(), None);
+    }
+
+    #[test]
+    fn matches_deposits_by_exact_amount() {
+        let (a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let amount = |value: i64| Decimal::new(value, 6);
+        let invoice = |id, outstanding| OpenInvoice { id, outstanding, exact_only: false };
+        let open = [invoice(a, amount(10_000_001)), invoice(b, amount(10_000_002)), invoice(c, amount(10_000_002))];
+
+        assert_eq!(match_deposit(&open, amount(10_000_001)), DepositMatch::Invoice(a));
+        assert_eq!(
+            match_deposit(&open, amount(10_000_002)),
+            DepositMatch::Unmatched(UnmatchedReason::Ambiguous, vec![b, c]),
+        );
+        assert_eq!(
+            match_deposit(&open, amount(10_000_000)),
+            DepositMatch::Unmatched(UnmatchedReason::NoExactMatch, vec![a, b, c]),
+        );
+        // with a single open invoice there is nobody else to pay
+        assert_eq!(match_deposit(&open[..1], amount(5_000_000)), DepositMatch::Invoice(a));
+        assert_eq!(match_deposit(&[], amount(5_000_000)), DepositMatch::None);
+    }
+
+    #[test]
+    fn client_invoices_take_only_their_exact_amount() {
+        let (a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let amount = |value: i64| Decimal::new(value, 6);
+        let client = |id, outstanding| OpenInvoice { id, outstanding, exact_only: true };
+        let open = [client(a, amount(10_000_001)), client(b, amount(20_000_001))];
+
+        assert_eq!(match_deposit(&open, amount(20_000_001)), DepositMatch::Invoice(b));
+        // a stray deposit is a plain donation however many messages wait for payment
+        assert_eq!(match_deposit(&open, amount(10_000_000)), DepositMatch::None);
+        assert_eq!(match_deposit(&open[..1], amount(10_000_000)), DepositMatch::None);
+
+        let owned = OpenInvoice { id: c, outstanding: amount(30_000_000), exact_only: false };
+        assert_eq!(match_deposit(&[client(a, amount(10_000_001)), owned], amount(5_000_000)), DepositMatch::Invoice(c));
+    }
+
+    #[test]
+    fn picks_the_smallest_free_offset() {
+        let amount = Decimal::new(10, 0);
+        assert_eq!(unique_offset(amount, 6, &[]), Some(Decimal::new(1, 6)));
+        assert_eq!(
+            unique_offset(amount, 6, &[Decimal::new(10_000_001, 6), Decimal::new(10_000_002, 6)]),
+            Some(Decimal::new(3, 6)),
+        );
+        // finer tokens still step by a micro unit, so wallets show the difference
+        assert_eq!(unique_offset(amount, 18, &[]), Some(Decimal::new(1, 6)));
+        assert_eq!(unique_offset(amount, 8, &[]), Some(Decimal::new(1, 6)));
+
+        let taken: Vec<Decimal> = (1..=MAX_OFFSET_STEPS).map(|steps| amount + Decimal::new(steps, 6)).collect();
+        assert_eq!(unique_offset(amount, 6, &taken), None);
+    }
+
+    /// A donation on a fresh tron wallet, returning the ids of both.
+    async fn donation_wallet(db: &PgPool) -> (Uuid, Uuid) {
+        let owner = fixtures::owner(db).await;
+        let wallet_id = fixtures::wallet(&owner, true, db).await;
+        (fixtures::donation(&owner, "Roof", Some(wallet_id), db).await, wallet_id)
+    }
+
+    fn deposit(wallet_id: Uuid, transaction_id: &str, amount: Decimal, token_contract: &str) -> Deposit {
+        Deposit {
+            wallet_id,
+            chain: Chain::Tron,
+            address: fixtures::TRON_ADDRESS.to_string(),
+            transaction_id: transaction_id.to_string(),
+            amount,
+            token_contract: Some(token_contract.to_string()),
+            confirmed: true,
+        }
+    }
+
+    #[sqlx::test]
+    async fn only_known_contracts_pay_invoices(db: PgPool) {
+        let (donation_id, wallet_id) = donation_wallet(&db).await;
+        let invoice = Invoice::create(donation_id, wallet_id, Chain::Tron, "USDT", &json_invoice(Decimal::new(5, 0)), Decimal::ZERO, &db)
+            .await
+            .unwrap();
+
+        // a look-alike contract reporting itself as USDT
+        let spoofed = deposit(wallet_id, "tx1", Decimal::new(5, 0), fixtures::TRON_ADDRESS);
+        assert!(Invoice::apply_deposit(&spoofed, &db).await.unwrap().is_none());
+
+        let usdt = deposit(wallet_id, "tx2", Decimal::new(5, 0), fixtures::USDT_CONTRACT);
+        let paid = Invoice::apply_deposit(&usdt, &db).await.unwrap().unwrap();
+        assert_eq!((paid.id, paid.status.as_str()), (invoice.id, InvoiceStatus::Paid.as_str()));
+    }
+
+    #[sqlx::test]
+    async fn client_invoices_leave_stray_deposits_alone(db: PgPool) {
+        let (donation_id, wallet_id) = donation_wallet(&db).await;
+        let j_invoice = |client: &str| JsonInvoiceIn {
+            unique_amount: true,
+            client_key: Some(client.to_string()),
+            ..json_invoice(Decimal::new(10, 0))
+        };
+        for client in ["a", "a", "b"] {
+            Invoice::create_unique(donation_id, wallet_id, Chain::Tron, "USDT", &j_invoice(client), &db).await.unwrap().unwrap();
+        }
+        assert_eq!(Invoice::open_client_counts(donation_id, "a", &db).await.unwrap(), (3, 2));
+
+        let usdt = fixtures::USDT_CONTRACT;
+        assert!(Invoice::apply_deposit(&deposit(wallet_id, "tx1", Decimal::new(10, 0), usdt), &db).await.unwrap().is_none());
+        let unmatched: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM unmatched_deposits").fetch_one(&db).await.unwrap();
+        assert_eq!(unmatched, 0);
+
+        let paid = Invoice::apply_deposit(&deposit(wallet_id, "tx2", Decimal::new(10_000_002, 6), usdt), &db).await.unwrap().unwrap();
+        assert_eq!(paid.status, InvoiceStatus::Paid.as_str());
+        assert_eq!(Invoice::open_client_counts(donation_id, "a", &db).await.unwrap(), (2, 1));
+    }
+
+    #[sqlx::test]
+    async fn unique_amounts_avoid_created_outstanding_and_late_amounts(db: PgPool) {
+        let (donation_id, wallet_id) = donation_wallet(&db).await;
+        let j_invoice = JsonInvoiceIn { unique_amount: true, ..json_invoice(Decimal::new(10, 0)) };
+        let create = || Invoice::create_unique(donation_id, wallet_id, Chain::Tron, "USDT", &j_invoice, &db);
+
+        let first = create().await.unwrap().unwrap();
+        assert_eq!(first.amount, Decimal::new(10_000_001, 6));
+
+        // underpaid, it still holds 10.000001 in the unique index while expecting 5.000001
+        let usdt = fixtures::USDT_CONTRACT;
+        Invoice::apply_deposit(&deposit(wallet_id, "tx1", Decimal::new(5, 0), usdt), &db).await.unwrap().unwrap();
+        let second = create().await.unwrap().unwrap();
+        assert_eq!(second.amount, Decimal::new(10_000_002, 6));
+
+        // a recently expired invoice still claims late payments of its amount
+        sqlx::query("UPDATE invoices SET status = 'expired', expires_at = CURRENT_TIMESTAMP - INTERVAL '1 hour' WHERE id = $1")
+            .bind(second.id)
+            .execute(&db)
+            .await
+            .unwrap();
+        let third = create().await.unwrap().unwrap();
+        assert_eq!(third.amount, Decimal::new(10_000_003, 6));
+
+        let taken = Invoice::taken_amounts(wallet_id, "USDT", &db).await.unwrap();
+        for amount in [first.amount, Decimal::new(5_000_001, 6), second.amount, third.amount] {
+            assert!(taken.contains(&amount), "{} should be taken", amount);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, PgConnection, PgPool};
+use sqlx::types::Uuid;
+
+use crate::amqp;
+use crate::invoice::Invoice;
+use crate::models::{Donation, Wallet};
+use crate::state::AppState;
+use crate::stream::StreamEvent;
+use crate::webhook::{Publication, WebhookEvent};
+
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DonationStatus {
+    Draft,
+    Scheduled,
+    Active,
+    Paused,
+    Completed,
+    Archived,
+}
+
+impl DonationStatus {
+    /// Statuses in which a donation is shown on its public page.
+    pub const PUBLIC: [DonationStatus; 4] = [
+        DonationStatus::Scheduled, DonationStatus::Active, DonationStatus::Paused, DonationStatus::Completed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DonationStatus::Draft => "draft",
+            DonationStatus::Scheduled => "scheduled",
+            DonationStatus::Active => "active",
+            DonationStatus::Paused => "paused",
+            DonationStatus::Completed => "completed",
+            DonationStatus::Archived => "archived",
+        }
+    }
+
+    /// Statuses a donation in this one may be moved to.
+    pub fn next(&self) -> &'static [DonationStatus] {
+        use DonationStatus::*;
+
+        match self {
+            Draft => &[Scheduled, Active, Archived],
+            Scheduled => &[Draft, Active, Archived],
+            Active => &[Paused, Completed, Archived],
+            Paused => &[Active, Completed, Archived],
+            Completed => &[Archived],
+            Archived => &[],
+        }
+    }
+
+    pub fn check_transition(&self, next: DonationStatus) -> Result<(), String> {
+        if self.next().contains(&next) {
+            return Ok(());
+        }
+
+        Err(match self.next() {
+            [] => format!("{} donations can't change status", self),
+            allowed => format!(
+                "a {} donation can't become {}, only {}",
+                self,
+                next,
+                allowed.iter().map(|status| status.as_str()).collect::<Vec<_>>().join(", "),
+            ),
+        })
+    }
+}
+
+impl fmt::Display for DonationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for DonationStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "draft" => Ok(DonationStatus::Draft),
+            "scheduled" => Ok(DonationStatus::Scheduled),
+            "active" => Ok(DonationStatus::Active),
+            "paused" => Ok(DonationStatus::Paused),
+            "completed" => Ok(DonationStatus::Completed),
+            "archived" => Ok(DonationStatus::Archived),
+            _ => Err(format!("unknown status: {}", value)),
+        }
+    }
+}
+
+/// The status a new donation starts in: the requested one, or `scheduled` or `active`
+/// depending on `starts_at`.
+pub fn initial_status(
+    requested: Option<&str>, starts_at: Option<DateTime<Utc>>, now: DateTime<Utc>
+) -> Result<DonationStatus, String> {
+    let starts_later = starts_at.is_some_and(|starts_at| starts_at > now);
+    let requested = requested.map(DonationStatus::try_from).transpose()?;
+
+    match requested {
+        None if starts_later => Ok(DonationStatus::Scheduled),
+        None => Ok(DonationStatus::Active),
+        Some(DonationStatus::Draft) => Ok(DonationStatus::Draft),
+        Some(DonationStatus::Scheduled) if starts_later => Ok(DonationStatus::Scheduled),
+        Some(DonationStatus::Scheduled) => Err("a scheduled donation needs a starts_at in the future".to_string()),
+        Some(DonationStatus::Active) if starts_later => Err("starts_at is in the future, use scheduled".to_string()),
+        Some(DonationStatus::Active) => Ok(DonationStatus::Active),
+        Some(status) => Err(format!("a new donation can't be {}, only draft, scheduled or active", status)),
+    }
+}
+
+pub fn check_window(starts_at: Option<DateTime<Utc>>, ends_at: Option<DateTime<Utc>>) -> Result<(), String> {
+    match (starts_at, ends_at) {
+        (Some(starts_at), Some(ends_at)) if ends_at <= starts_at => Err("ends_at must be after starts_at".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Starts and ends donations as their windows open and close, expires unpaid invoices and
+/// prunes old stream events, until the process exits.
+pub async fn run_scheduler(state: Arc<AppState>) {
+    loop {
+        if let Err(err) = advance(&state.db).await {
+            error!("Failed advance donation schedules: {}", err);
+        }
+
+        tokio::time::sleep(SCHEDULE_INTERVAL).await;
+    }
+}
+
+async fn advance(db: &PgPool) -> Result<(), Error> {
+    // statuses and wallets change together, so a failure leaves the whole batch for the next tick
+    let mut tx = db.begin().await?;
+    // ended first, so a donation whose whole window passed never becomes active
+    let ended = Donation::end_due(&mut *tx).await?;
+    for (_, wallet_id) in &ended {
+        if let Some(wallet_id) = wallet_id {
+            release_wallet_in(*wallet_id, &mut tx).await?;
+        }
+    }
+
+    let started = Donation::start_due(&mut *tx).await?;
+    for (_, wallet_id) in &started {
+        if let Some(wallet_id) = wallet_id {
+            switch_wallet(*wallet_id, DonationStatus::Active, &mut tx).await?;
+        }
+    }
+    tx.commit().await?;
+    for (id, _) in &ended {
+        info!("Donation {} ended", id);
+    }
+    for (id, _) in &started {
+        info!("Donation {} started", id);
+    }
+
+    // also retries whatever earlier ticks and requests failed to send
+    sync_collector(db).await?;
+
+    for id in Invoice::expire_due(db).await? {
+        info!("Invoice {} expired", id);
+    }
+
+    let pruned = StreamEvent::prune(db).await?;
+    if pruned > 0 {
+        info!("Pruned {} stream events", pruned);
+    }
+
+    Ok(())
+}
+
+/// Starts or stops watching the wallet of a donation that moved to `status`, as part of
+/// the transaction changing it; the collector hears about it from `sync_collector`.
+pub async fn switch_wallet(wallet_id: Uuid, status: DonationStatus, conn: &mut PgConnection) -> Result<(), Error> {
+    match status {
+        DonationStatus::Active => Wallet::set_active(wallet_id, true, &mut *conn).await.map(|_| ()),
+        _ => release_wallet_in(wallet_id, conn).await,
+    }
+}
+
+/// Stops watching a wallet once no active donation uses it.
+pub async fn release_wallet(wallet_id: Uuid, db: &PgPool) -> Result<(), Error> {
+    let mut tx = db.begin().await?;
+    release_wallet_in(wallet_id, &mut tx).await?;
+    tx.commit().await?;
+
+    sync_collector(db).await
+}
+
+async fn release_wallet_in(wallet_id: Uuid, conn: &mut PgConnection) -> Result<(), Error> {
+    let Some(wallet) = Wallet::deactivate_unused(wallet_id, &mut *conn).await? else {
+        return Ok(());
+    };
+
+    if let Some(organization_id) = wallet.organization_id {
+        let donation_ids = Donation::ids_using_wallet(wallet_id, &mut *conn).await?;
+        WebhookEvent::publish(&Publication::wallet_deactivated(&wallet, organization_id, donation_ids), &mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Tells the collector about every wallet switched since it last heard, leaving the ones
+/// rabbitmq couldn't take queued for the next call or scheduler tick.
+pub async fn sync_collector(db: &PgPool) -> Result<(), Error> {
+    for wallet in Wallet::unsynced(db).await? {
+        let (id, is_active) = (wallet.id, wallet.is_active);
+        let msg: amqp::Message = wallet.into();
+        if let Err(err_msg) = msg.try_send().await {
+            error!("Failed tell the collector about wallet {}: {}", id, err_msg);
+            return Ok(());
+        }
+
+        Wallet::mark_synced(id, is_active, db).await?;
+        info!("Wallet {} is now {}", id, if is_active { "active" } else { "inactive" });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::fixtures;
+
+    #[test]
+    fn allows_listed_transitions_only() {
+        assert!(DonationStatus::Draft.check_transition(DonationStatus::Active).is_ok());
+        assert!(DonationStatus::Active.check_transition(DonationStatus::Paused).is_ok());
+        assert!(DonationStatus::Paused.check_transition(DonationStatus::Active).is_ok());
+        assert!(DonationStatus::Completed.check_transition(DonationStatus::Archived).is_ok());
+
+        assert_eq!(
+            DonationStatus::Completed.check_transition(DonationStatus::Active),
+            Err("a completed donation can't become active, only archived".to_string()),
+        );
+        assert_eq!(
+            DonationStatus::Archived.check_transition(DonationStatus::Draft),
+            Err("archived donations can't change status".to_string()),
+        );
+        assert!(DonationStatus::Active.check_transition(DonationStatus::Active).is_err());
+        assert!(DonationStatus::Draft.check_transition(DonationStatus::Paused).is_err());
+    }
+
+    #[test]
+    fn statuses_round_trip() {
+        for status in [
+            DonationStatus::Draft, DonationStatus::Scheduled, DonationStatus::Active,
+            DonationStatus::Paused, DonationStatus::Completed, DonationStatus::Archived,
+        ] {
+            assert_eq!(DonationStatus::try_from(status.as_str()), Ok(status));
+            assert_eq!(serde_json::to_value(status).unwrap(), status.as_str());
+        }
+        assert!(DonationStatus::try_from("deleted").is_err());
+    }
+
+    #[test]
+    fn picks_initial_status_from_starts_at() {
+        let now = Utc::now();
+        let later = Some(now + Duration::hours(1));
+        let earlier = Some(now - Duration::hours(1));
+
+        assert_eq!(initial_status(None, None, now), Ok(DonationStatus::Active));
+        assert_eq!(initial_status(None, earlier, now), Ok(DonationStatus::Active));
+        assert_eq!(initial_status(None, later, now), Ok(DonationStatus::Scheduled));
+        assert_eq!(initial_status(Some("draft"), later, now), Ok(DonationStatus::Draft));
+        assert_eq!(initial_status(Some("scheduled"), later, now), Ok(DonationStatus::Scheduled));
+        assert!(initial_status(Some("scheduled"), None, now).is_err());
+        assert!(initial_status(Some("active"), later, now).is_err());
+        assert!(initial_status(Some("completed"), None, now).is_err());
+        assert!(initial_status(Some("live"), None, now).is_err());
+    }
+
+    #[test]
+    fn checks_window() {
+        let now = Utc::now();
+        assert!(check_window(None, None).is_ok());
+        assert!(check_window(Some(now), None).is_ok());
+        assert!(check_window(None, Some(now)).is_ok());
+        assert!(check_window(Some(now), Some(now + Duration::days(1))).is_ok());
+        assert!(check_window(Some(now), Some(now)).is_err());
+    }
+
+    /// A scheduled donation whose window already opened, on an inactive wallet.
+    async fn due_donation(db: &PgPool) -> (Uuid, Uuid) {
+        let owner = fixtures::owner(db).await;
+        let wallet_id = fixtures::wallet(&owner, false, db).await;
+        let donation_id = fixtures::donation(&owner, "Roof", Some(wallet_id), db).await;
+        sqlx::query("UPDATE donations SET status = 'scheduled', starts_at = CURRENT_TIMESTAMP - INTERVAL '1 minute' WHERE id = $1")
+            .bind(donation_id)
+            .execute(db)
+            .await
+            .unwrap();
+        (donation_id, wallet_id)
+    }
+
+    async fn state(donation_id: Uuid, wallet_id: Uuid, db: &PgPool) -> (String, bool, usize) {
+        let status = sqlx::query_scalar("SELECT status FROM donations WHERE id = $1")
+            .bind(donation_id).fetch_one(db).await.unwrap();
+        let is_active = sqlx::query_scalar("SELECT is_active FROM wallets WHERE id = $1")
+            .bind(wallet_id).fetch_one(db).await.unwrap();
+        (status, is_active, Wallet::unsynced(db).await.unwrap().len())
+    }
+
+    #[sqlx::test]
+    async fn statuses_and_wallets_switch_together(db: PgPool) {
+        let (donation_id, wallet_id) = due_donation(&db).await;
+
+        // a failure before commit leaves both for the next tick
+        let mut tx = db.begin().await.unwrap();
+        assert_eq!(Donation::start_due(&mut *tx).await.unwrap(), vec![(donation_id, Some(wallet_id))]);
+        switch_wallet(wallet_id, DonationStatus::Active, &mut tx).await.unwrap();
+        tx.rollback().await.unwrap();
+        assert_eq!(state(donation_id, wallet_id, &db).await, ("scheduled".to_string(), false, 0));
+
+        let mut tx = db.begin().await.unwrap();
+        Donation::start_due(&mut *tx).await.unwrap();
+        switch_wallet(wallet_id, DonationStatus::Active, &mut tx).await.unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(state(donation_id, wallet_id, &db).await, ("active".to_string(), true, 1));
+
+        // only cleared once the collector heard the wallet's current state
+        Wallet::mark_synced(wallet_id, false, &db).await.unwrap();
+        assert_eq!(Wallet::unsynced(&db).await.unwrap().len(), 1);
+        Wallet::mark_synced(wallet_id, true, &db).await.unwrap();
+        assert!(Wallet::unsynced(&db).await.unwrap().is_empty());
+
+        // still in use by the active donation
+        let mut tx = db.begin().await.unwrap();
+        switch_wallet(wallet_id, DonationStatus::Paused, &mut tx).await.unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(state(donation_id, wallet_id, &db).await, ("active".to_string(), true, 0));
+    }
+}
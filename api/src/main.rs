@@ -1,15 +1,23 @@
 use std::env;
-use axum::{routing::{get, post, put, delete}, Router, extract::{Path, State, Json}, http::StatusCode, response::IntoResponse, http, Extension};
+use std::str::FromStr;
+use axum::{routing::{get, post, put, delete}, Router, extract::{Path, State, Json}, http::{HeaderMap, StatusCode}, response::IntoResponse, http, Extension};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use axum::extract::Request;
+use std::time::{Duration, Instant};
+use axum::extract::{ConnectInfo, Query, Request};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::middleware::{self, Next};
 use axum::response::Response;
-use log::{error, info};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use futures_util::{Stream, StreamExt};
+use log::{error, info, warn};
 use reqwest::{Client};
 use serde_json::json;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::types::Decimal;
 use tower_http::trace::TraceLayer;
 use uuid::Uuid;
+use validator::Validate;
 
 mod error;
 mod models;
@@ -17,10 +25,68 @@ mod state;
 mod amqp;
 mod hdwallet;
 mod transaction;
+mod auth;
+mod cli;
+mod organization;
+mod crypto;
+mod chain;
+mod tron;
+mod ssrf;
+mod pool;
+mod events;
+mod webhook;
+mod webhook_endpoint;
+mod notification;
+mod lifecycle;
+mod public;
+mod qr;
+mod invoice;
+mod message;
+mod alert;
+mod channel;
+mod template;
+mod stream;
+#[cfg(test)]
+mod fixtures;
 
+use crate::alert::{AlertEvaluation, AlertRule, JsonAlertRuleIn};
+use crate::auth::{hash_token, AuthConfig, JsonCredentials, JsonPasswordChange, JsonRefreshToken, JsonTokenPair};
+use crate::chain::Chain;
+use crate::channel::{ChannelClient, ChannelConfig, JsonChannelCode, JsonChannelIn, NotificationChannel};
+use crate::crypto::MasterKey;
 use crate::error::AppError;
-use crate::models::{Donation, JsonDonation, JsonWallet, User, Wallet, WalletData};
+use crate::invoice::{
+    Invoice, InvoiceStatus, JsonInvoiceIn, JsonPublicInvoice, JsonUnmatchedResolution, UnmatchedDeposit, CLIENT_EXPIRES_IN,
+    MAX_OPEN_CLIENT_INVOICES, MAX_OPEN_INVOICES_PER_CLIENT,
+};
+use crate::lifecycle::DonationStatus;
+use crate::message::{DonorMessage, JsonDonorMessageIn, JsonDonorMessageModeration, JsonPublicMessage, JsonSubmittedMessage};
+use crate::models::{
+    ApiKey, ApiKeyScope, Derivation, Donation, DonationProgress, ExtendedKey, JsonApiKey, JsonDonation, JsonDonationStatus,
+    JsonExtendedKey, JsonWallet, JsonWalletImport, JsonWebhookSecretRotation, RefreshToken, User, Wallet, WalletData, WebhookSecret,
+};
+use crate::organization::{
+    Invite, JsonInvite, JsonInviteToken, JsonMember, JsonMemberRole, JsonOrganization, Member, Organization, Role,
+};
+use crate::pool::WalletPool;
+use crate::public::{JsonPublicDeposit, JsonPublicDonation, RateLimiter};
+use crate::qr::QrQuery;
 use crate::state::AppState;
+use crate::stream::{StreamScope, StreamToken, Streamed};
+use crate::template::{
+    JsonReceiptTemplate, JsonTemplateIn, JsonTemplatePreview, MessageTemplate, ReceiptTemplate, RenderedMessage,
+    TemplateFormat, TemplateValues,
+};
+use crate::notification::Notification;
+use crate::ssrf::UrlPolicy;
+use crate::transaction::{AddressStats, StatsScope};
+use crate::webhook::{EventFilter, Publication, WebhookClient, WebhookEvent};
+use crate::webhook_endpoint::{EventType, JsonWebhookEndpointIn, WebhookEndpoint};
+
+const DUPLICATE_CODE: &str = "23505";
+const PUBLIC_DEPOSITS_LIMIT: usize = 10;
+const PUBLIC_CACHE_CONTROL: &str = "public, max-age=30";
+const STREAM_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
 
 #[tokio::main]
 async fn main() {
@@ -29,10 +95,53 @@ async fn main() {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let db = models::get_connection(&db_url).await.expect("Failed to connect to database");
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(err_msg) = cli::run(&args, &db).await {
+            error!("{}", err_msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let master_key = MasterKey::from_env("MASTER_KEY")
+        .expect("Invalid MASTER_KEY")
+        .expect("MASTER_KEY or MASTER_KEY_FILE must be set");
+    check_wallet_keys(&master_key, &db).await;
+    let purged = WalletPool::purge_foreign_keys(master_key.id(), &db).await.expect("Failed to check wallet pool");
+    if purged > 0 {
+        info!("Dropped {} pooled wallets sealed with another master key", purged);
+    }
+
     let http_client = Client::new();
 
-    let app_state = AppState { db, http_client };
-    let routes = create_routes(Arc::new(app_state));
+    let auth = AuthConfig {
+        secret: env::var("AUTH_SECRET").expect("AUTH_SECRET must be set").into_bytes(),
+        access_token_ttl: chrono::Duration::seconds(env_number::<u32>("ACCESS_TOKEN_TTL_SECONDS", 15 * 60).into()),
+        refresh_token_ttl: chrono::Duration::seconds(env_number::<u32>("REFRESH_TOKEN_TTL_SECONDS", 30 * 24 * 60 * 60).into()),
+    };
+
+    let wallet_pool = WalletPool::new(env_number::<u32>("WALLET_POOL_SIZE", 20).into());
+    let webhook_client = WebhookClient::new(UrlPolicy::from_env());
+    let channel_client = ChannelClient::from_env(UrlPolicy::from_env());
+    let public_rate_limit = RateLimiter::new(
+        env_number("PUBLIC_RATE_LIMIT_PER_MINUTE", 60),
+        Duration::from_secs(60),
+        ssrf::env_flag("TRUST_FORWARDED_FOR"),
+    );
+
+    let (events, _) = tokio::sync::broadcast::channel(stream::BUFFER);
+    let app_state = Arc::new(AppState {
+        db, http_client, auth, master_key, wallet_pool, webhook_client, channel_client, public_rate_limit, events,
+    });
+    tokio::spawn(pool::run_refill(app_state.clone()));
+    tokio::spawn(events::run_consumer(app_state.clone()));
+    tokio::spawn(webhook::run_delivery(app_state.clone()));
+    tokio::spawn(channel::run_delivery(app_state.clone()));
+    tokio::spawn(lifecycle::run_scheduler(app_state.clone()));
+    tokio::spawn(stream::run_listener(app_state.clone()));
+    tokio::spawn(serve_metrics(app_state.clone()));
+    let routes = create_routes(app_state);
 
     let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
     let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
@@ -42,10 +151,76 @@ async fn main() {
         .await
         .unwrap();
 
-    axum::serve(listener, routes.into_make_service()).await.unwrap();
+    axum::serve(listener, routes.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+}
+
+/// Refuses to serve while private keys are stored in plaintext or sealed with a key we don't hold.
+async fn check_wallet_keys(master_key: &MasterKey, db: &PgPool) {
+    let plaintext = Wallet::count_plaintext_keys(db).await.expect("Failed to check wallet keys");
+    if plaintext > 0 {
+        error!("Found {} wallets with plaintext private keys, run `api rotate-master-key` first", plaintext);
+        std::process::exit(1);
+    }
+
+    let foreign = Wallet::count_foreign_keys(master_key.id(), db).await.expect("Failed to check wallet keys");
+    if foreign > 0 {
+        error!(
+            "Found {} wallet keys sealed with a master key other than {}, run `api rotate-master-key` with OLD_MASTER_KEY",
+            foreign, master_key.id(),
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Reads a number from the environment, refusing to start on values that don't fit `T`
+/// (e.g. a negative count) instead of quietly wrapping or falling back to the default.
+fn env_number<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| panic!("{} must be a non-negative integer", name)),
+        Err(_) => default,
+    }
+}
+
+/// Serves `/metrics` on its own address, loopback unless `METRICS_HOST` says otherwise,
+/// so it is never reachable through the public api.
+async fn serve_metrics(state: Arc<AppState>) {
+    let host = env::var("METRICS_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+    let port = env::var("METRICS_PORT").unwrap_or_else(|_| "9100".to_string());
+    let bind_address = format!("{}:{}", host, port);
+    let listener = match tokio::net::TcpListener::bind(&bind_address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to bind metrics listener on {}: {}", bind_address, err);
+            return;
+        },
+    };
+    info!("Serving metrics on {}", bind_address);
+
+    let routes = Router::new().route("/metrics", get(metrics)).with_state(state);
+    if let Err(err) = axum::serve(listener, routes).await {
+        error!("Metrics listener failed: {}", err);
+    }
 }
 
 fn create_routes(state: Arc<AppState>) -> Router {
+    let public_routes = Router::new()
+        .route("/auth/register", post(register))
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .merge(
+            Router::new()
+                .route("/public/donations/:key", get(get_public_donation))
+                .route("/public/donations/:key/qr", get(get_public_donation_qr))
+                .route("/public/donations/:key/messages", post(submit_donor_message))
+                .route("/public/donations/:key/messages", get(list_public_donor_messages))
+                .route("/public/donations/:key/events/stream", get(stream_public_donation_events))
+                .route("/public/donations/:key/events/ws", get(stream_public_donation_events_ws))
+                .route("/public/invoices/:id", get(get_public_invoice))
+                .route("/public/invoices/:id/qr", get(get_public_invoice_qr))
+                .route_layer(middleware::from_fn_with_state(state.clone(), public_rate_limit))
+        );
+
     Router::new()
         .route("/donations", post(create_donation))
         .route("/donations", get(list_donation))
@@ -54,13 +229,91 @@ fn create_routes(state: Arc<AppState>) -> Router {
         .route("/donations/:id/transactions/sum", get(get_donation_transactions_sum))
         .route("/donations/:id", put(update_donation))
         .route("/donations/:id", delete(delete_donation))
+        .route("/donations/:id/status", post(update_donation_status))
+        .route("/donations/:id/qr", get(get_donation_qr))
+        .route("/donations/:id/invoices", post(create_invoice))
+        .route("/donations/:id/invoices", get(list_invoices))
+        .route("/donations/:id/invoices/:invoice_id", get(get_invoice))
+        .route("/donations/:id/invoices/:invoice_id/qr", get(get_invoice_qr))
+        .route("/donations/:id/messages", get(list_donor_messages))
+        .route("/donations/:id/messages/:message_id", put(moderate_donor_message))
+        .route("/donations/:id/messages/:message_id", delete(delete_donor_message))
+        .route("/donations/:id/events/stream", get(stream_donation_events))
+        .route("/donations/:id/events/ws", get(stream_donation_events_ws))
+        .route("/donations/:id/stream-token", get(get_stream_token))
+        .route("/donations/:id/stream-token", post(create_stream_token))
+        .route("/donations/:id/stream-token/rotate", post(rotate_stream_token))
+        .route("/donations/:id/receipt-template", put(set_receipt_template))
+        .route("/events/stream", get(stream_account_events))
+        .route("/events/ws", get(stream_account_events_ws))
+        .route("/unmatched-deposits", get(list_unmatched_deposits))
+        .route("/unmatched-deposits/:id/resolve", post(resolve_unmatched_deposit))
+        .route("/donations/:id/webhook-events", get(list_webhook_events))
+        .route("/donations/:id/webhook-events/:event_id", get(get_webhook_event))
+        .route("/donations/:id/webhook-events/:event_id/redeliver", post(redeliver_webhook_event))
+        .route("/donations/:id/webhook-ping", post(ping_webhook))
         .route("/wallets", post(create_wallet))
         .route("/wallets", get(list_wallet))
+        .route("/wallets/import", post(import_wallet))
         .route("/wallets/:id", get(get_wallet))
         .route("/wallets/:id", put(update_wallet))
         .route("/wallets/:id", delete(delete_wallet))
+        .route("/me", get(get_me))
+        .route("/me/webhook-secret", get(get_webhook_secret))
+        .route("/me/webhook-secret/rotate", post(rotate_webhook_secret))
+        .route("/webhook-endpoints", post(create_webhook_endpoint))
+        .route("/webhook-endpoints", get(list_webhook_endpoints))
+        .route("/webhook-endpoints/:id", get(get_webhook_endpoint))
+        .route("/webhook-endpoints/:id", put(update_webhook_endpoint))
+        .route("/webhook-endpoints/:id", delete(delete_webhook_endpoint))
+        .route("/webhook-endpoints/:id/events", get(list_webhook_endpoint_events))
+        .route("/webhook-endpoints/:id/events/:event_id/redeliver", post(redeliver_webhook_endpoint_event))
+        .route("/webhook-endpoints/:id/ping", post(ping_webhook_endpoint))
+        .route("/alert-rules", post(create_alert_rule))
+        .route("/alert-rules", get(list_alert_rules))
+        .route("/alert-rules/:id", get(get_alert_rule))
+        .route("/alert-rules/:id", put(update_alert_rule))
+        .route("/alert-rules/:id", delete(delete_alert_rule))
+        .route("/alert-rules/:id/evaluations", get(list_alert_evaluations))
+        .route("/notification-channels", post(create_notification_channel))
+        .route("/notification-channels", get(list_notification_channels))
+        .route("/notification-channels/:id", get(get_notification_channel))
+        .route("/notification-channels/:id", put(update_notification_channel))
+        .route("/notification-channels/:id", delete(delete_notification_channel))
+        .route("/notification-channels/:id/test", post(test_notification_channel))
+        .route("/notification-channels/:id/verification", post(send_notification_channel_code))
+        .route("/notification-channels/:id/verify", post(verify_notification_channel))
+        .route("/message-templates", post(create_message_template))
+        .route("/message-templates", get(list_message_templates))
+        .route("/message-templates/preview", post(preview_message_template))
+        .route("/message-templates/:id", get(get_message_template))
+        .route("/message-templates/:id", put(update_message_template))
+        .route("/message-templates/:id", delete(delete_message_template))
+        .route("/notifications", get(list_notifications))
+        .route("/notifications/:id/read", post(read_notification))
+        .route("/api-keys", post(create_api_key))
+        .route("/api-keys", get(list_api_keys))
+        .route("/api-keys/:id", delete(revoke_api_key))
+        .route("/auth/password", put(change_password))
+        .route("/extended-keys", post(create_extended_key))
+        .route("/extended-keys", get(list_extended_keys))
+        .route("/extended-keys/:id", get(get_extended_key))
+        .route("/extended-keys/:id/addresses", post(derive_extended_key_address))
+        .route("/organizations", post(create_organization))
+        .route("/organizations", get(list_organizations))
+        .route("/organizations/:id", get(get_organization))
+        .route("/organizations/:id", put(update_organization))
+        .route("/organizations/:id/members", get(list_members))
+        .route("/organizations/:id/members/:user_id", put(update_member))
+        .route("/organizations/:id/members/:user_id", delete(remove_member))
+        .route("/organizations/:id/invites", post(create_invite))
+        .route("/organizations/:id/invites", get(list_organization_invites))
+        .route("/organizations/:id/invites/:invite_id", delete(delete_invite))
+        .route("/invites", get(list_my_invites))
+        .route("/invites/:id/accept", post(accept_invite))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth))
+        .merge(public_routes)
         .layer(TraceLayer::new_for_http())
-        .layer(middleware::from_fn(auth))
         .with_state(state)
 }
 
@@ -69,21 +322,64 @@ async fn create_donation(
     Extension(user): Extension<User>,
     Json(j_in_donation): Json<JsonDonation>,
 ) -> Result<impl IntoResponse, AppError> {
-    let in_donation: Donation = j_in_donation.into();
+    let organization_id = resolve_organization(j_in_donation.organization_id.as_deref(), user.id, &state.db).await?;
+    let provision_wallet = j_in_donation.provision_wallet;
+    let status = lifecycle::initial_status(j_in_donation.status.as_deref(), j_in_donation.starts_at, chrono::Utc::now())
+        .map_err(AppError::InvalidInput)?;
+    if j_in_donation.ends_at.is_some_and(|ends_at| ends_at <= chrono::Utc::now()) {
+        return Err(AppError::InvalidInput("ends_at must be in the future".to_string()));
+    }
+    let mut in_donation = Donation::try_from(j_in_donation)?;
+    in_donation.status = status.as_str().to_string();
+    check_donation_fields(&in_donation)?;
+    let chain = check_donation_payment(&in_donation, organization_id, user.id, &state.db).await?;
+    if let Some(webhook) = &in_donation.webhook {
+        check_webhook_url(webhook, &state).await?;
+    }
+    if provision_wallet && (in_donation.wallet_id.is_some() || in_donation.extended_key_id.is_some()) {
+        return Err(AppError::InvalidInput(
+            "provision_wallet can not be combined with wallet_id or extended_key_id".to_string()
+        ));
+    }
 
-    let j_out_donation: JsonDonation = in_donation.create(user.id, &state.db).await?.into();
+    let mut tx = state.db.begin().await.map_err(AppError::DbError)?;
 
-    Ok((StatusCode::CREATED, Json(j_out_donation)))
-}
+    // each donation gets its own receive address, derived from the xpub or freshly generated,
+    // which is only watched once the donation is active
+    let is_active = status == DonationStatus::Active;
+    let new_wallet = match (in_donation.wallet_id, in_donation.extended_key_id) {
+        (None, Some(extended_key_id)) => Some(derive_wallet(extended_key_id, is_active, user.id, &mut tx).await?),
+        (None, None) if provision_wallet => {
+            let data = new_wallet_data(chain, &state, &mut tx).await?;
+            Some(Wallet::create(&mut *tx, chain, data, is_active, user.id, organization_id).await?)
+        },
+        _ => None,
+    };
+    if let Some(wallet) = &new_wallet {
+        in_donation.wallet_id = Some(wallet.id);
+    }
 
-async fn gen_wallet(http_client: &Client) -> Result<(String, String), AppError> {
-    let (private_key, address) = hdwallet::gen_wallet(http_client)
+    let j_out_donation: JsonDonation = in_donation.create(user.id, organization_id, &mut *tx)
         .await
-        .map_err(|err_msg| {
-            error!("Failed gen wallet: {}", err_msg);
-            return AppError::InternalServerError
-        })?;
-    Ok((private_key, address))
+        .map_err(map_donation_write)?
+        .into();
+    tx.commit().await.map_err(AppError::DbError)?;
+
+    if let Some(id) = j_out_donation.id.as_deref().and_then(|id| Uuid::parse_str(id).ok()) {
+        publish_event(Publication::for_donation(
+            EventType::DonationCreated, organization_id, id, json!(j_out_donation),
+        ), &state.db).await;
+    }
+
+    if let Some(wallet) = new_wallet {
+        if wallet.data.derivation.is_none() {
+            state.wallet_pool.request_refill();
+        }
+        let msg: amqp::Message = wallet.into();
+        msg.send().await;
+    }
+
+    Ok((StatusCode::CREATED, Json(j_out_donation)))
 }
 
 async fn get_donation(
@@ -91,13 +387,30 @@ async fn get_donation(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
 ) -> Result<impl IntoResponse, AppError> {
-    let j_wallet: JsonDonation = get_json_donation(&id_str, user.id, &state.db).await?;
-    Ok(Json(j_wallet))
+    let mut j_donation: JsonDonation = get_json_donation(&id_str, user.id, &state.db).await?;
+
+    // a donation without a wallet hasn't received anything yet
+    let stats = match &j_donation.wallet_id {
+        Some(wid) => {
+            let j_wallet = get_json_wallet(wid, user.id, &state.db).await?;
+            let wallet_data: WalletData = j_wallet.data.unwrap().into();
+            let chain = j_donation.chain.as_deref().and_then(|chain| Chain::try_from(chain).ok()).unwrap_or_default();
+            let scope = StatsScope::new(chain, j_donation.token.as_deref(), j_donation.created_at, j_donation.starts_at);
+            transaction::get_address_stats(&state.http_client, &wallet_data.address, &scope)
+                .await
+                .inspect_err(|err_msg| warn!("Failed get progress of donation {}: {}", id_str, err_msg))
+                .ok()
+        },
+        None => Some(AddressStats::default()),
+    };
+    j_donation.progress = stats.map(|stats| DonationProgress::new(j_donation.amount, stats));
+
+    Ok(Json(j_donation))
 }
 
 async fn get_json_donation(id_str: &str, user_id: Uuid, db: &PgPool) -> Result<JsonDonation, AppError> {
     let id = Uuid::parse_str(id_str).map_err(
-        |_| return AppError::InvalidInput("Invalid id".to_string())
+        |_| AppError::InvalidInput("Invalid id".to_string())
     )?;
 
     Ok(Donation::get(id, user_id, db)
@@ -129,6 +442,84 @@ async fn get_donation_transactions(
     Ok(Json(json!([])))
 }
 
+async fn list_webhook_events(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    Donation::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+
+    let filter = EventFilter { user_id: user.id, donation_id: Some(id), ..Default::default() };
+    Ok(Json(WebhookEvent::list(&filter, &state.db).await?))
+}
+
+async fn get_webhook_event(
+    Path((id_str, event_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let event_id = parse_id(&event_id_str)?;
+    Donation::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+
+    let filter = EventFilter { user_id: user.id, id: Some(event_id), donation_id: Some(id), ..Default::default() };
+    let event = WebhookEvent::get(&filter, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(event))
+}
+
+async fn redeliver_webhook_event(
+    Path((id_str, event_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let event_id = parse_id(&event_id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+
+    let filter = EventFilter { user_id: user.id, id: Some(event_id), donation_id: Some(id), ..Default::default() };
+    redeliver(filter, &state).await
+}
+
+/// Sends a `ping` event to the donation's webhook and returns how it went.
+async fn ping_webhook(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    let (url, owner_id) = donation_webhook(id, user.id, &state.db).await?;
+
+    let event_id = WebhookEvent::create_ping(Some(id), None, &state.db).await?;
+    let attempt = webhook::deliver_now(event_id, &url, owner_id, "failed", &state.webhook_client, &state.db).await?;
+    Ok((StatusCode::CREATED, Json(json!({"event_id": event_id, "attempt": attempt}))))
+}
+
+async fn redeliver(filter: EventFilter, state: &AppState) -> Result<Json<webhook::Attempt>, AppError> {
+    let event = WebhookEvent::get(&filter, &state.db).await.map_err(map_not_found)?;
+    let (url, owner_id) = match (event.endpoint_id, event.donation_id) {
+        (Some(endpoint_id), _) => {
+            let endpoint = WebhookEndpoint::get(endpoint_id, filter.user_id, &state.db).await.map_err(map_not_found)?;
+            (endpoint.url, filter.user_id)
+        },
+        (None, Some(donation_id)) => donation_webhook(donation_id, filter.user_id, &state.db).await?,
+        (None, None) => return Err(AppError::NotFound),
+    };
+
+    let attempt = webhook::deliver_now(event.id, &url, owner_id, &event.status, &state.webhook_client, &state.db).await?;
+    Ok(Json(attempt))
+}
+
+/// The webhook url of a donation, and the user whose secret signs it.
+async fn donation_webhook(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<(String, Uuid), AppError> {
+    let donation = Donation::get(id, user_id, db).await.map_err(map_not_found)?;
+    let url = donation.webhook
+        .ok_or_else(|| AppError::InvalidInput("Donation has no webhook".to_string()))?;
+
+    Ok((url, donation.user_id.unwrap_or(user_id)))
+}
+
 async fn get_donation_transactions_sum(
     Path(id_str): Path<String>,
     State(state): State<Arc<AppState>>,
@@ -156,29 +547,73 @@ async fn update_donation(
     Json(j_in_donation): Json<JsonDonation>,
 ) -> Result<impl IntoResponse, AppError> {
     let id = Uuid::parse_str(&id_str).map_err(
-        |_| return AppError::InvalidInput("Invalid id".to_string())
+        |_| AppError::InvalidInput("Invalid id".to_string())
     )?;
-    let in_donation: Donation = j_in_donation.into();
+    let organization_id = authorize_donation_write(id, user.id, &state.db).await?;
+    let in_donation = Donation::try_from(j_in_donation)?;
+    check_donation_fields(&in_donation)?;
+    check_donation_payment(&in_donation, organization_id, user.id, &state.db).await?;
+    if let Some(webhook) = &in_donation.webhook {
+        check_webhook_url(webhook, &state).await?;
+    }
     let j_out_donation: JsonDonation = in_donation.update(id, user.id, &state.db)
         .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => AppError::NotFound,
-            _ => AppError::DbError(e),
-        })?
+        .map_err(map_donation_write)?
         .into();
 
     Ok((StatusCode::OK, Json(j_out_donation)))
 }
 
+/// Moves a donation along its lifecycle, switching its wallet on or off to match.
+async fn update_donation_status(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_status): Json<JsonDonationStatus>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    let donation = Donation::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+    let current = DonationStatus::try_from(donation.status.as_str()).map_err(|err_msg| {
+        error!("Donation {} has an invalid status: {}", id, err_msg);
+        AppError::InternalServerError
+    })?;
+
+    let next = j_status.status;
+    current.check_transition(next).map_err(AppError::Conflict)?;
+    if next == DonationStatus::Scheduled && donation.starts_at.is_none_or(|starts_at| starts_at <= chrono::Utc::now()) {
+        return Err(AppError::Conflict("a scheduled donation needs a starts_at in the future".to_string()));
+    }
+
+    let mut tx = state.db.begin().await?;
+    let donation = Donation::set_status(id, current, next, &mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::Conflict("Donation status changed meanwhile, try again".to_string()),
+            _ => AppError::DbError(e),
+        })?;
+    if let Some(wallet_id) = donation.wallet_id {
+        lifecycle::switch_wallet(wallet_id, next, &mut tx).await?;
+    }
+    tx.commit().await?;
+    if let Err(err) = lifecycle::sync_collector(&state.db).await {
+        warn!("Failed tell the collector about donation {}: {}", id, err);
+    }
+
+    Ok(Json(JsonDonation::from(donation)))
+}
+
 async fn delete_donation(
     Path(id_str): Path<String>,
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
 ) -> Result<impl IntoResponse, AppError> {
     let id = Uuid::parse_str(&id_str).map_err(
-        |_| return AppError::InvalidInput("Invalid id".to_string())
+        |_| AppError::InvalidInput("Invalid id".to_string())
     )?;
 
+    authorize_donation_write(id, user.id, &state.db).await?;
+
     if Donation::delete(id, user.id, &state.db)
         .await
         .map_err(AppError::DbError)?
@@ -201,7 +636,11 @@ async fn list_donation(
     Ok(Json(j_wallets))
 }
 
-async fn auth(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+async fn auth(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
     let auth_header = req.headers()
         .get(http::header::AUTHORIZATION)
         .and_then(|header| header.to_str().ok());
@@ -212,161 +651,2092 @@ async fn auth(mut req: Request, next: Next) -> Result<Response, StatusCode> {
         return Err(StatusCode::UNAUTHORIZED);
     };
 
-    if let Some(current_user) = authorize_current_user(auth_header).await {
+    if let Some((current_user, scope)) = authorize_current_user(auth_header, &state).await {
+        if !scope.allows(req.method()) {
+            return Err(StatusCode::FORBIDDEN);
+        }
         // insert the current user into a request extension so the handler can
         // extract it
         req.extensions_mut().insert(current_user);
+        req.extensions_mut().insert(scope);
         Ok(next.run(req).await)
     } else {
         Err(StatusCode::UNAUTHORIZED)
     }
 }
 
-async fn authorize_current_user(auth_token: &str) -> Option<User> {
-    let user_id = match Uuid::parse_str(&auth_token) {
-        Ok(uuid) => uuid,
-        Err(_) => return None
+async fn public_rate_limit(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let client = state.public_rate_limit.client(req.headers(), peer);
+    state.public_rate_limit.check(client, Instant::now()).map_err(AppError::RateLimited)?;
+
+    Ok(next.run(req).await)
+}
+
+/// The page donors see, by slug or public id. Progress and deposits are left out while the
+/// transactions service can't be reached.
+async fn get_public_donation(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let donation = Donation::get_public(&key, &state.db).await.map_err(map_not_found)?;
+    let chain = Chain::try_from(donation.chain.as_str()).unwrap_or_default();
+    let address = match donation.wallet_id {
+        Some(wallet_id) => Some(Wallet::address(wallet_id, &state.db).await?),
+        None => None,
     };
 
-    match User::get(user_id).await {
-        Ok(user) => Some(user),
-        Err(sqlx::Error::RowNotFound) => None,
-        Err(e) => {
-            error!("{:?}", e);
-            None
+    let (stats, incomes) = match &address {
+        Some(address) => {
+            let scope = StatsScope::new(chain, donation.token.as_deref(), donation.created_at, donation.starts_at);
+            let (stats, incomes) = tokio::join!(
+                transaction::get_address_stats(&state.http_client, address, &scope),
+                transaction::get_recent_incomes(&state.http_client, address, &scope, PUBLIC_DEPOSITS_LIMIT),
+            );
+            if let Some(err_msg) = stats.as_ref().err().or(incomes.as_ref().err()) {
+                warn!("Failed get deposits of public donation {}: {}", donation.public_id, err_msg);
+            }
+            (stats.ok(), incomes.unwrap_or_default())
         },
-    }
+        None => (Some(AddressStats::default()), vec![]),
+    };
+
+    let j_donation = JsonPublicDonation {
+        id: donation.public_id,
+        slug: donation.slug,
+        title: donation.title,
+        description: donation.description,
+        status: donation.status,
+        chain: chain.as_str().to_string(),
+        payment_uri: address.as_ref()
+            .and_then(|address| chain.payment_uri(address, donation.token.as_deref(), None, None).ok()),
+        address,
+        accepted_tokens: vec![donation.token.unwrap_or_else(|| chain.tokens()[0].to_string())],
+        goal: donation.amount,
+        progress: stats.map(|stats| DonationProgress::new(donation.amount, stats)),
+        recent_deposits: incomes.into_iter()
+            .map(|income| JsonPublicDeposit {
+                transaction_id: income.id,
+                amount: income.amount,
+                received_at: income.created_timestamp.and_then(|ts| chrono::DateTime::from_timestamp(ts, 0)),
+            })
+            .collect(),
+        starts_at: donation.starts_at,
+        ends_at: donation.ends_at,
+        completed_at: donation.completed_at,
+    };
+
+    Ok(([(http::header::CACHE_CONTROL, PUBLIC_CACHE_CONTROL)], Json(j_donation)))
 }
 
-async fn create_wallet(
+async fn get_donation_qr(
+    Path(id_str): Path<String>,
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
-    Json(mut j_in_wallet): Json<JsonWallet>,
+    Query(query): Query<QrQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    if let Some(data) = j_in_wallet.data.clone() {
-        let data: WalletData = data.try_into().map_err(
-            |_| {
-                return AppError::InvalidInput("Invalid data".to_string())
-            }
-        )?;
-        data.validate().map_err(|err| return AppError::InvalidInput(err))?;
-    } else {
-        let (private_key, address) = gen_wallet(&state.http_client).await?;
-        j_in_wallet.data = Some(json!(WalletData { address, private_key: Some(private_key) }))
-    }
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
+    let (content_type, body) = render_donation_qr(&donation, query, &state.db).await?;
 
-    let in_wallet: Wallet = j_in_wallet.into();
+    Ok(([(http::header::CONTENT_TYPE, content_type), (http::header::CACHE_CONTROL, "private, no-cache")], body))
+}
 
-    let out_wallet: Wallet = Wallet::create(
-        &state.db,
-        in_wallet.data.address,
-        in_wallet.data.private_key,
-        in_wallet.is_active,
-        user.id,
-    ).await?;
+async fn get_public_donation_qr(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<QrQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let donation = Donation::get_public(&key, &state.db).await.map_err(map_not_found)?;
+    let (content_type, body) = render_donation_qr(&donation, query, &state.db).await?;
 
-    let j_out_wallet: JsonWallet = out_wallet.clone().into();
+    Ok(([(http::header::CONTENT_TYPE, content_type), (http::header::CACHE_CONTROL, PUBLIC_CACHE_CONTROL)], body))
+}
 
-    if out_wallet.is_active {
-        let msg: amqp::Message = out_wallet.into();
-        msg.send().await;
-    }
+/// A QR code of the URI paying into the donation's wallet.
+async fn render_donation_qr(donation: &Donation, query: QrQuery, db: &PgPool) -> Result<(&'static str, Vec<u8>), AppError> {
+    let Some(wallet_id) = donation.wallet_id else {
+        return Err(AppError::Conflict("Donation has no wallet to pay to".to_string()));
+    };
+    let address = Wallet::address(wallet_id, db).await?;
+    let chain = Chain::try_from(donation.chain.as_str()).unwrap_or_default();
 
-    Ok((StatusCode::CREATED, Json(j_out_wallet)))
+    let uri = chain.payment_uri(&address, donation.token.as_deref(), query.amount, query.memo.as_deref())
+        .map_err(AppError::InvalidInput)?;
+
+    render_qr(&uri, &query)
 }
 
-async fn list_wallet(
+fn render_qr(uri: &str, query: &QrQuery) -> Result<(&'static str, Vec<u8>), AppError> {
+    qr::render(uri, query.format.unwrap_or_default(), query.size, query.ec.unwrap_or_default())
+        .map_err(AppError::InvalidInput)
+}
+
+async fn create_invoice(
+    Path(id_str): Path<String>,
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
+    Json(mut j_invoice): Json<JsonInvoiceIn>,
 ) -> Result<impl IntoResponse, AppError> {
-    let wallets: Vec<Wallet> = Wallet::list(user.id, &state.db)
-        .await
-        .map_err(AppError::DbError)?;
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    let donation = Donation::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+    let invoice = open_invoice(&donation, &mut j_invoice, &state.db).await?;
 
-    let j_wallets: Vec<JsonWallet> = wallets.into_iter().map(|wallet| wallet.into()).collect();
-    Ok(Json(j_wallets))
+    Ok((StatusCode::CREATED, Json(invoice)))
 }
 
-async fn get_wallet(
+async fn open_invoice(donation: &Donation, j_invoice: &mut JsonInvoiceIn, db: &PgPool) -> Result<Invoice, AppError> {
+    if donation.status != DonationStatus::Active.as_str() {
+        return Err(AppError::Conflict(format!("Donation is {}, only active donations take invoices", donation.status)));
+    }
+    let Some(wallet_id) = donation.wallet_id else {
+        return Err(AppError::Conflict("Donation has no wallet to pay to".to_string()));
+    };
+    let chain = Chain::try_from(donation.chain.as_str()).unwrap_or_default();
+    let token = j_invoice.validate(chain, donation.token.as_deref()).map_err(AppError::InvalidInput)?;
+
+    if !j_invoice.unique_amount {
+        return Ok(Invoice::create(donation.id, wallet_id, chain, &token, j_invoice, Decimal::ZERO, db).await?);
+    }
+
+    Invoice::create_unique(donation.id, wallet_id, chain, &token, j_invoice, db)
+        .await?
+        .ok_or_else(|| AppError::Conflict("No unique amount is free on this wallet right now, try again later".to_string()))
+}
+
+async fn list_invoices(
     Path(id_str): Path<String>,
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
 ) -> Result<impl IntoResponse, AppError> {
-    let j_wallet: JsonWallet = get_json_wallet(&id_str, user.id, &state.db).await?;
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
 
-    Ok(Json(j_wallet))
+    Ok(Json(Invoice::list(donation.id, &state.db).await?))
 }
 
-async fn get_json_wallet(id_str: &str, user_id: Uuid, db: &PgPool) -> Result<JsonWallet, AppError> {
-    let id = Uuid::parse_str(id_str).map_err(
-        |_| return AppError::InvalidInput("Invalid id".to_string())
-    )?;
+async fn get_invoice(
+    Path((id_str, invoice_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
+    let invoice = Invoice::get(parse_id(&invoice_id_str)?, donation.id, &state.db).await.map_err(map_not_found)?;
 
-    Ok(Wallet::get(db, id, user_id)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => AppError::NotFound,
-            _ => AppError::DbError(e),
-        })?
-        .into())
+    Ok(Json(invoice))
 }
 
-async fn update_wallet(
-    Path(id_str): Path<String>,
+async fn get_invoice_qr(
+    Path((id_str, invoice_id_str)): Path<(String, String)>,
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
-    Json(j_in_wallet): Json<JsonWallet>,
+    Query(query): Query<QrQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let id = Uuid::parse_str(&id_str).map_err(
-        |_| return AppError::InvalidInput("Invalid id".to_string())
-    )?;
-    let in_wallet: Wallet = j_in_wallet.into();
-    let out_wallet: Wallet = in_wallet.update(id, user.id, &state.db)
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
+    let invoice = Invoice::get(parse_id(&invoice_id_str)?, donation.id, &state.db).await.map_err(map_not_found)?;
+    let uri = invoice.payment_uri(query.memo.as_deref()).map_err(AppError::InvalidInput)?;
+    let (content_type, body) = render_qr(&uri, &query)?;
+
+    Ok(([(http::header::CONTENT_TYPE, content_type), (http::header::CACHE_CONTROL, "private, no-cache")], body))
+}
+
+async fn submit_donor_message(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(mut j_message): Json<JsonDonorMessageIn>,
+) -> Result<impl IntoResponse, AppError> {
+    j_message.validate().map_err(AppError::InvalidInput)?;
+    let donation = Donation::get_public(&key, &state.db).await.map_err(map_not_found)?;
+
+    let invoice = match &j_message.invoice_id {
+        Some(public_id) => {
+            let invoice = Invoice::get_public(public_id, &state.db).await.map_err(map_not_found)?;
+            if invoice.donation_id != donation.id {
+                return Err(AppError::NotFound);
+            }
+            if invoice.outstanding().is_none() {
+                return Err(AppError::Conflict(format!("Invoice is {}", invoice.status)));
+            }
+            invoice
+        },
+        None => {
+            let client_key = hash_token(&state.public_rate_limit.client(&headers, peer).to_string());
+            let (total, for_client) = Invoice::open_client_counts(donation.id, &client_key, &state.db).await?;
+            if total >= MAX_OPEN_CLIENT_INVOICES || for_client >= MAX_OPEN_INVOICES_PER_CLIENT {
+                return Err(AppError::Conflict("Too many messages are waiting for payment, try again later".to_string()));
+            }
+
+            let mut j_invoice = JsonInvoiceIn {
+                amount: j_message.amount.unwrap_or_default(),
+                token: j_message.token.clone(),
+                expires_in: Some(CLIENT_EXPIRES_IN),
+                reference: None,
+                unique_amount: true,
+                client_key: Some(client_key),
+            };
+            open_invoice(&donation, &mut j_invoice, &state.db).await?
+        },
+    };
+
+    let message = DonorMessage::create(donation.id, invoice.id, &invoice.token, &j_message, &state.db)
         .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => AppError::NotFound,
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(DUPLICATE_CODE) => {
+                AppError::Conflict("Invoice already has a message".to_string())
+            },
             _ => AppError::DbError(e),
         })?;
 
-    let j_out_wallet: JsonWallet = out_wallet.clone().into();
+    Ok((StatusCode::CREATED, Json(JsonSubmittedMessage { id: message.id, invoice: invoice.into() })))
+}
 
-    let msg: amqp::Message = out_wallet.into();
-    msg.send().await;
+async fn list_public_donor_messages(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let donation = Donation::get_public(&key, &state.db).await.map_err(map_not_found)?;
+    let messages: Vec<JsonPublicMessage> = DonorMessage::list_public(donation.id, &state.db)
+        .await?
+        .into_iter()
+        .map(JsonPublicMessage::from)
+        .collect();
 
-    Ok((StatusCode::OK, Json(j_out_wallet)))
+    Ok(([(http::header::CACHE_CONTROL, PUBLIC_CACHE_CONTROL)], Json(messages)))
 }
 
-async fn delete_wallet(
+async fn list_donor_messages(
     Path(id_str): Path<String>,
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
 ) -> Result<impl IntoResponse, AppError> {
-    let id = Uuid::parse_str(&id_str).map_err(
-        |_| return AppError::InvalidInput("Invalid id".to_string())
-    )?;
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
 
-    let donations_ids = Donation::ids_by_wallet_id(id, user.id, &state.db)
-        .await
-        .map_err(AppError::DbError)?;
-    if ! donations_ids.is_empty() {
-        return Err(AppError::InvalidInput(
-            format!(
-                "Сan not be deleted, because linked to donations: {}",
-                donations_ids
-                .iter()
-                .map(Uuid::to_string)
-                .collect::<Vec<String>>()
-                .join(", ")
-            )
-        ));
-    }
+    Ok(Json(DonorMessage::list(donation.id, &state.db).await?))
+}
 
-    if Wallet::delete(id, user.id, &state.db)
+async fn moderate_donor_message(
+    Path((id_str, message_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_moderation): Json<JsonDonorMessageModeration>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    let message = DonorMessage::set_hidden(parse_id(&message_id_str)?, id, j_moderation.hidden, &state.db)
         .await
-        .map_err(AppError::DbError)?
-        .rows_affected() == 0 {
-            return Err(AppError::NotFound);
-        }
+        .map_err(map_not_found)?;
+
+    Ok(Json(message))
+}
+
+async fn delete_donor_message(
+    Path((id_str, message_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    let result = DonorMessage::delete(parse_id(&message_id_str)?, id, &state.db).await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[derive(serde::Deserialize)]
+struct UnmatchedDepositQuery {
+    #[serde(default)]
+    unresolved: bool,
+}
+
+async fn list_unmatched_deposits(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Query(query): Query<UnmatchedDepositQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(UnmatchedDeposit::list(user.id, query.unresolved, &state.db).await?))
+}
+
+async fn resolve_unmatched_deposit(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_resolution): Json<JsonUnmatchedResolution>,
+) -> Result<impl IntoResponse, AppError> {
+    let deposit = UnmatchedDeposit::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
+    authorize_wallet_write(deposit.wallet_id, user.id, &state.db).await?;
+    if deposit.resolved_at.is_some() {
+        return Err(AppError::Conflict("Deposit was resolved already".to_string()));
+    }
+
+    let deposit = UnmatchedDeposit::resolve(deposit.id, j_resolution.invoice_id, &state.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::InvalidInput(
+                "invoice_id must be an invoice on the deposit's wallet and token".to_string()
+            ),
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(DUPLICATE_CODE) => {
+                AppError::Conflict("Deposit was resolved already".to_string())
+            },
+            _ => AppError::DbError(e),
+        })?;
+
+    Ok(Json(deposit))
+}
+
+/// Polled by checkout pages while they wait for payment, so never cached.
+async fn get_public_invoice(
+    Path(public_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let invoice = Invoice::get_public(&public_id, &state.db).await.map_err(map_not_found)?;
+    let receipt = render_receipt(&invoice, &state).await?;
+
+    Ok(([(http::header::CACHE_CONTROL, "no-store")], Json(JsonPublicInvoice { receipt, ..JsonPublicInvoice::from(invoice) })))
+}
+
+/// The donation's receipt for a paid invoice, if it has a template; progress is left empty
+/// while the transactions service can't be reached.
+async fn render_receipt(invoice: &Invoice, state: &AppState) -> Result<Option<RenderedMessage>, AppError> {
+    let paid = invoice.status == InvoiceStatus::Paid.as_str() || invoice.status == InvoiceStatus::Overpaid.as_str();
+    if !paid {
+        return Ok(None);
+    }
+    let Some(template) = ReceiptTemplate::get(invoice.donation_id, &state.db).await? else {
+        return Ok(None);
+    };
+
+    let message = DonorMessage::for_invoice(invoice.id, &state.db).await?
+        .filter(|message| !message.hidden)
+        .map(JsonPublicMessage::from);
+    let scope = Donation::stats_scope(invoice.donation_id, &state.db).await?;
+    let received = match transaction::get_address_stats(&state.http_client, &invoice.address, &scope).await {
+        Ok(stats) => Some(stats.received),
+        Err(err_msg) => {
+            warn!("Failed get progress for receipt of invoice {}: {}", invoice.public_id, err_msg);
+            None
+        },
+    };
+
+    let values = TemplateValues {
+        donor_name: message.as_ref().and_then(|message| message.display_name.clone()),
+        donor_message: message.as_ref().map(|message| message.message.clone()).filter(|message| !message.is_empty()),
+        amount: Some(invoice.received.to_string()),
+        token: Some(invoice.token.clone()),
+        chain: Some(invoice.chain.clone()),
+        transaction_id: None,
+        donation_title: Some(template.donation_title.clone()),
+        goal: Some(template.goal.normalize().to_string()),
+        received: received.map(|received| received.normalize().to_string()),
+        progress_percent: received.map(|received| DonationProgress::percent(template.goal, received).normalize().to_string()),
+        rule_name: None,
+        kind: Some("receipt".to_string()),
+        message: None,
+    };
+
+    Ok(Some(template.render(&values)))
+}
+
+async fn get_public_invoice_qr(
+    Path(public_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<QrQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let invoice = Invoice::get_public(&public_id, &state.db).await.map_err(map_not_found)?;
+    let uri = invoice.payment_uri(query.memo.as_deref()).map_err(AppError::InvalidInput)?;
+    let (content_type, body) = render_qr(&uri, &query)?;
+
+    Ok(([(http::header::CONTENT_TYPE, content_type), (http::header::CACHE_CONTROL, "no-store")], body))
+}
+
+/// `token` authorizes public streams; `last_event_id` resumes WebSocket streams, whose browser
+/// clients can't send the `Last-Event-ID` header that `EventSource` reconnects with.
+#[derive(serde::Deserialize)]
+struct EventStreamQuery {
+    token: Option<String>,
+    last_event_id: Option<i64>,
+}
+
+impl EventStreamQuery {
+    fn last_event_id(&self, headers: &http::HeaderMap) -> Result<Option<i64>, AppError> {
+        match headers.get("last-event-id") {
+            Some(value) => value.to_str().ok()
+                .and_then(|value| value.trim().parse().ok())
+                .map(Some)
+                .ok_or_else(|| AppError::InvalidInput("Last-Event-ID must be an event id".to_string())),
+            None => Ok(self.last_event_id),
+        }
+    }
+}
+
+async fn stream_account_events(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    headers: http::HeaderMap,
+    Query(query): Query<EventStreamQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let scope = account_stream_scope(user.id, &state.db).await?;
+    sse_events(state, scope, query.last_event_id(&headers)?).await
+}
+
+async fn stream_account_events_ws(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    headers: http::HeaderMap,
+    Query(query): Query<EventStreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, AppError> {
+    let scope = account_stream_scope(user.id, &state.db).await?;
+    ws_events(ws, state, scope, query.last_event_id(&headers)?).await
+}
+
+/// Deposits on every donation of the organizations the user belongs to when the stream opens.
+async fn account_stream_scope(user_id: Uuid, db: &PgPool) -> Result<StreamScope, AppError> {
+    let organizations = Organization::list(user_id, db).await?;
+    Ok(StreamScope::Organizations(organizations.into_iter().map(|organization| organization.id).collect()))
+}
+
+async fn stream_donation_events(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    headers: http::HeaderMap,
+    Query(query): Query<EventStreamQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
+    sse_events(state, StreamScope::Donation(donation.id), query.last_event_id(&headers)?).await
+}
+
+async fn stream_donation_events_ws(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    headers: http::HeaderMap,
+    Query(query): Query<EventStreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, AppError> {
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
+    ws_events(ws, state, StreamScope::Donation(donation.id), query.last_event_id(&headers)?).await
+}
+
+/// For overlays and widgets, which get the donation's stream token instead of credentials.
+async fn stream_public_donation_events(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: http::HeaderMap,
+    Query(query): Query<EventStreamQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let scope = public_stream_scope(&key, &query, &state.db).await?;
+    sse_events(state, scope, query.last_event_id(&headers)?).await
+}
+
+async fn stream_public_donation_events_ws(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: http::HeaderMap,
+    Query(query): Query<EventStreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, AppError> {
+    let scope = public_stream_scope(&key, &query, &state.db).await?;
+    ws_events(ws, state, scope, query.last_event_id(&headers)?).await
+}
+
+async fn public_stream_scope(key: &str, query: &EventStreamQuery, db: &PgPool) -> Result<StreamScope, AppError> {
+    let donation = Donation::get_public(key, db).await.map_err(map_not_found)?;
+    let token = query.token.as_deref().ok_or(AppError::Unauthorized)?;
+    if !StreamToken::is_valid(donation.id, token, db).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(StreamScope::Donation(donation.id))
+}
+
+/// Server-sent events named after the event type, with the event as json data.
+async fn sse_events(
+    state: Arc<AppState>, scope: StreamScope, last_id: Option<i64>
+) -> Result<impl IntoResponse, AppError> {
+    let events = stream::subscribe(state, scope, last_id).await?.map(|streamed| {
+        SseEvent::default()
+            .id(streamed.last_event_id.to_string())
+            .event(&streamed.event.event_type)
+            .json_data(&streamed)
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(STREAM_HEARTBEAT_INTERVAL)))
+}
+
+/// Replays before upgrading, so a bad `last_event_id` or database error gets a proper response.
+async fn ws_events(
+    ws: WebSocketUpgrade, state: Arc<AppState>, scope: StreamScope, last_id: Option<i64>
+) -> Result<impl IntoResponse, AppError> {
+    let events = stream::subscribe(state, scope, last_id).await?;
+    Ok(ws.on_upgrade(move |socket| relay_events(socket, events)))
+}
+
+/// Sends each event as a json text frame, pinging while idle, until either side closes.
+async fn relay_events(mut socket: WebSocket, events: impl Stream<Item = Streamed>) {
+    let mut events = std::pin::pin!(events);
+    let mut heartbeat = tokio::time::interval_at(
+        tokio::time::Instant::now() + STREAM_HEARTBEAT_INTERVAL, STREAM_HEARTBEAT_INTERVAL,
+    );
+
+    loop {
+        let message = tokio::select! {
+            event = events.next() => match event {
+                Some(event) => Message::Text(json!(event).to_string()),
+                None => break,
+            },
+            _ = heartbeat.tick() => Message::Ping(vec![]),
+            received = socket.recv() => match received {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if socket.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn get_stream_token(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let donation = Donation::get(parse_id(&id_str)?, user.id, &state.db).await.map_err(map_not_found)?;
+    let token = StreamToken::get(donation.id, &state.db).await?.ok_or(AppError::NotFound)?;
+
+    Ok(Json(json!({ "token": token })))
+}
+
+/// Returns the existing token if there is one.
+async fn create_stream_token(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    let token = StreamToken::get_or_create(id, &state.db).await?;
+
+    Ok(Json(json!({ "token": token })))
+}
+
+async fn rotate_stream_token(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    let token = StreamToken::rotate(id, &state.db).await?;
+
+    Ok(Json(json!({ "token": token })))
+}
+
+async fn authorize_current_user(auth_header: &str, state: &AppState) -> Option<(User, ApiKeyScope)> {
+    let token = auth::extract_token(auth_header);
+
+    let (user_id, scope) = if auth::is_access_token(token) {
+        (auth::verify_access_token(token, &state.auth)?, ApiKeyScope::ReadWrite)
+    } else {
+        match ApiKey::authenticate(&auth::hash_token(token), &state.db).await {
+            Ok(result) => result,
+            Err(sqlx::Error::RowNotFound) => return None,
+            Err(e) => {
+                error!("{:?}", e);
+                return None
+            },
+        }
+    };
+
+    match User::get(user_id, &state.db).await {
+        Ok(user) => Some((user, scope)),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(e) => {
+            error!("{:?}", e);
+            None
+        },
+    }
+}
+
+async fn register(
+    State(state): State<Arc<AppState>>,
+    Json(credentials): Json<JsonCredentials>,
+) -> Result<impl IntoResponse, AppError> {
+    credentials.validate().map_err(|err| AppError::InvalidInput(err.to_string()))?;
+
+    let email = credentials.email.trim().to_lowercase();
+    let password_hash = auth::hash_password(credentials.password)
+        .await
+        .map_err(|err_msg| {
+            error!("{}", err_msg);
+            AppError::InternalServerError
+        })?;
+
+    let user = User::create(&email, &password_hash, &state.db)
+        .await
+        .map_err(|err| {
+            if let sqlx::Error::Database(db_err) = &err {
+                if db_err.code().as_deref() == Some(DUPLICATE_CODE) {
+                    return AppError::Conflict("Email already registered".to_string())
+                }
+            };
+            AppError::DbError(err)
+        })?;
+
+    Ok((StatusCode::CREATED, Json(json!({"id": user.id.to_string(), "email": user.email}))))
+}
+
+async fn login(
+    State(state): State<Arc<AppState>>,
+    Json(credentials): Json<JsonCredentials>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = credentials.email.trim().to_lowercase();
+    let user_id = match User::get_by_email(&email, &state.db).await {
+        Ok(user) => Some(user.id),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(e) => return Err(AppError::DbError(e)),
+    };
+    let password_hash = match user_id {
+        Some(user_id) => User::get_password_hash(user_id, &state.db).await.map_err(AppError::DbError)?,
+        None => None,
+    };
+
+    let verified = match password_hash {
+        Some(password_hash) => auth::verify_password(credentials.password, password_hash).await,
+        None => {
+            auth::verify_password(credentials.password, auth::DUMMY_PASSWORD_HASH.to_string()).await;
+            false
+        },
+    };
+    match user_id {
+        Some(user_id) if verified => Ok(Json(issue_token_pair(user_id, &state).await?)),
+        _ => Err(AppError::Unauthorized),
+    }
+}
+
+async fn refresh(
+    State(state): State<Arc<AppState>>,
+    Json(j_refresh_token): Json<JsonRefreshToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token_hash = auth::hash_token(&j_refresh_token.refresh_token);
+    let user_id = RefreshToken::consume(&token_hash, &state.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::Unauthorized,
+            _ => AppError::DbError(e),
+        })?;
+
+    Ok(Json(issue_token_pair(user_id, &state).await?))
+}
+
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    Json(j_refresh_token): Json<JsonRefreshToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token_hash = auth::hash_token(&j_refresh_token.refresh_token);
+    match RefreshToken::consume(&token_hash, &state.db).await {
+        Ok(_) | Err(sqlx::Error::RowNotFound) => Ok(StatusCode::NO_CONTENT),
+        Err(e) => Err(AppError::DbError(e)),
+    }
+}
+
+async fn change_password(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_password_change): Json<JsonPasswordChange>,
+) -> Result<impl IntoResponse, AppError> {
+    j_password_change.validate().map_err(|err| AppError::InvalidInput(err.to_string()))?;
+
+    // accounts created before registration existed (e.g. the seeded admin) have no password yet
+    if let Some(password_hash) = User::get_password_hash(user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)? {
+        let current_password = j_password_change.current_password.ok_or(
+            AppError::InvalidInput("current_password is required".to_string())
+        )?;
+        if !auth::verify_password(current_password, password_hash).await {
+            return Err(AppError::Unauthorized);
+        }
+    }
+
+    let password_hash = auth::hash_password(j_password_change.new_password)
+        .await
+        .map_err(|err_msg| {
+            error!("{}", err_msg);
+            AppError::InternalServerError
+        })?;
+    User::set_password_hash(user.id, &password_hash, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+    RefreshToken::revoke_all(user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn issue_token_pair(user_id: Uuid, state: &AppState) -> Result<JsonTokenPair, AppError> {
+    let access_token = auth::issue_access_token(user_id, &state.auth).map_err(|err_msg| {
+        error!("{}", err_msg);
+        AppError::InternalServerError
+    })?;
+
+    let (refresh_token, token_hash) = auth::generate_refresh_token();
+    let expires_at = chrono::Utc::now() + state.auth.refresh_token_ttl;
+    RefreshToken::create(user_id, &token_hash, expires_at, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    Ok(JsonTokenPair {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: state.auth.access_token_ttl.num_seconds(),
+        refresh_token,
+    })
+}
+
+async fn get_me(
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(json!({"id": user.id.to_string(), "email": user.email})))
+}
+
+/// Needs a read-write key, since the secret lets whoever holds it sign deliveries.
+async fn get_webhook_secret(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scope): Extension<ApiKeyScope>,
+) -> Result<impl IntoResponse, AppError> {
+    if scope != ApiKeyScope::ReadWrite {
+        return Err(AppError::Forbidden);
+    }
+    let secret = User::webhook_secret(user.id, &state.db).await?;
+    Ok(Json(json_webhook_secret(secret)))
+}
+
+async fn rotate_webhook_secret(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    j_rotation: Option<Json<JsonWebhookSecretRotation>>,
+) -> Result<impl IntoResponse, AppError> {
+    let grace_period_hours = j_rotation
+        .and_then(|Json(j_rotation)| j_rotation.grace_period_hours)
+        .unwrap_or(24);
+    if !(0..=7 * 24).contains(&grace_period_hours) {
+        return Err(AppError::InvalidInput("grace_period_hours must be between 0 and 168".to_string()));
+    }
+
+    let secret = User::rotate_webhook_secret(user.id, chrono::Duration::hours(grace_period_hours), &state.db).await?;
+    Ok(Json(json_webhook_secret(secret)))
+}
+
+fn json_webhook_secret(secret: WebhookSecret) -> serde_json::Value {
+    json!({
+        "secret": secret.secret,
+        "previous_secret_expires_at": secret.previous_secret_expires_at,
+        "header": webhook::SIGNATURE_HEADER,
+    })
+}
+
+async fn create_webhook_endpoint(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_endpoint): Json<JsonWebhookEndpointIn>,
+) -> Result<impl IntoResponse, AppError> {
+    check_webhook_endpoint(&mut j_endpoint, user.id, &state).await?;
+    if WebhookEndpoint::limit_reached(WebhookEndpoint::count(user.id, &state.db).await?) {
+        return Err(AppError::Conflict("Too many webhook endpoints".to_string()));
+    }
+
+    let endpoint = WebhookEndpoint::create(user.id, &j_endpoint, &state.db).await?;
+    Ok((StatusCode::CREATED, Json(endpoint)))
+}
+
+async fn list_webhook_endpoints(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(WebhookEndpoint::list(user.id, &state.db).await?))
+}
+
+async fn get_webhook_endpoint(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let endpoint = WebhookEndpoint::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(endpoint))
+}
+
+async fn update_webhook_endpoint(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_endpoint): Json<JsonWebhookEndpointIn>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    check_webhook_endpoint(&mut j_endpoint, user.id, &state).await?;
+
+    let endpoint = WebhookEndpoint::update(id, user.id, &j_endpoint, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(endpoint))
+}
+
+async fn delete_webhook_endpoint(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let result = WebhookEndpoint::delete(id, user.id, &state.db).await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_webhook_endpoint_events(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    WebhookEndpoint::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+
+    let filter = EventFilter { user_id: user.id, endpoint_id: Some(id), ..Default::default() };
+    Ok(Json(WebhookEvent::list(&filter, &state.db).await?))
+}
+
+async fn redeliver_webhook_endpoint_event(
+    Path((id_str, event_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let event_id = parse_id(&event_id_str)?;
+
+    let filter = EventFilter { user_id: user.id, id: Some(event_id), endpoint_id: Some(id), ..Default::default() };
+    redeliver(filter, &state).await
+}
+
+async fn ping_webhook_endpoint(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let endpoint = WebhookEndpoint::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+
+    let event_id = WebhookEvent::create_ping(None, Some(id), &state.db).await?;
+    let attempt = webhook::deliver_now(event_id, &endpoint.url, user.id, "failed", &state.webhook_client, &state.db).await?;
+    Ok((StatusCode::CREATED, Json(json!({"event_id": event_id, "attempt": attempt}))))
+}
+
+/// Validates an endpoint, making sure it only filters on donations the user can see.
+async fn check_webhook_endpoint(
+    j_endpoint: &mut JsonWebhookEndpointIn, user_id: Uuid, state: &AppState
+) -> Result<(), AppError> {
+    j_endpoint.validate()?;
+    check_webhook_url(&j_endpoint.url, state).await?;
+    check_donation_ids(&j_endpoint.donation_ids, user_id, &state.db).await
+}
+
+async fn check_donation_ids(donation_ids: &[Uuid], user_id: Uuid, db: &PgPool) -> Result<(), AppError> {
+    for donation_id in donation_ids {
+        Donation::get(*donation_id, user_id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AppError::InvalidInput(format!("Unknown donation {}", donation_id)),
+                _ => AppError::DbError(e),
+            })?;
+    }
+
+    Ok(())
+}
+
+async fn create_alert_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_rule): Json<JsonAlertRuleIn>,
+) -> Result<impl IntoResponse, AppError> {
+    check_alert_rule(&mut j_rule, user.id, &state.db).await?;
+    if AlertRule::limit_reached(AlertRule::count(user.id, &state.db).await?) {
+        return Err(AppError::Conflict("Too many alert rules".to_string()));
+    }
+
+    let rule = AlertRule::create(user.id, &j_rule, &state.db).await?;
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+async fn list_alert_rules(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(AlertRule::list(user.id, &state.db).await?))
+}
+
+async fn get_alert_rule(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let rule = AlertRule::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(rule))
+}
+
+async fn update_alert_rule(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_rule): Json<JsonAlertRuleIn>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    check_alert_rule(&mut j_rule, user.id, &state.db).await?;
+
+    let rule = AlertRule::update(id, user.id, &j_rule, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(rule))
+}
+
+async fn delete_alert_rule(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let result = AlertRule::delete(id, user.id, &state.db).await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Why the rule fired or not for recent deposits, for debugging its conditions.
+async fn list_alert_evaluations(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    AlertRule::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+
+    Ok(Json(AlertEvaluation::list(id, &state.db).await?))
+}
+
+async fn create_notification_channel(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_channel): Json<JsonChannelIn>,
+) -> Result<impl IntoResponse, AppError> {
+    check_notification_channel(&mut j_channel, user.id, &state).await?;
+    if NotificationChannel::limit_reached(NotificationChannel::count(user.id, &state.db).await?) {
+        return Err(AppError::Conflict("Too many notification channels".to_string()));
+    }
+
+    let channel = NotificationChannel::create(user.id, &j_channel, &state.db).await?;
+    Ok((StatusCode::CREATED, Json(channel)))
+}
+
+async fn list_notification_channels(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(NotificationChannel::list(user.id, &state.db).await?))
+}
+
+async fn get_notification_channel(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let channel = NotificationChannel::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(channel))
+}
+
+async fn update_notification_channel(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_channel): Json<JsonChannelIn>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    check_notification_channel(&mut j_channel, user.id, &state).await?;
+
+    let channel = NotificationChannel::update(id, user.id, &j_channel, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(channel))
+}
+
+async fn delete_notification_channel(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let result = NotificationChannel::delete(id, user.id, &state.db).await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sends a sample notification right away, so users can check a channel's settings.
+async fn test_notification_channel(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let (channel, config) = get_channel_config(id, user.id, &state.db).await?;
+    if channel.verified_at.is_none() {
+        return Err(AppError::Conflict("Verify the channel before testing it".to_string()));
+    }
+    claim_manual_send(channel.id, &state.db).await?;
+
+    let message = "Test notification from donation-hub";
+    let template = match channel.template_id {
+        Some(template_id) => Some(MessageTemplate::get(template_id, user.id, &state.db).await?),
+        None => None,
+    };
+    let values = TemplateValues {
+        kind: Some(channel::TEST_KIND.to_string()),
+        message: Some(message.to_string()),
+        ..TemplateValues::sample()
+    };
+    let rendered = channel::compose(
+        &config,
+        template.as_ref().map(|template| (template.format(), template.subject.as_deref(), template.body.as_str())),
+        channel.template.as_deref(),
+        message,
+        &values,
+    );
+    let result = state.channel_client.send(&config, message, &rendered).await;
+    NotificationChannel::record_result(channel.id, result.as_ref().err().map(String::as_str), &state.db).await?;
+
+    Ok(Json(json!({
+        "delivered": result.is_ok(),
+        "error": result.err(),
+    })))
+}
+
+/// Sends a code to an email address or Telegram chat, to be passed to `verify`.
+async fn send_notification_channel_code(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let (channel, config) = get_channel_config(id, user.id, &state.db).await?;
+    if channel.verified_at.is_some() {
+        return Err(AppError::Conflict("Channel is already verified".to_string()));
+    }
+    claim_manual_send(channel.id, &state.db).await?;
+
+    let code = NotificationChannel::start_verification(channel.id, &state.db).await?;
+    let message = format!("Your donation-hub verification code is {}", code);
+    let rendered = channel::compose(&config, None, None, &message, &TemplateValues::default());
+    let result = state.channel_client.send(&config, "Verification code", &rendered).await;
+    NotificationChannel::record_result(channel.id, result.as_ref().err().map(String::as_str), &state.db).await?;
+
+    Ok(Json(json!({
+        "delivered": result.is_ok(),
+        "error": result.err(),
+    })))
+}
+
+async fn verify_notification_channel(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_code): Json<JsonChannelCode>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    match NotificationChannel::verify(id, user.id, &j_code.code, &state.db).await? {
+        Some(true) => Ok(Json(NotificationChannel::get(id, user.id, &state.db).await?)),
+        Some(false) => Err(AppError::InvalidInput("Wrong verification code".to_string())),
+        None => {
+            NotificationChannel::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+            Err(AppError::Conflict("No verification code is waiting, request a new one".to_string()))
+        },
+    }
+}
+
+async fn get_channel_config(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<(NotificationChannel, ChannelConfig), AppError> {
+    let channel = NotificationChannel::get(id, user_id, db).await.map_err(map_not_found)?;
+    let config = serde_json::from_value(channel.config.clone()).map_err(|err| {
+        error!("Invalid config of notification channel {}: {}", channel.id, err);
+        AppError::InternalServerError
+    })?;
+
+    Ok((channel, config))
+}
+
+/// Test messages and codes go out right away, so they are limited per channel.
+async fn claim_manual_send(id: Uuid, db: &PgPool) -> Result<(), AppError> {
+    if !NotificationChannel::claim_manual_send(id, db).await? {
+        return Err(AppError::RateLimited(channel::MANUAL_SEND_INTERVAL));
+    }
+
+    Ok(())
+}
+
+async fn check_notification_channel(
+    j_channel: &mut JsonChannelIn, user_id: Uuid, state: &AppState
+) -> Result<(), AppError> {
+    j_channel.validate().map_err(AppError::InvalidInput)?;
+    if let Some(template_id) = j_channel.template_id {
+        let template = get_own_template(template_id, user_id, &state.db).await?;
+        // chat webhooks post plain text
+        let is_chat_webhook = matches!(j_channel.config, ChannelConfig::ChatWebhook { .. });
+        if is_chat_webhook && template.format == TemplateFormat::Html.as_str() {
+            return Err(AppError::InvalidInput("chat_webhook channels can't use html templates".to_string()));
+        }
+    }
+    if !state.channel_client.supports(&j_channel.config) {
+        return Err(AppError::InvalidInput(
+            format!("{} channels are not configured on this server", j_channel.config.kind())
+        ));
+    }
+    if let ChannelConfig::ChatWebhook { url, .. } = &j_channel.config {
+        state.channel_client.policy.check_url_resolved(url).await.map_err(AppError::InvalidInput)?;
+    }
+
+    Ok(())
+}
+
+async fn create_message_template(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_template): Json<JsonTemplateIn>,
+) -> Result<impl IntoResponse, AppError> {
+    j_template.validate().map_err(AppError::InvalidInput)?;
+    if MessageTemplate::limit_reached(MessageTemplate::count(user.id, &state.db).await?) {
+        return Err(AppError::Conflict("Too many message templates".to_string()));
+    }
+
+    let template = MessageTemplate::create(user.id, &j_template, &state.db).await?;
+    Ok((StatusCode::CREATED, Json(template)))
+}
+
+async fn list_message_templates(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(MessageTemplate::list(user.id, &state.db).await?))
+}
+
+async fn get_message_template(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let template = MessageTemplate::get(id, user.id, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(template))
+}
+
+async fn update_message_template(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_template): Json<JsonTemplateIn>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    j_template.validate().map_err(AppError::InvalidInput)?;
+
+    let template = MessageTemplate::update(id, user.id, &j_template, &state.db).await.map_err(map_not_found)?;
+    Ok(Json(template))
+}
+
+async fn delete_message_template(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let result = MessageTemplate::delete(id, user.id, &state.db).await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Renders a template without saving it, against a sample deposit unless values are given.
+async fn preview_message_template(
+    Json(mut j_preview): Json<JsonTemplatePreview>,
+) -> Result<impl IntoResponse, AppError> {
+    j_preview.validate().map_err(AppError::InvalidInput)?;
+
+    Ok(Json(j_preview.render()))
+}
+
+/// Sets the template paid invoices of the donation show as a receipt, or stops showing one.
+async fn set_receipt_template(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_receipt): Json<JsonReceiptTemplate>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    authorize_donation_write(id, user.id, &state.db).await?;
+    if let Some(template_id) = j_receipt.template_id {
+        get_own_template(template_id, user.id, &state.db).await?;
+    }
+    MessageTemplate::set_receipt(id, j_receipt.template_id, &state.db).await?;
+
+    Ok(Json(json!({ "template_id": j_receipt.template_id })))
+}
+
+async fn get_own_template(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<MessageTemplate, AppError> {
+    MessageTemplate::get(id, user_id, db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::InvalidInput(format!("Unknown template {}", id)),
+            _ => AppError::DbError(e),
+        })
+}
+
+async fn check_alert_rule(j_rule: &mut JsonAlertRuleIn, user_id: Uuid, db: &PgPool) -> Result<(), AppError> {
+    j_rule.validate().map_err(AppError::InvalidInput)?;
+    check_donation_ids(&j_rule.donation_ids, user_id, db).await
+}
+
+/// Rejects webhook urls that could reach the service's own network, resolving the host now
+/// so the user gets the error up front; deliveries check again on every send.
+async fn check_webhook_url(url: &str, state: &AppState) -> Result<(), AppError> {
+    state.webhook_client.policy
+        .check_url_resolved(url)
+        .await
+        .map(|_| ())
+        .map_err(AppError::InvalidInput)
+}
+
+#[derive(serde::Deserialize)]
+struct NotificationQuery {
+    #[serde(default)]
+    unread: bool,
+}
+
+async fn list_notifications(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Query(query): Query<NotificationQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(Notification::list(user.id, query.unread, &state.db).await?))
+}
+
+async fn read_notification(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let result = Notification::mark_read(id, user.id, &state.db).await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_in_api_key): Json<JsonApiKey>,
+) -> Result<impl IntoResponse, AppError> {
+    let name = j_in_api_key.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(AppError::InvalidInput("Invalid name".to_string()));
+    }
+
+    let key = auth::generate_api_key();
+    let api_key = ApiKey::create(user.id, name, j_in_api_key.scope, &key.prefix, &key.hash, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let mut j_out_api_key: JsonApiKey = api_key.into();
+    j_out_api_key.key = Some(key.secret);
+
+    Ok((StatusCode::CREATED, Json(j_out_api_key)))
+}
+
+async fn list_api_keys(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let api_keys: Vec<ApiKey> = ApiKey::list(user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_api_keys: Vec<JsonApiKey> = api_keys.into_iter().map(|api_key| api_key.into()).collect();
+    Ok(Json(j_api_keys))
+}
+
+async fn revoke_api_key(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = Uuid::parse_str(&id_str).map_err(
+        |_| AppError::InvalidInput("Invalid id".to_string())
+    )?;
+
+    if ApiKey::revoke(id, user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?
+        .rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn create_wallet(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(mut j_in_wallet): Json<JsonWallet>,
+) -> Result<impl IntoResponse, AppError> {
+    let chain: Chain = match j_in_wallet.chain.as_deref() {
+        Some(chain) => chain.try_into().map_err(AppError::InvalidInput)?,
+        None => Chain::default(),
+    };
+
+    let organization_id = resolve_organization(j_in_wallet.organization_id.as_deref(), user.id, &state.db).await?;
+    let mut tx = state.db.begin().await.map_err(AppError::DbError)?;
+
+    let data = if let Some(data) = j_in_wallet.data.take() {
+        let data: WalletData = serde_json::from_value(data).map_err(
+            |_| AppError::InvalidInput("Invalid data".to_string())
+        )?;
+        data.validate(chain)?;
+        WalletData { address: data.address, ..Default::default() }
+    } else {
+        new_wallet_data(chain, &state, &mut tx).await?
+    };
+
+    let in_wallet: Wallet = j_in_wallet.into();
+    let out_wallet: Wallet = Wallet::create(
+        &mut *tx,
+        chain,
+        data,
+        in_wallet.is_active,
+        user.id,
+        organization_id,
+    ).await?;
+    tx.commit().await.map_err(AppError::DbError)?;
+    state.wallet_pool.request_refill();
+
+    let j_out_wallet: JsonWallet = out_wallet.clone().into();
+
+    if out_wallet.is_active {
+        let msg: amqp::Message = out_wallet.into();
+        msg.send().await;
+    }
+
+    Ok((StatusCode::CREATED, Json(j_out_wallet)))
+}
+
+/// A fresh wallet with a sealed private key: taken from the pool for TRON, generated
+/// inline for other chains or when the pool has run dry.
+async fn new_wallet_data(
+    chain: Chain, state: &AppState, tx: &mut Transaction<'_, Postgres>
+) -> Result<WalletData, AppError> {
+    if !chain.supports_keys() {
+        return Err(AppError::InvalidInput(format!("Can not generate {} wallets, provide data.address", chain.as_str())));
+    }
+
+    if chain == Chain::Tron {
+        if let Some(data) = WalletPool::take(tx).await? {
+            return Ok(data);
+        }
+        warn!("Wallet pool is empty, generating a wallet inline");
+    }
+
+    pool::generate_wallet(chain, &state.master_key).map_err(|err_msg| {
+        error!("Failed generate wallet: {}", err_msg);
+        AppError::InternalServerError
+    })
+}
+
+async fn import_wallet(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_import): Json<JsonWalletImport>,
+) -> Result<impl IntoResponse, AppError> {
+    let chain: Chain = match j_import.chain.as_deref() {
+        Some(chain) => chain.try_into().map_err(AppError::InvalidInput)?,
+        None => Chain::default(),
+    };
+    let unsupported = || AppError::InvalidInput(format!("Can not import {} wallets", chain.as_str()));
+
+    let secret_key = match (&j_import.mnemonic, &j_import.private_key) {
+        (Some(mnemonic), None) => {
+            let path = match j_import.path.as_deref() {
+                Some(path) => path,
+                None => hdwallet::default_path(chain).ok_or_else(unsupported)?,
+            };
+            let passphrase = j_import.passphrase.as_deref().unwrap_or_default();
+            hdwallet::secret_key_from_mnemonic(mnemonic, passphrase, path).map_err(AppError::InvalidInput)?
+        },
+        (None, Some(private_key)) => hdwallet::parse_private_key(private_key).map_err(AppError::InvalidInput)?,
+        _ => return Err(AppError::InvalidInput("Provide either mnemonic or private_key".to_string())),
+    };
+
+    let address = chain.address_from_public_key(&secret_key.public_key()).ok_or_else(unsupported)?;
+    if let Some(expected) = j_import.address.as_deref() {
+        chain.validate_address(expected).map_err(|error| AppError::InvalidAddress { chain, error })?;
+        if !chain.same_address(expected, &address) {
+            return Err(AppError::InvalidInput(format!("Secret controls {}, not {}", address, expected)));
+        }
+    }
+
+    let private_key = hex::encode(secret_key.to_bytes());
+    let encrypted_private_key = WalletData::seal_private_key(&private_key, &address, &state.master_key)
+        .map_err(|err_msg| {
+            error!("Failed encrypt private key: {}", err_msg);
+            AppError::InternalServerError
+        })?;
+
+    let organization_id = resolve_organization(j_import.organization_id.as_deref(), user.id, &state.db).await?;
+    let data = WalletData { address, encrypted_private_key: Some(encrypted_private_key), ..Default::default() };
+    let out_wallet: Wallet = Wallet::create(
+        &state.db,
+        chain,
+        data,
+        j_import.is_active.unwrap_or(false),
+        user.id,
+        organization_id,
+    ).await?;
+
+    let j_out_wallet: JsonWallet = out_wallet.clone().into();
+
+    if out_wallet.is_active {
+        let msg: amqp::Message = out_wallet.into();
+        msg.send().await;
+    }
+
+    Ok((StatusCode::CREATED, Json(j_out_wallet)))
+}
+
+async fn list_wallet(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let wallets: Vec<Wallet> = Wallet::list(user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_wallets: Vec<JsonWallet> = wallets.into_iter().map(|wallet| wallet.into()).collect();
+    Ok(Json(j_wallets))
+}
+
+async fn get_wallet(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let j_wallet: JsonWallet = get_json_wallet(&id_str, user.id, &state.db).await?;
+
+    Ok(Json(j_wallet))
+}
+
+async fn get_json_wallet(id_str: &str, user_id: Uuid, db: &PgPool) -> Result<JsonWallet, AppError> {
+    let id = Uuid::parse_str(id_str).map_err(
+        |_| AppError::InvalidInput("Invalid id".to_string())
+    )?;
+
+    Ok(Wallet::get(db, id, user_id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::NotFound,
+            _ => AppError::DbError(e),
+        })?
+        .into())
+}
+
+async fn update_wallet(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_in_wallet): Json<JsonWallet>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = Uuid::parse_str(&id_str).map_err(
+        |_| AppError::InvalidInput("Invalid id".to_string())
+    )?;
+    let organization_id = authorize_wallet_write(id, user.id, &state.db).await?;
+    let in_wallet: Wallet = j_in_wallet.into();
+
+    // queued for the collector with the switch, like donation status changes
+    let mut tx = state.db.begin().await?;
+    let switched = Wallet::set_active(id, in_wallet.is_active, &mut *tx).await?;
+    tx.commit().await?;
+    if let Err(err) = lifecycle::sync_collector(&state.db).await {
+        warn!("Failed tell the collector about wallet {}: {}", id, err);
+    }
+
+    let deactivated = switched.as_ref().is_some_and(|wallet| !wallet.is_active);
+    let out_wallet = match switched {
+        Some(wallet) => wallet,
+        None => Wallet::get(&state.db, id, user.id).await.map_err(map_not_found)?,
+    };
+    if deactivated {
+        let donation_ids = Donation::ids_by_wallet_id(id, user.id, &state.db).await?;
+        publish_event(Publication::wallet_deactivated(&out_wallet, organization_id, donation_ids), &state.db).await;
+    }
+
+    let j_out_wallet: JsonWallet = out_wallet.into();
+    Ok((StatusCode::OK, Json(j_out_wallet)))
+}
+
+async fn delete_wallet(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = Uuid::parse_str(&id_str).map_err(
+        |_| AppError::InvalidInput("Invalid id".to_string())
+    )?;
+
+    authorize_wallet_write(id, user.id, &state.db).await?;
+
+    let donations_ids = Donation::ids_by_wallet_id(id, user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+    if ! donations_ids.is_empty() {
+        return Err(AppError::InvalidInput(
+            format!(
+                "Сan not be deleted, because linked to donations: {}",
+                donations_ids
+                .iter()
+                .map(Uuid::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+            )
+        ));
+    }
+
+    if Wallet::delete(id, user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?
+        .rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn parse_id(id_str: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(id_str).map_err(
+        |_| AppError::InvalidInput("Invalid id".to_string())
+    )
+}
+
+fn map_not_found(e: sqlx::Error) -> AppError {
+    match e {
+        sqlx::Error::RowNotFound => AppError::NotFound,
+        _ => AppError::DbError(e),
+    }
+}
+
+/// The slug is the only unique column a donation write can collide on.
+fn map_donation_write(e: sqlx::Error) -> AppError {
+    match &e {
+        sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(DUPLICATE_CODE) => {
+            AppError::Conflict("slug is already taken".to_string())
+        },
+        _ => map_not_found(e),
+    }
+}
+
+fn check_donation_fields(donation: &Donation) -> Result<(), AppError> {
+    lifecycle::check_window(donation.starts_at, donation.ends_at).map_err(AppError::InvalidInput)?;
+    if let Some(slug) = &donation.slug {
+        public::check_slug(slug).map_err(AppError::InvalidInput)?;
+    }
+
+    Ok(())
+}
+
+/// The organization a new resource is created in: the requested one if the user may
+/// write to it, otherwise the user's default organization.
+async fn resolve_organization(organization_id: Option<&str>, user_id: Uuid, db: &PgPool) -> Result<Uuid, AppError> {
+    match organization_id {
+        Some(id_str) => {
+            let organization_id = parse_id(id_str)?;
+            require_role(organization_id, user_id, Role::can_write, db).await?;
+            Ok(organization_id)
+        },
+        None => Organization::default_for_user(user_id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AppError::InvalidInput("organization_id is required".to_string()),
+                _ => AppError::DbError(e),
+            }),
+    }
+}
+
+async fn require_role(
+    organization_id: Uuid, user_id: Uuid, allowed: fn(&Role) -> bool, db: &PgPool
+) -> Result<Role, AppError> {
+    let role = Organization::role(organization_id, user_id, db)
+        .await
+        .map_err(map_not_found)?;
+
+    if !allowed(&role) {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(role)
+}
+
+async fn authorize_donation_write(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<Uuid, AppError> {
+    let donation = Donation::get(id, user_id, db).await.map_err(map_not_found)?;
+    let organization_id = donation.organization_id.ok_or(AppError::NotFound)?;
+    require_role(organization_id, user_id, Role::can_write, db).await?;
+
+    Ok(organization_id)
+}
+
+/// Webhook events are best effort for the request that triggers them, so failures are only logged.
+async fn publish_event(publication: Publication, db: &PgPool) {
+    if let Err(err) = WebhookEvent::publish(&publication, db).await {
+        error!("Failed publish {} webhook event: {}", publication.event_type.as_str(), err);
+    }
+}
+
+async fn authorize_wallet_write(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<Uuid, AppError> {
+    let wallet = Wallet::get(db, id, user_id).await.map_err(map_not_found)?;
+    let organization_id = wallet.organization_id.ok_or(AppError::NotFound)?;
+    require_role(organization_id, user_id, Role::can_write, db).await?;
+
+    Ok(organization_id)
+}
+
+/// Validates the chain, token, wallet and extended key of a donation, returning its chain.
+async fn check_donation_payment(
+    donation: &Donation, organization_id: Uuid, user_id: Uuid, db: &PgPool
+) -> Result<Chain, AppError> {
+    let chain: Chain = donation.chain.as_str().try_into().map_err(AppError::InvalidInput)?;
+    if let Some(token) = &donation.token {
+        if !chain.tokens().contains(&token.as_str()) {
+            return Err(AppError::InvalidInput(format!("Unsupported {} token: {}", chain.as_str(), token)));
+        }
+    }
+
+    if let Some(wallet_id) = donation.wallet_id {
+        let wallet = check_wallet_organization(wallet_id, organization_id, user_id, db).await?;
+        if wallet.chain != chain {
+            return Err(AppError::InvalidInput(format!("Wallet is on {}, not {}", wallet.chain.as_str(), chain.as_str())));
+        }
+    }
+
+    if let Some(extended_key_id) = donation.extended_key_id {
+        check_extended_key_organization(extended_key_id, organization_id, user_id, db).await?;
+        if chain != Chain::Tron {
+            return Err(AppError::InvalidInput("Extended keys derive tron addresses only".to_string()));
+        }
+    }
+
+    Ok(chain)
+}
+
+async fn check_wallet_organization(
+    wallet_id: Uuid, organization_id: Uuid, user_id: Uuid, db: &PgPool
+) -> Result<Wallet, AppError> {
+    let wallet = Wallet::get(db, wallet_id, user_id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::InvalidInput("Unknown wallet_id".to_string()),
+            _ => AppError::DbError(e),
+        })?;
+
+    if wallet.organization_id != Some(organization_id) {
+        return Err(AppError::InvalidInput("Wallet belongs to another organization".to_string()));
+    }
+
+    Ok(wallet)
+}
+
+async fn check_extended_key_organization(
+    extended_key_id: Uuid, organization_id: Uuid, user_id: Uuid, db: &PgPool
+) -> Result<(), AppError> {
+    let extended_key = ExtendedKey::get(extended_key_id, user_id, db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::InvalidInput("Unknown extended_key_id".to_string()),
+            _ => AppError::DbError(e),
+        })?;
+
+    if extended_key.organization_id != organization_id {
+        return Err(AppError::InvalidInput("Extended key belongs to another organization".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Derives the next receive address of an extended key into a new active wallet; the
+/// caller announces it once the transaction is committed.
+async fn derive_wallet(
+    extended_key_id: Uuid, is_active: bool, user_id: Uuid, tx: &mut Transaction<'_, Postgres>
+) -> Result<Wallet, AppError> {
+    let (xpub, organization_id, index) = ExtendedKey::reserve_index(extended_key_id, user_id, tx)
+        .await
+        .map_err(map_not_found)?;
+    let index_u32 = u32::try_from(index)
+        .map_err(|_| AppError::InvalidInput("Extended key has no unused indexes left".to_string()))?;
+    let address = hdwallet::derive_address(&xpub, index_u32).map_err(|err_msg| {
+        error!("Failed derive address: {}", err_msg);
+        AppError::InternalServerError
+    })?;
+
+    let data = WalletData {
+        address,
+        derivation: Some(Derivation { extended_key_id, index }),
+        ..Default::default()
+    };
+    Ok(Wallet::create(&mut **tx, Chain::Tron, data, is_active, user_id, organization_id).await?)
+}
+
+async fn create_extended_key(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_in_extended_key): Json<JsonExtendedKey>,
+) -> Result<impl IntoResponse, AppError> {
+    let name = j_in_extended_key.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(AppError::InvalidInput("Invalid name".to_string()));
+    }
+    let xpub = j_in_extended_key.xpub.trim();
+    hdwallet::derive_address(xpub, 0).map_err(AppError::InvalidInput)?;
+
+    let organization_id = resolve_organization(j_in_extended_key.organization_id.as_deref(), user.id, &state.db).await?;
+    let j_out_extended_key: JsonExtendedKey = ExtendedKey::create(name, xpub, user.id, organization_id, &state.db)
+        .await
+        .map_err(|err| {
+            if let sqlx::Error::Database(db_err) = &err {
+                if db_err.code().as_deref() == Some(DUPLICATE_CODE) {
+                    return AppError::Conflict("Extended key already registered".to_string())
+                }
+            };
+            AppError::DbError(err)
+        })?
+        .into();
+
+    Ok((StatusCode::CREATED, Json(j_out_extended_key)))
+}
+
+async fn list_extended_keys(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let extended_keys = ExtendedKey::list(user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_extended_keys: Vec<JsonExtendedKey> = extended_keys.into_iter().map(|k| k.into()).collect();
+    Ok(Json(j_extended_keys))
+}
+
+async fn get_extended_key(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let j_extended_key: JsonExtendedKey = ExtendedKey::get(id, user.id, &state.db)
+        .await
+        .map_err(map_not_found)?
+        .into();
+
+    Ok(Json(j_extended_key))
+}
+
+async fn derive_extended_key_address(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let extended_key = ExtendedKey::get(id, user.id, &state.db)
+        .await
+        .map_err(map_not_found)?;
+    require_role(extended_key.organization_id, user.id, Role::can_write, &state.db).await?;
+
+    let mut tx = state.db.begin().await.map_err(AppError::DbError)?;
+    let wallet = derive_wallet(id, true, user.id, &mut tx).await?;
+    tx.commit().await.map_err(AppError::DbError)?;
+
+    let j_wallet: JsonWallet = wallet.clone().into();
+
+    let msg: amqp::Message = wallet.into();
+    msg.send().await;
+
+    Ok((StatusCode::CREATED, Json(j_wallet)))
+}
+
+/// Prometheus text exposition of operational gauges.
+async fn metrics(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let available = WalletPool::count(&state.db).await.map_err(AppError::DbError)?;
+
+    let body = format!(
+        "# HELP wallet_pool_available Pre-generated wallets ready to be assigned.\n\
+         # TYPE wallet_pool_available gauge\n\
+         wallet_pool_available {}\n\
+         # HELP wallet_pool_target Configured size of the wallet pool.\n\
+         # TYPE wallet_pool_target gauge\n\
+         wallet_pool_target {}\n",
+        available, state.wallet_pool.size,
+    );
+
+    Ok(([(http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
+
+async fn create_organization(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_in_organization): Json<JsonOrganization>,
+) -> Result<impl IntoResponse, AppError> {
+    let name = j_in_organization.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(AppError::InvalidInput("Invalid name".to_string()));
+    }
+
+    let j_out_organization: JsonOrganization = Organization::create(name, user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?
+        .into();
+
+    Ok((StatusCode::CREATED, Json(j_out_organization)))
+}
+
+async fn list_organizations(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let organizations = Organization::list(user.id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_organizations: Vec<JsonOrganization> = organizations.into_iter().map(|o| o.into()).collect();
+    Ok(Json(j_organizations))
+}
+
+async fn get_organization(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let j_organization: JsonOrganization = Organization::get(id, user.id, &state.db)
+        .await
+        .map_err(map_not_found)?
+        .into();
+
+    Ok(Json(j_organization))
+}
+
+async fn update_organization(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_in_organization): Json<JsonOrganization>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    require_role(id, user.id, Role::can_write, &state.db).await?;
+
+    let name = j_in_organization.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(AppError::InvalidInput("Invalid name".to_string()));
+    }
+    Organization::rename(id, name, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_organization: JsonOrganization = Organization::get(id, user.id, &state.db)
+        .await
+        .map_err(map_not_found)?
+        .into();
+
+    Ok(Json(j_organization))
+}
+
+async fn list_members(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    require_role(id, user.id, |_| true, &state.db).await?;
+
+    let members = Member::list(id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_members: Vec<JsonMember> = members.into_iter().map(|m| m.into()).collect();
+    Ok(Json(j_members))
+}
+
+async fn update_member(
+    Path((id_str, member_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_member_role): Json<JsonMemberRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let member_id = parse_id(&member_id_str)?;
+    let role = require_role(id, user.id, Role::can_write, &state.db).await?;
+
+    let current_role = Organization::role(id, member_id, &state.db)
+        .await
+        .map_err(map_not_found)?;
+    if !role.can_manage(current_role) || !role.can_manage(j_member_role.role) {
+        return Err(AppError::Forbidden);
+    }
+    if !Member::update_role(id, member_id, j_member_role.role, &state.db).await.map_err(AppError::DbError)? {
+        return Err(AppError::Conflict("Organization must keep at least one owner".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_member(
+    Path((id_str, member_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let member_id = parse_id(&member_id_str)?;
+
+    // anyone may leave, removing someone else takes a role that can manage theirs
+    let role = require_role(id, user.id, |_| true, &state.db).await?;
+    let member_role = Organization::role(id, member_id, &state.db)
+        .await
+        .map_err(map_not_found)?;
+    if member_id != user.id && !(role.can_write() && role.can_manage(member_role)) {
+        return Err(AppError::Forbidden);
+    }
+    if !Member::delete(id, member_id, &state.db).await.map_err(AppError::DbError)? {
+        return Err(AppError::Conflict("Organization must keep at least one owner".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn create_invite(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_in_invite): Json<JsonInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let role = require_role(id, user.id, Role::can_write, &state.db).await?;
+    if !role.can_manage(j_in_invite.role) {
+        return Err(AppError::Forbidden);
+    }
+
+    let email = j_in_invite.email.trim().to_lowercase();
+    if !validator::ValidateEmail::validate_email(&email) {
+        return Err(AppError::InvalidInput("Invalid email".to_string()));
+    }
+    // only whoever reads the invited address gets the token
+    let config = ChannelConfig::Email { to: email.clone() };
+    if !state.channel_client.supports(&config) {
+        return Err(AppError::Conflict("Invites are sent by email, which is not configured on this server".to_string()));
+    }
+
+    let (token, token_hash) = auth::generate_invite_token();
+    let invite = Invite::create(id, &email, j_in_invite.role, user.id, &token_hash, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let message = format!(
+        "{} invited you to join {} as {}. Sign in as {} and accept with POST /invites/{}/accept and {{\"token\": \"{}\"}}",
+        user.email, invite.organization_name, invite.role.as_str(), email, invite.id, token,
+    );
+    let rendered = channel::compose(&config, None, None, &message, &TemplateValues::default());
+    if let Err(err_msg) = state.channel_client.send(&config, "Organization invite", &rendered).await {
+        error!("Failed send invite {}: {}", invite.id, err_msg);
+        return Err(AppError::InternalServerError);
+    }
+
+    let j_out_invite: JsonInvite = invite.into();
+    Ok((StatusCode::CREATED, Json(j_out_invite)))
+}
+
+async fn list_organization_invites(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    require_role(id, user.id, Role::can_write, &state.db).await?;
+
+    let invites = Invite::list_for_organization(id, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_invites: Vec<JsonInvite> = invites.into_iter().map(|i| i.into()).collect();
+    Ok(Json(j_invites))
+}
+
+async fn delete_invite(
+    Path((id_str, invite_id_str)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let invite_id = parse_id(&invite_id_str)?;
+    require_role(id, user.id, Role::can_write, &state.db).await?;
+
+    if Invite::delete(invite_id, id, &state.db)
+        .await
+        .map_err(AppError::DbError)?
+        .rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_my_invites(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = Invite::list_for_email(&user.email, &state.db)
+        .await
+        .map_err(AppError::DbError)?;
+
+    let j_invites: Vec<JsonInvite> = invites.into_iter().map(|i| i.into()).collect();
+    Ok(Json(j_invites))
+}
+
+async fn accept_invite(
+    Path(id_str): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(j_token): Json<JsonInviteToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = parse_id(&id_str)?;
+    let organization_id = Invite::accept(id, user.id, &user.email, &hash_token(j_token.token.trim()), &state.db)
+        .await
+        .map_err(map_not_found)?;
+
+    let j_organization: JsonOrganization = Organization::get(organization_id, user.id, &state.db)
+        .await
+        .map_err(map_not_found)?
+        .into();
+
+    Ok(Json(j_organization))
+}
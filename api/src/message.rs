@@ -0,0 +1,325 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, PgConnection, PgPool};
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::{Decimal, Uuid};
+
+use crate::invoice::JsonPublicInvoice;
+
+const MAX_DISPLAY_NAME_LEN: usize = 50;
+const MAX_MESSAGE_LEN: usize = 500;
+const PUBLIC_LIST_LIMIT: i64 = 50;
+
+/// What a donor sends before paying: either `invoice_id`, the public id of an open invoice on
+/// the donation, or `amount` (and `token`), for which an invoice with a unique amount is opened.
+#[derive(Deserialize)]
+pub struct JsonDonorMessageIn {
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub message: String,
+    /// Keeps the display name off the public page; the owner still sees it.
+    #[serde(default)]
+    pub anonymous: bool,
+    pub invoice_id: Option<String>,
+    pub amount: Option<Decimal>,
+    pub token: Option<String>,
+}
+
+impl JsonDonorMessageIn {
+    pub fn validate(&mut self) -> Result<(), String> {
+        self.display_name = self.display_name.as_ref().map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        self.message = self.message.trim().to_string();
+
+        match &self.display_name {
+            Some(name) => check_text("display_name", name, MAX_DISPLAY_NAME_LEN, false)?,
+            None if !self.anonymous => return Err("display_name is required unless anonymous".to_string()),
+            None => {},
+        }
+        check_text("message", &self.message, MAX_MESSAGE_LEN, true)?;
+
+        match (&self.invoice_id, self.amount) {
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err("pass either invoice_id or amount".to_string()),
+        }
+    }
+}
+
+/// Lengths count characters, and control characters are refused so names render on one line.
+fn check_text(field: &str, value: &str, max_len: usize, allow_newlines: bool) -> Result<(), String> {
+    if value.chars().count() > max_len {
+        return Err(format!("{} must be at most {} characters", field, max_len));
+    }
+    if value.chars().any(|c| c.is_control() && !(allow_newlines && c == '\n')) {
+        return Err(format!("{} must not contain control characters", field));
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct JsonDonorMessageModeration {
+    pub hidden: bool,
+}
+
+/// Returned to the donor, who pays the invoice to have the message shown.
+#[derive(Serialize)]
+pub struct JsonSubmittedMessage {
+    pub id: Uuid,
+    pub invoice: JsonPublicInvoice,
+}
+
+#[derive(Serialize)]
+pub struct JsonPublicMessage {
+    pub id: Uuid,
+    /// `None` for anonymous donors.
+    pub display_name: Option<String>,
+    pub message: String,
+    pub amount: Option<Decimal>,
+    pub token: String,
+    pub paid_at: Option<DateTime<Utc>>,
+}
+
+impl From<DonorMessage> for JsonPublicMessage {
+    fn from(message: DonorMessage) -> Self {
+        JsonPublicMessage {
+            id: message.id,
+            display_name: message.display_name.filter(|_| !message.anonymous),
+            message: message.message,
+            amount: message.amount,
+            token: message.token,
+            paid_at: message.paid_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DonorMessage {
+    pub id: Uuid,
+    pub donation_id: Uuid,
+    pub invoice_id: Uuid,
+    pub display_name: Option<String>,
+    pub message: String,
+    pub anonymous: bool,
+    pub hidden: bool,
+    pub token: String,
+    pub transaction_id: Option<String>,
+    pub amount: Option<Decimal>,
+    pub paid_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl DonorMessage {
+    pub async fn create(
+        donation_id: Uuid, invoice_id: Uuid, token: &str, j_message: &JsonDonorMessageIn, db: &PgPool
+    ) -> Result<DonorMessage, Error> {
+        sqlx::query_as!(
+            DonorMessage,
+            "
+            INSERT INTO donor_messages (donation_id, invoice_id, display_name, message, anonymous, token)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, donation_id, invoice_id, display_name, message, anonymous, hidden, token, transaction_id,
+                      amount, paid_at, created_at
+            ",
+            donation_id,
+            invoice_id,
+            j_message.display_name,
+            j_message.message,
+            j_message.anonymous,
+            token,
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    /// Every message of a donation, paid or not, newest first.
+    pub async fn list(donation_id: Uuid, db: &PgPool) -> Result<Vec<DonorMessage>, Error> {
+        sqlx::query_as!(
+            DonorMessage,
+            "
+            SELECT id, donation_id, invoice_id, display_name, message, anonymous, hidden, token, transaction_id,
+                   amount, paid_at, created_at
+            FROM donor_messages
+            WHERE donation_id = $1
+            ORDER BY created_at DESC
+            ",
+            donation_id
+        )
+            .fetch_all(db)
+            .await
+            .map(|messages| messages.into_iter().map(DonorMessage::normalized).collect())
+    }
+
+    /// The latest paid messages the owner didn't hide.
+    pub async fn list_public(donation_id: Uuid, db: &PgPool) -> Result<Vec<DonorMessage>, Error> {
+        sqlx::query_as!(
+            DonorMessage,
+            "
+            SELECT id, donation_id, invoice_id, display_name, message, anonymous, hidden, token, transaction_id,
+                   amount, paid_at, created_at
+            FROM donor_messages
+            WHERE donation_id = $1 AND paid_at IS NOT NULL AND NOT hidden
+            ORDER BY paid_at DESC
+            LIMIT $2
+            ",
+            donation_id,
+            PUBLIC_LIST_LIMIT,
+        )
+            .fetch_all(db)
+            .await
+            .map(|messages| messages.into_iter().map(DonorMessage::normalized).collect())
+    }
+
+    pub async fn set_hidden(id: Uuid, donation_id: Uuid, hidden: bool, db: &PgPool) -> Result<DonorMessage, Error> {
+        sqlx::query_as!(
+            DonorMessage,
+            "
+            UPDATE donor_messages SET hidden = $3
+            WHERE id = $1 AND donation_id = $2
+            RETURNING id, donation_id, invoice_id, display_name, message, anonymous, hidden, token, transaction_id,
+                      amount, paid_at, created_at
+            ",
+            id,
+            donation_id,
+            hidden,
+        )
+            .fetch_one(db)
+            .await
+            .map(DonorMessage::normalized)
+    }
+
+    pub async fn delete(id: Uuid, donation_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!("DELETE FROM donor_messages WHERE id = $1 AND donation_id = $2", id, donation_id)
+            .execute(db)
+            .await
+    }
+
+    pub async fn for_invoice(invoice_id: Uuid, db: &PgPool) -> Result<Option<DonorMessage>, Error> {
+        sqlx::query_as!(
+            DonorMessage,
+            "
+            SELECT id, donation_id, invoice_id, display_name, message, anonymous, hidden, token, transaction_id,
+                   amount, paid_at, created_at
+            FROM donor_messages
+            WHERE invoice_id = $1
+            ORDER BY created_at
+            LIMIT 1
+            ",
+            invoice_id,
+        )
+            .fetch_optional(db)
+            .await
+            .map(|message| message.map(DonorMessage::normalized))
+    }
+
+    /// The message paid for by a deposit, if any.
+    pub async fn for_transaction(
+        donation_id: Uuid, transaction_id: &str, db: &PgPool
+    ) -> Result<Option<DonorMessage>, Error> {
+        sqlx::query_as!(
+            DonorMessage,
+            "
+            SELECT id, donation_id, invoice_id, display_name, message, anonymous, hidden, token, transaction_id,
+                   amount, paid_at, created_at
+            FROM donor_messages
+            WHERE donation_id = $1 AND transaction_id = $2
+            ORDER BY created_at
+            LIMIT 1
+            ",
+            donation_id,
+            transaction_id,
+        )
+            .fetch_optional(db)
+            .await
+            .map(|message| message.map(DonorMessage::normalized))
+    }
+
+    /// Ties the message of an invoice to the first deposit paying it, keeping its amount at
+    /// what the invoice received so far.
+    pub async fn attach(
+        invoice_id: Uuid, transaction_id: &str, received: Decimal, conn: &mut PgConnection
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "
+            UPDATE donor_messages
+            SET transaction_id = COALESCE(transaction_id, $2), amount = $3,
+                paid_at = COALESCE(paid_at, CURRENT_TIMESTAMP)
+            WHERE invoice_id = $1
+            ",
+            invoice_id,
+            transaction_id,
+            received,
+        )
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+
+    fn normalized(mut self) -> DonorMessage {
+        self.amount = self.amount.map(|amount| amount.normalize());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn j_message(display_name: Option<&str>, message: &str, anonymous: bool) -> JsonDonorMessageIn {
+        JsonDonorMessageIn {
+            display_name: display_name.map(str::to_string),
+            message: message.to_string(),
+            anonymous,
+            invoice_id: None,
+            amount: Some(Decimal::TEN),
+            token: None,
+        }
+    }
+
+    #[test]
+    fn validates_messages() {
+        let mut message = j_message(Some("  Alice "), " great stream!\nthanks ", false);
+        assert!(message.validate().is_ok());
+        assert_eq!(message.display_name.as_deref(), Some("Alice"));
+        assert_eq!(message.message, "great stream!\nthanks");
+
+        assert!(j_message(None, "hi", true).validate().is_ok());
+        assert!(j_message(Some(" "), "hi", false).validate().is_err());
+        assert!(j_message(Some(&"я".repeat(50)), "", false).validate().is_ok());
+        assert!(j_message(Some(&"a".repeat(51)), "", false).validate().is_err());
+        assert!(j_message(Some("Alice\nBob"), "", false).validate().is_err());
+        assert!(j_message(Some("Alice"), "beep\u{7}", false).validate().is_err());
+        assert!(j_message(Some("Alice"), &"a".repeat(501), false).validate().is_err());
+    }
+
+    #[test]
+    fn binds_to_an_invoice_or_an_amount() {
+        let with_invoice = || JsonDonorMessageIn {
+            invoice_id: Some("0".repeat(32)),
+            amount: None,
+            ..j_message(Some("Alice"), "", false)
+        };
+        assert!(with_invoice().validate().is_ok());
+        assert!(JsonDonorMessageIn { amount: Some(Decimal::TEN), ..with_invoice() }.validate().is_err());
+        assert!(JsonDonorMessageIn { invoice_id: None, ..with_invoice() }.validate().is_err());
+    }
+
+    #[test]
+    fn hides_anonymous_names_publicly() {
+        let message = DonorMessage {
+            id: Uuid::nil(),
+            donation_id: Uuid::nil(),
+            invoice_id: Uuid::nil(),
+            display_name: Some("Alice".to_string()),
+            message: "great stream!".to_string(),
+            anonymous: true,
+            hidden: false,
+            token: "USDT".to_string(),
+            transaction_id: None,
+            amount: Some(Decimal::TEN),
+            paid_at: None,
+            created_at: Utc::now(),
+        };
+        assert_eq!(JsonPublicMessage::from(message).display_name, None);
+    }
+}
@@ -1,9 +1,16 @@
 use serde::{Serialize, Deserialize};
 use serde_json::{json, Value};
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgExecutor, PgPool, Postgres, Transaction};
 use sqlx::postgres::PgQueryResult;
 use sqlx::types::{Uuid, Decimal};
-use regex::Regex;
+use sqlx::types::chrono::{DateTime, Utc};
+use axum::http::Method;
+use crate::chain::Chain;
+use crate::crypto::{EncryptedSecret, MasterKey};
+use crate::error::AppError;
+use crate::lifecycle::DonationStatus;
+use crate::organization::Organization;
+use crate::transaction::{AddressStats, StatsScope};
 
 #[derive(Serialize, Deserialize)]
 pub struct JsonDonation {
@@ -13,6 +20,35 @@ pub struct JsonDonation {
     pub description: Option<String>,
     pub webhook: Option<String>,
     pub wallet_id: Option<String>,
+    pub organization_id: Option<String>,
+    pub extended_key_id: Option<String>,
+    pub chain: Option<String>,
+    pub token: Option<String>,
+    /// Only read on create, where it defaults to `scheduled` or `active` depending on
+    /// `starts_at`; afterwards it is changed through `POST /donations/:id/status`.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Stop watching the wallet once the goal is reached and no other active donation uses it.
+    #[serde(default)]
+    pub deactivate_wallet_on_completion: bool,
+    /// Create and link a dedicated receiving wallet instead of passing `wallet_id`.
+    #[serde(default, skip_serializing)]
+    pub provision_wallet: bool,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<DonationProgress>,
+    /// Where donors find the donation, along with `public_id`: `GET /public/donations/<slug>`.
+    #[serde(default)]
+    pub slug: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub public_id: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub created_at: Option<DateTime<Utc>>,
 }
 
 pub struct Donation {
@@ -22,55 +58,166 @@ pub struct Donation {
     pub description: Option<String>,
     pub webhook: Option<String>,
     pub wallet_id: Option<Uuid>,
-    pub user_id: Option<Uuid>
-}
-
-impl Into<Donation> for JsonDonation {
-    fn into(self) -> Donation {
-        Donation {
-            id: self.id.map_or_else(Uuid::new_v4, |id_str| Uuid::parse_str(&id_str).unwrap_or(Uuid::new_v4())),    // TODO
-            amount: self.amount,
-            title: self.title,
-            description: self.description,
-            webhook: self.webhook,
-            wallet_id: self.wallet_id.map_or(None, |id_str| Some(Uuid::parse_str(&id_str).unwrap())),    // TODO
-            user_id: None
+    pub user_id: Option<Uuid>,
+    pub organization_id: Option<Uuid>,
+    pub extended_key_id: Option<Uuid>,
+    pub chain: String,
+    pub token: Option<String>,
+    pub status: String,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub deactivate_wallet_on_completion: bool,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub public_id: String,
+    pub slug: Option<String>,
+    /// `None` for donations created before it was recorded.
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// How far a donation got towards its goal.
+#[derive(Serialize)]
+pub struct DonationProgress {
+    pub raised: Decimal,
+    pub percent: Decimal,
+    pub donors: i64,
+    pub deposits: i64,
+    pub last_deposit_at: Option<DateTime<Utc>>,
+}
+
+impl DonationProgress {
+    pub fn new(goal: Decimal, stats: AddressStats) -> DonationProgress {
+        DonationProgress {
+            raised: stats.received,
+            percent: DonationProgress::percent(goal, stats.received),
+            donors: stats.donors,
+            deposits: stats.deposits,
+            last_deposit_at: stats.last_deposit_timestamp.and_then(|ts| DateTime::from_timestamp(ts, 0)),
         }
     }
+
+    pub fn percent(goal: Decimal, raised: Decimal) -> Decimal {
+        match goal > Decimal::ZERO {
+            true => (raised * Decimal::ONE_HUNDRED / goal).round_dp(2),
+            false => Decimal::ONE_HUNDRED,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonDonationStatus {
+    pub status: DonationStatus,
 }
 
-impl Into<JsonDonation> for Donation {
-    fn into(self) -> JsonDonation {
+/// A malformed id is an error rather than a missing one, so it can't silently fall back to a default.
+fn parse_optional_id(id_str: Option<&str>, field: &str) -> Result<Option<Uuid>, AppError> {
+    id_str
+        .map(|id_str| Uuid::parse_str(id_str).map_err(|_| AppError::InvalidInput(format!("Invalid {}", field))))
+        .transpose()
+}
+
+impl TryFrom<JsonDonation> for Donation {
+    type Error = AppError;
+
+    fn try_from(value: JsonDonation) -> Result<Donation, AppError> {
+        Ok(Donation {
+            id: value.id.map_or_else(Uuid::new_v4, |id_str| Uuid::parse_str(&id_str).unwrap_or(Uuid::new_v4())),    // TODO
+            wallet_id: parse_optional_id(value.wallet_id.as_deref(), "wallet_id")?,
+            organization_id: parse_optional_id(value.organization_id.as_deref(), "organization_id")?,
+            extended_key_id: parse_optional_id(value.extended_key_id.as_deref(), "extended_key_id")?,
+            amount: value.amount,
+            title: value.title,
+            description: value.description,
+            webhook: value.webhook,
+            user_id: None,
+            chain: value.chain.unwrap_or_else(|| Chain::default().as_str().to_string()),
+            token: value.token.map(|token| token.to_uppercase()),
+            status: DonationStatus::Active.as_str().to_string(),
+            completed_at: None,
+            deactivate_wallet_on_completion: value.deactivate_wallet_on_completion,
+            starts_at: value.starts_at,
+            ends_at: value.ends_at,
+            public_id: String::new(),
+            slug: value.slug,
+            created_at: None,
+        })
+    }
+}
+
+impl From<Donation> for JsonDonation {
+    fn from(donation: Donation) -> JsonDonation {
         JsonDonation {
-            id: Some(self.id.to_string()),
-            amount: self.amount,
-            title: self.title,
-            description: self.description,
-            webhook: self.webhook,
-            wallet_id: self.wallet_id.map_or_else(|| None, |wid| Some(wid.to_string())),
+            id: Some(donation.id.to_string()),
+            amount: donation.amount,
+            title: donation.title,
+            description: donation.description,
+            webhook: donation.webhook,
+            wallet_id: donation.wallet_id.map(|wid| wid.to_string()),
+            organization_id: donation.organization_id.map(|oid| oid.to_string()),
+            extended_key_id: donation.extended_key_id.map(|kid| kid.to_string()),
+            chain: Some(donation.chain),
+            token: donation.token,
+            status: Some(donation.status),
+            starts_at: donation.starts_at,
+            ends_at: donation.ends_at,
+            completed_at: donation.completed_at,
+            deactivate_wallet_on_completion: donation.deactivate_wallet_on_completion,
+            provision_wallet: false,
+            progress: None,
+            slug: donation.slug,
+            public_id: Some(donation.public_id),
+            created_at: donation.created_at,
         }
     }
 }
 
+// Donations and wallets belong to an organization; `user_id` arguments below are the
+// acting user, whose membership (and role, for writes) is checked in the query itself.
 impl Donation {
-    pub async fn create(self, user_id: Uuid, db: &PgPool) -> Result<Donation, Error> {
+    pub async fn create<'e, E: PgExecutor<'e>>(
+        self, user_id: Uuid, organization_id: Uuid, executor: E
+    ) -> Result<Donation, Error> {
         sqlx::query_as!(
             Donation,
-            "INSERT INTO donations (amount, title, description, webhook, wallet_id, user_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+            "
+            INSERT INTO donations (
+              amount, title, description, webhook, wallet_id, user_id, organization_id, extended_key_id, chain, token,
+              deactivate_wallet_on_completion, status, starts_at, ends_at, slug
+            )
+            SELECT $1, $2, $3, $4, $5, $6, organization_id, $8, $9, $10, $11, $12, $13, $14, $15
+            FROM organization_members
+            WHERE organization_id = $7 AND user_id = $6 AND role IN ('owner', 'admin')
+            RETURNING *
+            ",
             self.amount,
             self.title,
             self.description,
             self.webhook,
             self.wallet_id,
             user_id,
+            organization_id,
+            self.extended_key_id,
+            self.chain,
+            self.token,
+            self.deactivate_wallet_on_completion,
+            self.status,
+            self.starts_at,
+            self.ends_at,
+            self.slug,
         )
-            .fetch_one(db)
+            .fetch_one(executor)
             .await
     }
 
     pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<Donation, Error> {
         sqlx::query_as!(
-            Donation, "SELECT * FROM donations WHERE id = $1 AND user_id = $2", id, user_id
+            Donation,
+            "
+            SELECT * FROM donations
+            WHERE id = $1
+              AND organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $2)
+            ",
+            id,
+            user_id
         )
             .fetch_one(db)
             .await
@@ -78,7 +225,12 @@ impl Donation {
 
     pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<Donation>, Error> {
         sqlx::query_as!(
-            Donation, "SELECT * FROM donations WHERE user_id = $1", user_id
+            Donation,
+            "
+            SELECT * FROM donations
+            WHERE organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
+            ",
+            user_id
         )
             .fetch_all(db)
             .await
@@ -86,7 +238,15 @@ impl Donation {
 
     pub async fn delete(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
         sqlx::query!(
-            "DELETE FROM donations WHERE id = $1 AND user_id = $2", id, user_id
+            "
+            DELETE FROM donations
+            WHERE id = $1
+              AND organization_id IN (
+                SELECT organization_id FROM organization_members WHERE user_id = $2 AND role IN ('owner', 'admin')
+              )
+            ",
+            id,
+            user_id
         )
             .execute(db)
             .await
@@ -97,8 +257,13 @@ impl Donation {
             Donation,
             "
             UPDATE donations
-            SET amount = $1, title = $2, description = $3, webhook = $4, wallet_id = $5
-            WHERE id = $6 AND user_id = $7
+            SET amount = $1, title = $2, description = $3, webhook = $4, wallet_id = $5, extended_key_id = $8,
+                chain = $9, token = $10, deactivate_wallet_on_completion = $11, starts_at = $12, ends_at = $13,
+                slug = $14
+            WHERE id = $6
+              AND organization_id IN (
+                SELECT organization_id FROM organization_members WHERE user_id = $7 AND role IN ('owner', 'admin')
+              )
             RETURNING *
             ",
             self.amount,
@@ -107,15 +272,133 @@ impl Donation {
             self.webhook,
             self.wallet_id,
             id,
-            user_id
+            user_id,
+            self.extended_key_id,
+            self.chain,
+            self.token,
+            self.deactivate_wallet_on_completion,
+            self.starts_at,
+            self.ends_at,
+            self.slug,
         )
             .fetch_one(db)
             .await
     }
 
+    /// Marks an active donation completed, returning whether it was still active.
+    pub async fn complete(id: Uuid, db: &PgPool) -> Result<bool, Error> {
+        sqlx::query!(
+            "
+            UPDATE donations SET status = $2, completed_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND status = $3
+            ",
+            id,
+            DonationStatus::Completed.as_str(),
+            DonationStatus::Active.as_str(),
+        )
+            .execute(db)
+            .await
+            .map(|result| result.rows_affected() > 0)
+    }
+
+    /// A donation donors may see, by its public id or slug.
+    pub async fn get_public(key: &str, db: &PgPool) -> Result<Donation, Error> {
+        let visible: Vec<String> = DonationStatus::PUBLIC.iter().map(|status| status.as_str().to_string()).collect();
+
+        sqlx::query_as!(
+            Donation,
+            "SELECT * FROM donations WHERE (public_id = $1 OR slug = $1) AND status = ANY($2)",
+            key,
+            &visible,
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    /// What counts towards the donation's progress.
+    pub async fn stats_scope(id: Uuid, db: &PgPool) -> Result<StatsScope, Error> {
+        let row = sqlx::query!("SELECT chain, token, created_at, starts_at FROM donations WHERE id = $1", id)
+            .fetch_one(db)
+            .await?;
+        let chain = Chain::try_from(row.chain.as_str()).unwrap_or_default();
+
+        Ok(StatsScope::new(chain, row.token.as_deref(), row.created_at, row.starts_at))
+    }
+
+    /// Moves a donation from `from` to `to`, failing with `RowNotFound` if it is no longer in `from`.
+    pub async fn set_status<'e, E: PgExecutor<'e>>(
+        id: Uuid, from: DonationStatus, to: DonationStatus, executor: E,
+    ) -> Result<Donation, Error> {
+        sqlx::query_as!(
+            Donation,
+            "
+            UPDATE donations
+            SET status = $3,
+                completed_at = CASE WHEN $4 THEN CURRENT_TIMESTAMP ELSE completed_at END
+            WHERE id = $1 AND status = $2
+            RETURNING *
+            ",
+            id,
+            from.as_str(),
+            to.as_str(),
+            to == DonationStatus::Completed,
+        )
+            .fetch_one(executor)
+            .await
+    }
+
+    /// Activates scheduled donations whose window opened, returning them with their wallets.
+    pub async fn start_due<'e, E: PgExecutor<'e>>(executor: E) -> Result<Vec<(Uuid, Option<Uuid>)>, Error> {
+        sqlx::query!(
+            "
+            UPDATE donations SET status = $1
+            WHERE status = $2
+              AND (starts_at IS NULL OR starts_at <= CURRENT_TIMESTAMP)
+              AND (ends_at IS NULL OR ends_at > CURRENT_TIMESTAMP)
+            RETURNING id, wallet_id
+            ",
+            DonationStatus::Active.as_str(),
+            DonationStatus::Scheduled.as_str(),
+        )
+            .fetch_all(executor)
+            .await
+            .map(|rows| rows.into_iter().map(|row| (row.id, row.wallet_id)).collect())
+    }
+
+    /// Completes donations whose window closed, returning them with their wallets.
+    pub async fn end_due<'e, E: PgExecutor<'e>>(executor: E) -> Result<Vec<(Uuid, Option<Uuid>)>, Error> {
+        let open = [DonationStatus::Scheduled, DonationStatus::Active, DonationStatus::Paused]
+            .map(|status| status.as_str().to_string());
+
+        sqlx::query!(
+            "
+            UPDATE donations SET status = $1, completed_at = CURRENT_TIMESTAMP
+            WHERE status = ANY($2) AND ends_at <= CURRENT_TIMESTAMP
+            RETURNING id, wallet_id
+            ",
+            DonationStatus::Completed.as_str(),
+            &open,
+        )
+            .fetch_all(executor)
+            .await
+            .map(|rows| rows.into_iter().map(|row| (row.id, row.wallet_id)).collect())
+    }
+
+    /// Every donation on the wallet, for system tasks acting on nobody's behalf.
+    pub async fn ids_using_wallet<'e, E: PgExecutor<'e>>(wallet_id: Uuid, executor: E) -> Result<Vec<Uuid>, Error> {
+        sqlx::query!("SELECT id FROM donations WHERE wallet_id = $1", wallet_id)
+            .fetch_all(executor)
+            .await
+            .map(|rows| rows.into_iter().map(|row| row.id).collect())
+    }
+
     pub async fn ids_by_wallet_id(wallet_id: Uuid, user_id: Uuid, db: &PgPool) -> Result<Vec<Uuid>, Error> {
         sqlx::query!(
-            "SELECT id FROM donations WHERE wallet_id = $1 AND user_id = $2",
+            "
+            SELECT id FROM donations
+            WHERE wallet_id = $1
+              AND organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $2)
+            ",
             wallet_id,
             user_id
         )
@@ -132,62 +415,462 @@ pub async fn get_connection(db_url: &str) -> Result<PgPool, Error> {
 #[derive(Clone, Debug)]
 pub struct User {
     pub id: Uuid,
-    // pub email: String,
+    pub email: String,
 }
 
 impl User {
-    pub async fn get(id: Uuid) -> Result<User, Error> {
-        Ok(Self {
+    pub async fn get(id: Uuid, db: &PgPool) -> Result<User, Error> {
+        sqlx::query_as!(
+            User, "SELECT id, email FROM users WHERE id = $1", id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn get_by_email(email: &str, db: &PgPool) -> Result<User, Error> {
+        sqlx::query_as!(
+            User, "SELECT id, email FROM users WHERE email = $1", email
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    /// Registers a user together with the personal organization that owns their data.
+    pub async fn create(email: &str, password_hash: &str, db: &PgPool) -> Result<User, Error> {
+        let mut tx = db.begin().await?;
+
+        let user = sqlx::query_as!(
+            User,
+            "INSERT INTO users (email, password_hash, webhook_secret) VALUES ($1, $2, $3) RETURNING id, email",
+            email,
+            password_hash,
+            crate::webhook::generate_secret(),
+        )
+            .fetch_one(&mut *tx)
+            .await?;
+        Organization::create_in(email, user.id, &mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    pub async fn get_password_hash(id: Uuid, db: &PgPool) -> Result<Option<String>, Error> {
+        sqlx::query!(
+            "SELECT password_hash FROM users WHERE id = $1", id
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.password_hash)
+    }
+
+    pub async fn set_password_hash(id: Uuid, password_hash: &str, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!(
+            "UPDATE users SET password_hash = $1 WHERE id = $2", password_hash, id
+        )
+            .execute(db)
+            .await
+    }
+
+    /// The secrets webhooks are signed with, generated with the user and on rotation.
+    pub async fn webhook_secret(id: Uuid, db: &PgPool) -> Result<WebhookSecret, Error> {
+        sqlx::query_as!(
+            WebhookSecret,
+            r#"
+            SELECT webhook_secret AS secret,
+                      CASE WHEN previous_webhook_secret_expires_at > CURRENT_TIMESTAMP
+                        THEN previous_webhook_secret END AS previous_secret,
+                      CASE WHEN previous_webhook_secret_expires_at > CURRENT_TIMESTAMP
+                        THEN previous_webhook_secret_expires_at END AS previous_secret_expires_at
+            FROM users
+            WHERE id = $1
+            "#,
             id,
-            // email: "admin".to_string()
-        })
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    /// Replaces the webhook secret, still signing with the old one until `grace_period` passes.
+    pub async fn rotate_webhook_secret(
+        id: Uuid, grace_period: chrono::Duration, db: &PgPool
+    ) -> Result<WebhookSecret, Error> {
+        sqlx::query_as!(
+            WebhookSecret,
+            r#"
+            UPDATE users
+            SET previous_webhook_secret = webhook_secret,
+                previous_webhook_secret_expires_at = $3,
+                webhook_secret = $2
+            WHERE id = $1
+            RETURNING webhook_secret AS secret,
+                      CASE WHEN previous_webhook_secret_expires_at > CURRENT_TIMESTAMP
+                        THEN previous_webhook_secret END AS previous_secret,
+                      CASE WHEN previous_webhook_secret_expires_at > CURRENT_TIMESTAMP
+                        THEN previous_webhook_secret_expires_at END AS previous_secret_expires_at
+            "#,
+            id,
+            crate::webhook::generate_secret(),
+            Utc::now() + grace_period,
+        )
+            .fetch_one(db)
+            .await
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+/// A user's webhook secret, and the one it replaced while that is still accepted.
+pub struct WebhookSecret {
+    pub secret: String,
+    pub previous_secret: Option<String>,
+    pub previous_secret_expires_at: Option<DateTime<Utc>>,
+}
+
+impl WebhookSecret {
+    /// Newest first.
+    pub fn signing_secrets(self) -> Vec<String> {
+        std::iter::once(self.secret).chain(self.previous_secret).collect()
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct JsonWebhookSecretRotation {
+    pub grace_period_hours: Option<i64>,
+}
+
+pub struct RefreshToken;
+
+impl RefreshToken {
+    pub async fn create(
+        user_id: Uuid, token_hash: &str, expires_at: DateTime<Utc>, db: &PgPool
+    ) -> Result<PgQueryResult, Error> {
+        sqlx::query!(
+            "INSERT INTO refresh_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+            user_id,
+            token_hash,
+            expires_at,
+        )
+            .execute(db)
+            .await
+    }
+
+    /// Revokes a live refresh token and returns its owner, so it can be used only once.
+    pub async fn consume(token_hash: &str, db: &PgPool) -> Result<Uuid, Error> {
+        sqlx::query!(
+            "
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id
+            ",
+            token_hash
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.user_id)
+    }
+
+    pub async fn revoke_all(user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!(
+            "
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ",
+            user_id
+        )
+            .execute(db)
+            .await
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    Read,
+    ReadWrite,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::ReadWrite => "read_write",
+        }
+    }
+
+    /// Read-only keys may only be used with safe HTTP methods.
+    pub fn allows(&self, method: &Method) -> bool {
+        match self {
+            ApiKeyScope::Read => matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS),
+            ApiKeyScope::ReadWrite => true,
+        }
+    }
+}
+
+impl TryFrom<&str> for ApiKeyScope {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "read" => Ok(ApiKeyScope::Read),
+            "read_write" => Ok(ApiKeyScope::ReadWrite),
+            _ => Err(format!("unknown scope: {}", value)),
+        }
+    }
+}
+
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scope: ApiKeyScope,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+pub struct ApiKeyRow {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scope: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        ApiKey {
+            id: row.id,
+            name: row.name,
+            prefix: row.prefix,
+            scope: row.scope.as_str().try_into().unwrap(),
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonApiKey {
+    pub id: Option<String>,
+    pub name: String,
+    pub scope: ApiKeyScope,
+    pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,    // plaintext secret, only returned once on creation
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for JsonApiKey {
+    fn from(api_key: ApiKey) -> Self {
+        JsonApiKey {
+            id: Some(api_key.id.to_string()),
+            name: api_key.name,
+            scope: api_key.scope,
+            prefix: Some(api_key.prefix),
+            key: None,
+            created_at: Some(api_key.created_at),
+            last_used_at: api_key.last_used_at,
+            revoked_at: api_key.revoked_at,
+        }
+    }
+}
+
+impl ApiKey {
+    pub async fn create(
+        user_id: Uuid, name: &str, scope: ApiKeyScope, prefix: &str, key_hash: &str, db: &PgPool
+    ) -> Result<ApiKey, Error> {
+        sqlx::query_as!(
+            ApiKeyRow,
+            "
+            INSERT INTO api_keys (user_id, name, prefix, key_hash, scope)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, prefix, scope, created_at, last_used_at, revoked_at
+            ",
+            user_id,
+            name,
+            prefix,
+            key_hash,
+            scope.as_str(),
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.into())
+    }
+
+    pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<ApiKey>, Error> {
+        sqlx::query_as!(
+            ApiKeyRow,
+            "
+            SELECT id, name, prefix, scope, created_at, last_used_at, revoked_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY created_at
+            ",
+            user_id
+        )
+            .fetch_all(db)
+            .await
+            .map(|rows| rows.into_iter().map(|row| row.into()).collect())
+    }
+
+    pub async fn revoke(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!(
+            "
+            UPDATE api_keys
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            ",
+            id,
+            user_id
+        )
+            .execute(db)
+            .await
+    }
+
+    /// Looks up an active key by the hash of its secret and records its use.
+    pub async fn authenticate(key_hash: &str, db: &PgPool) -> Result<(Uuid, ApiKeyScope), Error> {
+        let row = sqlx::query!(
+            "
+            UPDATE api_keys
+            SET last_used_at = CURRENT_TIMESTAMP
+            WHERE key_hash = $1 AND revoked_at IS NULL
+            RETURNING user_id, scope
+            ",
+            key_hash
+        )
+            .fetch_one(db)
+            .await?;
+
+        let scope = row.scope.as_str().try_into().map_err(|e: String| Error::Decode(e.into()))?;
+
+        Ok((row.user_id, scope))
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct WalletData {
     pub address: String,
-    pub private_key: Option<String>,    // TODO cypher
+    // Plaintext keys only exist in legacy rows and must be sealed with
+    // `api rotate-master-key`, the api refuses to start while any are left.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_private_key: Option<EncryptedSecret>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derivation: Option<Derivation>,
+}
+
+/// Where a watch-only address was derived from: `<extended key>/0/<index>`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Derivation {
+    pub extended_key_id: Uuid,
+    pub index: i32,
 }
 
 impl WalletData {
-    pub fn validate(self) -> Result<(), String> {
-        if self.private_key.is_some() {
-            return Err("private_key not allowed".to_string())
+    pub fn seal_private_key(private_key: &str, address: &str, master_key: &MasterKey) -> Result<EncryptedSecret, String> {
+        master_key.encrypt(private_key.as_bytes(), address.as_bytes())
+    }
+
+    /// Only for code paths that have to sign with the wallet; everything else works
+    /// with the address alone.
+    pub fn decrypt_private_key(&self, master_key: &MasterKey) -> Result<Option<String>, String> {
+        match &self.encrypted_private_key {
+            Some(secret) => {
+                let bytes = master_key.decrypt(secret, self.address.as_bytes())?;
+                String::from_utf8(bytes)
+                    .map(Some)
+                    .map_err(|_| "private key is not valid utf-8".to_string())
+            },
+            None => Ok(self.private_key.clone()),
         }
+    }
 
-        let re = Regex::new(r"^T[A-Za-z1-9]{33}$").unwrap();
-        if !re.is_match(&self.address) {
-            return Err("invalid address".to_string())
+    /// The data with its key sealed with `master_key`, or `None` when it already is or has
+    /// no key. Keys sealed with another master key need that one as `old_master_key`.
+    pub fn reseal(&self, master_key: &MasterKey, old_master_key: Option<&MasterKey>) -> Result<Option<WalletData>, String> {
+        let private_key = match &self.encrypted_private_key {
+            Some(secret) if secret.key_id == master_key.id() => return Ok(None),
+            Some(_) => {
+                let old_master_key = old_master_key.ok_or("sealed with another key, set OLD_MASTER_KEY")?;
+                self.decrypt_private_key(old_master_key)?
+            },
+            None => self.private_key.clone(),
+        };
+        let Some(private_key) = private_key else { return Ok(None) };
+
+        Ok(Some(WalletData {
+            address: self.address.clone(),
+            private_key: None,
+            encrypted_private_key: Some(WalletData::seal_private_key(&private_key, &self.address, master_key)?),
+            derivation: self.derivation.clone(),
+        }))
+    }
+
+    pub fn validate(&self, chain: Chain) -> Result<(), AppError> {
+        if self.private_key.is_some() {
+            return Err(AppError::InvalidInput("private_key not allowed, use POST /wallets/import".to_string()))
         }
 
-        Ok(())
+        chain.validate_address(&self.address)
+            .map_err(|error| AppError::InvalidAddress { chain, error })
     }
 }
 
 #[derive(Clone)]
 pub struct Wallet {
     pub id: Uuid,
+    pub chain: Chain,
     pub data: WalletData,
     pub is_active: bool,
+    pub spendable: bool,
+    #[allow(dead_code)]
     pub user_id: Option<Uuid>,
+    pub organization_id: Option<Uuid>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct JsonWallet {
     pub id: Option<String>,
+    pub chain: Option<String>,
     pub data: Option<Value>,
     pub is_active: Option<bool>,
+    pub spendable: Option<bool>,
+    pub organization_id: Option<String>,
+}
+
+/// Either `mnemonic` (with an optional BIP44 `path`) or `private_key`; `address`, when
+/// given, must match the one derived from the secret.
+#[derive(Deserialize)]
+pub struct JsonWalletImport {
+    pub chain: Option<String>,
+    pub mnemonic: Option<String>,
+    pub passphrase: Option<String>,
+    pub path: Option<String>,
+    pub private_key: Option<String>,
+    pub address: Option<String>,
+    pub is_active: Option<bool>,
+    pub organization_id: Option<String>,
 }
 
 impl From<Wallet> for JsonWallet {
     fn from(wallet: Wallet) -> Self {
         JsonWallet {
             id: Some(wallet.id.to_string()),
+            chain: Some(wallet.chain.as_str().to_string()),
             data: Some(json!({
                 "address": wallet.data.address,
+                "derivation": wallet.data.derivation,
             })),
             is_active: Some(wallet.is_active),
+            spendable: Some(wallet.spendable),
+            organization_id: wallet.organization_id.map(|oid| oid.to_string()),
         }
     }
 }
@@ -196,18 +879,24 @@ impl From<JsonWallet> for Wallet {
     fn from(value: JsonWallet) -> Wallet {
         Wallet {
             id: value.id.map_or_else(Uuid::new_v4, |id_str| Uuid::parse_str(&id_str).unwrap_or(Uuid::new_v4())),    // TODO,
-            data: value.data.unwrap_or(WalletData { address: "".to_string(), private_key: None }.into()).into(),
+            chain: value.chain.and_then(|chain| chain.as_str().try_into().ok()).unwrap_or_default(),
+            data: value.data.unwrap_or(WalletData::default().into()).into(),
             is_active: value.is_active.unwrap_or(false),
+            spendable: false,
             user_id: None,
+            organization_id: value.organization_id.and_then(|id_str| Uuid::parse_str(&id_str).ok()),
         }
     }
 }
 
 pub struct WalletRow {
     pub id: Uuid,
+    pub chain: String,
     pub data: Value,
     pub is_active: bool,
+    pub spendable: bool,
     pub user_id: Uuid,
+    pub organization_id: Uuid,
 }
 
 impl From<Value> for WalletData {
@@ -226,43 +915,64 @@ impl From<WalletRow> for Wallet {
     fn from(row: WalletRow) -> Self {
         Wallet {
             id: row.id,
+            chain: row.chain.as_str().try_into().unwrap(),
             data: row.data.into(),
             is_active: row.is_active,
-            user_id: Some(row.user_id)
+            spendable: row.spendable,
+            user_id: Some(row.user_id),
+            organization_id: Some(row.organization_id),
         }
     }
 }
 
 impl Wallet {
-    pub async fn create(
-        pool: &PgPool, address: String, private_key: Option<String>, is_active: bool, user_id: Uuid
+    pub async fn create<'e, E: PgExecutor<'e>>(
+        executor: E,
+        chain: Chain,
+        data: WalletData,
+        is_active: bool,
+        user_id: Uuid,
+        organization_id: Uuid,
     ) -> Result<Wallet, Error> {
-        let data: Value = json!(WalletData { address, private_key });
+        let spendable = data.encrypted_private_key.is_some();
+        let data: Value = data.into();
 
         let row = sqlx::query!(
            "
-           INSERT INTO wallets (data, is_active, user_id)
-           VALUES ($1, $2, $3)
-           RETURNING id, data, is_active, user_id
+           INSERT INTO wallets (chain, data, is_active, spendable, user_id, organization_id)
+           SELECT $1, $2, $3, $4, $5, organization_id
+           FROM organization_members
+           WHERE organization_id = $6 AND user_id = $5 AND role IN ('owner', 'admin')
+           RETURNING id, data, is_active, spendable, user_id, organization_id
            ",
-           data, is_active, user_id
+           chain.as_str(), data, is_active, spendable, user_id, organization_id
         )
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await?;
 
         let wallet_data: WalletData = serde_json::from_value(row.data).unwrap();
 
         Ok(Wallet {
             id: row.id,
+            chain,
             data: wallet_data,
             is_active: row.is_active,
+            spendable: row.spendable,
             user_id: Some(row.user_id),
+            organization_id: Some(row.organization_id),
         })
     }
 
     pub async fn get(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Wallet, Error> {
         let row = sqlx::query_as!(
-            WalletRow, "SELECT * FROM wallets WHERE id = $1 AND user_id = $2", id, user_id
+            WalletRow,
+            "
+            SELECT * FROM wallets
+            WHERE id = $1
+              AND organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $2)
+            ",
+            id,
+            user_id
         )
             .fetch_one(pool)
             .await?;
@@ -273,7 +983,10 @@ impl Wallet {
     pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<Wallet>, Error> {
         sqlx::query_as!(
             WalletRow,
-            "SELECT * FROM wallets WHERE user_id = $1",
+            "
+            SELECT * FROM wallets
+            WHERE organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
+            ",
             user_id
         )
         .fetch_all(db)
@@ -281,36 +994,309 @@ impl Wallet {
         .map(|rows| rows.into_iter().map(|row| row.into()).collect())
     }
 
-    pub async fn update(
-        self,
-        id: Uuid,
-        user_id: Uuid,
-        db: &PgPool,
-    ) -> Result<Wallet, Error> {
+    /// The address of a wallet, without checking who is asking.
+    pub async fn address(id: Uuid, db: &PgPool) -> Result<String, Error> {
+        sqlx::query!(r#"SELECT data->>'address' AS "address!" FROM wallets WHERE id = $1"#, id)
+            .fetch_one(db)
+            .await
+            .map(|row| row.address)
+    }
+
+    /// Switches a wallet on or off, returning it if that changed anything. Changes are queued
+    /// for the collector along with the switch, see `lifecycle::sync_collector`.
+    pub async fn set_active<'e, E: PgExecutor<'e>>(id: Uuid, is_active: bool, executor: E) -> Result<Option<Wallet>, Error> {
         sqlx::query_as!(
-            WalletRow, // Use WalletRow for the result type
+            WalletRow,
             r#"
-            UPDATE wallets
-            SET is_active = $1
-            WHERE id = $2 AND user_id = $3
-            RETURNING *
+            WITH switched AS (
+              UPDATE wallets SET is_active = $2 WHERE id = $1 AND is_active <> $2 RETURNING *
+            ), queued AS (
+              INSERT INTO collector_outbox (wallet_id) SELECT id FROM switched ON CONFLICT DO NOTHING
+            )
+            SELECT id AS "id!", chain AS "chain!", data AS "data!", is_active AS "is_active!", spendable AS "spendable!",
+                   user_id AS "user_id!", organization_id AS "organization_id!"
+            FROM switched
             "#,
-            self.is_active,
             id,
-            user_id
+            is_active,
         )
-        .fetch_one(db)
-        .await
-        .map(|row| row.into())
+            .fetch_optional(executor)
+            .await
+            .map(|row| row.map(Wallet::from))
+    }
+
+    /// Switches off a wallet no active donation uses any more, returning it if it was on.
+    pub async fn deactivate_unused<'e, E: PgExecutor<'e>>(id: Uuid, executor: E) -> Result<Option<Wallet>, Error> {
+        sqlx::query_as!(
+            WalletRow,
+            r#"
+            WITH switched AS (
+              UPDATE wallets SET is_active = FALSE
+              WHERE id = $1 AND is_active
+                AND NOT EXISTS (SELECT 1 FROM donations WHERE wallet_id = $1 AND status = $2)
+              RETURNING *
+            ), queued AS (
+              INSERT INTO collector_outbox (wallet_id) SELECT id FROM switched ON CONFLICT DO NOTHING
+            )
+            SELECT id AS "id!", chain AS "chain!", data AS "data!", is_active AS "is_active!", spendable AS "spendable!",
+                   user_id AS "user_id!", organization_id AS "organization_id!"
+            FROM switched
+            "#,
+            id,
+            DonationStatus::Active.as_str(),
+        )
+            .fetch_optional(executor)
+            .await
+            .map(|row| row.map(Wallet::from))
+    }
+
+    /// Wallets switched since the collector was last told, oldest first.
+    pub async fn unsynced(db: &PgPool) -> Result<Vec<Wallet>, Error> {
+        sqlx::query_as!(
+            WalletRow,
+            "
+            SELECT wallets.* FROM wallets
+            JOIN collector_outbox ON collector_outbox.wallet_id = wallets.id
+            ORDER BY collector_outbox.queued_at
+            "
+        )
+            .fetch_all(db)
+            .await
+            .map(|rows| rows.into_iter().map(Wallet::from).collect())
+    }
+
+    /// Clears a wallet from the collector outbox, unless it was switched again since.
+    pub async fn mark_synced(id: Uuid, is_active: bool, db: &PgPool) -> Result<(), Error> {
+        sqlx::query!(
+            "
+            DELETE FROM collector_outbox
+            USING wallets
+            WHERE collector_outbox.wallet_id = $1 AND wallets.id = $1 AND wallets.is_active = $2
+            ",
+            id,
+            is_active,
+        )
+            .execute(db)
+            .await
+            .map(|_| ())
     }
 
     pub async fn delete(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
         sqlx::query!(
-            "DELETE FROM wallets WHERE id = $1 AND user_id = $2",
+            "
+            DELETE FROM wallets
+            WHERE id = $1
+              AND organization_id IN (
+                SELECT organization_id FROM organization_members WHERE user_id = $2 AND role IN ('owner', 'admin')
+              )
+            ",
             id,
             user_id
         )
         .execute(db)
         .await
     }
+
+    pub async fn count_plaintext_keys(db: &PgPool) -> Result<i64, Error> {
+        sqlx::query!(
+            "SELECT COUNT(*) AS count FROM wallets WHERE data->>'private_key' IS NOT NULL"
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.count.unwrap_or_default())
+    }
+
+    /// Counts keys sealed with a master key other than `key_id`.
+    pub async fn count_foreign_keys(key_id: &str, db: &PgPool) -> Result<i64, Error> {
+        sqlx::query!(
+            "
+            SELECT COUNT(*) AS count FROM wallets
+            WHERE data->'encrypted_private_key'->>'key_id' <> $1
+            ",
+            key_id
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.count.unwrap_or_default())
+    }
+
+    /// Locks and returns every wallet holding a private key, sealed or not.
+    pub async fn lock_with_keys(tx: &mut Transaction<'_, Postgres>) -> Result<Vec<(Uuid, WalletData)>, Error> {
+        sqlx::query!(
+            "
+            SELECT id, data FROM wallets
+            WHERE data->>'private_key' IS NOT NULL OR data->'encrypted_private_key' IS NOT NULL
+            FOR UPDATE
+            "
+        )
+            .fetch_all(&mut **tx)
+            .await
+            .map(|rows| rows.into_iter().map(|row| (row.id, row.data.into())).collect())
+    }
+
+    pub async fn set_data(id: Uuid, data: WalletData, tx: &mut Transaction<'_, Postgres>) -> Result<PgQueryResult, Error> {
+        let data: Value = data.into();
+        sqlx::query!(
+            "UPDATE wallets SET data = $1 WHERE id = $2", data, id
+        )
+            .execute(&mut **tx)
+            .await
+    }
+}
+
+pub struct ExtendedKey {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub xpub: String,
+    pub next_index: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonExtendedKey {
+    pub id: Option<String>,
+    pub organization_id: Option<String>,
+    pub name: String,
+    pub xpub: String,
+    pub next_index: Option<i32>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<ExtendedKey> for JsonExtendedKey {
+    fn from(key: ExtendedKey) -> Self {
+        JsonExtendedKey {
+            id: Some(key.id.to_string()),
+            organization_id: Some(key.organization_id.to_string()),
+            name: key.name,
+            xpub: key.xpub,
+            next_index: Some(key.next_index),
+            created_at: Some(key.created_at),
+        }
+    }
+}
+
+impl ExtendedKey {
+    pub async fn create(
+        name: &str, xpub: &str, user_id: Uuid, organization_id: Uuid, db: &PgPool
+    ) -> Result<ExtendedKey, Error> {
+        sqlx::query_as!(
+            ExtendedKey,
+            "
+            INSERT INTO extended_keys (name, xpub, user_id, organization_id)
+            SELECT $1, $2, $3, organization_id
+            FROM organization_members
+            WHERE organization_id = $4 AND user_id = $3 AND role IN ('owner', 'admin')
+            RETURNING id, organization_id, name, xpub, next_index, created_at
+            ",
+            name,
+            xpub,
+            user_id,
+            organization_id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<ExtendedKey, Error> {
+        sqlx::query_as!(
+            ExtendedKey,
+            "
+            SELECT id, organization_id, name, xpub, next_index, created_at
+            FROM extended_keys
+            WHERE id = $1
+              AND organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $2)
+            ",
+            id,
+            user_id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<ExtendedKey>, Error> {
+        sqlx::query_as!(
+            ExtendedKey,
+            "
+            SELECT id, organization_id, name, xpub, next_index, created_at
+            FROM extended_keys
+            WHERE organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
+            ORDER BY created_at
+            ",
+            user_id
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    /// Claims the next unused index, returning it with the key's xpub and organization.
+    /// Run it in the transaction that stores the derived wallet, so a failed insert
+    /// doesn't burn the index.
+    pub async fn reserve_index(
+        id: Uuid, user_id: Uuid, tx: &mut Transaction<'_, Postgres>
+    ) -> Result<(String, Uuid, i32), Error> {
+        sqlx::query!(
+            "
+            UPDATE extended_keys
+            SET next_index = next_index + 1
+            WHERE id = $1
+              AND organization_id IN (
+                SELECT organization_id FROM organization_members WHERE user_id = $2 AND role IN ('owner', 'admin')
+              )
+            RETURNING xpub, organization_id, next_index - 1 AS \"index!\"
+            ",
+            id,
+            user_id
+        )
+            .fetch_one(&mut **tx)
+            .await
+            .map(|row| (row.xpub, row.organization_id, row.index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_donation_ids() {
+        let j_donation = |field: &str, value: &str| -> JsonDonation {
+            let mut j = json!({"amount": "100", "title": "Roof"});
+            j[field] = json!(value);
+            serde_json::from_value(j).unwrap()
+        };
+        let id = Uuid::new_v4().to_string();
+
+        for field in ["wallet_id", "organization_id", "extended_key_id"] {
+            let err = Donation::try_from(j_donation(field, "not-a-uuid")).err().unwrap();
+            assert!(matches!(err, AppError::InvalidInput(ref msg) if msg == &format!("Invalid {}", field)));
+            assert!(Donation::try_from(j_donation(field, &id)).is_ok());
+        }
+    }
+
+    #[test]
+    fn progress_towards_the_goal() {
+        let stats = AddressStats {
+            received: Decimal::new(125, 1),
+            deposits: 3,
+            donors: 2,
+            last_deposit_timestamp: Some(1_700_000_000),
+        };
+        let progress = DonationProgress::new(Decimal::new(50, 0), stats);
+        assert_eq!(progress.raised, Decimal::new(125, 1));
+        assert_eq!(progress.percent, Decimal::new(25, 0));
+        assert_eq!((progress.deposits, progress.donors), (3, 2));
+        assert_eq!(progress.last_deposit_at, DateTime::from_timestamp(1_700_000_000, 0));
+
+        let progress = DonationProgress::new(Decimal::new(100, 0), AddressStats::default());
+        assert_eq!((progress.raised, progress.percent, progress.last_deposit_at), (Decimal::ZERO, Decimal::ZERO, None));
+    }
+
+    #[test]
+    fn percent_is_rounded_and_may_pass_the_goal() {
+        assert_eq!(DonationProgress::percent(Decimal::new(3, 0), Decimal::ONE), Decimal::new(3333, 2));
+        assert_eq!(DonationProgress::percent(Decimal::new(10, 0), Decimal::new(15, 0)), Decimal::new(150, 0));
+        // a goal of nothing is reached right away
+        assert_eq!(DonationProgress::percent(Decimal::ZERO, Decimal::ZERO), Decimal::ONE_HUNDRED);
+    }
 }
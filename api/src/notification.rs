@@ -0,0 +1,126 @@
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Error, PgExecutor, PgPool};
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+pub const WEBHOOK_ENDPOINT_DISABLED: &str = "webhook_endpoint.disabled";
+pub const DEPOSIT_UNMATCHED: &str = "deposit.unmatched";
+pub const ALERT_TRIGGERED: &str = "alert.triggered";
+pub const DEPOSIT_RECEIVED: &str = "deposit.received";
+/// Every kind, for channels that only forward some of them.
+pub const KINDS: [&str; 4] = [WEBHOOK_ENDPOINT_DISABLED, DEPOSIT_UNMATCHED, ALERT_TRIGGERED, DEPOSIT_RECEIVED];
+
+const LIST_LIMIT: i64 = 100;
+
+/// An in-app message for a user about something that needs their attention.
+#[derive(Serialize)]
+pub struct Notification {
+    pub id: Uuid,
+    pub kind: String,
+    pub message: String,
+    pub data: Value,
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Notification {
+    /// Also queues it for the user's active, verified channels that forward its kind.
+    pub async fn create<'e, E: PgExecutor<'e>>(
+        user_id: Uuid, kind: &str, message: &str, data: Value, executor: E
+    ) -> Result<Notification, Error> {
+        sqlx::query_as!(
+            Notification,
+            r#"
+            WITH notification AS (
+              INSERT INTO notifications (user_id, kind, message, data)
+              VALUES ($1, $2, $3, $4)
+              RETURNING id, user_id, kind, message, data, read_at, created_at
+            ), deliveries AS (
+              INSERT INTO notification_deliveries (notification_id, channel_id)
+              SELECT notification.id, channels.id
+              FROM notification
+              JOIN notification_channels channels ON channels.user_id = notification.user_id
+              WHERE channels.is_active AND channels.verified_at IS NOT NULL
+                AND (cardinality(channels.notification_kinds) = 0 OR notification.kind = ANY(channels.notification_kinds))
+            )
+            SELECT id AS "id!", kind AS "kind!", message AS "message!", data AS "data!", read_at,
+                   created_at AS "created_at!"
+            FROM notification
+            "#,
+            user_id,
+            kind,
+            message,
+            data,
+        )
+            .fetch_one(executor)
+            .await
+    }
+
+    /// Like `create`, unless a notification with the same `dedupe_key` was already made.
+    pub async fn create_once<'e, E: PgExecutor<'e>>(
+        user_id: Uuid, kind: &str, message: &str, data: Value, dedupe_key: &str, executor: E
+    ) -> Result<Option<Notification>, Error> {
+        sqlx::query_as!(
+            Notification,
+            r#"
+            WITH notification AS (
+              INSERT INTO notifications (user_id, kind, message, data, dedupe_key)
+              VALUES ($1, $2, $3, $4, $5)
+              ON CONFLICT (dedupe_key) DO NOTHING
+              RETURNING id, user_id, kind, message, data, read_at, created_at
+            ), deliveries AS (
+              INSERT INTO notification_deliveries (notification_id, channel_id)
+              SELECT notification.id, channels.id
+              FROM notification
+              JOIN notification_channels channels ON channels.user_id = notification.user_id
+              WHERE channels.is_active AND channels.verified_at IS NOT NULL
+                AND (cardinality(channels.notification_kinds) = 0 OR notification.kind = ANY(channels.notification_kinds))
+            )
+            SELECT id AS "id!", kind AS "kind!", message AS "message!", data AS "data!", read_at,
+                   created_at AS "created_at!"
+            FROM notification
+            "#,
+            user_id,
+            kind,
+            message,
+            data,
+            dedupe_key,
+        )
+            .fetch_optional(executor)
+            .await
+    }
+
+    /// The latest notifications, newest first.
+    pub async fn list(user_id: Uuid, unread_only: bool, db: &PgPool) -> Result<Vec<Notification>, Error> {
+        sqlx::query_as!(
+            Notification,
+            "
+            SELECT id, kind, message, data, read_at, created_at
+            FROM notifications
+            WHERE user_id = $1 AND (NOT $2 OR read_at IS NULL)
+            ORDER BY created_at DESC
+            LIMIT $3
+            ",
+            user_id,
+            unread_only,
+            LIST_LIMIT,
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    pub async fn mark_read(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!(
+            "
+            UPDATE notifications SET read_at = COALESCE(read_at, CURRENT_TIMESTAMP)
+            WHERE id = $1 AND user_id = $2
+            ",
+            id,
+            user_id
+        )
+            .execute(db)
+            .await
+    }
+}
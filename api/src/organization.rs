@@ -0,0 +1,565 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, PgPool, Postgres, Transaction};
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Admin,
+    Viewer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    /// Owners and admins manage wallets, donations and members; viewers only read.
+    pub fn can_write(&self) -> bool {
+        matches!(self, Role::Owner | Role::Admin)
+    }
+
+    /// Only owners may grant or take away the owner role.
+    pub fn can_manage(&self, other: Role) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Admin => other != Role::Owner,
+            Role::Viewer => false,
+        }
+    }
+}
+
+impl TryFrom<&str> for Role {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "owner" => Ok(Role::Owner),
+            "admin" => Ok(Role::Admin),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(format!("unknown role: {}", value)),
+        }
+    }
+}
+
+fn decode_role(value: &str) -> Result<Role, Error> {
+    value.try_into().map_err(|e: String| Error::Decode(e.into()))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonOrganization {
+    pub id: Option<String>,
+    pub name: String,
+    pub role: Option<Role>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Organization> for JsonOrganization {
+    fn from(organization: Organization) -> Self {
+        JsonOrganization {
+            id: Some(organization.id.to_string()),
+            name: organization.name,
+            role: Some(organization.role),
+            created_at: Some(organization.created_at),
+        }
+    }
+}
+
+impl Organization {
+    pub async fn create(name: &str, owner_id: Uuid, db: &PgPool) -> Result<Organization, Error> {
+        let mut tx = db.begin().await?;
+        let organization = Self::create_in(name, owner_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(organization)
+    }
+
+    pub async fn create_in(
+        name: &str, owner_id: Uuid, tx: &mut Transaction<'_, Postgres>
+    ) -> Result<Organization, Error> {
+        let row = sqlx::query!(
+            "INSERT INTO organizations (name) VALUES ($1) RETURNING id, name, created_at",
+            name
+        )
+            .fetch_one(&mut **tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, $3)",
+            row.id,
+            owner_id,
+            Role::Owner.as_str(),
+        )
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(Organization { id: row.id, name: row.name, role: Role::Owner, created_at: row.created_at })
+    }
+
+    pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<Organization, Error> {
+        let row = sqlx::query!(
+            "
+            SELECT o.id, o.name, o.created_at, m.role
+            FROM organizations o
+            JOIN organization_members m ON m.organization_id = o.id
+            WHERE o.id = $1 AND m.user_id = $2
+            ",
+            id,
+            user_id
+        )
+            .fetch_one(db)
+            .await?;
+
+        Ok(Organization { id: row.id, name: row.name, role: decode_role(&row.role)?, created_at: row.created_at })
+    }
+
+    pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<Organization>, Error> {
+        let rows = sqlx::query!(
+            "
+            SELECT o.id, o.name, o.created_at, m.role
+            FROM organizations o
+            JOIN organization_members m ON m.organization_id = o.id
+            WHERE m.user_id = $1
+            ORDER BY o.created_at
+            ",
+            user_id
+        )
+            .fetch_all(db)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| Ok(Organization {
+                id: row.id, name: row.name, role: decode_role(&row.role)?, created_at: row.created_at
+            }))
+            .collect()
+    }
+
+    pub async fn rename(id: Uuid, name: &str, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!(
+            "UPDATE organizations SET name = $1 WHERE id = $2", name, id
+        )
+            .execute(db)
+            .await
+    }
+
+    /// The organization new wallets and donations land in when none is given:
+    /// the oldest one the user can write to.
+    pub async fn default_for_user(user_id: Uuid, db: &PgPool) -> Result<Uuid, Error> {
+        sqlx::query!(
+            "
+            SELECT organization_id
+            FROM organization_members
+            WHERE user_id = $1 AND role IN ('owner', 'admin')
+            ORDER BY created_at
+            LIMIT 1
+            ",
+            user_id
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.organization_id)
+    }
+
+    pub async fn role(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<Role, Error> {
+        let row = sqlx::query!(
+            "SELECT role FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+            .fetch_one(db)
+            .await?;
+
+        decode_role(&row.role)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonMember {
+    pub user_id: String,
+    pub email: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct Member {
+    pub user_id: Uuid,
+    pub email: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Member> for JsonMember {
+    fn from(member: Member) -> Self {
+        JsonMember {
+            user_id: member.user_id.to_string(),
+            email: member.email,
+            role: member.role,
+            created_at: member.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonMemberRole {
+    pub role: Role,
+}
+
+impl Member {
+    pub async fn list(organization_id: Uuid, db: &PgPool) -> Result<Vec<Member>, Error> {
+        let rows = sqlx::query!(
+            "
+            SELECT m.user_id, u.email, m.role, m.created_at
+            FROM organization_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.organization_id = $1
+            ORDER BY m.created_at
+            ",
+            organization_id
+        )
+            .fetch_all(db)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| Ok(Member {
+                user_id: row.user_id, email: row.email, role: decode_role(&row.role)?, created_at: row.created_at
+            }))
+            .collect()
+    }
+
+    /// Returns `false`, changing nothing, when it would leave the organization without an owner.
+    pub async fn update_role(organization_id: Uuid, user_id: Uuid, role: Role, db: &PgPool) -> Result<bool, Error> {
+        let mut tx = db.begin().await?;
+        if role != Role::Owner && Self::is_last_owner(organization_id, user_id, &mut tx).await? {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            "UPDATE organization_members SET role = $1 WHERE organization_id = $2 AND user_id = $3",
+            role.as_str(),
+            organization_id,
+            user_id
+        )
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Returns `false`, changing nothing, when it would leave the organization without an owner.
+    pub async fn delete(organization_id: Uuid, user_id: Uuid, db: &PgPool) -> Result<bool, Error> {
+        let mut tx = db.begin().await?;
+        if Self::is_last_owner(organization_id, user_id, &mut tx).await? {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            "DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+            organization_id,
+            user_id
+        )
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Locks the organization's owners until the transaction ends, so concurrent demotions and
+    /// removals see each other.
+    async fn is_last_owner(
+        organization_id: Uuid, user_id: Uuid, tx: &mut Transaction<'_, Postgres>
+    ) -> Result<bool, Error> {
+        let owners = sqlx::query!(
+            "
+            SELECT user_id FROM organization_members
+            WHERE organization_id = $1 AND role = 'owner'
+            FOR UPDATE
+            ",
+            organization_id
+        )
+            .fetch_all(&mut **tx)
+            .await?;
+
+        Ok(owners.len() <= 1 && owners.iter().any(|owner| owner.user_id == user_id))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonInvite {
+    pub id: Option<String>,
+    pub organization_id: Option<String>,
+    pub organization_name: Option<String>,
+    pub email: String,
+    pub role: Role,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct JsonInviteToken {
+    pub token: String,
+}
+
+pub struct Invite {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub organization_name: String,
+    pub email: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Invite> for JsonInvite {
+    fn from(invite: Invite) -> Self {
+        JsonInvite {
+            id: Some(invite.id.to_string()),
+            organization_id: Some(invite.organization_id.to_string()),
+            organization_name: Some(invite.organization_name),
+            email: invite.email,
+            role: invite.role,
+            created_at: Some(invite.created_at),
+        }
+    }
+}
+
+impl Invite {
+    /// Re-inviting the same email replaces the pending invite's role and token.
+    pub async fn create(
+        organization_id: Uuid, email: &str, role: Role, invited_by: Uuid, token_hash: &str, db: &PgPool
+    ) -> Result<Invite, Error> {
+        let row = sqlx::query!(
+            "
+            WITH invite AS (
+                INSERT INTO organization_invites (organization_id, email, role, invited_by, token_hash)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (organization_id, email) DO UPDATE SET role = EXCLUDED.role, token_hash = EXCLUDED.token_hash
+                RETURNING id, organization_id, email, role, created_at
+            )
+            SELECT i.id, i.organization_id, o.name AS organization_name, i.email, i.role, i.created_at
+            FROM invite i
+            JOIN organizations o ON o.id = i.organization_id
+            ",
+            organization_id,
+            email,
+            role.as_str(),
+            invited_by,
+            token_hash
+        )
+            .fetch_one(db)
+            .await?;
+
+        Ok(Invite {
+            id: row.id,
+            organization_id: row.organization_id,
+            organization_name: row.organization_name,
+            email: row.email,
+            role: decode_role(&row.role)?,
+            created_at: row.created_at,
+        })
+    }
+
+    pub async fn list_for_organization(organization_id: Uuid, db: &PgPool) -> Result<Vec<Invite>, Error> {
+        let rows = sqlx::query!(
+            "
+            SELECT i.id, i.organization_id, o.name AS organization_name, i.email, i.role, i.created_at
+            FROM organization_invites i
+            JOIN organizations o ON o.id = i.organization_id
+            WHERE i.organization_id = $1
+            ORDER BY i.created_at
+            ",
+            organization_id
+        )
+            .fetch_all(db)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| Ok(Invite {
+                id: row.id,
+                organization_id: row.organization_id,
+                organization_name: row.organization_name,
+                email: row.email,
+                role: decode_role(&row.role)?,
+                created_at: row.created_at,
+            }))
+            .collect()
+    }
+
+    pub async fn list_for_email(email: &str, db: &PgPool) -> Result<Vec<Invite>, Error> {
+        let rows = sqlx::query!(
+            "
+            SELECT i.id, i.organization_id, o.name AS organization_name, i.email, i.role, i.created_at
+            FROM organization_invites i
+            JOIN organizations o ON o.id = i.organization_id
+            WHERE i.email = $1
+            ORDER BY i.created_at
+            ",
+            email
+        )
+            .fetch_all(db)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| Ok(Invite {
+                id: row.id,
+                organization_id: row.organization_id,
+                organization_name: row.organization_name,
+                email: row.email,
+                role: decode_role(&row.role)?,
+                created_at: row.created_at,
+            }))
+            .collect()
+    }
+
+    pub async fn delete(id: Uuid, organization_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!(
+            "DELETE FROM organization_invites WHERE id = $1 AND organization_id = $2",
+            id,
+            organization_id
+        )
+            .execute(db)
+            .await
+    }
+
+    /// Turns the invite addressed to `email` into a membership, keeping an existing
+    /// membership untouched. Takes the token mailed with the invite, since having registered
+    /// `email` doesn't prove owning it.
+    pub async fn accept(id: Uuid, user_id: Uuid, email: &str, token_hash: &str, db: &PgPool) -> Result<Uuid, Error> {
+        let mut tx = db.begin().await?;
+
+        let invite = sqlx::query!(
+            "
+            DELETE FROM organization_invites WHERE id = $1 AND email = $2 AND token_hash = $3
+            RETURNING organization_id, role
+            ",
+            id,
+            email,
+            token_hash
+        )
+            .fetch_one(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            "
+            INSERT INTO organization_members (organization_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (organization_id, user_id) DO NOTHING
+            ",
+            invite.organization_id,
+            user_id,
+            invite.role
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(invite.organization_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::models::User;
+
+    async fn owned_organization(db: &PgPool) -> (Uuid, Uuid) {
+        let owner = fixtures::owner(db).await;
+        (owner.organization_id, owner.user_id)
+    }
+
+    async fn add_member(organization_id: Uuid, email: &str, role: Role, db: &PgPool) -> Uuid {
+        let user = User::create(email, "hash", db).await.unwrap();
+        sqlx::query!(
+            "INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, $3)",
+            organization_id,
+            user.id,
+            role.as_str(),
+        )
+            .execute(db)
+            .await
+            .unwrap();
+        user.id
+    }
+
+    async fn owners(organization_id: Uuid, db: &PgPool) -> usize {
+        Member::list(organization_id, db).await.unwrap().into_iter().filter(|member| member.role == Role::Owner).count()
+    }
+
+    #[test]
+    fn only_owners_manage_owners() {
+        assert!(Role::Owner.can_manage(Role::Owner));
+        assert!(Role::Admin.can_manage(Role::Admin));
+        assert!(!Role::Admin.can_manage(Role::Owner));
+        assert!(!Role::Viewer.can_manage(Role::Viewer));
+    }
+
+    #[sqlx::test]
+    async fn keeps_the_last_owner(db: PgPool) {
+        let (organization_id, owner_id) = owned_organization(&db).await;
+        let admin_id = add_member(organization_id, "admin@example.com", Role::Admin, &db).await;
+
+        assert!(!Member::update_role(organization_id, owner_id, Role::Admin, &db).await.unwrap());
+        assert!(!Member::delete(organization_id, owner_id, &db).await.unwrap());
+        assert_eq!(Organization::role(organization_id, owner_id, &db).await.unwrap(), Role::Owner);
+
+        // other members come and go
+        assert!(Member::update_role(organization_id, admin_id, Role::Viewer, &db).await.unwrap());
+        assert!(Member::delete(organization_id, admin_id, &db).await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn demotes_one_of_several_owners(db: PgPool) {
+        let (organization_id, owner_id) = owned_organization(&db).await;
+        let other_id = add_member(organization_id, "other@example.com", Role::Owner, &db).await;
+
+        assert!(Member::update_role(organization_id, owner_id, Role::Admin, &db).await.unwrap());
+        assert!(!Member::delete(organization_id, other_id, &db).await.unwrap());
+        assert_eq!(owners(organization_id, &db).await, 1);
+    }
+
+    #[sqlx::test]
+    async fn invites_take_the_mailed_token_once(db: PgPool) {
+        let (organization_id, owner_id) = owned_organization(&db).await;
+        let (_, token_hash) = crate::auth::generate_invite_token();
+        let invite = Invite::create(organization_id, "new@example.com", Role::Admin, owner_id, &token_hash, &db).await.unwrap();
+        let user = User::create("new@example.com", "hash", &db).await.unwrap();
+
+        // registering the invited address isn't enough
+        let (_, other_hash) = crate::auth::generate_invite_token();
+        let wrong = Invite::accept(invite.id, user.id, &user.email, &other_hash, &db).await;
+        assert!(matches!(wrong, Err(Error::RowNotFound)));
+
+        assert_eq!(Invite::accept(invite.id, user.id, &user.email, &token_hash, &db).await.unwrap(), organization_id);
+        assert_eq!(Organization::role(organization_id, user.id, &db).await.unwrap(), Role::Admin);
+        let again = Invite::accept(invite.id, user.id, &user.email, &token_hash, &db).await;
+        assert!(matches!(again, Err(Error::RowNotFound)));
+    }
+
+    #[sqlx::test]
+    async fn concurrent_demotions_keep_an_owner(db: PgPool) {
+        let (organization_id, owner_id) = owned_organization(&db).await;
+        let other_id = add_member(organization_id, "other@example.com", Role::Owner, &db).await;
+
+        let (first, second) = tokio::join!(
+            Member::update_role(organization_id, owner_id, Role::Viewer, &db),
+            Member::delete(organization_id, other_id, &db),
+        );
+        assert_ne!(first.unwrap(), second.unwrap());
+        assert_eq!(owners(organization_id, &db).await, 1);
+    }
+}
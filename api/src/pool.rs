@@ -0,0 +1,188 @@
+use std::sync::Arc;
+use std::time::Duration;
+use log::{error, info};
+use serde_json::Value;
+use sqlx::{Error, PgExecutor, PgPool, Postgres, Transaction};
+use tokio::sync::Notify;
+
+use crate::chain::Chain;
+use crate::crypto::MasterKey;
+use crate::hdwallet;
+use crate::models::WalletData;
+use crate::state::AppState;
+
+const REFILL_INTERVAL: Duration = Duration::from_secs(60);
+const REFILL_BATCH: i64 = 50;
+
+/// Pre-generated TRON wallets, with their keys already sealed, waiting to be assigned.
+pub struct WalletPool {
+    pub size: i64,
+    refill: Notify,
+}
+
+impl WalletPool {
+    pub fn new(size: i64) -> WalletPool {
+        WalletPool { size, refill: Notify::new() }
+    }
+
+    /// Wakes the refill task without waiting for it.
+    pub fn request_refill(&self) {
+        self.refill.notify_one();
+    }
+
+    pub async fn count(db: &PgPool) -> Result<i64, Error> {
+        sqlx::query!("SELECT COUNT(*) AS count FROM wallet_pool")
+            .fetch_one(db)
+            .await
+            .map(|row| row.count.unwrap_or_default())
+    }
+
+    /// Removes and returns the oldest pooled wallet. Concurrent callers skip each other's
+    /// locked rows, and the row comes back if the surrounding transaction rolls back.
+    pub async fn take(tx: &mut Transaction<'_, Postgres>) -> Result<Option<WalletData>, Error> {
+        sqlx::query!(
+            "
+            DELETE FROM wallet_pool
+            WHERE id = (
+              SELECT id FROM wallet_pool ORDER BY created_at FOR UPDATE SKIP LOCKED LIMIT 1
+            )
+            RETURNING data
+            "
+        )
+            .fetch_optional(&mut **tx)
+            .await
+            .map(|row| row.map(|row| row.data.into()))
+    }
+
+    async fn insert(wallets: Vec<WalletData>, db: &PgPool) -> Result<u64, Error> {
+        let data: Vec<Value> = wallets.into_iter().map(|data| data.into()).collect();
+        sqlx::query!("INSERT INTO wallet_pool (data) SELECT * FROM UNNEST($1::jsonb[])", &data)
+            .execute(db)
+            .await
+            .map(|result| result.rows_affected())
+    }
+
+    /// Pooled wallets are disposable, so keys sealed with another master key are dropped
+    /// instead of re-encrypted.
+    pub async fn purge_foreign_keys<'e, E: PgExecutor<'e>>(key_id: &str, executor: E) -> Result<u64, Error> {
+        sqlx::query!(
+            "DELETE FROM wallet_pool WHERE data->'encrypted_private_key'->>'key_id' <> $1",
+            key_id
+        )
+            .execute(executor)
+            .await
+            .map(|result| result.rows_affected())
+    }
+}
+
+pub fn generate_wallet(chain: Chain, master_key: &MasterKey) -> Result<WalletData, String> {
+    let (private_key, address) = hdwallet::gen_wallet(chain)
+        .ok_or_else(|| format!("can not generate {} wallets", chain.as_str()))?;
+    let encrypted_private_key = WalletData::seal_private_key(&private_key, &address, master_key)?;
+
+    Ok(WalletData { address, encrypted_private_key: Some(encrypted_private_key), ..Default::default() })
+}
+
+/// Keeps the pool topped up to `WALLET_POOL_SIZE`, checking again whenever a wallet is
+/// taken and at least once a minute.
+pub async fn run_refill(state: Arc<AppState>) {
+    if state.wallet_pool.size <= 0 {
+        info!("Wallet pool disabled");
+        return;
+    }
+
+    loop {
+        if let Err(err_msg) = refill(&state.wallet_pool, &state.master_key, &state.db).await {
+            error!("Failed refill wallet pool: {}", err_msg);
+        }
+
+        tokio::select! {
+            _ = state.wallet_pool.refill.notified() => {},
+            _ = tokio::time::sleep(REFILL_INTERVAL) => {},
+        }
+    }
+}
+
+async fn refill(pool: &WalletPool, master_key: &MasterKey, db: &PgPool) -> Result<(), String> {
+    loop {
+        let available = WalletPool::count(db).await.map_err(|err| err.to_string())?;
+        let missing = (pool.size - available).min(REFILL_BATCH);
+        if missing <= 0 {
+            return Ok(());
+        }
+
+        let wallets = (0..missing)
+            .map(|_| generate_wallet(Chain::Tron, master_key))
+            .collect::<Result<Vec<WalletData>, String>>()?;
+        let inserted = WalletPool::insert(wallets, db).await.map_err(|err| err.to_string())?;
+        info!("Added {} wallets to the pool", inserted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use tokio::sync::Barrier;
+    use super::*;
+
+    fn master_key() -> MasterKey {
+        MasterKey::from_bytes(&[7; 32]).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn refills_up_to_size(db: PgPool) {
+        let pool = WalletPool::new(5);
+        refill(&pool, &master_key(), &db).await.unwrap();
+        assert_eq!(WalletPool::count(&db).await.unwrap(), 5);
+
+        refill(&pool, &master_key(), &db).await.unwrap();
+        assert_eq!(WalletPool::count(&db).await.unwrap(), 5);
+
+        let mut tx = db.begin().await.unwrap();
+        let wallet = WalletPool::take(&mut tx).await.unwrap().unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(wallet.decrypt_private_key(&master_key()).unwrap().map(|key| key.len()), Some(64));
+        assert_eq!(WalletPool::count(&db).await.unwrap(), 4);
+
+        refill(&pool, &master_key(), &db).await.unwrap();
+        assert_eq!(WalletPool::count(&db).await.unwrap(), 5);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_takes_get_distinct_wallets(db: PgPool) {
+        refill(&WalletPool::new(3), &master_key(), &db).await.unwrap();
+
+        // every taker holds its transaction open until all of them have taken
+        let takers = 5;
+        let barrier = Arc::new(Barrier::new(takers));
+        let handles: Vec<_> = (0..takers).map(|_| {
+            let (db, barrier) = (db.clone(), barrier.clone());
+            tokio::spawn(async move {
+                let mut tx = db.begin().await.unwrap();
+                let wallet = WalletPool::take(&mut tx).await.unwrap();
+                barrier.wait().await;
+                tx.commit().await.unwrap();
+                wallet.map(|wallet| wallet.address)
+            })
+        }).collect();
+
+        let mut taken = vec![];
+        for handle in handles {
+            taken.extend(handle.await.unwrap());
+        }
+        assert_eq!(taken.len(), 3);
+        assert_eq!(taken.iter().collect::<HashSet<_>>().len(), 3);
+        assert_eq!(WalletPool::count(&db).await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn rolled_back_takes_return_the_wallet(db: PgPool) {
+        refill(&WalletPool::new(1), &master_key(), &db).await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        assert!(WalletPool::take(&mut tx).await.unwrap().is_some());
+        tx.rollback().await.unwrap();
+
+        assert_eq!(WalletPool::count(&db).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::types::Decimal;
+
+use crate::models::DonationProgress;
+
+const SLUG_MIN_LEN: usize = 3;
+const SLUG_MAX_LEN: usize = 64;
+const PUBLIC_ID_LEN: usize = 32;
+/// Clients counted per window; past this, new ones wait for the next window.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// What donors see of a donation: nothing about its owner, webhook or wallet besides the
+/// address they pay to.
+#[derive(Serialize)]
+pub struct JsonPublicDonation {
+    pub id: String,
+    pub slug: Option<String>,
+    pub title: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub chain: String,
+    pub address: Option<String>,
+    /// What the donation's QR code encodes, for wallets opened by a link instead.
+    pub payment_uri: Option<String>,
+    pub accepted_tokens: Vec<String>,
+    pub goal: Decimal,
+    pub progress: Option<DonationProgress>,
+    pub recent_deposits: Vec<JsonPublicDeposit>,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPublicDeposit {
+    pub transaction_id: String,
+    pub amount: Decimal,
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+/// Slugs are lowercase words joined by hyphens, and never look like a public id.
+pub fn check_slug(slug: &str) -> Result<(), String> {
+    if !(SLUG_MIN_LEN..=SLUG_MAX_LEN).contains(&slug.len()) {
+        return Err(format!("slug must be {} to {} characters long", SLUG_MIN_LEN, SLUG_MAX_LEN));
+    }
+    if !slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        || slug.starts_with('-')
+        || slug.ends_with('-')
+        || slug.contains("--")
+    {
+        return Err("slug may only contain lowercase letters and digits separated by single hyphens".to_string());
+    }
+    if slug.len() == PUBLIC_ID_LEN && slug.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("slug must not look like a public id".to_string());
+    }
+
+    Ok(())
+}
+
+/// Counts requests per client address in fixed windows shared by all clients, so the whole
+/// count is dropped at once when a window ends instead of swept client by client.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    /// Take the client from the last `X-Forwarded-For` entry, for deployments behind a proxy.
+    trust_forwarded_for: bool,
+    current: Mutex<Window>,
+}
+
+#[derive(Default)]
+struct Window {
+    started: Option<Instant>,
+    counts: HashMap<IpAddr, u32>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration, trust_forwarded_for: bool) -> RateLimiter {
+        RateLimiter { limit, window, trust_forwarded_for, current: Mutex::new(Window::default()) }
+    }
+
+    /// Counts a request, or returns how long the client has to wait.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut current = self.current.lock().unwrap();
+        let started = match current.started {
+            Some(started) if now.saturating_duration_since(started) < self.window => started,
+            _ => {
+                *current = Window { started: Some(now), counts: HashMap::new() };
+                now
+            },
+        };
+        let retry_after = self.window.saturating_sub(now.saturating_duration_since(started));
+
+        let tracked = current.counts.len();
+        match current.counts.get_mut(&client) {
+            Some(count) if *count >= self.limit => Err(retry_after),
+            Some(count) => {
+                *count += 1;
+                Ok(())
+            },
+            None if tracked >= MAX_TRACKED_CLIENTS || self.limit == 0 => Err(retry_after),
+            None => {
+                current.counts.insert(client, 1);
+                Ok(())
+            },
+        }
+    }
+
+    pub fn client(&self, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+        let forwarded = self.trust_forwarded_for
+            .then(|| headers.get("x-forwarded-for"))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|last| last.trim().parse().ok());
+
+        client_key(forwarded.unwrap_or(peer.ip()))
+    }
+}
+
+/// IPv6 clients are counted per /64, the smallest block a single host usually gets.
+fn client_key(ip: IpAddr) -> IpAddr {
+    let IpAddr::V6(ip) = ip else { return ip };
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return IpAddr::V4(ip);
+    }
+
+    let segments = ip.segments();
+    IpAddr::V6([segments[0], segments[1], segments[2], segments[3], 0, 0, 0, 0].into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_slugs() {
+        for valid in ["spring-drive", "abc", "drive-2026", &"a".repeat(64)] {
+            assert!(check_slug(valid).is_ok(), "{} should be valid", valid);
+        }
+
+        for invalid in [
+            "ab", "Spring", "spring drive", "-drive", "drive-", "spring--drive", "drive_2026", "дом",
+            &"a".repeat(65), "0123456789abcdef0123456789abcdef",
+        ] {
+            assert!(check_slug(invalid).is_err(), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn limits_each_client_per_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60), false);
+        let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let now = Instant::now();
+
+        assert!(limiter.check(a, now).is_ok());
+        assert!(limiter.check(a, now).is_ok());
+        assert_eq!(limiter.check(a, now + Duration::from_secs(20)), Err(Duration::from_secs(40)));
+        assert!(limiter.check(b, now).is_ok());
+        assert!(limiter.check(a, now + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn tracks_a_bounded_number_of_clients() {
+        let limiter = RateLimiter::new(5, Duration::from_secs(60), false);
+        let now = Instant::now();
+        for i in 0..MAX_TRACKED_CLIENTS as u32 {
+            assert!(limiter.check(IpAddr::V4(i.into()), now).is_ok());
+        }
+
+        let newcomer: IpAddr = "192.168.1.1".parse().unwrap();
+        assert_eq!(limiter.check(newcomer, now + Duration::from_secs(10)), Err(Duration::from_secs(50)));
+        assert!(limiter.check(IpAddr::V4(0.into()), now + Duration::from_secs(10)).is_ok());
+
+        // the next window starts from nothing
+        assert!(limiter.check(newcomer, now + Duration::from_secs(60)).is_ok());
+        assert_eq!(limiter.current.lock().unwrap().counts.len(), 1);
+    }
+
+    #[test]
+    fn trusts_forwarded_for_only_when_told() {
+        let peer: SocketAddr = "172.18.0.5:4000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4, 5.6.7.8".parse().unwrap());
+
+        let direct = RateLimiter::new(1, Duration::from_secs(1), false);
+        assert_eq!(direct.client(&headers, peer), peer.ip());
+
+        let proxied = RateLimiter::new(1, Duration::from_secs(1), true);
+        assert_eq!(proxied.client(&headers, peer), "5.6.7.8".parse::<IpAddr>().unwrap());
+        assert_eq!(proxied.client(&HeaderMap::new(), peer), peer.ip());
+
+        let v6: SocketAddr = "[2001:db8:1:2:3:4:5:6]:4000".parse().unwrap();
+        assert_eq!(direct.client(&headers, v6), "2001:db8:1:2::".parse::<IpAddr>().unwrap());
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:4000".parse().unwrap();
+        assert_eq!(direct.client(&headers, mapped), "1.2.3.4".parse::<IpAddr>().unwrap());
+    }
+}
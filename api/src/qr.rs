@@ -0,0 +1,137 @@
+use qrcode::render::svg;
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+use sqlx::types::Decimal;
+
+const DEFAULT_SIZE: u32 = 256;
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 1024;
+/// Light modules around the code, as the spec asks for.
+const QUIET_ZONE: u32 = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+/// Error correction level, from `l` (7% of the code can be lost) to `h` (30%).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorCorrection {
+    L,
+    #[default]
+    M,
+    Q,
+    H,
+}
+
+impl From<ErrorCorrection> for EcLevel {
+    fn from(value: ErrorCorrection) -> Self {
+        match value {
+            ErrorCorrection::L => EcLevel::L,
+            ErrorCorrection::M => EcLevel::M,
+            ErrorCorrection::Q => EcLevel::Q,
+            ErrorCorrection::H => EcLevel::H,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct QrQuery {
+    pub format: Option<QrFormat>,
+    /// Minimum width and height in pixels; the code is scaled by whole pixels per module.
+    pub size: Option<u32>,
+    pub ec: Option<ErrorCorrection>,
+    pub amount: Option<Decimal>,
+    pub memo: Option<String>,
+}
+
+/// Renders `data` as a QR code, returning its content type and body.
+pub fn render(data: &str, format: QrFormat, size: Option<u32>, ec: ErrorCorrection) -> Result<(&'static str, Vec<u8>), String> {
+    let size = size.unwrap_or(DEFAULT_SIZE);
+    if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+        return Err(format!("size must be between {} and {}", MIN_SIZE, MAX_SIZE));
+    }
+
+    let code = QrCode::with_error_correction_level(data, ec.into())
+        .map_err(|err| format!("Failed encode qr code: {}", err))?;
+
+    match format {
+        QrFormat::Png => Ok(("image/png", render_png(&code, size)?)),
+        QrFormat::Svg => {
+            let image = code.render::<svg::Color>().quiet_zone(true).min_dimensions(size, size).build();
+            Ok(("image/svg+xml", image.into_bytes()))
+        },
+    }
+}
+
+/// An 8 bit grayscale png, which keeps codes small.
+fn render_png(code: &QrCode, size: u32) -> Result<Vec<u8>, String> {
+    let modules = code.width() as u32;
+    let scale = size.div_ceil(modules + 2 * QUIET_ZONE).max(1);
+    let dimension = (modules + 2 * QUIET_ZONE) * scale;
+
+    let mut pixels = vec![u8::MAX; (dimension * dimension) as usize];
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color != Color::Dark {
+            continue;
+        }
+        let (x, y) = (i as u32 % modules + QUIET_ZONE, i as u32 / modules + QUIET_ZONE);
+        for row in y * scale..(y + 1) * scale {
+            let start = (row * dimension + x * scale) as usize;
+            pixels[start..start + scale as usize].fill(0);
+        }
+    }
+
+    let mut bytes = vec![];
+    let mut encoder = png::Encoder::new(&mut bytes, dimension, dimension);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|err| format!("Failed encode png: {}", err))?;
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "tron:TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY?amount=10";
+
+    #[test]
+    fn renders_png_of_at_least_the_requested_size() {
+        let (content_type, body) = render(URI, QrFormat::Png, Some(200), ErrorCorrection::M).unwrap();
+        assert_eq!(content_type, "image/png");
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(body));
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.width, info.height);
+        assert!((200..200 + 40).contains(&info.width), "got {}", info.width);
+    }
+
+    #[test]
+    fn higher_error_correction_needs_more_modules() {
+        let low = QrCode::with_error_correction_level(URI, EcLevel::L).unwrap();
+        let high = QrCode::with_error_correction_level(URI, EcLevel::H).unwrap();
+        assert!(high.width() > low.width());
+    }
+
+    #[test]
+    fn renders_svg() {
+        let (content_type, body) = render(URI, QrFormat::Svg, None, ErrorCorrection::H).unwrap();
+        assert_eq!(content_type, "image/svg+xml");
+        assert!(String::from_utf8(body).unwrap().contains("<svg"));
+    }
+
+    #[test]
+    fn rejects_sizes_out_of_range() {
+        assert!(render(URI, QrFormat::Png, Some(MIN_SIZE - 1), ErrorCorrection::M).is_err());
+        assert!(render(URI, QrFormat::Svg, Some(MAX_SIZE + 1), ErrorCorrection::M).is_err());
+    }
+}
@@ -1,8 +1,24 @@
 use reqwest::Client;
 use sqlx::PgPool;
+use tokio::sync::broadcast;
+use crate::auth::AuthConfig;
+use crate::channel::ChannelClient;
+use crate::crypto::MasterKey;
+use crate::pool::WalletPool;
+use crate::public::RateLimiter;
+use crate::stream::Relayed;
+use crate::webhook::WebhookClient;
 
 
 pub struct AppState {
     pub db: PgPool,
     pub http_client: Client,
+    pub auth: AuthConfig,
+    pub master_key: MasterKey,
+    pub wallet_pool: WalletPool,
+    pub webhook_client: WebhookClient,
+    pub channel_client: ChannelClient,
+    pub public_rate_limit: RateLimiter,
+    /// Donation events recorded by any api instance, relayed to the streams of this one.
+    pub events: broadcast::Sender<Relayed>,
 }
@@ -0,0 +1,503 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use log::{error, info, warn};
+use rand::RngCore;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Error, PgExecutor, PgPool};
+use sqlx::postgres::PgListener;
+use sqlx::types::Uuid;
+use tokio::sync::broadcast;
+
+use crate::state::AppState;
+
+const CHANNEL: &str = "donation_events";
+const TOKEN_PREFIX: &str = "dst_";
+/// Live events a slow stream may fall behind by before it catches up from the database.
+pub const BUFFER: usize = 1024;
+const REPLAY_PAGE: i64 = 500;
+const RETENTION_DAYS: i32 = 7;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How long a transaction recording an event may take to commit. Ids are taken when events are
+/// inserted, so a lower id can commit after a higher one; streams hold back their resume position
+/// until every lower id had this long to show up.
+const COMMIT_LAG: Duration = Duration::from_secs(10);
+
+/// A deposit event as recorded.
+#[derive(Clone, Debug, Serialize)]
+pub struct StreamEvent {
+    pub id: i64,
+    pub donation_id: Uuid,
+    #[serde(skip)]
+    pub organization_id: Uuid,
+    pub event_type: String,
+    pub data: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An event as streams send it.
+#[derive(Clone, Debug, Serialize)]
+pub struct Streamed {
+    #[serde(flatten)]
+    pub event: StreamEvent,
+    /// What clients pass back as `Last-Event-ID`; trails `id` while lower ids may still commit, so
+    /// resuming can repeat events already sent.
+    pub last_event_id: i64,
+}
+
+/// What the listener relays to the streams of this instance.
+#[derive(Clone, Debug)]
+pub enum Relayed {
+    Event(StreamEvent),
+    /// The listener (re)connected; events recorded while it was away were never relayed.
+    Connected,
+}
+
+/// Which events a stream follows.
+#[derive(Clone, Debug)]
+pub enum StreamScope {
+    /// Every donation of these organizations, for a signed in user.
+    Organizations(Vec<Uuid>),
+    Donation(Uuid),
+}
+
+impl StreamScope {
+    fn includes(&self, event: &StreamEvent) -> bool {
+        match self {
+            StreamScope::Organizations(ids) => ids.contains(&event.organization_id),
+            StreamScope::Donation(id) => event.donation_id == *id,
+        }
+    }
+
+    fn filter(&self) -> (Vec<Uuid>, Option<Uuid>) {
+        match self {
+            StreamScope::Organizations(ids) => (ids.clone(), None),
+            StreamScope::Donation(id) => (vec![], Some(*id)),
+        }
+    }
+}
+
+impl StreamEvent {
+    /// Records an event; the insert trigger wakes the listener of every api instance.
+    pub async fn publish<'e, E: PgExecutor<'e>>(
+        donation_id: Uuid, organization_id: Uuid, event_type: &str, data: &Value, dedupe_key: &str,
+        executor: E,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "
+            INSERT INTO donation_events (donation_id, organization_id, event_type, data, dedupe_key)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (dedupe_key) DO NOTHING
+            ",
+            donation_id,
+            organization_id,
+            event_type,
+            data,
+            dedupe_key,
+        )
+            .execute(executor)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get(id: i64, db: &PgPool) -> Result<StreamEvent, Error> {
+        sqlx::query_as!(
+            StreamEvent,
+            "
+            SELECT id, donation_id, organization_id, event_type, data, created_at
+            FROM donation_events WHERE id = $1
+            ",
+            id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    /// The oldest page of events in scope after `last_id`.
+    async fn since(scope: &StreamScope, last_id: i64, db: &PgPool) -> Result<Vec<StreamEvent>, Error> {
+        let (organization_ids, donation_id) = scope.filter();
+
+        sqlx::query_as!(
+            StreamEvent,
+            "
+            SELECT id, donation_id, organization_id, event_type, data, created_at
+            FROM donation_events
+            WHERE id > $1 AND (organization_id = ANY($2) OR donation_id = $3)
+            ORDER BY id
+            LIMIT $4
+            ",
+            last_id,
+            &organization_ids,
+            donation_id,
+            REPLAY_PAGE,
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    /// Where a new stream starts: an id below every event that may still commit, and the ids in
+    /// scope recorded after it, which the stream won't send.
+    async fn tail(scope: &StreamScope, db: &PgPool) -> Result<(i64, Vec<i64>), Error> {
+        let (organization_ids, donation_id) = scope.filter();
+
+        // an event recorded over two lags ago was inserted before any transaction still running began
+        let row = sqlx::query!(
+            r#"
+            WITH settled AS (
+              SELECT COALESCE(MAX(id), 0) AS id FROM donation_events
+              WHERE created_at < CURRENT_TIMESTAMP - make_interval(secs => $1)
+            )
+            SELECT settled.id AS "settled!", ARRAY(
+              SELECT id FROM donation_events
+              WHERE id > settled.id AND (organization_id = ANY($2) OR donation_id = $3)
+              ORDER BY id
+            ) AS "recent!"
+            FROM settled
+            "#,
+            2.0 * COMMIT_LAG.as_secs_f64(),
+            &organization_ids,
+            donation_id,
+        )
+            .fetch_one(db)
+            .await?;
+
+        Ok((row.settled, row.recent))
+    }
+
+    pub async fn prune(db: &PgPool) -> Result<u64, Error> {
+        sqlx::query!(
+            "DELETE FROM donation_events WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1)",
+            RETENTION_DAYS
+        )
+            .execute(db)
+            .await
+            .map(|result| result.rows_affected())
+    }
+}
+
+/// Relays events recorded by any api instance to the streams of this one, until the process exits.
+pub async fn run_listener(state: Arc<AppState>) {
+    loop {
+        match listen(&state).await {
+            Ok(()) => warn!("Event listener stopped, reconnecting"),
+            Err(err) => error!("Event listener failed: {}", err),
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(state: &AppState) -> Result<(), Error> {
+    let mut listener = PgListener::connect_with(&state.db).await?;
+    listener.listen(CHANNEL).await?;
+    info!("Listening for donation events");
+    // streams catch up on whatever was recorded before this connection
+    let _ = state.events.send(Relayed::Connected);
+
+    loop {
+        let notification = listener.recv().await?;
+        let Ok(id) = notification.payload().parse() else {
+            warn!("Ignored donation event notification {:?}", notification.payload());
+            continue;
+        };
+        match StreamEvent::get(id, &state.db).await {
+            // nobody streaming isn't an error
+            Ok(event) => { let _ = state.events.send(Relayed::Event(event)); },
+            Err(Error::RowNotFound) => {},
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Events in scope after `last_id`, replayed from the database, followed by live ones as they
+/// come. A stream that falls behind the live buffer, or whose listener reconnected, catches up
+/// from the database.
+pub async fn subscribe(
+    state: Arc<AppState>, scope: StreamScope, last_id: Option<i64>
+) -> Result<impl Stream<Item = Streamed>, Error> {
+    // subscribe before replaying, so nothing recorded in between is missed
+    let receiver = state.events.subscribe();
+    let mut position = Position::default();
+    match last_id {
+        Some(last_id) => position.settled = last_id,
+        None => {
+            let (settled, recent) = StreamEvent::tail(&scope, &state.db).await?;
+            position.settled = settled;
+            if let Some(&last) = recent.iter().max() {
+                position.observe(last, Instant::now());
+            }
+            position.sent.extend(recent);
+        },
+    }
+    let mut subscription = Subscription {
+        db: state.db.clone(),
+        receiver,
+        scope,
+        backlog: VecDeque::new(),
+        replayed: position.settled,
+        position,
+        replaying: last_id.is_some(),
+    };
+    if subscription.replaying {
+        subscription.replay().await?;
+    }
+
+    Ok(futures_util::stream::unfold(subscription, |mut subscription| async move {
+        let event = subscription.next().await?;
+        Some((event, subscription))
+    }))
+}
+
+/// How far a stream got, allowing for ids that commit out of order.
+#[derive(Debug, Default)]
+struct Position {
+    /// Every event up to here was sent, or will never commit.
+    settled: i64,
+    /// Ids past `settled` already sent.
+    sent: BTreeSet<i64>,
+    /// Ids seen committed, and when; any lower id has `COMMIT_LAG` from then to commit.
+    seen: VecDeque<(i64, Instant)>,
+}
+
+impl Position {
+    /// Settles the ids whose lag ran out.
+    fn settle(&mut self, now: Instant) {
+        while let Some(&(id, seen_at)) = self.seen.front() {
+            if now < seen_at + COMMIT_LAG {
+                break;
+            }
+            self.seen.pop_front();
+            self.settled = self.settled.max(id);
+        }
+        self.sent = self.sent.split_off(&(self.settled + 1));
+    }
+
+    fn observe(&mut self, id: i64, now: Instant) {
+        self.settle(now);
+        self.seen.push_back((id, now));
+    }
+
+    /// Whether `id` is due, recording it as sent.
+    fn send(&mut self, id: i64) -> bool {
+        id > self.settled && self.sent.insert(id)
+    }
+}
+
+struct Subscription {
+    db: PgPool,
+    receiver: broadcast::Receiver<Relayed>,
+    scope: StreamScope,
+    backlog: VecDeque<StreamEvent>,
+    position: Position,
+    /// The last id replayed; replays page from here.
+    replayed: i64,
+    /// Whether the database may hold events past the backlog.
+    replaying: bool,
+}
+
+impl Subscription {
+    async fn replay(&mut self) -> Result<(), Error> {
+        let page = StreamEvent::since(&self.scope, self.replayed, &self.db).await?;
+        self.replaying = page.len() as i64 == REPLAY_PAGE;
+        if let Some(last) = page.last() {
+            self.replayed = last.id;
+            // the backlog is empty, so settling can't pass events still to be sent
+            self.position.observe(last.id, Instant::now());
+        }
+        self.backlog.extend(page);
+
+        Ok(())
+    }
+
+    /// Replays everything past the settled position again, skipping what was already sent.
+    fn catch_up(&mut self) {
+        self.replayed = self.position.settled;
+        self.replaying = true;
+    }
+
+    fn streamed(&self, event: StreamEvent) -> Streamed {
+        Streamed { event, last_event_id: self.position.settled }
+    }
+
+    async fn next(&mut self) -> Option<Streamed> {
+        loop {
+            if self.backlog.is_empty() && self.replaying {
+                if let Err(err) = self.replay().await {
+                    error!("Failed replay donation events: {}", err);
+                    return None;
+                }
+            }
+            if let Some(event) = self.backlog.pop_front() {
+                if self.position.send(event.id) {
+                    return Some(self.streamed(event));
+                }
+                continue;
+            }
+
+            match self.receiver.recv().await {
+                Ok(Relayed::Event(event)) => {
+                    self.position.observe(event.id, Instant::now());
+                    if self.scope.includes(&event) && self.position.send(event.id) {
+                        return Some(self.streamed(event));
+                    }
+                },
+                Ok(Relayed::Connected) => self.catch_up(),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Event stream fell {} events behind, catching up", skipped);
+                    self.catch_up();
+                },
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+pub struct StreamToken;
+
+impl StreamToken {
+    pub async fn get(donation_id: Uuid, db: &PgPool) -> Result<Option<String>, Error> {
+        sqlx::query_scalar!("SELECT token FROM donation_stream_tokens WHERE donation_id = $1", donation_id)
+            .fetch_optional(db)
+            .await
+    }
+
+    /// The donation's token, generated on first use.
+    pub async fn get_or_create(donation_id: Uuid, db: &PgPool) -> Result<String, Error> {
+        sqlx::query!(
+            "
+            INSERT INTO donation_stream_tokens (donation_id, token) VALUES ($1, $2)
+            ON CONFLICT (donation_id) DO UPDATE SET token = donation_stream_tokens.token
+            RETURNING token
+            ",
+            donation_id,
+            generate_token(),
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.token)
+    }
+
+    /// Replaces the token; streams opened with the old one keep running until they reconnect.
+    pub async fn rotate(donation_id: Uuid, db: &PgPool) -> Result<String, Error> {
+        sqlx::query!(
+            "
+            INSERT INTO donation_stream_tokens (donation_id, token) VALUES ($1, $2)
+            ON CONFLICT (donation_id) DO UPDATE SET token = EXCLUDED.token, created_at = CURRENT_TIMESTAMP
+            RETURNING token
+            ",
+            donation_id,
+            generate_token(),
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.token)
+    }
+
+    pub async fn is_valid(donation_id: Uuid, token: &str, db: &PgPool) -> Result<bool, Error> {
+        sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM donation_stream_tokens WHERE donation_id = $1 AND token = $2) AS "valid!""#,
+            donation_id,
+            token
+        )
+            .fetch_one(db)
+            .await
+            .map(|row| row.valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn event(id: i64, donation_id: Uuid, organization_id: Uuid) -> StreamEvent {
+        StreamEvent {
+            id,
+            donation_id,
+            organization_id,
+            event_type: "deposit.confirmed".to_string(),
+            data: Value::Null,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn scopes_events() {
+        let (org, other_org) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (donation, other_donation) = (Uuid::from_u128(3), Uuid::from_u128(4));
+
+        let account = StreamScope::Organizations(vec![org]);
+        assert!(account.includes(&event(1, donation, org)));
+        assert!(account.includes(&event(2, other_donation, org)));
+        assert!(!account.includes(&event(3, donation, other_org)));
+
+        let single = StreamScope::Donation(donation);
+        assert!(single.includes(&event(4, donation, other_org)));
+        assert!(!single.includes(&event(5, other_donation, org)));
+    }
+
+    #[test]
+    fn sends_ids_that_commit_late() {
+        let start = Instant::now();
+        let mut position = Position { settled: 1, ..Position::default() };
+
+        position.observe(3, start);
+        assert!(position.send(3));
+        position.observe(2, start + Duration::from_secs(1));
+        assert!(position.send(2));
+        // a replay after a reconnect sees both again
+        assert!(!position.send(2));
+        assert!(!position.send(3));
+        assert_eq!(position.settled, 1);
+
+        position.observe(5, start + COMMIT_LAG);
+        assert_eq!(position.settled, 3);
+        assert_eq!(position.sent, BTreeSet::new());
+        assert!(!position.send(3));
+        assert!(position.send(5));
+        assert!(position.send(4));
+
+        position.observe(6, start + COMMIT_LAG * 3);
+        assert_eq!(position.settled, 5);
+        assert_eq!(position.sent, BTreeSet::new());
+    }
+
+    #[sqlx::test]
+    async fn new_streams_start_below_events_that_may_still_commit(db: PgPool) {
+        let owner = fixtures::owner(&db).await;
+        let (roof, bell) = (fixtures::donation(&owner, "Roof", None, &db).await, fixtures::donation(&owner, "Bell", None, &db).await);
+        for (i, donation_id) in [roof, roof, bell, roof].into_iter().enumerate() {
+            StreamEvent::publish(donation_id, owner.organization_id, "deposit.confirmed", &Value::Null, &i.to_string(), &db)
+                .await
+                .unwrap();
+        }
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM donation_events ORDER BY id").fetch_all(&db).await.unwrap();
+        sqlx::query("UPDATE donation_events SET created_at = CURRENT_TIMESTAMP - INTERVAL '1 minute' WHERE id = $1")
+            .bind(ids[0])
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let scope = StreamScope::Donation(roof);
+        assert_eq!(StreamEvent::tail(&scope, &db).await.unwrap(), (ids[0], vec![ids[1], ids[3]]));
+        let replayed: Vec<i64> = StreamEvent::since(&scope, ids[0], &db).await.unwrap().iter().map(|event| event.id).collect();
+        assert_eq!(replayed, vec![ids[1], ids[3]]);
+    }
+
+    #[test]
+    fn tokens_are_random_and_prefixed() {
+        let (a, b) = (generate_token(), generate_token());
+        assert!(a.starts_with(TOKEN_PREFIX));
+        assert_eq!(a.len(), TOKEN_PREFIX.len() + 64);
+        assert_ne!(a, b);
+    }
+}
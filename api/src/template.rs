@@ -0,0 +1,740 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Error, PgPool};
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::{Decimal, Uuid};
+
+const MAX_TEMPLATES_PER_USER: i64 = 50;
+const MAX_NAME_LEN: usize = 100;
+const MAX_SUBJECT_LEN: usize = 255;
+const MAX_BODY_LEN: usize = 4000;
+/// What Telegram's MarkdownV2 wants escaped, which covers CommonMark too.
+const MARKDOWN_SPECIAL: &str = "\\_*[]()~`>#+-=|{}.!";
+/// Characters that open and close MarkdownV2 formatting, which have to come in pairs.
+const MARKDOWN_PAIRED: &str = "*_~`|";
+
+/// Placeholders templates may use, as `{{name}}`.
+pub const PLACEHOLDERS: [&str; 13] = [
+    "donor_name", "donor_message", "amount", "token", "chain", "transaction_id", "donation_title", "goal", "received",
+    "progress_percent", "rule_name", "kind", "message",
+];
+
+/// What a template is written in. Markdown uses Telegram's MarkdownV2 markup (`*bold*`,
+/// `_italic_`, `[text](url)`), with the characters it reserves escaped as needed when sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateFormat {
+    #[default]
+    Text,
+    Html,
+    Markdown,
+}
+
+impl TemplateFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateFormat::Text => "text",
+            TemplateFormat::Html => "html",
+            TemplateFormat::Markdown => "markdown",
+        }
+    }
+
+    /// How the template shows up where it isn't sent to a channel, such as in previews.
+    pub fn markup(&self) -> Markup {
+        match self {
+            TemplateFormat::Text => Markup::Plain,
+            TemplateFormat::Html => Markup::Html,
+            TemplateFormat::Markdown => Markup::Markdown,
+        }
+    }
+
+    /// Makes literal template text fit the markup it is sent as.
+    fn literal(&self, text: &str, markup: Markup) -> String {
+        match (self, markup) {
+            // shown as written, like values
+            (TemplateFormat::Text, markup) => markup.escape(text),
+            (TemplateFormat::Markdown, Markup::Plain | Markup::Slack) => unescape_markdown(text),
+            (TemplateFormat::Markdown, Markup::TelegramMarkdown) => escape_markdown_literal(text),
+            _ => text.to_string(),
+        }
+    }
+}
+
+/// How a message is shown where it ends up; placeholder values are escaped to match, so donors
+/// can't inject markup.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Markup {
+    #[default]
+    Plain,
+    Html,
+    /// Backslash escapes, as CommonMark and Discord take them.
+    Markdown,
+    /// Telegram's MarkdownV2, which rejects reserved characters left unescaped.
+    TelegramMarkdown,
+    /// Slack's mrkdwn, which only escapes `&`, `<` and `>`.
+    Slack,
+}
+
+impl Markup {
+    /// Escapes a value so it shows up as written.
+    pub fn escape(&self, value: &str) -> String {
+        match self {
+            Markup::Plain => value.to_string(),
+            Markup::Html => {
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    match c {
+                        '&' => escaped.push_str("&amp;"),
+                        '<' => escaped.push_str("&lt;"),
+                        '>' => escaped.push_str("&gt;"),
+                        '"' => escaped.push_str("&quot;"),
+                        '\'' => escaped.push_str("&#39;"),
+                        c => escaped.push(c),
+                    }
+                }
+                escaped
+            },
+            Markup::Markdown | Markup::TelegramMarkdown => {
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    if MARKDOWN_SPECIAL.contains(c) {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                escaped
+            },
+            Markup::Slack => value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"),
+        }
+    }
+}
+
+/// Escapes the reserved characters of MarkdownV2 that don't format anything, keeping those
+/// already escaped and link urls as they are.
+fn escape_markdown_literal(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                escaped.push('\\');
+                escaped.push(chars.next().unwrap_or('\\'));
+            },
+            ']' if chars.peek() == Some(&'(') => {
+                escaped.push(']');
+                for c in chars.by_ref() {
+                    escaped.push(c);
+                    if c == ')' {
+                        break;
+                    }
+                }
+            },
+            c if MARKDOWN_PAIRED.contains(c) || c == '[' || c == ']' => escaped.push(c),
+            c => {
+                if MARKDOWN_SPECIAL.contains(c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            },
+        }
+    }
+
+    escaped
+}
+
+/// Drops the backslashes of markdown escapes, for channels that show markdown as plain text.
+fn unescape_markdown(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next().unwrap_or('\\')),
+            c => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// Checks that the formatting characters of a markdown template's text come in pairs, so
+/// Telegram doesn't reject its messages.
+fn check_markdown(segments: &[Segment<'_>]) -> Result<(), String> {
+    let mut open: Vec<char> = vec![];
+    let mut brackets = 0;
+    for segment in segments {
+        let Segment::Text(text) = segment else { continue };
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                },
+                '[' => brackets += 1,
+                ']' if brackets == 0 => return Err("markdown has a ] without [, escape it as \\]".to_string()),
+                ']' => brackets -= 1,
+                c if MARKDOWN_PAIRED.contains(c) => match open.iter().position(|&opened| opened == c) {
+                    Some(position) => {
+                        open.remove(position);
+                    },
+                    None => open.push(c),
+                },
+                _ => {},
+            }
+        }
+    }
+
+    if brackets > 0 {
+        return Err("markdown has a [ without ], escape it as \\[".to_string());
+    }
+    match open.first() {
+        Some(c) => Err(format!("markdown has an unpaired {}, escape it as \\{}", c, c)),
+        None => Ok(()),
+    }
+}
+
+impl TryFrom<&str> for TemplateFormat {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "text" => Ok(TemplateFormat::Text),
+            "html" => Ok(TemplateFormat::Html),
+            "markdown" => Ok(TemplateFormat::Markdown),
+            _ => Err(format!("unknown template format: {}", value)),
+        }
+    }
+}
+
+/// What placeholders are filled with; missing values render empty.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemplateValues {
+    pub donor_name: Option<String>,
+    pub donor_message: Option<String>,
+    pub amount: Option<String>,
+    pub token: Option<String>,
+    pub chain: Option<String>,
+    pub transaction_id: Option<String>,
+    pub donation_title: Option<String>,
+    pub goal: Option<String>,
+    pub received: Option<String>,
+    pub progress_percent: Option<String>,
+    pub rule_name: Option<String>,
+    pub kind: Option<String>,
+    pub message: Option<String>,
+}
+
+impl TemplateValues {
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "donor_name" => self.donor_name.as_deref(),
+            "donor_message" => self.donor_message.as_deref(),
+            "amount" => self.amount.as_deref(),
+            "token" => self.token.as_deref(),
+            "chain" => self.chain.as_deref(),
+            "transaction_id" => self.transaction_id.as_deref(),
+            "donation_title" => self.donation_title.as_deref(),
+            "goal" => self.goal.as_deref(),
+            "received" => self.received.as_deref(),
+            "progress_percent" => self.progress_percent.as_deref(),
+            "rule_name" => self.rule_name.as_deref(),
+            "kind" => self.kind.as_deref(),
+            "message" => self.message.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// A made up deposit, for previews.
+    pub fn sample() -> TemplateValues {
+        TemplateValues {
+            donor_name: Some("Alice".to_string()),
+            donor_message: Some("Keep up the great work!".to_string()),
+            amount: Some("50".to_string()),
+            token: Some("USDT".to_string()),
+            chain: Some("tron".to_string()),
+            transaction_id: Some("4b1e7f3c9a0d2e6b8f5a1c7d3e9b0a2f4c6e8d1b3a5f7c9e0d2b4a6c8e1f3a5b".to_string()),
+            donation_title: Some("New stream setup".to_string()),
+            goal: Some("1000".to_string()),
+            received: Some("420".to_string()),
+            progress_percent: Some("42".to_string()),
+            rule_name: Some("Big tips".to_string()),
+            kind: Some("alert.triggered".to_string()),
+            message: Some("Alert \"Big tips\": received 50 USDT".to_string()),
+        }
+    }
+
+    /// Fills the values left out from `defaults`.
+    pub fn or(self, defaults: TemplateValues) -> TemplateValues {
+        TemplateValues {
+            donor_name: self.donor_name.or(defaults.donor_name),
+            donor_message: self.donor_message.or(defaults.donor_message),
+            amount: self.amount.or(defaults.amount),
+            token: self.token.or(defaults.token),
+            chain: self.chain.or(defaults.chain),
+            transaction_id: self.transaction_id.or(defaults.transaction_id),
+            donation_title: self.donation_title.or(defaults.donation_title),
+            goal: self.goal.or(defaults.goal),
+            received: self.received.or(defaults.received),
+            progress_percent: self.progress_percent.or(defaults.progress_percent),
+            rule_name: self.rule_name.or(defaults.rule_name),
+            kind: self.kind.or(defaults.kind),
+            message: self.message.or(defaults.message),
+        }
+    }
+
+    /// Takes the deposit details from a notification's data, where there are any.
+    pub fn from_notification(kind: &str, message: &str, data: &Value) -> TemplateValues {
+        let field = |value: &Value, name: &str| match value.get(name) {
+            Some(Value::String(value)) => Some(value.clone()),
+            Some(Value::Null) | None => None,
+            Some(value) => Some(value.to_string()),
+        };
+        let donor_message = data.get("message").filter(|message| message.is_object()).unwrap_or(&Value::Null);
+
+        TemplateValues {
+            donor_name: field(donor_message, "display_name"),
+            donor_message: field(donor_message, "message").filter(|message| !message.is_empty()),
+            amount: field(data, "amount"),
+            token: field(data, "token"),
+            chain: field(data, "chain"),
+            transaction_id: field(data, "transaction_id"),
+            donation_title: field(data, "donation_title"),
+            goal: field(data, "goal"),
+            received: field(data, "received"),
+            progress_percent: field(data, "progress_percent"),
+            rule_name: field(data, "rule_name"),
+            kind: Some(kind.to_string()),
+            message: Some(message.to_string()),
+        }
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a template into text and `{{name}}` placeholders, which can't span lines.
+fn parse(template: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = after.find("}}").filter(|&end| !after[..end].contains('\n')).ok_or("template has an unclosed {{")?;
+        segments.push(Segment::Text(&rest[..start]));
+        segments.push(Segment::Placeholder(after[..end].trim()));
+        rest = &after[end + 2..];
+    }
+    segments.push(Segment::Text(rest));
+
+    Ok(segments)
+}
+
+/// Checks that a template only uses known placeholders, and that markdown pairs its formatting.
+pub fn check(template: &str, format: TemplateFormat) -> Result<(), String> {
+    let segments = parse(template)?;
+    for segment in &segments {
+        if let Segment::Placeholder(name) = segment {
+            if !PLACEHOLDERS.contains(name) {
+                return Err(format!("unknown placeholder {{{{{}}}}}, use one of {}", name, PLACEHOLDERS.join(", ")));
+            }
+        }
+    }
+    if format == TemplateFormat::Markdown {
+        check_markdown(&segments)?;
+    }
+
+    Ok(())
+}
+
+/// Fills a checked template written in `format`, escaping it for `markup`.
+pub fn render(template: &str, format: TemplateFormat, markup: Markup, values: &TemplateValues) -> String {
+    let Ok(segments) = parse(template) else {
+        return template.to_string();
+    };
+
+    let mut rendered = String::with_capacity(template.len());
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => rendered.push_str(&format.literal(text, markup)),
+            Segment::Placeholder(name) => rendered.push_str(&markup.escape(values.get(name).unwrap_or_default())),
+        }
+    }
+
+    rendered
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RenderedMessage {
+    pub format: TemplateFormat,
+    /// For email; plain text.
+    pub subject: Option<String>,
+    pub body: String,
+    /// What the body was escaped for, which tells channels how to send it.
+    #[serde(skip)]
+    pub markup: Markup,
+}
+
+impl RenderedMessage {
+    /// A plain text message, escaped for `markup`.
+    pub fn text(body: &str, markup: Markup) -> RenderedMessage {
+        RenderedMessage { format: TemplateFormat::Text, subject: None, body: markup.escape(body), markup }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonTemplateIn {
+    pub name: String,
+    #[serde(default)]
+    pub format: TemplateFormat,
+    pub subject: Option<String>,
+    pub body: String,
+}
+
+impl JsonTemplateIn {
+    pub fn validate(&mut self) -> Result<(), String> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() || self.name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("name must be 1 to {} characters", MAX_NAME_LEN));
+        }
+
+        check_content(self.format, &mut self.subject, &self.body)
+    }
+}
+
+/// Trims away a blank subject, then checks the subject and body.
+fn check_content(format: TemplateFormat, subject: &mut Option<String>, body: &str) -> Result<(), String> {
+    *subject = subject.take().map(|subject| subject.trim().to_string()).filter(|subject| !subject.is_empty());
+    if let Some(subject) = subject {
+        if subject.chars().count() > MAX_SUBJECT_LEN || subject.contains('\n') {
+            return Err(format!("subject must be one line of at most {} characters", MAX_SUBJECT_LEN));
+        }
+        check(subject, TemplateFormat::Text).map_err(|err| format!("subject: {}", err))?;
+    }
+
+    if body.trim().is_empty() || body.chars().count() > MAX_BODY_LEN {
+        return Err(format!("body must be 1 to {} characters", MAX_BODY_LEN));
+    }
+    check(body, format).map_err(|err| format!("body: {}", err))
+}
+
+/// Subjects are plain text whatever the body's format.
+pub fn render_message(
+    format: TemplateFormat, subject: Option<&str>, body: &str, markup: Markup, values: &TemplateValues
+) -> RenderedMessage {
+    RenderedMessage {
+        format,
+        subject: subject.map(|subject| render(subject, TemplateFormat::Text, Markup::Plain, values)),
+        body: render(body, format, markup, values),
+        markup,
+    }
+}
+
+/// An unsaved template to check and render against the sample deposit, with any `values` given
+/// in its place.
+#[derive(Deserialize)]
+pub struct JsonTemplatePreview {
+    #[serde(default)]
+    pub format: TemplateFormat,
+    pub subject: Option<String>,
+    pub body: String,
+    pub values: Option<TemplateValues>,
+}
+
+impl JsonTemplatePreview {
+    pub fn validate(&mut self) -> Result<(), String> {
+        check_content(self.format, &mut self.subject, &self.body)
+    }
+
+    pub fn render(&self) -> RenderedMessage {
+        let values = self.values.clone().unwrap_or_default().or(TemplateValues::sample());
+        render_message(self.format, self.subject.as_deref(), &self.body, self.format.markup(), &values)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonReceiptTemplate {
+    /// `None` stops rendering receipts.
+    pub template_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct MessageTemplate {
+    pub id: Uuid,
+    pub name: String,
+    pub format: String,
+    pub subject: Option<String>,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl MessageTemplate {
+    pub async fn count(user_id: Uuid, db: &PgPool) -> Result<i64, Error> {
+        sqlx::query!("SELECT COUNT(*) AS count FROM message_templates WHERE user_id = $1", user_id)
+            .fetch_one(db)
+            .await
+            .map(|row| row.count.unwrap_or_default())
+    }
+
+    pub fn limit_reached(count: i64) -> bool {
+        count >= MAX_TEMPLATES_PER_USER
+    }
+
+    pub async fn create(user_id: Uuid, j_template: &JsonTemplateIn, db: &PgPool) -> Result<MessageTemplate, Error> {
+        sqlx::query_as!(
+            MessageTemplate,
+            "
+            INSERT INTO message_templates (user_id, name, format, subject, body)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, format, subject, body, created_at, updated_at
+            ",
+            user_id,
+            j_template.name,
+            j_template.format.as_str(),
+            j_template.subject,
+            j_template.body,
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<MessageTemplate, Error> {
+        sqlx::query_as!(
+            MessageTemplate,
+            "
+            SELECT id, name, format, subject, body, created_at, updated_at
+            FROM message_templates
+            WHERE id = $1 AND user_id = $2
+            ",
+            id,
+            user_id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<MessageTemplate>, Error> {
+        sqlx::query_as!(
+            MessageTemplate,
+            "
+            SELECT id, name, format, subject, body, created_at, updated_at
+            FROM message_templates
+            WHERE user_id = $1
+            ORDER BY created_at
+            ",
+            user_id
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    pub async fn update(
+        id: Uuid, user_id: Uuid, j_template: &JsonTemplateIn, db: &PgPool
+    ) -> Result<MessageTemplate, Error> {
+        sqlx::query_as!(
+            MessageTemplate,
+            "
+            UPDATE message_templates
+            SET name = $3, format = $4, subject = $5, body = $6, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, name, format, subject, body, created_at, updated_at
+            ",
+            id,
+            user_id,
+            j_template.name,
+            j_template.format.as_str(),
+            j_template.subject,
+            j_template.body,
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    /// Channels and donations using it fall back to the plain notification message and no receipt.
+    pub async fn delete(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!("DELETE FROM message_templates WHERE id = $1 AND user_id = $2", id, user_id)
+            .execute(db)
+            .await
+    }
+
+    pub fn format(&self) -> TemplateFormat {
+        TemplateFormat::try_from(self.format.as_str()).unwrap_or_default()
+    }
+
+
+    pub async fn set_receipt(donation_id: Uuid, template_id: Option<Uuid>, db: &PgPool) -> Result<(), Error> {
+        match template_id {
+            Some(template_id) => sqlx::query!(
+                "
+                INSERT INTO donation_receipt_templates (donation_id, template_id) VALUES ($1, $2)
+                ON CONFLICT (donation_id) DO UPDATE SET template_id = EXCLUDED.template_id
+                ",
+                donation_id,
+                template_id
+            )
+                .execute(db)
+                .await,
+            None => sqlx::query!("DELETE FROM donation_receipt_templates WHERE donation_id = $1", donation_id)
+                .execute(db)
+                .await,
+        }
+            .map(|_| ())
+    }
+}
+
+/// The template paid invoices of a donation show as their receipt, with the donation's details.
+pub struct ReceiptTemplate {
+    pub format: String,
+    pub subject: Option<String>,
+    pub body: String,
+    pub donation_title: String,
+    pub goal: Decimal,
+}
+
+impl ReceiptTemplate {
+    pub async fn get(donation_id: Uuid, db: &PgPool) -> Result<Option<ReceiptTemplate>, Error> {
+        sqlx::query_as!(
+            ReceiptTemplate,
+            "
+            SELECT templates.format, templates.subject, templates.body, donations.title AS donation_title,
+                   donations.amount AS goal
+            FROM donation_receipt_templates receipts
+            JOIN message_templates templates ON templates.id = receipts.template_id
+            JOIN donations ON donations.id = receipts.donation_id
+            WHERE receipts.donation_id = $1
+            ",
+            donation_id
+        )
+            .fetch_optional(db)
+            .await
+    }
+
+    pub fn render(&self, values: &TemplateValues) -> RenderedMessage {
+        let format = TemplateFormat::try_from(self.format.as_str()).unwrap_or_default();
+        render_message(format, self.subject.as_deref(), &self.body, format.markup(), values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn j_template(format: TemplateFormat, body: &str) -> JsonTemplateIn {
+        JsonTemplateIn {
+            name: " Tip ".to_string(),
+            format,
+            subject: Some(" {{amount}} {{token}} from {{donor_name}} ".to_string()),
+            body: body.to_string(),
+        }
+    }
+
+    fn preview(format: TemplateFormat, body: &str, values: &TemplateValues) -> RenderedMessage {
+        JsonTemplatePreview {
+            format,
+            subject: Some("{{amount}} {{token}} from {{donor_name}}".to_string()),
+            body: body.to_string(),
+            values: Some(values.clone()),
+        }.render()
+    }
+
+    #[test]
+    fn validates_templates() {
+        let mut template = j_template(TemplateFormat::Text, "{{ donor_name }} sent {{amount}} {{token}}");
+        assert!(template.validate().is_ok());
+        assert_eq!(template.name, "Tip");
+        assert_eq!(template.subject.as_deref(), Some("{{amount}} {{token}} from {{donor_name}}"));
+
+        assert!(j_template(TemplateFormat::Text, "{{amount}} for {{wallet_id}}").validate().is_err());
+        assert!(j_template(TemplateFormat::Text, "{{amount").validate().is_err());
+        assert!(j_template(TemplateFormat::Text, "{{amount\n}}").validate().is_err());
+        assert!(j_template(TemplateFormat::Text, " ").validate().is_err());
+        assert!(JsonTemplateIn { subject: Some("{{nope}}".to_string()), ..j_template(TemplateFormat::Text, "x") }.validate().is_err());
+        assert!(JsonTemplateIn { subject: Some("a\nb".to_string()), ..j_template(TemplateFormat::Text, "x") }.validate().is_err());
+
+        let mut j_preview = JsonTemplatePreview {
+            format: TemplateFormat::Html,
+            subject: Some(" ".to_string()),
+            body: "{{amount}}".to_string(),
+            values: None,
+        };
+        assert!(j_preview.validate().is_ok());
+        assert_eq!(j_preview.subject, None);
+        assert_eq!(j_preview.render().body, "50");
+    }
+
+    #[test]
+    fn escapes_values_for_the_format() {
+        let values = TemplateValues {
+            donor_name: Some("<b>Eve</b> & *co*".to_string()),
+            amount: Some("12.5".to_string()),
+            ..Default::default()
+        };
+
+        let text = preview(TemplateFormat::Text, "{{donor_name}}: {{amount}}", &values);
+        assert_eq!(text.body, "<b>Eve</b> & *co*: 12.5");
+
+        let html = preview(TemplateFormat::Html, "<p>{{donor_name}}: {{amount}}</p>", &values);
+        assert_eq!(html.body, "<p>&lt;b&gt;Eve&lt;/b&gt; &amp; *co*: 12.5</p>");
+        // subjects are plain text, and the sample fills in the token
+        assert_eq!(html.subject.as_deref(), Some("12.5 USDT from <b>Eve</b> & *co*"));
+
+        let markdown = preview(TemplateFormat::Markdown, "*{{donor_name}}*: {{amount}}", &values);
+        assert_eq!(markdown.body, "*<b\\>Eve</b\\> & \\*co\\**: 12\\.5");
+    }
+
+    #[test]
+    fn fits_markdown_to_the_markup() {
+        let values = TemplateValues { donor_name: Some("Bob".to_string()), amount: Some("1.5".to_string()), ..Default::default() };
+        let body = "Thanks, *{{donor_name}}*! {{amount}} \\- [see](https://x.io/(a)) #1";
+        let render_as = |markup| render(body, TemplateFormat::Markdown, markup, &values);
+
+        assert_eq!(render_as(Markup::TelegramMarkdown), "Thanks, *Bob*\\! 1\\.5 \\- [see](https://x.io/(a)\\) \\#1");
+        assert_eq!(render_as(Markup::Markdown), "Thanks, *Bob*! 1\\.5 \\- [see](https://x.io/(a)) #1");
+        assert_eq!(render_as(Markup::Plain), "Thanks, *Bob*! 1.5 - [see](https://x.io/(a)) #1");
+
+        assert_eq!(render("<{{amount}}>", TemplateFormat::Text, Markup::Slack, &values), "&lt;1.5&gt;");
+        assert_eq!(render("<b>{{amount}}</b>", TemplateFormat::Html, Markup::Html, &values), "<b>1.5</b>");
+    }
+
+    #[test]
+    fn checks_markdown_pairs() {
+        assert!(check("Thanks, *{{donor_name}}*! _{{amount}}_ [site](https://x.io)", TemplateFormat::Markdown).is_ok());
+        assert!(check("2 \\* 3 \\[x", TemplateFormat::Markdown).is_ok());
+        assert!(check("*{{amount}}", TemplateFormat::Markdown).is_err());
+        assert!(check("snake_case", TemplateFormat::Markdown).is_err());
+        assert!(check("[x", TemplateFormat::Markdown).is_err());
+        assert!(check("x]", TemplateFormat::Markdown).is_err());
+        // only markdown uses them as markup
+        assert!(check("snake_case *", TemplateFormat::Text).is_ok());
+    }
+
+    #[test]
+    fn leaves_missing_values_empty() {
+        let rendered = render(
+            "{{donor_name}}|{{progress_percent}}|{{token}}", TemplateFormat::Text, Markup::Plain, &TemplateValues::default(),
+        );
+        assert_eq!(rendered, "||");
+    }
+
+    #[test]
+    fn takes_values_from_notifications() {
+        let data = json!({
+            "amount": "50",
+            "token": "USDT",
+            "progress_percent": 42,
+            "message": { "display_name": "Alice", "message": "" },
+        });
+        let values = TemplateValues::from_notification("alert.triggered", "Big tip", &data);
+        assert_eq!(values.donor_name.as_deref(), Some("Alice"));
+        assert_eq!(values.donor_message, None);
+        assert_eq!(values.amount.as_deref(), Some("50"));
+        assert_eq!(values.progress_percent.as_deref(), Some("42"));
+        assert_eq!(values.message.as_deref(), Some("Big tip"));
+
+        let values = TemplateValues::from_notification("deposit.unmatched", "x", &json!({ "message": null }));
+        assert_eq!(values.donor_name, None);
+    }
+}
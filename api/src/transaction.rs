@@ -1,6 +1,11 @@
 use std::env;
+use chrono::{DateTime, Utc};
 use reqwest::{Client, Response};
+use serde::Deserialize;
 use serde_json::Value;
+use sqlx::types::Decimal;
+
+use crate::chain::Chain;
 
 
 async fn fetch_json_response(client: &Client, url: &str) -> Result<Value, String> {
@@ -28,4 +33,99 @@ pub async fn get_transactions_sum(client: &Client, address: &str) -> Result<Valu
     let url: String = format!("{}/transactions/{}/sum", base_url, address);
 
     fetch_json_response(client, &url).await
-}
\ No newline at end of file
+}
+/// Totals of what an address received, as counted by the transactions service.
+#[derive(Default, Deserialize)]
+pub struct AddressStats {
+    pub received: Decimal,
+    pub deposits: i64,
+    pub donors: i64,
+    pub last_deposit_timestamp: Option<i64>,
+}
+
+/// Which of an address's incomes count towards a donation: transfers of its token, or of any
+/// tracked token when it has none, received since the donation was created or started.
+/// Wallets are shared and reused, and anyone can send them tokens of their own.
+#[derive(Debug, PartialEq)]
+pub struct StatsScope {
+    pub token_contracts: Vec<&'static str>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl StatsScope {
+    pub fn new(
+        chain: Chain, token: Option<&str>, created_at: Option<DateTime<Utc>>, starts_at: Option<DateTime<Utc>>,
+    ) -> StatsScope {
+        let token_contracts = match token {
+            Some(token) => chain.token(Some(token)).and_then(|token| token.contract).into_iter().collect(),
+            None => chain.tracked_tokens().filter_map(|symbol| chain.token(Some(symbol))?.contract).collect(),
+        };
+
+        StatsScope { token_contracts, since: created_at.max(starts_at) }
+    }
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![("token_contracts", self.token_contracts.join(","))];
+        query.extend(self.since.map(|since| ("since", since.timestamp().to_string())));
+        query
+    }
+}
+
+pub async fn get_address_stats(client: &Client, address: &str, scope: &StatsScope) -> Result<AddressStats, String> {
+    let base_url: String = env::var("TRANSACTIONS_URL")
+        .unwrap_or_else(|_| "http://localhost:3002".to_string());
+    let url = reqwest::Url::parse_with_params(&format!("{}/transactions/{}/stats", base_url, address), scope.query())
+        .map_err(|err| format!("Invalid stats url: {}", err))?;
+
+    let value = fetch_json_response(client, url.as_str()).await?;
+    serde_json::from_value(value).map_err(|err| format!("Unexpected stats: {}", err))
+}
+
+/// A transaction as stored by the transactions service.
+#[derive(Deserialize)]
+pub struct StoredTransaction {
+    pub id: String,
+    pub amount: Decimal,
+    pub created_timestamp: Option<i64>,
+}
+
+/// The latest incomes of an address within the scope, newest first.
+pub async fn get_recent_incomes(
+    client: &Client, address: &str, scope: &StatsScope, limit: usize,
+) -> Result<Vec<StoredTransaction>, String> {
+    let base_url: String = env::var("TRANSACTIONS_URL")
+        .unwrap_or_else(|_| "http://localhost:3002".to_string());
+    let mut query = scope.query();
+    query.extend([("type", "income".to_string()), ("limit", limit.to_string())]);
+    let url = reqwest::Url::parse_with_params(&format!("{}/transactions/{}", base_url, address), query)
+        .map_err(|err| format!("Invalid transactions url: {}", err))?;
+
+    let value = fetch_json_response(client, url.as_str()).await?;
+    serde_json::from_value(value).map_err(|err| format!("Unexpected transactions: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDT: &str = "TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t";
+    const USDC: &str = "TEkxiTehnzSmSe2XqrBj4w32RUN966rdz8";
+
+    #[test]
+    fn scopes_follow_the_donation() {
+        let created_at = DateTime::from_timestamp(1_700_000_000, 0);
+        let starts_at = DateTime::from_timestamp(1_700_100_000, 0);
+
+        let scope = StatsScope::new(Chain::Tron, Some("USDT"), created_at, None);
+        assert_eq!(scope, StatsScope { token_contracts: vec![USDT], since: created_at });
+        assert_eq!(scope.query(), [("token_contracts", USDT.to_string()), ("since", "1700000000".to_string())]);
+
+        // a later start wins, and no token means any tracked one
+        let scope = StatsScope::new(Chain::Tron, None, created_at, starts_at);
+        assert_eq!(scope, StatsScope { token_contracts: vec![USDT, USDC], since: starts_at });
+
+        // nothing is collected for the native coin, and old donations count from the start
+        let scope = StatsScope::new(Chain::Tron, Some("TRX"), None, None);
+        assert_eq!(scope.query(), [("token_contracts", String::new())]);
+    }
+}
@@ -0,0 +1,55 @@
+use k256::PublicKey;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use sha3::{Digest, Keccak256};
+
+/// Mainnet addresses are `0x41 || last 20 bytes of keccak256(public key)`.
+pub const ADDRESS_PREFIX: u8 = 0x41;
+
+pub fn address_from_public_key(public_key: &PublicKey) -> String {
+    let point = public_key.to_encoded_point(false);
+    // skip the 0x04 uncompressed point tag
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+
+    let mut payload = Vec::with_capacity(21);
+    payload.push(ADDRESS_PREFIX);
+    payload.extend_from_slice(&hash[12..]);
+
+    bs58::encode(payload).with_check().into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::SecretKey;
+
+    fn address_from_private_key(private_key_hex: &str) -> Result<String, String> {
+        let bytes = hex::decode(private_key_hex).map_err(|e| e.to_string())?;
+        let secret_key = SecretKey::from_slice(&bytes).map_err(|e| e.to_string())?;
+        Ok(address_from_public_key(&secret_key.public_key()))
+    }
+
+    #[test]
+    fn address_from_private_key_one() {
+        // keccak part matches the well known ethereum address 0x7e5f4552091a69125d5dfcb7b8c2659029395bdf
+        let private_key = "0000000000000000000000000000000000000000000000000000000000000001";
+        assert_eq!(address_from_private_key(private_key).unwrap(), "TMVQGm1qAQYVdetCeGRRkTWYYrLXuHK2HC");
+    }
+
+    #[test]
+    fn address_from_tron_docs_private_key() {
+        let private_key = "da146374a75310b9666e834ee4ad0866d6f4035967bfc76217c5a495fff9f0d0";
+        assert_eq!(address_from_private_key(private_key).unwrap(), "TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY");
+    }
+
+    #[test]
+    fn base58check_encoding() {
+        let payload = hex::decode("41928c9af0651632157ef27a2cf17ca72c575a4d21").unwrap();
+        assert_eq!(bs58::encode(payload).with_check().into_string(), "TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY");
+    }
+
+    #[test]
+    fn rejects_invalid_private_keys() {
+        assert!(address_from_private_key("zz").is_err());
+        assert!(address_from_private_key(&"00".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,898 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{Error, PgExecutor, PgPool};
+use sqlx::types::{Decimal, Uuid};
+use tokio::task::JoinSet;
+
+use crate::alert::{self, AlertDonation};
+use crate::chain::Chain;
+use crate::invoice::Invoice;
+use crate::lifecycle::{self, DonationStatus};
+use crate::message::{DonorMessage, JsonPublicMessage};
+use crate::models::{Donation, DonationProgress, User, Wallet};
+use crate::notification::{self, Notification};
+use crate::ssrf::UrlPolicy;
+use crate::state::AppState;
+use crate::stream::StreamEvent;
+use crate::transaction::{self, StatsScope};
+use crate::webhook_endpoint::{EventType, WebhookEndpoint};
+
+/// Sent to a donation's own `webhook` for every confirmed deposit.
+pub const DEPOSIT_EVENT: &str = "donation.deposit";
+pub const PING_EVENT: &str = "ping";
+pub const SIGNATURE_HEADER: &str = "X-Donation-Signature";
+const SECRET_PREFIX: &str = "whsec_";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 20;
+const FIRST_RETRY_SECONDS: i64 = 30;
+const MAX_RETRY_SECONDS: i64 = 6 * 60 * 60;
+const RETRY_WINDOW_HOURS: i64 = 24;
+const MAX_ERROR_LEN: usize = 500;
+const MAX_BODY_LEN: usize = 1000;
+const LOG_LIMIT: i64 = 100;
+
+/// A deposit announced by the collector.
+#[derive(Deserialize)]
+pub struct Deposit {
+    pub wallet_id: Uuid,
+    pub chain: Chain,
+    pub address: String,
+    pub transaction_id: String,
+    pub amount: Decimal,
+    /// Older collectors sent the symbol the contract reported instead, which is not trusted.
+    #[serde(default)]
+    pub token_contract: Option<String>,
+    /// Collectors before unconfirmed transfers were reported only sent confirmed ones.
+    #[serde(default = "confirmed_by_default")]
+    pub confirmed: bool,
+}
+
+fn confirmed_by_default() -> bool {
+    true
+}
+
+impl Deposit {
+    /// The token transferred, `None` unless its contract is one the chain knows.
+    pub fn token(&self) -> Option<&'static str> {
+        self.token_contract.as_deref().and_then(|contract| self.chain.token_by_contract(contract))
+    }
+}
+
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", SECRET_PREFIX, hex::encode(bytes))
+}
+
+/// `t=<unix seconds>,v1=<hex hmac-sha256 of "<t>.<body>">`, with one `v1` per secret so
+/// receivers keep verifying while a rotated secret is in its grace period. The timestamp
+/// is signed too, so receivers can reject replayed deliveries.
+pub fn sign(secrets: &[String], timestamp: i64, body: &str) -> String {
+    let mut signature = format!("t={}", timestamp);
+    for secret in secrets {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+        mac.update(format!("{}.{}", timestamp, body).as_bytes());
+        signature.push_str(&format!(",v1={}", hex::encode(mac.finalize().into_bytes())));
+    }
+
+    signature
+}
+
+/// Exponential backoff from 30s, capped at 6h between attempts; `None` once the next
+/// attempt would fall outside the 24h retry window.
+pub fn next_attempt_at(created_at: DateTime<Utc>, attempts: i32, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    let delay = (FIRST_RETRY_SECONDS * 2_i64.pow(exponent)).min(MAX_RETRY_SECONDS);
+    let next = now + chrono::Duration::seconds(delay);
+
+    (next <= created_at + chrono::Duration::hours(RETRY_WINDOW_HOURS)).then_some(next)
+}
+
+/// An event to send to every endpoint subscribed to it.
+pub struct Publication {
+    pub event_type: EventType,
+    pub organization_id: Uuid,
+    /// Only send to this member's endpoints, for events about their own settings.
+    pub user_id: Option<Uuid>,
+    /// The donation the event is about, if any.
+    pub donation_id: Option<Uuid>,
+    /// Donations an endpoint's donation filter is matched against.
+    pub donation_ids: Vec<Uuid>,
+    pub transaction_id: Option<String>,
+    /// Events with the same key are only sent once per endpoint.
+    pub dedupe_key: Option<String>,
+    pub data: Value,
+}
+
+impl Publication {
+    pub fn for_donation(event_type: EventType, organization_id: Uuid, donation_id: Uuid, data: Value) -> Self {
+        Publication {
+            event_type,
+            organization_id,
+            user_id: None,
+            donation_id: Some(donation_id),
+            donation_ids: vec![donation_id],
+            transaction_id: None,
+            dedupe_key: None,
+            data,
+        }
+    }
+
+    pub fn wallet_deactivated(wallet: &Wallet, organization_id: Uuid, donation_ids: Vec<Uuid>) -> Self {
+        Publication {
+            event_type: EventType::WalletDeactivated,
+            organization_id,
+            user_id: None,
+            donation_id: None,
+            donation_ids: donation_ids.clone(),
+            transaction_id: None,
+            dedupe_key: None,
+            data: json!({
+                "wallet_id": wallet.id,
+                "chain": wallet.chain,
+                "address": wallet.data.address,
+                "donation_ids": donation_ids,
+            }),
+        }
+    }
+}
+
+struct DueEvent {
+    id: Uuid,
+    endpoint_id: Option<Uuid>,
+    payload: Value,
+    attempts: i32,
+    created_at: DateTime<Utc>,
+    webhook: Option<String>,
+    user_id: Uuid,
+}
+
+struct LockedEvent {
+    payload: Value,
+    attempts: i32,
+}
+
+struct EventRow {
+    id: Uuid,
+    donation_id: Option<Uuid>,
+    endpoint_id: Option<Uuid>,
+    event_type: String,
+    status: String,
+    next_attempt_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    payload: Value,
+}
+
+/// One request made for an event.
+#[derive(Serialize)]
+pub struct Attempt {
+    pub attempt: i32,
+    pub url: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub latency_ms: i32,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Attempt {
+    pub fn delivered(&self) -> bool {
+        self.response_status.is_some_and(|status| (200..300).contains(&status))
+    }
+}
+
+/// An event with its delivery log, newest attempt last.
+#[derive(Serialize)]
+pub struct Event {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub donation_id: Option<Uuid>,
+    /// `None` for events sent to the donation's own `webhook`.
+    pub endpoint_id: Option<Uuid>,
+    pub status: String,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub payload: Value,
+    pub attempts: Vec<Attempt>,
+}
+
+impl From<EventRow> for Event {
+    fn from(row: EventRow) -> Self {
+        Event {
+            id: row.id,
+            next_attempt_at: (row.status == "pending").then_some(row.next_attempt_at),
+            event_type: row.event_type,
+            donation_id: row.donation_id,
+            endpoint_id: row.endpoint_id,
+            status: row.status,
+            delivered_at: row.delivered_at,
+            created_at: row.created_at,
+            payload: row.payload,
+            attempts: Vec::new(),
+        }
+    }
+}
+
+/// Which events to read; events of endpoints other than the user's own are never included.
+#[derive(Default)]
+pub struct EventFilter {
+    pub user_id: Uuid,
+    pub id: Option<Uuid>,
+    pub donation_id: Option<Uuid>,
+    pub endpoint_id: Option<Uuid>,
+}
+
+pub struct WebhookEvent;
+
+impl WebhookEvent {
+    /// Queues the event for every active endpoint subscribed to it, owned by a member of
+    /// the organization and not filtered to other donations. Returns how many were queued.
+    pub async fn publish<'e, E: PgExecutor<'e>>(publication: &Publication, executor: E) -> Result<u64, Error> {
+        let payload = json!({
+            "type": publication.event_type.as_str(),
+            "created_at": Utc::now(),
+            "data": publication.data,
+        });
+
+        sqlx::query!(
+            "
+            WITH targets AS (
+              SELECT uuid_generate_v4() AS event_id, id AS endpoint_id
+              FROM webhook_endpoints
+              WHERE is_active
+                AND $1 = ANY(event_types)
+                AND (cardinality(donation_ids) = 0 OR donation_ids && $2)
+                AND user_id IN (SELECT user_id FROM organization_members WHERE organization_id = $3)
+                AND ($8::uuid IS NULL OR user_id = $8)
+            )
+            INSERT INTO webhook_events (id, endpoint_id, donation_id, event_type, transaction_id, dedupe_key, payload)
+            SELECT event_id, endpoint_id, $4, $1, $5, $6, jsonb_build_object('id', event_id) || $7
+            FROM targets
+            ON CONFLICT DO NOTHING
+            ",
+            publication.event_type.as_str(),
+            &publication.donation_ids,
+            publication.organization_id,
+            publication.donation_id,
+            publication.transaction_id,
+            publication.dedupe_key,
+            payload,
+            publication.user_id,
+        )
+            .execute(executor)
+            .await
+            .map(|result| result.rows_affected())
+    }
+
+    /// Queues the legacy deposit event for the donation's own `webhook`.
+    async fn enqueue_deposit(donation_id: Uuid, data: &Value, transaction_id: &str, db: &PgPool) -> Result<u64, Error> {
+        let id = Uuid::new_v4();
+        let payload = json!({
+            "id": id,
+            "type": DEPOSIT_EVENT,
+            "created_at": Utc::now(),
+            "data": data,
+        });
+
+        sqlx::query!(
+            "
+            INSERT INTO webhook_events (id, donation_id, event_type, transaction_id, dedupe_key, payload)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT DO NOTHING
+            ",
+            id,
+            donation_id,
+            DEPOSIT_EVENT,
+            transaction_id,
+            format!("{}:{}:{}", donation_id, DEPOSIT_EVENT, transaction_id),
+            payload,
+        )
+            .execute(db)
+            .await
+            .map(|result| result.rows_affected())
+    }
+
+    /// Records a test event for a donation's `webhook` or for an endpoint, leased so the
+    /// background worker leaves it to the caller, who delivers it with [`deliver_now`].
+    pub async fn create_ping(donation_id: Option<Uuid>, endpoint_id: Option<Uuid>, db: &PgPool) -> Result<Uuid, Error> {
+        let id = Uuid::new_v4();
+        let payload = json!({
+            "id": id,
+            "type": PING_EVENT,
+            "created_at": Utc::now(),
+            "data": {"donation_id": donation_id, "endpoint_id": endpoint_id},
+        });
+
+        sqlx::query!(
+            "
+            INSERT INTO webhook_events (id, donation_id, endpoint_id, event_type, payload, next_attempt_at)
+            VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP + INTERVAL '5 minutes')
+            ",
+            id,
+            donation_id,
+            endpoint_id,
+            PING_EVENT,
+            payload,
+        )
+            .execute(db)
+            .await
+            .map(|_| id)
+    }
+
+    /// The latest matching events with their attempts, newest first.
+    pub async fn list(filter: &EventFilter, db: &PgPool) -> Result<Vec<Event>, Error> {
+        let rows = sqlx::query_as!(
+            EventRow,
+            "
+            SELECT id, donation_id, endpoint_id, event_type, status, next_attempt_at, delivered_at, created_at, payload
+            FROM webhook_events
+            WHERE ($2::uuid IS NULL OR id = $2)
+              AND ($3::uuid IS NULL OR donation_id = $3)
+              AND ($4::uuid IS NULL OR endpoint_id = $4)
+              AND (endpoint_id IS NULL OR endpoint_id IN (SELECT id FROM webhook_endpoints WHERE user_id = $1))
+            ORDER BY created_at DESC
+            LIMIT $5
+            ",
+            filter.user_id,
+            filter.id,
+            filter.donation_id,
+            filter.endpoint_id,
+            LOG_LIMIT,
+        )
+            .fetch_all(db)
+            .await?;
+
+        Self::with_attempts(rows, db).await
+    }
+
+    pub async fn get(filter: &EventFilter, db: &PgPool) -> Result<Event, Error> {
+        Self::list(filter, db)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(Error::RowNotFound)
+    }
+
+    async fn with_attempts(rows: Vec<EventRow>, db: &PgPool) -> Result<Vec<Event>, Error> {
+        let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+        let attempts = sqlx::query!(
+            "
+            SELECT event_id, attempt, url, response_status, response_body, latency_ms, error, created_at
+            FROM webhook_attempts
+            WHERE event_id = ANY($1)
+            ORDER BY created_at
+            ",
+            &ids,
+        )
+            .fetch_all(db)
+            .await?;
+
+        let mut by_event: HashMap<Uuid, Vec<Attempt>> = HashMap::new();
+        for row in attempts {
+            by_event.entry(row.event_id).or_default().push(Attempt {
+                attempt: row.attempt,
+                url: row.url,
+                response_status: row.response_status,
+                response_body: row.response_body,
+                latency_ms: row.latency_ms,
+                error: row.error,
+                created_at: row.created_at,
+            });
+        }
+
+        Ok(rows.into_iter()
+            .map(|row| {
+                let attempts = by_event.remove(&row.id).unwrap_or_default();
+                Event { attempts, ..row.into() }
+            })
+            .collect())
+    }
+
+    /// Claims due events for five minutes, so a crashed worker's events are picked up again.
+    /// Events of a disabled endpoint or a donation without a webhook come back without one.
+    async fn claim_due(limit: i64, db: &PgPool) -> Result<Vec<DueEvent>, Error> {
+        sqlx::query_as!(
+            DueEvent,
+            r#"
+            WITH due AS (
+              UPDATE webhook_events
+              SET next_attempt_at = CURRENT_TIMESTAMP + INTERVAL '5 minutes'
+              WHERE id IN (
+                SELECT id FROM webhook_events
+                WHERE status = 'pending' AND next_attempt_at <= CURRENT_TIMESTAMP
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+              )
+              RETURNING id, donation_id, endpoint_id, payload, attempts, created_at
+            )
+            SELECT due.id AS "id!", due.endpoint_id, due.payload AS "payload!", due.attempts AS "attempts!",
+                   due.created_at AS "created_at!",
+                   CASE WHEN due.endpoint_id IS NULL THEN donations.webhook
+                        WHEN webhook_endpoints.is_active THEN webhook_endpoints.url END AS webhook,
+                   COALESCE(webhook_endpoints.user_id, donations.user_id) AS "user_id!"
+            FROM due
+            LEFT JOIN donations ON donations.id = due.donation_id
+            LEFT JOIN webhook_endpoints ON webhook_endpoints.id = due.endpoint_id
+            "#,
+            limit
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    /// Pushes a pending event's next attempt out by five minutes, like [`Self::claim_due`],
+    /// so the worker doesn't send it while it is delivered by hand.
+    async fn lock(id: Uuid, db: &PgPool) -> Result<LockedEvent, Error> {
+        sqlx::query_as!(
+            LockedEvent,
+            "
+            UPDATE webhook_events
+            SET next_attempt_at = GREATEST(next_attempt_at, CURRENT_TIMESTAMP + INTERVAL '5 minutes')
+            WHERE id = $1
+            RETURNING payload, attempts
+            ",
+            id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    async fn record_attempt(event_id: Uuid, attempt: &Attempt, db: &PgPool) -> Result<(), Error> {
+        sqlx::query!(
+            "
+            INSERT INTO webhook_attempts (event_id, attempt, url, response_status, response_body, latency_ms, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+            event_id,
+            attempt.attempt,
+            attempt.url,
+            attempt.response_status,
+            attempt.response_body,
+            attempt.latency_ms,
+            attempt.error,
+        )
+            .execute(db)
+            .await
+            .map(|_| ())
+    }
+
+    /// Counts an attempt; `next_attempt_at` is only used for events left pending. An event
+    /// settled while the attempt was in flight, e.g. by its endpoint being disabled, stays so.
+    async fn finish_attempt(
+        id: Uuid, status: &str, next_attempt_at: Option<DateTime<Utc>>, db: &PgPool
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "
+            UPDATE webhook_events
+            SET status = CASE WHEN $2 = 'pending' THEN status ELSE $2 END,
+                attempts = attempts + 1,
+                next_attempt_at = COALESCE($3, next_attempt_at),
+                delivered_at = CASE WHEN $4 THEN CURRENT_TIMESTAMP ELSE delivered_at END
+            WHERE id = $1
+            ",
+            id, status, next_attempt_at, status == "delivered"
+        )
+            .execute(db)
+            .await
+            .map(|_| ())
+    }
+}
+
+/// Queues the events a deposit triggers for every donation on its wallet: `deposit.detected`
+/// once per transfer, and once it is confirmed `deposit.confirmed` and the legacy deposit event
+/// for the donation's `webhook`, then runs the alert rules that apply and notifies the owner
+/// with `deposit.received`. A confirmed deposit covering an active donation's amount completes
+/// it, which fires `goal.reached` and may switch its wallet off, and is counted towards the open
+/// invoice it pays, if any.
+pub async fn handle_deposit(deposit: &Deposit, state: &AppState) -> Result<u64, Error> {
+    // any contract can name itself USDT, transfers of unknown ones are spam at best
+    let Some(token) = deposit.token() else {
+        info!("Ignoring deposit {} of unknown token contract {:?}", deposit.transaction_id, deposit.token_contract);
+        return Ok(0);
+    };
+
+    let donations = sqlx::query!(
+        "
+        SELECT id, user_id, organization_id, title, amount, webhook, status, deactivate_wallet_on_completion, chain,
+               token, created_at, starts_at
+        FROM donations WHERE wallet_id = $1
+        ",
+        deposit.wallet_id
+    )
+        .fetch_all(&state.db)
+        .await?;
+
+    // before the donations, so stream events carry the message the deposit paid for
+    if deposit.confirmed {
+        if let Some(invoice) = Invoice::apply_deposit(deposit, &state.db).await? {
+            info!("Deposit {} made invoice {} {}", deposit.transaction_id, invoice.id, invoice.status);
+        }
+    }
+
+    let mut queued = 0;
+    let mut release_wallet = false;
+    for donation in donations {
+        let organization_id = donation.organization_id;
+        let data = json!({
+            "donation_id": donation.id,
+            "wallet_id": deposit.wallet_id,
+            "chain": deposit.chain,
+            "address": deposit.address,
+            "transaction_id": deposit.transaction_id,
+            "amount": deposit.amount,
+            "token": token,
+            "token_contract": deposit.token_contract,
+            "confirmed": deposit.confirmed,
+        });
+
+        let mut event_types = vec![EventType::DepositDetected];
+        if deposit.confirmed {
+            event_types.push(EventType::DepositConfirmed);
+        }
+        for &event_type in &event_types {
+            queued += WebhookEvent::publish(&Publication {
+                transaction_id: Some(deposit.transaction_id.clone()),
+                dedupe_key: Some(format!("{}:{}:{}", donation.id, event_type.as_str(), deposit.transaction_id)),
+                ..Publication::for_donation(event_type, organization_id, donation.id, data.clone())
+            }, &state.db).await?;
+        }
+        let message = DonorMessage::for_transaction(donation.id, &deposit.transaction_id, &state.db).await?
+            .filter(|message| !message.hidden)
+            .map(JsonPublicMessage::from);
+        publish_stream_events(deposit, donation.id, organization_id, &event_types, message.as_ref(), &state.db).await?;
+        if !deposit.confirmed {
+            continue;
+        }
+
+        // what the donation received so far, for alerts and its goal; both go without it if
+        // the transactions service can't be reached
+        let chain = Chain::try_from(donation.chain.as_str()).unwrap_or_default();
+        let scope = StatsScope::new(chain, donation.token.as_deref(), donation.created_at, donation.starts_at);
+        let received = match transaction::get_address_stats(&state.http_client, &deposit.address, &scope).await {
+            Ok(stats) => Some(stats.received),
+            Err(err_msg) => {
+                warn!("Failed get stats of donation {}: {}", donation.id, err_msg);
+                None
+            },
+        };
+
+        let alert_donation = AlertDonation {
+            id: donation.id,
+            organization_id,
+            title: &donation.title,
+            goal: donation.amount,
+            received,
+        };
+        let triggered = alert::evaluate(deposit, &alert_donation, message.as_ref(), &state.db).await?;
+        if triggered > 0 {
+            info!("Deposit {} triggered {} alert rules on donation {}", deposit.transaction_id, triggered, donation.id);
+        }
+
+        let text = format!("Received {} {} for \"{}\"", deposit.amount.normalize(), token, donation.title);
+        Notification::create_once(
+            donation.user_id,
+            notification::DEPOSIT_RECEIVED,
+            &text,
+            json!({
+                "donation_id": donation.id,
+                "transaction_id": deposit.transaction_id,
+                "chain": deposit.chain,
+                "amount": deposit.amount,
+                "token": token,
+                "message": message,
+                "donation_title": donation.title,
+                "goal": donation.amount.normalize(),
+                "received": received.map(|received| received.normalize()),
+                "progress_percent": received.map(|received| DonationProgress::percent(donation.amount, received).normalize()),
+            }),
+            &format!("{}:{}:{}", donation.id, notification::DEPOSIT_RECEIVED, deposit.transaction_id),
+            &state.db,
+        ).await?;
+
+        if donation.webhook.is_some() {
+            queued += WebhookEvent::enqueue_deposit(donation.id, &data, &deposit.transaction_id, &state.db).await?;
+        }
+
+        if donation.status != DonationStatus::Active.as_str() {
+            continue;
+        }
+        if received.is_some_and(|received| received >= donation.amount) && Donation::complete(donation.id, &state.db).await? {
+            info!("Donation {} reached its goal", donation.id);
+            queued += WebhookEvent::publish(&Publication {
+                dedupe_key: Some(format!("{}:{}", donation.id, EventType::GoalReached.as_str())),
+                ..Publication::for_donation(EventType::GoalReached, organization_id, donation.id, json!({
+                    "donation_id": donation.id,
+                    "goal": donation.amount,
+                    "received": received,
+                    "transaction_id": deposit.transaction_id,
+                }))
+            }, &state.db).await?;
+            release_wallet |= donation.deactivate_wallet_on_completion;
+        }
+    }
+
+    if release_wallet {
+        lifecycle::release_wallet(deposit.wallet_id, &state.db).await?;
+    }
+
+    Ok(queued)
+}
+
+/// Feeds the live deposit streams, which show a donor's message next to the deposit paying it.
+async fn publish_stream_events(
+    deposit: &Deposit, donation_id: Uuid, organization_id: Uuid, event_types: &[EventType],
+    message: Option<&JsonPublicMessage>, db: &PgPool,
+) -> Result<(), Error> {
+    let data = json!({
+        "donation_id": donation_id,
+        "transaction_id": deposit.transaction_id,
+        "amount": deposit.amount,
+        "token": deposit.token(),
+        "token_contract": deposit.token_contract,
+        "chain": deposit.chain,
+        "confirmed": deposit.confirmed,
+        "message": message,
+    });
+
+    for event_type in event_types {
+        let dedupe_key = format!("{}:{}:{}", donation_id, event_type.as_str(), deposit.transaction_id);
+        StreamEvent::publish(donation_id, organization_id, event_type.as_str(), &data, &dedupe_key, db).await?;
+    }
+
+    Ok(())
+}
+
+/// Sends webhooks to user supplied urls, checking every url, lookup and redirect against
+/// the policy so they can't reach internal services.
+#[derive(Clone)]
+pub struct WebhookClient {
+    client: reqwest::Client,
+    pub policy: UrlPolicy,
+}
+
+impl WebhookClient {
+    pub fn new(policy: UrlPolicy) -> WebhookClient {
+        WebhookClient { client: policy.client(REQUEST_TIMEOUT), policy }
+    }
+}
+
+/// Delivers due webhook events until the process exits.
+pub async fn run_delivery(state: Arc<AppState>) {
+    let client = state.webhook_client.clone();
+
+    loop {
+        match WebhookEvent::claim_due(BATCH_SIZE, &state.db).await {
+            Ok(events) if !events.is_empty() => {
+                let mut deliveries = JoinSet::new();
+                for event in events {
+                    deliveries.spawn(deliver(event, client.clone(), state.clone()));
+                }
+                while deliveries.join_next().await.is_some() {}
+                continue;
+            },
+            Ok(_) => {},
+            Err(err) => error!("Failed claim webhook events: {}", err),
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn deliver(event: DueEvent, client: WebhookClient, state: Arc<AppState>) {
+    let number = event.attempts + 1;
+    let Some(url) = event.webhook else {
+        warn!("Webhook of event {} was removed or disabled, giving up", event.id);
+        finish(event.id, "failed", None, &state.db).await;
+        return;
+    };
+
+    let attempt = attempt(event.id, number, &event.payload, &url, event.user_id, &client, &state.db).await;
+    if let Some(endpoint_id) = event.endpoint_id {
+        match WebhookEndpoint::record_result(endpoint_id, attempt.delivered(), &state.db).await {
+            Ok(true) => warn!("Disabled webhook endpoint {} after repeated failures", endpoint_id),
+            Ok(false) => {},
+            Err(err) => error!("Failed update webhook endpoint {}: {}", endpoint_id, err),
+        }
+    }
+    if attempt.delivered() {
+        finish(event.id, "delivered", None, &state.db).await;
+        return;
+    }
+
+    let next_attempt_at = next_attempt_at(event.created_at, number, Utc::now());
+    info!(
+        "Webhook {} attempt {} failed (status {:?}, error {:?}), next attempt at {:?}",
+        event.id, number, attempt.response_status, attempt.error, next_attempt_at,
+    );
+    match next_attempt_at {
+        Some(_) => finish(event.id, "pending", next_attempt_at, &state.db).await,
+        None => finish(event.id, "failed", None, &state.db).await,
+    }
+}
+
+/// Sends an event right away, outside the retry schedule. A successful delivery marks
+/// the event delivered, a failed one leaves it `status_on_failure`.
+pub async fn deliver_now(
+    event_id: Uuid, url: &str, user_id: Uuid, status_on_failure: &str, client: &WebhookClient, db: &PgPool
+) -> Result<Attempt, Error> {
+    let event = WebhookEvent::lock(event_id, db).await?;
+    let attempt = attempt(event_id, event.attempts + 1, &event.payload, url, user_id, client, db).await;
+
+    let status = if attempt.delivered() { "delivered" } else { status_on_failure };
+    WebhookEvent::finish_attempt(event_id, status, None, db).await?;
+
+    Ok(attempt)
+}
+
+async fn finish(id: Uuid, status: &str, next_attempt_at: Option<DateTime<Utc>>, db: &PgPool) {
+    if let Err(err) = WebhookEvent::finish_attempt(id, status, next_attempt_at, db).await {
+        error!("Failed update webhook event {}: {}", id, err);
+    }
+}
+
+/// Sends the payload once and logs the attempt.
+async fn attempt(
+    event_id: Uuid, number: i32, payload: &Value, url: &str, user_id: Uuid, client: &WebhookClient, db: &PgPool
+) -> Attempt {
+    let started = Instant::now();
+    let result = send(payload, url, user_id, client, db).await;
+    let latency_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+
+    let (response_status, response_body, error) = match result {
+        Ok((status, body)) => (Some(status as i32), Some(excerpt(&body, MAX_BODY_LEN)), None),
+        Err(err_msg) => (None, None, Some(excerpt(&err_msg, MAX_ERROR_LEN))),
+    };
+    let attempt = Attempt {
+        attempt: number,
+        url: url.to_string(),
+        response_status,
+        response_body,
+        latency_ms,
+        error,
+        created_at: Utc::now(),
+    };
+
+    if let Err(err) = WebhookEvent::record_attempt(event_id, &attempt, db).await {
+        error!("Failed record webhook attempt of {}: {}", event_id, err);
+    }
+
+    attempt
+}
+
+/// Returns the response status and body.
+async fn send(payload: &Value, url: &str, user_id: Uuid, client: &WebhookClient, db: &PgPool) -> Result<(u16, String), String> {
+    // urls are checked again on every send, since the policy may have changed since they were saved
+    let url = client.policy.check_url(url)?;
+    let secrets = User::webhook_secret(user_id, db)
+        .await
+        .map_err(|err| format!("Failed load webhook secret: {}", err))?
+        .signing_secrets();
+    let body = payload.to_string();
+    let event_type = payload["type"].as_str().unwrap_or_default();
+    let event_id = payload["id"].as_str().unwrap_or_default();
+
+    let response = client.client.post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Donation-Event", event_type)
+        .header("X-Donation-Delivery", event_id)
+        .header(SIGNATURE_HEADER, sign(&secrets, Utc::now().timestamp(), &body))
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let status = response.status().as_u16();
+    // the body is only kept for debugging, so a broken one doesn't fail the attempt
+    let body = body_excerpt(response, MAX_BODY_LEN).await;
+
+    Ok((status, body))
+}
+
+/// Reads no more than `max_len` bytes of a response body, so endpoints can't make us buffer an
+/// endless one; a broken body ends the read early.
+pub async fn body_excerpt(mut response: reqwest::Response, max_len: usize) -> String {
+    let mut body = Vec::new();
+    while body.len() < max_len {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk[..chunk.len().min(max_len - body.len())]),
+            Ok(None) | Err(_) => break,
+        }
+    }
+
+    excerpt(&String::from_utf8_lossy(&body), max_len)
+}
+
+/// Cuts `text` to at most `max_len` bytes without splitting a character.
+fn excerpt(text: &str, max_len: usize) -> String {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    text[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        // hmac-sha256("secret", "1700000000.{}")
+        let secret = vec!["secret".to_string()];
+        assert_eq!(
+            sign(&secret, 1700000000, "{}"),
+            "t=1700000000,v1=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163",
+        );
+        assert_ne!(sign(&secret, 1700000000, "{}"), sign(&secret, 1700000001, "{}"));
+        assert_ne!(sign(&secret, 1700000000, "{}"), sign(&["other".to_string()], 1700000000, "{}"));
+    }
+
+    #[test]
+    fn signs_with_every_secret() {
+        let secrets = vec!["new".to_string(), "secret".to_string()];
+        let signature = sign(&secrets, 1700000000, "{}");
+
+        assert!(signature.starts_with("t=1700000000,v1="));
+        assert!(signature.ends_with(",v1=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"));
+        assert_eq!(signature.matches("v1=").count(), 2);
+    }
+
+    #[test]
+    fn backs_off_exponentially_for_a_day() {
+        let created_at = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let delay = |attempts, now: DateTime<Utc>| next_attempt_at(created_at, attempts, now).map(|next| (next - now).num_seconds());
+
+        assert_eq!(delay(1, created_at), Some(30));
+        assert_eq!(delay(2, created_at), Some(60));
+        assert_eq!(delay(5, created_at), Some(480));
+        assert_eq!(delay(15, created_at), Some(MAX_RETRY_SECONDS));
+
+        let late = created_at + chrono::Duration::hours(23);
+        assert_eq!(delay(3, late), Some(120));
+        assert_eq!(delay(12, late), None);
+    }
+
+    #[tokio::test]
+    async fn reads_only_the_start_of_endless_bodies() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            socket.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 1000000000\r\n\r\n").await.unwrap();
+            // writes until the client hangs up
+            while socket.write_all(&[b'x'; 4096]).await.is_ok() {}
+        });
+
+        let response = reqwest::Client::new().get(url).send().await.unwrap();
+        let body = tokio::time::timeout(std::time::Duration::from_secs(5), body_excerpt(response, MAX_BODY_LEN))
+            .await
+            .unwrap();
+        assert_eq!(body, "x".repeat(MAX_BODY_LEN));
+    }
+
+    #[test]
+    fn excerpts_on_char_boundaries() {
+        assert_eq!(excerpt("hello", 10), "hello");
+        assert_eq!(excerpt("hello", 4), "hell");
+        assert_eq!(excerpt("héllo", 2), "h");
+    }
+}
@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Error, PgPool};
+use sqlx::postgres::PgQueryResult;
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::error::AppError;
+use crate::notification::{self, Notification};
+
+/// Consecutive failed attempts after which an endpoint is switched off.
+const MAX_CONSECUTIVE_FAILURES: i32 = 25;
+const MAX_ENDPOINTS_PER_USER: i64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EventType {
+    #[serde(rename = "donation.created")]
+    DonationCreated,
+    #[serde(rename = "deposit.detected")]
+    DepositDetected,
+    #[serde(rename = "deposit.confirmed")]
+    DepositConfirmed,
+    #[serde(rename = "goal.reached")]
+    GoalReached,
+    #[serde(rename = "wallet.deactivated")]
+    WalletDeactivated,
+    #[serde(rename = "alert.triggered")]
+    AlertTriggered,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::DonationCreated => "donation.created",
+            EventType::DepositDetected => "deposit.detected",
+            EventType::DepositConfirmed => "deposit.confirmed",
+            EventType::GoalReached => "goal.reached",
+            EventType::WalletDeactivated => "wallet.deactivated",
+            EventType::AlertTriggered => "alert.triggered",
+        }
+    }
+}
+
+impl TryFrom<&str> for EventType {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "donation.created" => Ok(EventType::DonationCreated),
+            "deposit.detected" => Ok(EventType::DepositDetected),
+            "deposit.confirmed" => Ok(EventType::DepositConfirmed),
+            "goal.reached" => Ok(EventType::GoalReached),
+            "wallet.deactivated" => Ok(EventType::WalletDeactivated),
+            "alert.triggered" => Ok(EventType::AlertTriggered),
+            _ => Err(format!("unknown event type: {}", value)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JsonWebhookEndpointIn {
+    pub url: String,
+    pub description: Option<String>,
+    pub event_types: Vec<EventType>,
+    /// Only send events of these donations; empty or missing means all of them.
+    #[serde(default)]
+    pub donation_ids: Vec<Uuid>,
+    /// Setting it back to `true` re-enables an endpoint that was switched off.
+    pub is_active: Option<bool>,
+}
+
+impl JsonWebhookEndpointIn {
+    /// Checks the event types, leaving the url and `donation_ids` to the caller.
+    pub fn validate(&mut self) -> Result<(), AppError> {
+        self.url = self.url.trim().to_string();
+        if self.description.as_ref().is_some_and(|description| description.len() > 255) {
+            return Err(AppError::InvalidInput("description is too long".to_string()));
+        }
+
+        if self.event_types.is_empty() {
+            return Err(AppError::InvalidInput("event_types must not be empty".to_string()));
+        }
+        self.event_types.sort_by_key(|event_type| event_type.as_str());
+        self.event_types.dedup();
+        self.donation_ids.sort();
+        self.donation_ids.dedup();
+
+        Ok(())
+    }
+
+    fn event_type_names(&self) -> Vec<String> {
+        self.event_types.iter().map(|event_type| event_type.as_str().to_string()).collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    pub description: Option<String>,
+    pub event_types: Vec<String>,
+    pub donation_ids: Vec<Uuid>,
+    pub is_active: bool,
+    pub consecutive_failures: i32,
+    pub disabled_reason: Option<String>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookEndpoint {
+    pub async fn count(user_id: Uuid, db: &PgPool) -> Result<i64, Error> {
+        sqlx::query!("SELECT COUNT(*) AS count FROM webhook_endpoints WHERE user_id = $1", user_id)
+            .fetch_one(db)
+            .await
+            .map(|row| row.count.unwrap_or_default())
+    }
+
+    pub fn limit_reached(count: i64) -> bool {
+        count >= MAX_ENDPOINTS_PER_USER
+    }
+
+    pub async fn create(user_id: Uuid, j_endpoint: &JsonWebhookEndpointIn, db: &PgPool) -> Result<WebhookEndpoint, Error> {
+        sqlx::query_as!(
+            WebhookEndpoint,
+            "
+            INSERT INTO webhook_endpoints (user_id, url, description, event_types, donation_ids, is_active)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, url, description, event_types, donation_ids, is_active, consecutive_failures,
+                      disabled_reason, disabled_at, created_at
+            ",
+            user_id,
+            j_endpoint.url,
+            j_endpoint.description,
+            &j_endpoint.event_type_names(),
+            &j_endpoint.donation_ids,
+            j_endpoint.is_active.unwrap_or(true),
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn get(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<WebhookEndpoint, Error> {
+        sqlx::query_as!(
+            WebhookEndpoint,
+            "
+            SELECT id, url, description, event_types, donation_ids, is_active, consecutive_failures,
+                   disabled_reason, disabled_at, created_at
+            FROM webhook_endpoints
+            WHERE id = $1 AND user_id = $2
+            ",
+            id,
+            user_id
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn list(user_id: Uuid, db: &PgPool) -> Result<Vec<WebhookEndpoint>, Error> {
+        sqlx::query_as!(
+            WebhookEndpoint,
+            "
+            SELECT id, url, description, event_types, donation_ids, is_active, consecutive_failures,
+                   disabled_reason, disabled_at, created_at
+            FROM webhook_endpoints
+            WHERE user_id = $1
+            ORDER BY created_at
+            ",
+            user_id
+        )
+            .fetch_all(db)
+            .await
+    }
+
+    /// Enabling an endpoint clears its failure streak.
+    pub async fn update(
+        id: Uuid, user_id: Uuid, j_endpoint: &JsonWebhookEndpointIn, db: &PgPool
+    ) -> Result<WebhookEndpoint, Error> {
+        sqlx::query_as!(
+            WebhookEndpoint,
+            "
+            UPDATE webhook_endpoints
+            SET url = $3, description = $4, event_types = $5, donation_ids = $6,
+                is_active = COALESCE($7, is_active),
+                consecutive_failures = CASE WHEN $7 THEN 0 ELSE consecutive_failures END,
+                disabled_reason = CASE WHEN $7 THEN NULL ELSE disabled_reason END,
+                disabled_at = CASE WHEN $7 THEN NULL ELSE disabled_at END
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, url, description, event_types, donation_ids, is_active, consecutive_failures,
+                      disabled_reason, disabled_at, created_at
+            ",
+            id,
+            user_id,
+            j_endpoint.url,
+            j_endpoint.description,
+            &j_endpoint.event_type_names(),
+            &j_endpoint.donation_ids,
+            j_endpoint.is_active,
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn delete(id: Uuid, user_id: Uuid, db: &PgPool) -> Result<PgQueryResult, Error> {
+        sqlx::query!("DELETE FROM webhook_endpoints WHERE id = $1 AND user_id = $2", id, user_id)
+            .execute(db)
+            .await
+    }
+
+    /// Tracks the endpoint's failure streak, switching it off, failing its queued events and
+    /// notifying its owner once the streak gets too long. Returns whether it was switched off.
+    pub async fn record_result(id: Uuid, delivered: bool, db: &PgPool) -> Result<bool, Error> {
+        let mut tx = db.begin().await?;
+
+        let disabled = sqlx::query!(
+            "
+            UPDATE webhook_endpoints
+            SET consecutive_failures = CASE WHEN $2 THEN 0 ELSE consecutive_failures + 1 END,
+                is_active = $2 OR consecutive_failures + 1 < $3,
+                disabled_reason = CASE WHEN $2 OR consecutive_failures + 1 < $3 THEN NULL
+                  ELSE 'Disabled after ' || $3 || ' consecutive failed deliveries' END,
+                disabled_at = CASE WHEN $2 OR consecutive_failures + 1 < $3 THEN NULL ELSE CURRENT_TIMESTAMP END
+            WHERE id = $1 AND is_active
+            RETURNING user_id, url, is_active, disabled_reason
+            ",
+            id,
+            delivered,
+            MAX_CONSECUTIVE_FAILURES,
+        )
+            .fetch_optional(&mut *tx)
+            .await?
+            .filter(|row| !row.is_active);
+
+        if let Some(row) = &disabled {
+            sqlx::query!(
+                "UPDATE webhook_events SET status = 'failed' WHERE endpoint_id = $1 AND status = 'pending'",
+                id
+            )
+                .execute(&mut *tx)
+                .await?;
+
+            Notification::create(
+                row.user_id,
+                notification::WEBHOOK_ENDPOINT_DISABLED,
+                &format!("Webhook endpoint {} was disabled because it kept failing", row.url),
+                json!({"endpoint_id": id, "url": row.url, "reason": row.disabled_reason}),
+                &mut *tx,
+            ).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(disabled.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_types_round_trip() {
+        for event_type in [
+            EventType::DonationCreated, EventType::DepositDetected, EventType::DepositConfirmed,
+            EventType::GoalReached, EventType::WalletDeactivated, EventType::AlertTriggered,
+        ] {
+            assert_eq!(EventType::try_from(event_type.as_str()), Ok(event_type));
+            assert_eq!(serde_json::to_value(event_type).unwrap(), event_type.as_str());
+        }
+        assert!(EventType::try_from("donation.deleted").is_err());
+    }
+}
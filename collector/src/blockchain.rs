@@ -12,7 +12,28 @@ struct ApiResponse {
 #[derive(Deserialize)]
 struct TokenTransfer {
     transaction_id: String,
+    from_address: Option<String>,
     quant: String,
+    #[serde(rename = "tokenInfo")]
+    token_info: Option<TokenInfo>,
+    /// Missing means pending, so a transfer is never reported confirmed by mistake.
+    #[serde(default)]
+    confirmed: bool,
+}
+
+#[derive(Deserialize)]
+struct TokenInfo {
+    /// The contract address; the symbol next to it is whatever the contract chose to call itself.
+    #[serde(rename = "tokenId")]
+    token_id: String,
+}
+
+pub struct Transfer {
+    pub transaction_id: String,
+    pub sender: Option<String>,
+    pub amount: Decimal,
+    pub token_contract: Option<String>,
+    pub confirmed: bool,
 }
 
 pub enum GetTransactionError {
@@ -20,10 +41,10 @@ pub enum GetTransactionError {
     RetryAfter(Option<u64>)
 }
 
-pub async fn get_completed_transactions(address: &str, start_ts: Option<i64>, end_ts: Option<i64>) -> Result<Vec<(String, Decimal)>, GetTransactionError> {
+/// Confirmed and still pending transfers to the address.
+pub async fn get_transfers(address: &str, start_ts: Option<i64>, end_ts: Option<i64>) -> Result<Vec<Transfer>, GetTransactionError> {
     let url = "https://apilist.tronscanapi.com/api/token_trc20/transfers";
     let mut params = vec![
-        ("confirm", "true".to_string()),
         ("toAddress", address.to_string()),
     ];
     for (key, ts) in [("start_timestamp", start_ts), ("end_timestamp", end_ts)] {
@@ -33,12 +54,10 @@ pub async fn get_completed_transactions(address: &str, start_ts: Option<i64>, en
     }
 
     let url = reqwest::Url::parse_with_params(url, &params).unwrap().to_string();
-    let response = reqwest::get(&url).await.map_err(
-        |err| GetTransactionError::Request(err)
-    )?;
+    let response = reqwest::get(&url).await.map_err(GetTransactionError::Request)?;
 
     let response_status = response.status();
-    if vec![
+    if [
         http::StatusCode::FORBIDDEN,
         http::StatusCode::REQUEST_TIMEOUT,
         http::StatusCode::GATEWAY_TIMEOUT
@@ -47,11 +66,8 @@ pub async fn get_completed_transactions(address: &str, start_ts: Option<i64>, en
             .headers()
             .get("retry-after")
             .and_then(|hv| hv.to_str().ok())
-            .and_then(|hv_str| hv_str.parse::<u64>().map_err(
-                |e| {
-                    warn!("Invalid format of retry=({}): {:?}", hv_str, e.clone());
-                    e
-                }
+            .and_then(|hv_str| hv_str.parse::<u64>().inspect_err(
+                |e| warn!("Invalid format of retry=({}): {:?}", hv_str, e)
             ).ok())
             .unwrap_or(1000));
         return Err(GetTransactionError::RetryAfter(retry_after));
@@ -61,16 +77,21 @@ pub async fn get_completed_transactions(address: &str, start_ts: Option<i64>, en
 
     if let Err(err) = data {
         error!("Failed get response(code={}): {:?}", response_status, err);
-        let res: Vec<(String, Decimal)> = vec![];
-        return Ok(res)
+        return Ok(vec![])
     }
 
-    let transactions: Vec<(String, Decimal)> = data.unwrap().token_transfers
+    let transactions: Vec<Transfer> = data.unwrap().token_transfers
         .into_iter()
         .map(|transfer| {
             let amount = transfer.quant;
             let amount: Decimal = Decimal::from_str_exact(&amount).unwrap() / dec!(1_000_000.0);
-            (transfer.transaction_id, amount)
+            Transfer {
+                transaction_id: transfer.transaction_id,
+                sender: transfer.from_address,
+                amount,
+                token_contract: transfer.token_info.map(|info| info.token_id),
+                confirmed: transfer.confirmed,
+            }
         })
         .collect();
 
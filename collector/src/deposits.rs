@@ -0,0 +1,347 @@
+use std::collections::HashSet;
+use std::env;
+use async_trait::async_trait;
+use amqprs::{callbacks::{DefaultChannelCallback, DefaultConnectionCallback}, channel::{
+    BasicPublishArguments, QueueBindArguments, QueueDeclareArguments,
+}, connection::{Connection, OpenConnectionArguments}, BasicProperties};
+use redis::aio::MultiplexedConnection;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::Transaction;
+
+const EXCHANGE: &str = "amq.topic";
+const QUEUE: &str = "donation.deposits";
+const ROUTING_KEY: &str = "deposit.created";
+const DETECTED_PREFIX: &str = "detected:";
+/// Long enough for any transfer to be confirmed or dropped.
+const DETECTED_TTL_SECONDS: u64 = 24 * 60 * 60;
+const OUTBOX: &str = "deposits:outbox";
+const PUBLISH_BATCH: usize = 100;
+/// Marks a pending transaction detected and queues its deposit in one step.
+const DETECT_SCRIPT: &str = r"
+if redis.call('SET', KEYS[1], 1, 'NX', 'EX', ARGV[1]) then
+  redis.call('RPUSH', KEYS[2], ARGV[2])
+  return 1
+end
+return 0
+";
+
+/// A transfer to a watched wallet, queued once while it is pending and once more when
+/// it is confirmed and recorded.
+#[derive(Serialize)]
+struct Deposit {
+    wallet_id: String,
+    chain: String,
+    address: String,
+    transaction_id: String,
+    amount: Decimal,
+    token_contract: Option<String>,
+    confirmed: bool,
+}
+
+impl From<Transaction> for Deposit {
+    fn from(transaction: Transaction) -> Self {
+        Deposit {
+            wallet_id: transaction.wallet.wallet_id,
+            chain: transaction.wallet.chain,
+            address: transaction.wallet.address,
+            transaction_id: transaction.id,
+            amount: transaction.amount,
+            token_contract: transaction.token_contract,
+            confirmed: transaction.confirmed,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StoredTransaction {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    created: Vec<StoredTransaction>,
+}
+
+/// Deposits waiting to be published, so one that fails to publish is retried on the next poll
+/// instead of being lost once its transaction is stored or marked detected.
+#[async_trait]
+pub trait Outbox {
+    /// Queues the deposit of a pending transaction, unless it was detected before.
+    async fn push_detected(&mut self, transaction_id: &str, deposit: Vec<u8>) -> Result<bool, String>;
+    async fn push(&mut self, deposits: Vec<Vec<u8>>) -> Result<(), String>;
+    /// The oldest deposits queued, up to `limit`.
+    async fn peek(&mut self, limit: usize) -> Result<Vec<Vec<u8>>, String>;
+    /// Drops the oldest `count` deposits, once they are published.
+    async fn remove(&mut self, count: usize) -> Result<(), String>;
+}
+
+#[async_trait]
+impl Outbox for MultiplexedConnection {
+    async fn push_detected(&mut self, transaction_id: &str, deposit: Vec<u8>) -> Result<bool, String> {
+        let queued: i32 = redis::Script::new(DETECT_SCRIPT)
+            .key(format!("{}{}", DETECTED_PREFIX, transaction_id))
+            .key(OUTBOX)
+            .arg(DETECTED_TTL_SECONDS)
+            .arg(deposit)
+            .invoke_async(self)
+            .await
+            .map_err(|err| format!("Failed mark transaction {} detected: {}", transaction_id, err))?;
+
+        Ok(queued == 1)
+    }
+
+    async fn push(&mut self, deposits: Vec<Vec<u8>>) -> Result<(), String> {
+        if deposits.is_empty() {
+            return Ok(());
+        }
+
+        redis::cmd("RPUSH").arg(OUTBOX).arg(deposits)
+            .query_async(self)
+            .await
+            .map_err(|err| format!("Failed queue deposits: {}", err))
+    }
+
+    async fn peek(&mut self, limit: usize) -> Result<Vec<Vec<u8>>, String> {
+        redis::cmd("LRANGE").arg(OUTBOX).arg(0).arg(limit as i64 - 1)
+            .query_async(self)
+            .await
+            .map_err(|err| format!("Failed read queued deposits: {}", err))
+    }
+
+    async fn remove(&mut self, count: usize) -> Result<(), String> {
+        redis::cmd("LTRIM").arg(OUTBOX).arg(count).arg(-1)
+            .query_async(self)
+            .await
+            .map_err(|err| format!("Failed drop published deposits: {}", err))
+    }
+}
+
+#[async_trait]
+pub trait Publisher {
+    async fn publish(&self, deposits: &[Vec<u8>]) -> Result<(), String>;
+}
+
+pub struct RabbitMq;
+
+#[async_trait]
+impl Publisher for RabbitMq {
+    async fn publish(&self, deposits: &[Vec<u8>]) -> Result<(), String> {
+        publish(deposits).await
+    }
+}
+
+/// Stores the confirmed transactions and queues a deposit for each one the transactions
+/// service hadn't seen before, and for each pending one not seen yet, then publishes the
+/// queue. Returns how many were published.
+pub async fn record(
+    transactions: Vec<Transaction>, outbox: &mut impl Outbox, publisher: &impl Publisher,
+) -> Result<usize, String> {
+    let (confirmed, pending): (Vec<Transaction>, Vec<Transaction>) = transactions.into_iter()
+        .partition(|transaction| transaction.confirmed);
+
+    for transaction in pending {
+        let id = transaction.id.clone();
+        outbox.push_detected(&id, encode(transaction)?).await?;
+    }
+
+    if !confirmed.is_empty() {
+        let created = store(&confirmed).await?;
+        let deposits = confirmed.into_iter()
+            .filter(|transaction| created.contains(&transaction.id))
+            .map(encode)
+            .collect::<Result<Vec<_>, _>>()?;
+        outbox.push(deposits).await?;
+    }
+
+    flush(outbox, publisher).await
+}
+
+fn encode(transaction: Transaction) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(&Deposit::from(transaction)).map_err(|err| err.to_string())
+}
+
+/// Publishes the queued deposits oldest first. A batch that fails stays queued as a whole, so
+/// some deposits may be published twice; the api drops repeated deposits.
+async fn flush(outbox: &mut impl Outbox, publisher: &impl Publisher) -> Result<usize, String> {
+    let mut published = 0;
+    loop {
+        let batch = outbox.peek(PUBLISH_BATCH).await?;
+        if batch.is_empty() {
+            return Ok(published);
+        }
+
+        publisher.publish(&batch).await?;
+        outbox.remove(batch.len()).await?;
+        published += batch.len();
+    }
+}
+
+async fn store(transactions: &[Transaction]) -> Result<HashSet<String>, String> {
+    let base_url = env::var("TRANSACTIONS_URL").unwrap_or_else(|_| "http://localhost:3002".to_string());
+    let payload: Vec<_> = transactions.iter()
+        .map(|transaction| json!({
+            "id": transaction.id,
+            "address": transaction.wallet.address,
+            "amount": transaction.amount,
+            "type": "income",
+            "sender": transaction.sender,
+            "token_contract": transaction.token_contract,
+        }))
+        .collect();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/transactions/batch", base_url))
+        .json(&payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("Failed store transactions: {}", err))?;
+
+    let body: BatchResponse = response.json()
+        .await
+        .map_err(|err| format!("Failed read stored transactions: {}", err))?;
+
+    Ok(body.created.into_iter().map(|transaction| transaction.id).collect())
+}
+
+async fn publish(deposits: &[Vec<u8>]) -> Result<(), String> {
+    if deposits.is_empty() {
+        return Ok(());
+    }
+
+    let connection = Connection::open(&OpenConnectionArguments::new(
+        &env::var("RABBITMQ_HOST").unwrap_or("localhost".to_string()),
+        5672,
+        "guest",
+        "guest",
+    ))
+        .await
+        .map_err(|err| format!("Failed connect to rabbitmq: {}", err))?;
+    connection
+        .register_callback(DefaultConnectionCallback)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let channel = connection.open_channel(None).await.map_err(|err| err.to_string())?;
+    channel
+        .register_callback(DefaultChannelCallback)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // declared here as well, so deposits published before the api first connects are kept
+    channel
+        .queue_declare(QueueDeclareArguments::durable_client_named(QUEUE))
+        .await
+        .map_err(|err| err.to_string())?;
+    channel
+        .queue_bind(QueueBindArguments::new(QUEUE, EXCHANGE, ROUTING_KEY))
+        .await
+        .map_err(|err| err.to_string())?;
+
+    for deposit in deposits {
+        channel
+            .basic_publish(BasicProperties::default(), deposit.clone(), BasicPublishArguments::new(EXCHANGE, ROUTING_KEY))
+            .await
+            .map_err(|err| format!("Failed publish deposit: {}", err))?;
+    }
+
+    channel.close().await.map_err(|err| err.to_string())?;
+    connection.close().await.map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::Message;
+
+    #[derive(Default)]
+    struct MemoryOutbox {
+        detected: HashSet<String>,
+        queue: VecDeque<Vec<u8>>,
+    }
+
+    #[async_trait]
+    impl Outbox for MemoryOutbox {
+        async fn push_detected(&mut self, transaction_id: &str, deposit: Vec<u8>) -> Result<bool, String> {
+            let first = self.detected.insert(transaction_id.to_string());
+            if first {
+                self.queue.push_back(deposit);
+            }
+            Ok(first)
+        }
+
+        async fn push(&mut self, deposits: Vec<Vec<u8>>) -> Result<(), String> {
+            self.queue.extend(deposits);
+            Ok(())
+        }
+
+        async fn peek(&mut self, limit: usize) -> Result<Vec<Vec<u8>>, String> {
+            Ok(self.queue.iter().take(limit).cloned().collect())
+        }
+
+        async fn remove(&mut self, count: usize) -> Result<(), String> {
+            self.queue.drain(..count);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct FlakyPublisher {
+        down: AtomicBool,
+        published: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl Publisher for FlakyPublisher {
+        async fn publish(&self, deposits: &[Vec<u8>]) -> Result<(), String> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err("connection refused".to_string());
+            }
+            self.published.lock().unwrap().extend_from_slice(deposits);
+            Ok(())
+        }
+    }
+
+    fn pending(id: &str) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            sender: None,
+            amount: Decimal::new(10, 0),
+            token_contract: None,
+            confirmed: false,
+            wallet: Message {
+                chain: "tron".to_string(),
+                address: "TPL66VK2gCXNCD7EJg9pgJRfqcRazjhUZY".to_string(),
+                is_active: true,
+                wallet_id: "wallet".to_string(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_publishes_are_retried() {
+        let mut outbox = MemoryOutbox::default();
+        let publisher = FlakyPublisher { down: AtomicBool::new(true), ..FlakyPublisher::default() };
+
+        assert!(record(vec![pending("tx1")], &mut outbox, &publisher).await.is_err());
+        assert_eq!(outbox.queue.len(), 1);
+
+        // the next poll sees the transaction as detected already, and publishes it from the queue
+        publisher.down.store(false, Ordering::SeqCst);
+        assert_eq!(record(vec![pending("tx1"), pending("tx2")], &mut outbox, &publisher).await, Ok(2));
+        assert_eq!(record(vec![pending("tx1")], &mut outbox, &publisher).await, Ok(0));
+        assert!(outbox.queue.is_empty());
+
+        let published: Vec<String> = publisher.published.lock().unwrap().iter()
+            .map(|bytes| serde_json::from_slice::<serde_json::Value>(bytes).unwrap()["transaction_id"].to_string())
+            .collect();
+        assert_eq!(published, vec!["\"tx1\"", "\"tx2\""]);
+    }
+}
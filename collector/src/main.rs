@@ -1,4 +1,5 @@
 mod blockchain;
+mod deposits;
 
 use std::env;
 use amqprs::{callbacks::{DefaultChannelCallback, DefaultConnectionCallback}, channel::{
@@ -21,14 +22,22 @@ use crate::blockchain::GetTransactionError;
 const PREFIX: &str = "wid:";
 const RATE_LIMIT: i16 = 3;
 const MAX_TRIES: i8 = 3;
+/// Only TRON wallets are watched, wallets on other chains are stored but skipped.
+const WATCHED_CHAIN: &str = "tron";
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Message {
+    #[serde(default = "default_chain")]
+    chain: String,
     address: String,
     is_active: bool,
     wallet_id: String,
 }
 
+fn default_chain() -> String {
+    WATCHED_CHAIN.to_string()
+}
+
 struct MyConsumer {
     no_ack: bool,
     redis: MultiplexedConnection,
@@ -49,7 +58,7 @@ impl AsyncConsumer for MyConsumer {
         _basic_properties: BasicProperties,
         content: Vec<u8>,
     ) {
-        let msg: Message = serde_json::from_slice::<Message>(&content.clone()).unwrap().into();
+        let msg: Message = serde_json::from_slice::<Message>(&content.clone()).unwrap();
 
         let r_key = format!("{}{}", PREFIX, msg.wallet_id.clone());
         if msg.is_active {
@@ -104,24 +113,32 @@ async fn monitoring(mut redis: MultiplexedConnection) {
             time::sleep(time::Duration::from_secs(1)).await;
         } else {
             let msgs = values.iter()
-                .map(|bytes| {
-                    serde_json::from_slice::<Message>(bytes).unwrap().into()
-                })
+                .map(|bytes| serde_json::from_slice::<Message>(bytes).unwrap())
+                .filter(|msg| msg.chain == WATCHED_CHAIN)
                 .collect();
             let ts = check_wallets(msgs).await;
             info!("[{}] Found transactions:", c);
             for t in ts.iter() {
                 info!("# {:?}", t);
             }
+            match deposits::record(ts, &mut redis, &deposits::RabbitMq).await {
+                Ok(0) => {},
+                Ok(count) => info!("[{}] Published {} deposits", c, count),
+                Err(err_msg) => error!("[{}] Failed record deposits: {}", c, err_msg),
+            }
         }
         c += 1;
     }
 }
 
 #[derive(Debug)]
-struct Transaction {
+pub struct Transaction {
     id: String,
+    sender: Option<String>,
     amount: Decimal,
+    token_contract: Option<String>,
+    confirmed: bool,
+    wallet: Message,
 }
 
 async fn check_wallets(wallets: Vec<Message>) -> Vec<Transaction> {
@@ -153,7 +170,7 @@ async fn check_wallets(wallets: Vec<Message>) -> Vec<Transaction> {
         transactions.extend(process_batch_and_sleep(batch).await.0);
     }
 
-    return transactions;
+    transactions
 }
 
 async fn process_batch(batch: Vec<(Message, i8)>) -> (Vec<Transaction>, Vec<(Message, i8)>, Option<u64>) {
@@ -164,7 +181,7 @@ async fn process_batch(batch: Vec<(Message, i8)>) -> (Vec<Transaction>, Vec<(Mes
     // Collect all futures
     let futures: Vec<_> = batch.clone().into_iter().map(|(msg, tries)| {
         async move {
-            let result = blockchain::get_completed_transactions(&msg.address, None, None).await;
+            let result = blockchain::get_transfers(&msg.address, None, None).await;
             (msg, tries, result)
         }
     }).collect();
@@ -176,8 +193,14 @@ async fn process_batch(batch: Vec<(Message, i8)>) -> (Vec<Transaction>, Vec<(Mes
             Ok(trs) => {
                 ts.extend(
                     trs.into_iter()
-                        .map(|(id, amount)| Transaction { id, amount })
-                        .collect::<Vec<Transaction>>()
+                        .map(|transfer| Transaction {
+                            id: transfer.transaction_id,
+                            sender: transfer.sender,
+                            amount: transfer.amount,
+                            token_contract: transfer.token_contract,
+                            confirmed: transfer.confirmed,
+                            wallet: msg.clone(),
+                        })
                 );
             }
             Err(GetTransactionError::RetryAfter(retry)) => {
@@ -0,0 +1,2 @@
+-- Who sent an income, when the collector knows it
+ALTER TABLE transactions ADD COLUMN sender VARCHAR(100) DEFAULT NULL;
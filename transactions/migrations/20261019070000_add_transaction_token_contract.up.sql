@@ -0,0 +1,2 @@
+-- The contract of the transferred token; unknown for incomes stored before it was recorded
+ALTER TABLE transactions ADD COLUMN token_contract VARCHAR(100) DEFAULT NULL;
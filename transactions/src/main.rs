@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::{env};
 use std::collections::HashSet;
 use std::sync::Arc;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::response::{IntoResponse, Response};
 use chrono::Utc;
 use rust_decimal::Decimal;
@@ -16,7 +16,7 @@ use tower_http::trace::TraceLayer;
 
 mod models;
 
-use crate::models::{Transaction};
+use crate::models::{Scope, Transaction};
 
 const DUPLICATE_CODE: &str = "23505";
 
@@ -66,6 +66,39 @@ struct JsonTransaction {
     address: String,
     amount: Decimal,
     r#type: String,
+    #[serde(default)]
+    sender: Option<String>,
+    #[serde(default)]
+    token_contract: Option<String>,
+    #[serde(default, skip_deserializing)]
+    created_timestamp: Option<i64>,
+}
+
+/// `?token_contracts=<contract>,<contract>&since=<unix seconds>`; an empty `token_contracts`
+/// matches no contract at all. Lists also take `type` and `limit`.
+#[derive(Deserialize)]
+struct ScopeQuery {
+    token_contracts: Option<String>,
+    since: Option<i64>,
+    r#type: Option<String>,
+    limit: Option<i64>,
+}
+
+impl TryFrom<ScopeQuery> for Scope {
+    type Error = AppError;
+
+    fn try_from(query: ScopeQuery) -> Result<Scope, AppError> {
+        let since = query.since
+            .map(|since| OffsetDateTime::from_unix_timestamp(since)
+                .map_err(|_| AppError::InvalidInput("Invalid since".to_string())))
+            .transpose()?;
+        let token_contracts = query.token_contracts.map(|contracts| contracts.split(',')
+            .map(|contract| contract.trim().to_string())
+            .filter(|contract| !contract.is_empty())
+            .collect());
+
+        Ok(Scope { token_contracts, since })
+    }
 }
 
 impl From<JsonTransaction> for Transaction {
@@ -76,17 +109,22 @@ impl From<JsonTransaction> for Transaction {
             amount: value.amount,
             r#type: value.r#type,
             created_at: Some(OffsetDateTime::from_unix_timestamp(Utc::now().timestamp()).unwrap()),
+            sender: value.sender,
+            token_contract: value.token_contract,
         }
     }
 }
 
-impl Into<JsonTransaction> for Transaction {
-    fn into(self) -> JsonTransaction {
+impl From<Transaction> for JsonTransaction {
+    fn from(transaction: Transaction) -> Self {
         JsonTransaction {
-            id: self.id,
-            address: self.address,
-            amount: self.amount,
-            r#type: self.r#type,
+            id: transaction.id,
+            address: transaction.address,
+            amount: transaction.amount,
+            r#type: transaction.r#type,
+            sender: transaction.sender,
+            token_contract: transaction.token_contract,
+            created_timestamp: transaction.created_at.map(|at| at.unix_timestamp()),
         }
     }
 }
@@ -104,7 +142,7 @@ async fn create(
                     return AppError::InvalidInput("ID duplicate".to_string())
                 }
             };
-           return AppError::DbError(err);
+           AppError::DbError(err)
         }
     )?.into();
 
@@ -146,9 +184,14 @@ async fn create_batch(
 
 async fn list(
     Path(address): Path<String>,
+    Query(scope): Query<ScopeQuery>,
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let transactions: Vec<Transaction> = Transaction::list(&address, &state.db)
+    if scope.limit.is_some_and(|limit| limit < 0) {
+        return Err(AppError::InvalidInput("Invalid limit".to_string()));
+    }
+    let (r#type, limit) = (scope.r#type.clone(), scope.limit);
+    let transactions: Vec<Transaction> = Transaction::list(&address, &scope.try_into()?, r#type.as_deref(), limit, &state.db)
         .await
         .map_err(AppError::DbError)?;
 
@@ -165,6 +208,21 @@ async fn sum(
     Ok((StatusCode::OK, Json(json!({"result": sum}))))
 }
 
+async fn stats(
+    Path(address): Path<String>,
+    Query(scope): Query<ScopeQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let stats = Transaction::stats(&address, &scope.try_into()?, &state.db).await?;
+
+    Ok((StatusCode::OK, Json(json!({
+        "received": stats.received,
+        "deposits": stats.deposits,
+        "donors": stats.donors,
+        "last_deposit_timestamp": stats.last_deposit_at.map(|at| at.unix_timestamp()),
+    }))))
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
@@ -180,6 +238,7 @@ async fn main() {
         .route("/transactions/batch", post(create_batch))
         .route("/transactions/:address", get(list))
         .route("/transactions/:address/sum", get(sum))
+        .route("/transactions/:address/stats", get(stats))
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
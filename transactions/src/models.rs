@@ -1,5 +1,4 @@
 use rust_decimal::Decimal;
-use sqlx;
 use sqlx::{Error, PgPool};
 use sqlx::types::time::OffsetDateTime;
 
@@ -11,6 +10,24 @@ pub struct Transaction {
     pub address: String,
     pub r#type: String,
     pub created_at: Option<OffsetDateTime>,
+    pub sender: Option<String>,
+    pub token_contract: Option<String>,
+}
+
+/// Narrows an address's incomes down to some token contracts and to those stored since a
+/// point in time; left out, either matches everything.
+#[derive(Default)]
+pub struct Scope {
+    pub token_contracts: Option<Vec<String>>,
+    pub since: Option<OffsetDateTime>,
+}
+
+/// Totals of the incomes of an address.
+pub struct Stats {
+    pub received: Decimal,
+    pub deposits: i64,
+    pub donors: i64,
+    pub last_deposit_at: Option<OffsetDateTime>,
 }
 
 impl Transaction {
@@ -19,22 +36,41 @@ impl Transaction {
             Transaction,
             "
             INSERT
-            INTO transactions (id, amount, address, type)
-            VALUES ($1, $2, $3, $4)
+            INTO transactions (id, amount, address, type, sender, token_contract)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING *
             ",
             self.id,
             self.amount,
             self.address,
             self.r#type,
+            self.sender,
+            self.token_contract,
         )
             .fetch_one(db)
             .await
     }
 
-    pub async fn list(address: &str, db: &PgPool) -> Result<Vec<Transaction>, Error> {
+    /// Newest first, optionally only of one type and at most `limit` of them.
+    pub async fn list(
+        address: &str, scope: &Scope, r#type: Option<&str>, limit: Option<i64>, db: &PgPool
+    ) -> Result<Vec<Transaction>, Error> {
         sqlx::query_as!(
-            Transaction, "SELECT * FROM transactions WHERE address = $1", address
+            Transaction,
+            "
+            SELECT * FROM transactions
+            WHERE address = $1
+              AND ($2::text[] IS NULL OR token_contract = ANY($2))
+              AND ($3::timestamptz IS NULL OR created_at >= $3)
+              AND ($4::text IS NULL OR type = $4)
+            ORDER BY created_at DESC
+            LIMIT $5
+            ",
+            address,
+            scope.token_contracts.as_deref(),
+            scope.since,
+            r#type,
+            limit,
         )
             .fetch_all(db)
             .await
@@ -55,8 +91,91 @@ impl Transaction {
 
         Ok(rows.total.unwrap_or_default())
     }
+
+    /// Incomes without a known sender count as a donor each.
+    pub async fn stats(address: &str, scope: &Scope, db: &PgPool) -> Result<Stats, Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                SUM(amount) AS received,
+                COUNT(*) AS "deposits!",
+                COUNT(DISTINCT COALESCE(sender, id)) AS "donors!",
+                MAX(created_at) AS last_deposit_at
+            FROM transactions
+            WHERE address = $1 AND type = 'income'
+              AND ($2::text[] IS NULL OR token_contract = ANY($2))
+              AND ($3::timestamptz IS NULL OR created_at >= $3)
+            "#,
+            address,
+            scope.token_contracts.as_deref(),
+            scope.since,
+        )
+            .fetch_one(db)
+            .await?;
+
+        Ok(Stats {
+            received: row.received.unwrap_or_default(),
+            deposits: row.deposits,
+            donors: row.donors,
+            last_deposit_at: row.last_deposit_at,
+        })
+    }
 }
 
 pub async fn get_connection(db_url: &str) -> Result<PgPool, Error> {
     PgPool::connect(db_url).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDT: &str = "TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t";
+
+    async fn income(id: &str, amount: i64, sender: Option<&str>, token_contract: Option<&str>, db: &PgPool) {
+        Transaction {
+            id: id.to_string(),
+            amount: Decimal::new(amount, 0),
+            address: "TAddress".to_string(),
+            r#type: "income".to_string(),
+            created_at: None,
+            sender: sender.map(str::to_string),
+            token_contract: token_contract.map(str::to_string),
+        }
+            .create(db)
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn stats_count_only_the_scope(db: PgPool) {
+        income("early", 100, Some("TDonor1"), Some(USDT), &db).await;
+        sqlx::query("UPDATE transactions SET created_at = created_at - INTERVAL '1 day'").execute(&db).await.unwrap();
+        let since = OffsetDateTime::from_unix_timestamp(OffsetDateTime::now_utc().unix_timestamp() - 60 * 60).unwrap();
+
+        income("a", 10, Some("TDonor1"), Some(USDT), &db).await;
+        income("b", 5, Some("TDonor1"), Some(USDT), &db).await;
+        income("c", 7, None, Some(USDT), &db).await;
+        income("spam", 1000, Some("TSpammer"), Some("TFakeUsdt"), &db).await;
+        income("legacy", 3, None, None, &db).await;
+
+        let everything = Transaction::stats("TAddress", &Scope::default(), &db).await.unwrap();
+        assert_eq!((everything.received, everything.deposits, everything.donors), (Decimal::new(1125, 0), 6, 4));
+
+        let scope = Scope { token_contracts: Some(vec![USDT.to_string()]), since: Some(since) };
+        let stats = Transaction::stats("TAddress", &scope, &db).await.unwrap();
+        assert_eq!((stats.received, stats.deposits, stats.donors), (Decimal::new(22, 0), 3, 2));
+        assert!(stats.last_deposit_at.is_some());
+
+        let ids: Vec<String> = Transaction::list("TAddress", &scope, Some("income"), None, &db).await.unwrap()
+            .into_iter().map(|transaction| transaction.id).collect();
+        assert_eq!(ids.len(), 3);
+        assert!(!ids.contains(&"early".to_string()) && !ids.contains(&"spam".to_string()));
+        assert_eq!(Transaction::list("TAddress", &scope, Some("income"), Some(2), &db).await.unwrap().len(), 2);
+        assert!(Transaction::list("TAddress", &scope, Some("outcome"), None, &db).await.unwrap().is_empty());
+
+        let nothing = Scope { token_contracts: Some(vec![]), since: None };
+        let stats = Transaction::stats("TAddress", &nothing, &db).await.unwrap();
+        assert_eq!((stats.received, stats.deposits, stats.last_deposit_at), (Decimal::ZERO, 0, None));
+    }
+}